@@ -24,6 +24,13 @@ pub mod memmap {
     pub const AGC_MM_ERASABLE_END: usize = AGC_MM_ERASABLE_SIZE - 1;
     pub const AGC_MM_FIXED_START: usize = AGC_MM_ERASABLE_SIZE;
     pub const AGC_MM_FIXED_END: usize = AGC_MM_FIXED_START + AGC_MM_FIXED_SIZE - 1;
+
+    // Pseudo-addresses reach every word regardless of the bank registers:
+    // erasable bank E is at E * 0o400, and fixed bank F at 0o10000 + F * 0o2000
+    pub const AGC_PSEUDO_ERASABLE_END: usize = super::RAM_NUM_BANKS * super::RAM_BANK_NUM_WORDS - 1;
+    pub const AGC_PSEUDO_FIXED_START: usize = 0o10000;
+    pub const AGC_PSEUDO_FIXED_END: usize =
+        AGC_PSEUDO_FIXED_START + super::ROM_NUM_BANKS * super::ROM_BANK_NUM_WORDS - 1;
 }
 
 pub mod io {
@@ -49,6 +56,7 @@ pub mod io {
     pub const CHANNEL_CHAN33: usize = 0o33;
     pub const CHANNEL_CHAN34: usize = 0o34; // DOWNLIST WORD1
     pub const CHANNEL_CHAN35: usize = 0o35; // DOWNLIST WORD2
+    pub const CHANNEL_CHAN77: usize = 0o77; // RESTART MONITOR
 }
 
 pub mod cpu {
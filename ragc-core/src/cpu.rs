@@ -34,6 +34,42 @@ pub enum AgcOverflow {
     Negative,
 }
 
+///
+/// ## AgcRestartCause Enumeration
+///
+/// Hardware alarms which send a GOJAM restart to the AGC.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgcRestartCause {
+    ParityFail,
+    TcTrap,
+    RuptLock,
+    NightWatchman,
+    VoltageFail,
+    CounterFail,
+    ScalerFail,
+}
+
+impl AgcRestartCause {
+    ///
+    /// ## `channel77_bit` Function
+    ///
+    /// Returns the channel 77 bit of the alarm. Parity fails are reported as
+    /// E or F memory parity fails (bit 1).
+    ///
+    pub fn channel77_bit(self) -> u16 {
+        match self {
+            AgcRestartCause::ParityFail => 0o00001,
+            AgcRestartCause::TcTrap => 0o00004,
+            AgcRestartCause::RuptLock => 0o00010,
+            AgcRestartCause::NightWatchman => 0o00020,
+            AgcRestartCause::VoltageFail => 0o00040,
+            AgcRestartCause::CounterFail => 0o00100,
+            AgcRestartCause::ScalerFail => 0o00200,
+        }
+    }
+}
+
 trait AgcUnprogInstr {
     fn handle_goj(&mut self) -> u16;
 }
@@ -45,6 +81,9 @@ pub struct AgcCpu<'a> {
     pub idx_val: u16,
     pub ec_flag: bool,
     pub total_cycles: usize,
+    pub restarts: usize,
+    pub restart_cause: Option<AgcRestartCause>,
+    pub stolen_cycles: usize,
     mct_counter: f64,
     timer_counter: u8,
 
@@ -53,6 +92,8 @@ pub struct AgcCpu<'a> {
 
     unprog: heapless::Deque<AgcUnprogSeq, 8>,
     pub rupt: u16,
+    pub rupt_drop_mask: u16,
+    steal_mcts: u32,
 
     nightwatch: u16,
     nightwatch_cycles: u32,
@@ -104,6 +145,7 @@ impl <'a>AgcUnprogInstr for AgcCpu<'a> {
 
         // Reset the CPU by resetting to address 0x800
         self.restart();
+        self.restarts += 1;
 
         2
     }
@@ -135,12 +177,17 @@ impl <'a>AgcCpu<'a> {
             unprog: heapless::Deque::new(),
 
             total_cycles: 0,
+            restarts: 0,
+            restart_cause: None,
+            stolen_cycles: 0,
             mct_counter: 0.0,
             timer_counter: 0,
 
             gint: false,
             is_irupt: false,
             rupt: 1 << RUPT_DOWNRUPT,
+            rupt_drop_mask: 0,
+            steal_mcts: 0,
 
             nightwatch: 0,
             nightwatch_cycles: 0,
@@ -186,6 +233,31 @@ impl <'a>AgcCpu<'a> {
         }
     }
 
+    ///
+    /// ## `force_restart` Function
+    ///
+    /// Sends a GOJAM restart to the AGC, as the hardware alarm `cause` would.
+    /// The cause is kept in `restart_cause`, and its bit is latched in
+    /// channel 77 for the flight software until it writes the channel.
+    ///
+    pub fn force_restart(&mut self, cause: AgcRestartCause) {
+        self.restart_cause = Some(cause);
+        self.mem.set_restart_cause(cause.channel77_bit());
+        self.set_unprog_seq(AgcUnprogSeq::GOJ);
+    }
+
+    ///
+    /// ## `steal_cycles` Function
+    ///
+    /// Holds the CPU for `mcts` MCTs, starting with the next step, which then
+    /// runs one MCT at a time. Timers and peripherals keep running meanwhile,
+    /// as with counter increments taking cycles from the program. Stolen MCTs
+    /// are added to `stolen_cycles`.
+    ///
+    pub fn steal_cycles(&mut self, mcts: u32) {
+        self.steal_mcts += mcts;
+    }
+
     pub fn check_editing(&mut self, k: usize) {
         match k {
             0o20 | 0o21 | 0o22 | 0o23 => {
@@ -362,6 +434,26 @@ impl <'a>AgcCpu<'a> {
         self.mem.write_io(idx, val);
     }
 
+    pub fn stick_io_bits(&mut self, channel: usize, mask: u16, value: u16) -> bool {
+        self.mem.stick_io_bits(channel, mask, value)
+    }
+
+    pub fn release_io_bits(&mut self, channel: usize, mask: u16) {
+        self.mem.release_io_bits(channel, mask);
+    }
+
+    pub fn pseudo_address(&self, idx: usize) -> usize {
+        self.mem.pseudo_address(idx)
+    }
+
+    pub fn read_pseudo(&self, addr: usize) -> Option<u16> {
+        self.mem.read_pseudo(addr)
+    }
+
+    pub fn write_pseudo(&mut self, addr: usize, val: u16) -> bool {
+        self.mem.write_pseudo(addr, val)
+    }
+
     fn is_overflow(&mut self) -> bool {
         let a = self.read(REG_A);
         match a & 0xC000 {
@@ -388,7 +480,7 @@ impl <'a>AgcCpu<'a> {
 
     fn handle_rupt(&mut self) {
         debug!("Interrupt Mask: {:x}", self.rupt);
-        for i in 0..10 {
+        for i in 0..=RUPT_HANDRUPT as u16 {
             let mask = 1 << i;
             if self.rupt & mask != 0 {
                 // Set the interrupt flag to pending
@@ -527,7 +619,7 @@ impl <'a>AgcCpu<'a> {
                 self.ruptlock_count += cycles as i32;
                 if self.ruptlock_count > RUPT_LOCK_COUNT {
                     debug!("RUPTLOCK Restart. Sending GOJ");
-                    self.set_unprog_seq(AgcUnprogSeq::GOJ);
+                    self.force_restart(AgcRestartCause::RuptLock);
                 }
             }
             false => {
@@ -538,7 +630,7 @@ impl <'a>AgcCpu<'a> {
                 self.ruptlock_count -= cycles as i32;
                 if self.ruptlock_count < -RUPT_LOCK_COUNT {
                     debug!("RUPTLOCK Restart. Sending GOJ");
-                    self.set_unprog_seq(AgcUnprogSeq::GOJ);
+                    self.force_restart(AgcRestartCause::RuptLock);
                 }
             }
         }
@@ -556,7 +648,7 @@ impl <'a>AgcCpu<'a> {
             if self.nightwatch == 0 {
                 // Send GOJAM unprogram to restart the AGC.
                 debug!("NIGHT WATCHMAN Restart. Sending GOJ");
-                self.set_unprog_seq(AgcUnprogSeq::GOJ);
+                self.force_restart(AgcRestartCause::NightWatchman);
             }
 
             self.nightwatch = 0;
@@ -569,13 +661,13 @@ impl <'a>AgcCpu<'a> {
 
             // Send GOJAM unprogram to restart the AGC.
             debug!("TC TRAP Restart. Sending GOJ");
-            self.set_unprog_seq(AgcUnprogSeq::GOJ);
+            self.force_restart(AgcRestartCause::TcTrap);
         } else if self.non_tc_count >= TCMONITOR_COUNT {
             self.non_tc_count = 0;
 
             // Send GOJAM unprogram to restart the AGC.
             debug!("TC TRAP Restart. Sending GOJ");
-            self.set_unprog_seq(AgcUnprogSeq::GOJ);
+            self.force_restart(AgcRestartCause::TcTrap);
         }
     }
 
@@ -590,7 +682,9 @@ impl <'a>AgcCpu<'a> {
         self.handle_ruptlock(cycles);
 
         let timers = self.mem.fetch_timers();
-        self.rupt |= timers.pump_mcts(cycles, &mut self.unprog);
+        self.rupt |= timers.pump_mcts(cycles, &mut self.unprog) & !self.rupt_drop_mask;
+
+        self.mem.pump_periphs(cycles);
     }
 
     fn step_unprogrammed(&mut self) -> u16 {
@@ -618,7 +712,7 @@ impl <'a>AgcCpu<'a> {
         };
 
         if !self.rupt_disabled() {
-            self.rupt |= self.mem.check_interrupts();
+            self.rupt |= self.mem.check_interrupts() & !self.rupt_drop_mask;
             if self.rupt_pending() == true {
                 debug!("Handling Interrupt: {:?} {:x}", self.gint, self.rupt);
                 self.handle_rupt();
@@ -711,6 +805,15 @@ impl <'a>AgcCpu<'a> {
     }
 
     pub fn step(&mut self) -> u16 {
+        // Stolen cycles hold the CPU before anything else, one MCT per step
+        // like a counter increment
+        if self.steal_mcts > 0 {
+            self.steal_mcts -= 1;
+            self.stolen_cycles += 1;
+            self.update_cycles(1);
+            return 1;
+        }
+
         // Check to see if we have an unprogrammed sequence instruction
         // that was performed. If we did, create a bubble before executing
         if self.unprog.len() > 0 {
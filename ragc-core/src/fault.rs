@@ -0,0 +1,525 @@
+// Fault injection, provoking at chosen MCT times the conditions the restart
+// protection of the flight software has to recover from.
+
+use heapless::Vec;
+use log::info;
+
+use crate::consts::cpu::*;
+use crate::consts::memmap::{AGC_MM_ERASABLE_START, AGC_PSEUDO_ERASABLE_END};
+use crate::cpu::{AgcCpu, AgcRestartCause};
+
+/// Number of faults a `FaultInjector` can hold.
+pub const FAULT_NUM: usize = 32;
+
+const RUPT_NAMES: [(&str, u8); 10] = [
+    ("T6RUPT", RUPT_TIME6),
+    ("T5RUPT", RUPT_TIME5),
+    ("T3RUPT", RUPT_TIME3),
+    ("T4RUPT", RUPT_TIME4),
+    ("KEYRUPT1", RUPT_KEY1),
+    ("KEYRUPT2", RUPT_KEY2),
+    ("UPRUPT", RUPT_UPRUPT),
+    ("DOWNRUPT", RUPT_DOWNRUPT),
+    ("RADARUPT", RUPT_RADAR),
+    ("HANDRUPT", RUPT_HANDRUPT),
+];
+
+const CAUSE_NAMES: [(&str, AgcRestartCause); 7] = [
+    ("parity", AgcRestartCause::ParityFail),
+    ("tctrap", AgcRestartCause::TcTrap),
+    ("ruptlock", AgcRestartCause::RuptLock),
+    ("nightwatchman", AgcRestartCause::NightWatchman),
+    ("voltage", AgcRestartCause::VoltageFail),
+    ("counter", AgcRestartCause::CounterFail),
+    ("scaler", AgcRestartCause::ScalerFail),
+];
+
+///
+/// ## AgcFault Enumeration
+///
+/// Fault injected into the AGC. Flips and restarts happen once, the other
+/// faults last until the end of their duration.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgcFault {
+    /// XORs `mask` into the erasable word at pseudo-address `addr`
+    FlipBits { addr: usize, mask: u16 },
+    /// Sends a GOJAM restart with the given cause
+    Restart(AgcRestartCause),
+    /// Makes the bits of `mask` in an IO channel read as in `value`
+    StuckBits { channel: usize, mask: u16, value: u16 },
+    /// Discards the interrupt requests of the `1 << RUPT_xxx` bits of `mask`
+    DropInterrupts { mask: u16 },
+    /// Steals `percent` (1 to 99) of the MCTs from the program
+    Starve { percent: u8 },
+}
+
+///
+/// ## ScheduledFault Structure
+///
+/// Fault injected once the AGC has run `at_mcts` MCTs since reset. Lasting
+/// faults end `duration_mcts` MCTs later, or never with `None`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduledFault {
+    pub at_mcts: usize,
+    pub duration_mcts: Option<usize>,
+    pub fault: AgcFault,
+}
+
+impl ScheduledFault {
+    fn end_mcts(&self) -> Option<usize> {
+        self.duration_mcts.map(|x| self.at_mcts.saturating_add(x))
+    }
+
+    fn is_lasting(&self) -> bool {
+        !matches!(self.fault, AgcFault::FlipBits { .. } | AgcFault::Restart(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultErrorKind {
+    UnknownCommand,
+    BadArgument,
+    TooManyFaults,
+}
+
+///
+/// ## `FaultError` Structure
+///
+/// Error found while parsing a fault scenario. `line` starts at 1.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultError {
+    pub line: usize,
+    pub kind: FaultErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FaultState {
+    Pending,
+    Active,
+    Done,
+}
+
+fn parse_octal(text: Option<&str>, max: usize) -> Option<usize> {
+    usize::from_str_radix(text?, 8).ok().filter(|x| *x <= max)
+}
+
+///
+/// ## `parse_erasable` Function
+///
+/// Returns the pseudo-address of an erasable word of `AgcRam`, written as
+/// `E5,1400` for a bank, an unswitched address such as `0061`, or `@` and a
+/// pseudo-address.
+///
+fn parse_erasable(text: Option<&str>) -> Option<usize> {
+    let text = text?;
+    let addr = if let Some(x) = text.strip_prefix('@') {
+        parse_octal(Some(x), AGC_PSEUDO_ERASABLE_END)?
+    } else if let Some(x) = text.strip_prefix(|c| c == 'E' || c == 'e') {
+        let idx = x.find(',')?;
+        let bank = parse_octal(Some(&x[..idx]), 7)?;
+        match parse_octal(Some(&x[idx + 1..]), 0o1777)? {
+            x @ 0o1400..=0o1777 => (bank << 8) + x - 0o1400,
+            _ => return None,
+        }
+    } else {
+        parse_octal(Some(text), 0o1377)?
+    };
+    match addr {
+        AGC_MM_ERASABLE_START..=AGC_PSEUDO_ERASABLE_END => Some(addr),
+        _ => None,
+    }
+}
+
+fn parse_rupts(text: Option<&str>) -> Option<u16> {
+    let mut mask = 0;
+    for name in text?.split(',') {
+        let (_, bit) = RUPT_NAMES.iter().find(|(x, _)| x.eq_ignore_ascii_case(name))?;
+        mask |= 1 << bit;
+    }
+    Some(mask)
+}
+
+fn parse_fault<'a>(name: &str, args: &mut impl Iterator<Item = &'a str>) -> Result<AgcFault, FaultErrorKind> {
+    let bad = FaultErrorKind::BadArgument;
+    let fault = match name {
+        "flip" => AgcFault::FlipBits {
+            addr: parse_erasable(args.next()).ok_or(bad)?,
+            mask: parse_octal(args.next(), 0o177777).ok_or(bad)? as u16,
+        },
+        "restart" => {
+            let name = args.next().ok_or(bad)?;
+            let (_, cause) = CAUSE_NAMES.iter().find(|(x, _)| x.eq_ignore_ascii_case(name)).ok_or(bad)?;
+            AgcFault::Restart(*cause)
+        }
+        "stuck" => {
+            let channel = parse_octal(args.next(), 0o777).ok_or(bad)?;
+            let mask = parse_octal(args.next(), 0o177777).ok_or(bad)? as u16;
+            let value = parse_octal(args.next(), 0o177777).ok_or(bad)? as u16;
+            AgcFault::StuckBits {
+                channel,
+                mask,
+                value: value & mask,
+            }
+        }
+        "drop" => AgcFault::DropInterrupts {
+            mask: parse_rupts(args.next()).ok_or(bad)?,
+        },
+        "starve" => match args.next().map(|x| x.parse::<u8>()) {
+            Some(Ok(percent @ 1..=99)) => AgcFault::Starve { percent },
+            _ => return Err(bad),
+        },
+        _ => return Err(FaultErrorKind::UnknownCommand),
+    };
+    Ok(fault)
+}
+
+///
+/// ## FaultInjector Structure
+///
+/// Schedule of faults injected into the AGC. `pump` is called after each
+/// step of the CPU, such as from the `stop` closure of
+/// `AgcRunner::advance_until`, and injects the faults which are due.
+///
+/// The injector owns the interrupt drop mask of the CPU while it is pumped.
+///
+pub struct FaultInjector {
+    faults: Vec<(ScheduledFault, FaultState), FAULT_NUM>,
+    next_mcts: usize,
+    starve_percent: u8,
+    starve_debt: usize,
+    last_cycles: usize,
+    last_stolen: usize,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        FaultInjector {
+            faults: Vec::new(),
+            next_mcts: usize::MAX,
+            starve_percent: 0,
+            starve_debt: 0,
+            last_cycles: 0,
+            last_stolen: 0,
+        }
+    }
+
+    ///
+    /// ## `parse` Function
+    ///
+    /// Parses a fault scenario, with one fault per line. Times and durations
+    /// are decimal MCTs since reset, addresses and values are octal. Anything
+    /// after a `#` is a comment.
+    ///
+    /// ```text
+    /// at 100000 flip E5,1400 00004          # XOR a mask into an erasable word
+    /// at 200000 restart nightwatchman       # GOJAM with a restart cause
+    /// at 300000 stuck 30 00004 0 for 85470  # channel, mask and stuck value
+    /// at 400000 drop T3RUPT,T4RUPT for 8547 # interrupt requests discarded
+    /// at 500000 starve 40 for 85470         # percent of the MCTs stolen
+    /// ```
+    ///
+    /// The restart causes are `parity`, `tctrap`, `ruptlock`,
+    /// `nightwatchman`, `voltage`, `counter` and `scaler`. Faults other than
+    /// `flip` and `restart` last forever without a `for` duration.
+    ///
+    pub fn parse(text: &str) -> Result<FaultInjector, FaultError> {
+        let mut injector = FaultInjector::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(x) => &line[..x],
+                None => line,
+            };
+            let err = |kind| FaultError { line: idx + 1, kind };
+            let bad = || err(FaultErrorKind::BadArgument);
+
+            let mut args = line.split_whitespace();
+            match args.next() {
+                None => continue,
+                Some("at") => {}
+                Some(_) => return Err(err(FaultErrorKind::UnknownCommand)),
+            }
+            let at_mcts = args.next().and_then(|x| x.parse::<usize>().ok()).ok_or_else(bad)?;
+            let fault = parse_fault(args.next().ok_or_else(bad)?, &mut args).map_err(err)?;
+            let duration_mcts = match (args.next(), args.next()) {
+                (None, _) => None,
+                (Some("for"), Some(x)) => Some(x.parse::<usize>().map_err(|_| bad())?),
+                _ => return Err(bad()),
+            };
+            if args.next().is_some() {
+                return Err(bad());
+            }
+
+            let fault = ScheduledFault {
+                at_mcts,
+                duration_mcts,
+                fault,
+            };
+            if duration_mcts.is_some() && !fault.is_lasting() {
+                return Err(bad());
+            }
+            if injector.schedule(fault).is_err() {
+                return Err(err(FaultErrorKind::TooManyFaults));
+            }
+        }
+        Ok(injector)
+    }
+
+    ///
+    /// ## `schedule` Function
+    ///
+    /// Adds a fault to the schedule. Faults due at the same MCT are injected
+    /// in the order they were scheduled.
+    ///
+    /// ### Result
+    ///
+    ///  - `Err(fault)` is returned with the given fault if `FAULT_NUM` faults
+    ///    are already scheduled.
+    ///
+    pub fn schedule(&mut self, fault: ScheduledFault) -> Result<(), ScheduledFault> {
+        self.faults.push((fault, FaultState::Pending)).map_err(|(x, _)| x)?;
+        self.next_mcts = self.next_mcts.min(fault.at_mcts);
+        Ok(())
+    }
+
+    ///
+    /// ## `pending` Function
+    ///
+    /// Returns the number of faults which are yet to be injected or to end.
+    ///
+    pub fn pending(&self) -> usize {
+        self.faults.iter().filter(|(_, state)| *state != FaultState::Done).count()
+    }
+
+    ///
+    /// ## `pump` Function
+    ///
+    /// Injects the faults due by the current MCT count of `cpu`, ends the
+    /// lasting faults whose duration is over, and steals the cycles of any
+    /// starvation from the instructions run since the last call.
+    ///
+    pub fn pump(&mut self, cpu: &mut AgcCpu) {
+        if self.starve_percent > 0 {
+            let stolen = cpu.stolen_cycles - self.last_stolen;
+            let ran = (cpu.total_cycles - self.last_cycles).saturating_sub(stolen);
+            let left = 100 - self.starve_percent as usize;
+            self.starve_debt += ran * self.starve_percent as usize;
+            cpu.steal_cycles((self.starve_debt / left) as u32);
+            self.starve_debt %= left;
+        }
+        self.last_cycles = cpu.total_cycles;
+        self.last_stolen = cpu.stolen_cycles;
+
+        let now = cpu.total_cycles;
+        if now < self.next_mcts {
+            return;
+        }
+
+        // Faults end first, so one starting at the same MCT is not undone
+        for (fault, state) in self.faults.iter_mut() {
+            if *state == FaultState::Active && fault.end_mcts().is_some_and(|x| now >= x) {
+                info!("Fault ended after {} MCTs: {:?}", now, fault.fault);
+                if let AgcFault::StuckBits { channel, mask, .. } = fault.fault {
+                    cpu.release_io_bits(channel, mask);
+                }
+                *state = FaultState::Done;
+            }
+        }
+
+        for (fault, state) in self.faults.iter_mut() {
+            if *state != FaultState::Pending || now < fault.at_mcts {
+                continue;
+            }
+            info!("Injecting fault after {} MCTs: {:?}", now, fault.fault);
+            match fault.fault {
+                AgcFault::FlipBits { addr, mask } => {
+                    if let Some(x) = cpu.read_pseudo(addr) {
+                        cpu.write_pseudo(addr, x ^ mask);
+                    }
+                }
+                AgcFault::Restart(cause) => cpu.force_restart(cause),
+                AgcFault::StuckBits { channel, mask, value } => {
+                    cpu.stick_io_bits(channel, mask, value);
+                }
+                AgcFault::DropInterrupts { .. } | AgcFault::Starve { .. } => {}
+            }
+            *state = match fault.is_lasting() {
+                true => FaultState::Active,
+                false => FaultState::Done,
+            };
+        }
+
+        let mut drop_mask = 0;
+        self.starve_percent = 0;
+        self.next_mcts = usize::MAX;
+        for (fault, state) in self.faults.iter() {
+            match state {
+                FaultState::Pending => self.next_mcts = self.next_mcts.min(fault.at_mcts),
+                FaultState::Active => {
+                    if let Some(x) = fault.end_mcts() {
+                        self.next_mcts = self.next_mcts.min(x);
+                    }
+                    match fault.fault {
+                        AgcFault::DropInterrupts { mask } => drop_mask |= mask,
+                        AgcFault::Starve { percent } => {
+                            self.starve_percent = self.starve_percent.max(percent.min(99))
+                        }
+                        _ => {}
+                    }
+                }
+                FaultState::Done => {}
+            }
+        }
+        cpu.rupt_drop_mask = drop_mask;
+    }
+}
+
+#[cfg(test)]
+mod fault_tests {
+    use super::*;
+    use crate::consts::io::{CHANNEL_CHAN30, CHANNEL_CHAN77};
+    use crate::mem::AgcMemoryMap;
+    use core::fmt::Write;
+
+    ///
+    /// # Description
+    ///
+    /// Tests parsing each kind of fault, and the line and kind of the errors
+    /// found in invalid scenarios.
+    ///
+    #[test]
+    fn test_parse() {
+        let text = "
+            # Restart protection scenario
+            at 100 flip E5,1400 00004
+            at 200 restart nightwatchman   # GOJAM
+            at 300 stuck 30 00006 00002 for 50
+            at 400 drop T3RUPT,keyrupt1
+            at 500 starve 40 for 1000
+            at 600 flip @61 1
+        ";
+        let injector = FaultInjector::parse(text).unwrap();
+        let expected = [
+                ScheduledFault {
+                    at_mcts: 100,
+                    duration_mcts: None,
+                    fault: AgcFault::FlipBits { addr: 0o2400, mask: 4 },
+                },
+                ScheduledFault {
+                    at_mcts: 200,
+                    duration_mcts: None,
+                    fault: AgcFault::Restart(AgcRestartCause::NightWatchman),
+                },
+                ScheduledFault {
+                    at_mcts: 300,
+                    duration_mcts: Some(50),
+                    fault: AgcFault::StuckBits { channel: 0o30, mask: 6, value: 2 },
+                },
+                ScheduledFault {
+                    at_mcts: 400,
+                    duration_mcts: None,
+                    fault: AgcFault::DropInterrupts { mask: (1 << RUPT_TIME3) | (1 << RUPT_KEY1) },
+                },
+                ScheduledFault {
+                    at_mcts: 500,
+                    duration_mcts: Some(1000),
+                    fault: AgcFault::Starve { percent: 40 },
+                },
+                ScheduledFault {
+                    at_mcts: 600,
+                    duration_mcts: None,
+                    fault: AgcFault::FlipBits { addr: 0o61, mask: 1 },
+                },
+        ];
+        assert_eq!(injector.faults.len(), expected.len());
+        for ((fault, _), x) in injector.faults.iter().zip(expected.iter()) {
+            assert_eq!(fault, x);
+        }
+        assert_eq!(injector.pending(), 6);
+
+        let err = |text| FaultInjector::parse(text).err().map(|x| (x.line, x.kind));
+        assert_eq!(err("\nbreak 100"), Some((2, FaultErrorKind::UnknownCommand)));
+        assert_eq!(err("at 100 melt"), Some((1, FaultErrorKind::UnknownCommand)));
+        assert_eq!(err("at 100 flip 60 1"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at 100 flip E5,1377 1"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at 100 restart tired"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at 100 restart scaler for 5"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at 100 drop T7RUPT"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at 100 starve 100"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at 100 stuck 30 1 1 for"), Some((1, FaultErrorKind::BadArgument)));
+        assert_eq!(err("at x stuck 30 1 1"), Some((1, FaultErrorKind::BadArgument)));
+        let mut many: heapless::String<2048> = heapless::String::new();
+        for idx in 0..=FAULT_NUM {
+            writeln!(many, "at {} restart parity", idx).unwrap();
+        }
+        assert_eq!(err(&many), Some((FAULT_NUM + 1, FaultErrorKind::TooManyFaults)));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the faults are injected into a blank AGC when they are due, and
+    /// the lasting ones are undone once their duration is over.
+    ///
+    #[test]
+    fn test_inject() {
+        let mut q1: heapless::spsc::Queue<u8, 8> = heapless::spsc::Queue::new();
+        let (tx, _rx) = q1.split();
+        let mut cpu = AgcCpu::new(AgcMemoryMap::new_blank(tx));
+        cpu.reset();
+
+        let text = "
+            at 100 flip E5,1400 00004
+            at 150 restart voltage
+            at 200 stuck 30 00004 0 for 100
+            at 200 drop T3RUPT for 100
+            at 400 starve 75 for 400
+        ";
+        let mut injector = FaultInjector::parse(text).unwrap();
+        let run_to = |cpu: &mut AgcCpu, injector: &mut FaultInjector, mcts: usize| {
+            while cpu.total_cycles < mcts {
+                cpu.step();
+                injector.pump(cpu);
+            }
+        };
+
+        run_to(&mut cpu, &mut injector, 99);
+        assert_eq!(cpu.read_pseudo(0o2400), Some(0));
+        run_to(&mut cpu, &mut injector, 100);
+        assert_eq!(cpu.read_pseudo(0o2400), Some(4));
+
+        run_to(&mut cpu, &mut injector, 160);
+        assert_eq!(cpu.restarts, 1);
+        assert_eq!(cpu.restart_cause, Some(AgcRestartCause::VoltageFail));
+        assert_eq!(cpu.read_io(CHANNEL_CHAN77), 0o00040);
+        cpu.write_io(CHANNEL_CHAN77, 0o00040);
+        assert_eq!(cpu.read_io(CHANNEL_CHAN77), 0);
+
+        run_to(&mut cpu, &mut injector, 200);
+        assert_eq!(cpu.read_io(CHANNEL_CHAN30), 0o37773);
+        assert_eq!(cpu.rupt_drop_mask, 1 << RUPT_TIME3);
+        cpu.write_io(CHANNEL_CHAN30, 0o37777);
+        assert_eq!(cpu.read_io(CHANNEL_CHAN30), 0o37773);
+        run_to(&mut cpu, &mut injector, 300);
+        assert_eq!(cpu.read_io(CHANNEL_CHAN30), 0o37777);
+        assert_eq!(cpu.rupt_drop_mask, 0);
+
+        // Three MCTs stolen for each one run while starving
+        run_to(&mut cpu, &mut injector, 400);
+        assert_eq!(cpu.stolen_cycles, 0);
+        run_to(&mut cpu, &mut injector, 800);
+        assert!(cpu.stolen_cycles > 280 && cpu.stolen_cycles <= 300, "{}", cpu.stolen_cycles);
+        let stolen = cpu.stolen_cycles;
+        run_to(&mut cpu, &mut injector, 1000);
+        assert!(cpu.stolen_cycles <= stolen + 12);
+        assert_eq!(injector.pending(), 0);
+    }
+}
@@ -2,7 +2,9 @@
 
 pub mod cpu;
 pub mod disasm;
+pub mod fault;
 pub mod instr;
 pub mod mem;
+pub mod runner;
 pub mod utils;
 pub mod consts;
\ No newline at end of file
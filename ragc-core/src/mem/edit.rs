@@ -44,7 +44,7 @@ impl AgcMemType for AgcEditRegs {
         }
     }
 
-    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) {
+    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) -> bool {
         let newval = value & 0x7FFF;
         trace!("Edit Write: 0o{:o}", bank_offset);
 
@@ -65,8 +65,10 @@ impl AgcMemType for AgcEditRegs {
             SG_EDOP => self.edop = (newval >> 7) & 0o177,
             _ => {
                 error!("Invalid EditRegister Write: {:o}", bank_offset);
+                return false;
             }
         }
+        true
     }
 }
 
@@ -1,15 +1,15 @@
-use super::periph::engines::LmEngines;
-use super::periph::AgcIoPeriph;
+use super::periph::{AgcIoPeriph, MAX_ATTACHED_PERIPHS};
 use crate::{utils::Option as Option};
 use crate::consts::io;
 
+use heapless::Vec;
 use log::{debug, error, warn};
 
 pub struct AgcIoSpace<'a> {
     io_mem: [u16; 256],
     downrupt: Option<&'a mut dyn AgcIoPeriph>,
     dsky: Option<&'a mut dyn AgcIoPeriph>,
-    lm_engines: LmEngines,
+    periphs: Vec<&'a mut dyn AgcIoPeriph, MAX_ATTACHED_PERIPHS>,
 }
 
 impl<'a> AgcIoSpace<'a> {
@@ -20,10 +20,9 @@ impl<'a> AgcIoSpace<'a> {
             io_mem: [0; 256],
             downrupt: Option::Some(downrupt),
             dsky: Option::Some(dsky),
-            lm_engines: LmEngines::new(),
+            periphs: Vec::new(),
         };
 
-        // Mark the engine as off to start off with
         //s.io_mem[CHANNEL_DSALMOUT] = 0x0000;
         s.io_mem[0o30] = 0o37777;
         s.io_mem[0o31] = 0o77777;
@@ -37,10 +36,9 @@ impl<'a> AgcIoSpace<'a> {
             io_mem: [0; 256],
             downrupt: Option::None,
             dsky: Option::None,
-            lm_engines: LmEngines::new(),
+            periphs: Vec::new(),
         };
 
-        // Mark the engine as off to start off with
         //s.io_mem[CHANNEL_DSALMOUT] = 0x0000;
         s.io_mem[0o30] = 0o37777;
         s.io_mem[0o31] = 0o77777;
@@ -50,36 +48,88 @@ impl<'a> AgcIoSpace<'a> {
     }
 
     ///
+    /// ## `attach` Function
     ///
+    /// Attaches an additional peripheral to the IO space. Attached peripherals
+    /// see every channel write, contribute to the input channels they own and
+    /// are able to generate interrupts and counter updates.
     ///
+    /// ### Result
     ///
-    fn _handle_channel11_read(&mut self) -> u16 {
-        // Get the current status of Channel 11 from the engines section
-        // of the hardware bits
-        let engine_enabled = if self.lm_engines._get_engine_enable() {
-            1 << 12
-        } else {
-            1 << 13
-        };
+    ///  - `Err(periph)` is returned with the given peripheral if there are
+    ///    already `MAX_ATTACHED_PERIPHS` peripherals attached.
+    ///
+    pub fn attach(&mut self, periph: &'a mut dyn AgcIoPeriph) -> Result<(), &'a mut dyn AgcIoPeriph> {
+        self.periphs.push(periph)
+    }
 
-        // Rest of the bits are DSKY related bit. Mostly BIT1 through BIT7
-        // where index starts at 1.
-        engine_enabled & 0x3000 | self.io_mem[0o11] & 0x037F
+    ///
+    /// ## `read_periphs_inverted` Function
+    ///
+    /// Channels 30 through 33 are inverted, so a signal is present when the
+    /// bit is 0. Each attached peripheral only pulls down the bits it owns,
+    /// so the result is the AND of all the peripheral values.
+    ///
+    fn read_periphs_inverted(&self, channel_idx: usize) -> u16 {
+        self.periphs
+            .iter()
+            .fold(0o77777, |val, x| val & x.read(channel_idx))
     }
 
-    fn handle_channel30_read(&mut self) -> u16 {
-        let ae = self.lm_engines.get_active_engine();
+    fn read_periphs(&self, channel_idx: usize) -> u16 {
+        self.periphs
+            .iter()
+            .fold(0o00000, |val, x| val | x.read(channel_idx))
+    }
 
-        // For Bit2 - Get which engine is active currently via the command
-        // module. If the module is set to DECEND, BIT2 is high. Otherwise,
-        // it is ascend engine
-        let verify_engine_bit = if ae == super::periph::engines::LM_DECEND_ENGINE {
-            1 << 1
-        } else {
-            0o00000
-        };
+    ///
+    /// ## `pump_mcts` Function
+    ///
+    /// Propagates the elapsed MCTs to the DSKY, DOWNRUPT and all attached
+    /// peripherals.
+    ///
+    pub fn pump_mcts(&mut self, mcts: u16) {
+        if let Option::Some(x) = &mut self.dsky {
+            x.pump_mcts(mcts);
+        }
+        if let Option::Some(x) = &mut self.downrupt {
+            x.pump_mcts(mcts);
+        }
+        for x in self.periphs.iter_mut() {
+            x.pump_mcts(mcts);
+        }
+    }
+
+    ///
+    /// ## `write_counter` Function
+    ///
+    /// Delivers the pulses of an output counter cell to all attached
+    /// peripherals.
+    ///
+    pub fn write_counter(&mut self, counter_idx: usize, value: u16) {
+        for x in self.periphs.iter_mut() {
+            x.write_counter(counter_idx, value);
+        }
+    }
+
+    ///
+    /// ## `counter_update` Function
+    ///
+    /// Returns the next pending counter cell update from any of the attached
+    /// peripherals, if there is one.
+    ///
+    pub fn counter_update(&mut self) -> core::option::Option<(usize, u16)> {
+        self.periphs.iter_mut().find_map(|x| x.counter_update())
+    }
 
-        0o37775 | verify_engine_bit
+    ///
+    /// ## `counter_increment` Function
+    ///
+    /// Returns the next pending counter cell increment from any of the
+    /// attached peripherals, if there is one.
+    ///
+    pub fn counter_increment(&mut self) -> core::option::Option<(usize, i16)> {
+        self.periphs.iter_mut().find_map(|x| x.counter_increment())
     }
 
     pub fn read(&mut self, channel_idx: usize) -> u16 {
@@ -197,12 +247,13 @@ impl<'a> AgcIoSpace<'a> {
             // # CHANNEL 15    MNKEYIN; INPUT CHANNEL;KEY CODE INPUT FROM KEYBOARD OF DSKY, SENSED BY PROGRAM WHEN
             // #               PROGRAM INTERRUPT #5 IS RECEIVED. USES BITS 5-1
             io::CHANNEL_MNKEYIN => {
-                match &self.dsky {
+                let val = match &self.dsky {
                     Option::Some(x) => {
                         x.read(channel_idx)
                     },
                     Option::None => { 0o00000 }
-                }
+                };
+                val | self.read_periphs(channel_idx)
             },
             // # CHANNEL 16    NAVKEYIN; INPUT CHANNEL; OPTICS MARK INFORMATION AND NAVIGA ION PANEL DSKY (CM) OR THRUST
             // #               CONTROL (LM) SENSED BY PROGRAM WHEN PROGRAM INTER-RUPT #6 IS RECEIVED. USES BITS 3-7 ONLY.
@@ -214,7 +265,10 @@ impl<'a> AgcIoSpace<'a> {
             // #               BIT 5           OPTICS MARK REJECT SIGNAL
             // #               BIT 6           DESCENT+ ; CREW DESIRED SLOWING RATE OF DESCENT
             // #               BIT 7           DESCENT- ; CREW DESIRED SPEEDING UP RATE OF D'CENT
-            io::CHANNEL_NAVKEYIN => 0,
+            //
+            // On the CM, bits 1-5 hold the navigation DSKY keycode, bit 6 is
+            // the optics MARK and bit 7 the MARK REJECT button.
+            io::CHANNEL_NAVKEYIN => self.read_periphs(channel_idx),
 
             // # NOTE: ALL BITS IN CHANNELS 30-33 ARE INVERTED AS SENSED BY THE  PROGRAM, SO THAT A VALUE OF ZERO MEANS
             // # THAT THE INDICATED SIGNAL IS PRESENT.
@@ -237,7 +291,10 @@ impl<'a> AgcIoSpace<'a> {
             // #               BIT 13          IMU FAIL (MALFUNCTION OF IMU STABILIZATION LOOPS)
             // #               BIT 14          ISS TURN ON REQUESTED
             // #               BIT 15          TEMPERATURE OF STABLE MEMBER WITHIN DESIGN LIMITS
-            io::CHANNEL_CHAN30 => self.handle_channel30_read(),
+            //
+            // The engine bits come from the attached engine peripheral, and
+            // the stable member temperature is always within limits.
+            io::CHANNEL_CHAN30 => 0o37777 & self.read_periphs_inverted(channel_idx),
 
             // # CHANNEL 31    INPUT CHANNEL; BITS ASSOCIATED WITH THE ATTITUDE CONTROLLER, TRANSLATIONAL CONTROLLER,
             // #               AND SPACECRAFT ATTITUDE CONTROL; USED BY RCS DAP
@@ -260,7 +317,7 @@ impl<'a> AgcIoSpace<'a> {
             // #               BIT 13          ATTITUDE HOLD MODE ON SCS MODE CONTROL SWITCH
             // #               BIT 14          AUTO STABILIZATION OF ATTITUDE ON SCS MODE SWITCH
             // #               BIT 15          ATTITUDE CONTROL OUT OF DETENT (RHC NOT IN NEUTRAL
-            io::CHANNEL_CHAN31 => 0o77777,
+            io::CHANNEL_CHAN31 => self.read_periphs_inverted(channel_idx),
 
             // # CHANNEL 32    INPUT CHANNEL.
             //
@@ -283,7 +340,7 @@ impl<'a> AgcIoSpace<'a> {
                     Option::None => { 0o77777 }
                 };
                 //println!("CHAN32: {:5o}", val);
-                val | (self.io_mem[0o32] & 0o57777)
+                (val | (self.io_mem[0o32] & 0o57777)) & self.read_periphs_inverted(channel_idx)
             }
 
             // # CHANNEL 33    CHAN33; INPUT CHANNEL; FOR HARDWARE STATUS AND COMMAND INFORMATION. BITS 15-11 ARE FLIP-
@@ -305,7 +362,7 @@ impl<'a> AgcIoSpace<'a> {
             // #               BIT 13          PIPA FAIL
             // #               BIT 14          WARNING OF REPEATED ALARMS: RESTART,COUNTER FAIL, VOLTAGE FAIL,AND SCALAR DOUBLE.
             // #               BIT 15          LGC OSCILLATOR STOPPED
-            io::CHANNEL_CHAN33 => 0o77777,
+            io::CHANNEL_CHAN33 => self.read_periphs_inverted(channel_idx),
 
             // # CHANNEL 34    DNT M1; OUTPUT CHANNEL; DOWNLINK 1  FIRST OF TWO WORDS SERIALIZATION.
             // # CHANNEL 35    DNT M2; OUTPUT CHANNEL DOWNLINK 2 SOCOND OF TWO   WORDS SERIALIZATION.
@@ -317,6 +374,12 @@ impl<'a> AgcIoSpace<'a> {
                     Option::None => { 0o77777 }
                 }
             },
+            // Channel 77 is the restart monitor: the hardware alarms which
+            // caused a restart, kept until the AGC writes the channel. Bit 1 is
+            // a parity fail (E or F memory), bit 2 a parity fail (E memory),
+            // then TC trap, RUPT lock, night watchman, voltage fail, counter
+            // fail, scaler fail and scaler double frequency up to bit 9.
+            io::CHANNEL_CHAN77 => self.io_mem[channel_idx],
             0o163 => {
                 match &self.dsky {
                     Option::Some(x) => {
@@ -353,6 +416,10 @@ impl<'a> AgcIoSpace<'a> {
             _ => {}
         }
 
+        for x in self.periphs.iter_mut() {
+            x.write(channel_idx, val);
+        }
+
         match channel_idx {
             io::CHANNEL_DSALMOUT => {
                 self.io_mem[io::CHANNEL_DSALMOUT] = val; //val & 0x33FF;
@@ -364,12 +431,23 @@ impl<'a> AgcIoSpace<'a> {
             io::CHANNEL_CHAN32 => {
                 warn!("Attempting to write to IO CHAN32 which is only an input");
             }
+            io::CHANNEL_CHAN77 => self.io_mem[channel_idx] = 0,
             _ => {
                 self.io_mem[channel_idx] = val;
             }
         }
     }
 
+    ///
+    /// ## `set_restart_cause` Function
+    ///
+    /// Sets the given alarm bits in channel 77, where they are kept until
+    /// the AGC writes the channel.
+    ///
+    pub fn set_restart_cause(&mut self, bits: u16) {
+        self.io_mem[io::CHANNEL_CHAN77] |= bits;
+    }
+
     pub fn check_interrupt(&mut self) -> u16 {
         let mut val = 0;
 
@@ -387,6 +465,10 @@ impl<'a> AgcIoSpace<'a> {
             Option::None => { 0o00000 },
         };
 
+        for x in self.periphs.iter_mut() {
+            val |= x.is_interrupt();
+        }
+
         val
     }
 }
@@ -11,10 +11,12 @@ mod timer;
 mod tests;
 
 pub use io::AgcIoSpace;
+pub use rom::ROM_PATCH_NUM;
 
 use heapless::spsc::Producer;
+use heapless::LinearMap;
 
-use log::{error, trace};
+use log::{error, trace, warn};
 
 use self::periph::AgcIoPeriph;
 
@@ -26,9 +28,13 @@ use crate::consts::memmap;
 // ============================================================================
 trait AgcMemType {
     fn read(&self, bank_idx: usize, bank_offset: usize) -> u16;
-    fn write(&mut self, bank_idx: usize, bank_offset: usize, value: u16);
+    // Returns `false` when the word could not be written
+    fn write(&mut self, bank_idx: usize, bank_offset: usize, value: u16) -> bool;
 }
 
+/// Number of IO channels which can have stuck bits at the same time.
+pub const IO_STUCK_NUM: usize = 8;
+
 pub struct AgcMemoryMap<'a> {
     ram: ram::AgcRam,
     rom: rom::AgcRom<'a>,
@@ -39,6 +45,7 @@ pub struct AgcMemoryMap<'a> {
     regs: regs::AgcRegs,
     rom_debug: bool,
     superbank: bool,
+    stuck_io: LinearMap<usize, (u16, u16), IO_STUCK_NUM>,
 }
 
 impl<'a> AgcMemoryMap<'a> {
@@ -56,6 +63,7 @@ impl<'a> AgcMemoryMap<'a> {
             regs: regs::AgcRegs::new(),
             superbank: false,
             rom_debug: false,
+            stuck_io: LinearMap::new(),
         }
     }
 
@@ -76,6 +84,7 @@ impl<'a> AgcMemoryMap<'a> {
             regs: regs::AgcRegs::new(),
             superbank: false,
             rom_debug: false,
+            stuck_io: LinearMap::new(),
         }
     }
 
@@ -86,6 +95,12 @@ impl<'a> AgcMemoryMap<'a> {
         //self.io.reset();     // TODO: Implement a reset for IO Space
     }
 
+    ///
+    /// ## `enable_rom_write` Function
+    ///
+    /// Allows writes to fixed memory, for debugging. Written words are kept
+    /// as patches over the rope, up to `ROM_PATCH_NUM` of them.
+    ///
     pub fn enable_rom_write(&mut self) {
         self.rom_debug = true;
     }
@@ -94,6 +109,46 @@ impl<'a> AgcMemoryMap<'a> {
         &mut self.timers
     }
 
+    ///
+    /// ## `attach_periph` Function
+    ///
+    /// Attaches an additional peripheral (i.e. radar, hand controller) to the
+    /// IO space of the memory map.
+    ///
+    /// ### Result
+    ///
+    ///  - `Err(periph)` is returned with the given peripheral if no more
+    ///    peripherals can be attached.
+    ///
+    pub fn attach_periph(&mut self, periph: &'a mut dyn AgcIoPeriph) -> Result<(), &'a mut dyn AgcIoPeriph> {
+        self.io.attach(periph)
+    }
+
+    ///
+    /// ## `set_restart_cause` Function
+    ///
+    /// Latches the alarm bits of a hardware restart in channel 77.
+    ///
+    pub fn set_restart_cause(&mut self, bits: u16) {
+        self.io.set_restart_cause(bits);
+    }
+
+    ///
+    /// ## `pump_periphs` Function
+    ///
+    /// Advances the attached peripherals by `mcts` MCTs and loads any counter
+    /// cell values or pulses they produced into the special registers.
+    ///
+    pub fn pump_periphs(&mut self, mcts: u16) {
+        self.io.pump_mcts(mcts);
+        while let Some((counter_idx, value)) = self.io.counter_update() {
+            self.special.load_counter(counter_idx, value);
+        }
+        while let Some((counter_idx, pulses)) = self.io.counter_increment() {
+            self.special.increment_counter(counter_idx, pulses);
+        }
+    }
+
     pub fn write_io(&mut self, idx: usize, value: u16) {
         match idx {
             consts::io::CHANNEL_L => {
@@ -121,6 +176,18 @@ impl<'a> AgcMemoryMap<'a> {
                 }
                 self.io.write(idx, value & 0o37777);
             }
+            consts::io::CHANNEL_CHAN14 => {
+                self.io.write(idx, value);
+
+                // THRUST DRIVE ACTIVITY sends the pulses stored in THRUST to
+                // the descent engine throttle.
+                if value & 0o00010 != 0 {
+                    let pulses = self.special.drain_thrust();
+                    if pulses != 0 {
+                        self.io.write_counter(consts::special::SG_THRUST, pulses);
+                    }
+                }
+            }
             consts::io::CHANNEL_CHAN34 => {
                 self.timers.set_downrupt_flags(1);
                 self.io.write(idx, value);
@@ -136,7 +203,7 @@ impl<'a> AgcMemoryMap<'a> {
     }
 
     pub fn read_io(&mut self, idx: usize) -> u16 {
-        match idx {
+        let value = match idx {
             consts::io::CHANNEL_L => self.regs.read(0, consts::cpu::REG_L),
             consts::io::CHANNEL_Q => self.regs.read(0, consts::cpu::REG_Q),
             consts::io::CHANNEL_HISCALAR => {
@@ -155,6 +222,47 @@ impl<'a> AgcMemoryMap<'a> {
                 res
             }
             _ => self.io.read(idx),
+        };
+        match self.stuck_io.get(&idx) {
+            Some((mask, stuck)) => (value & !mask) | stuck,
+            None => value,
+        }
+    }
+
+    ///
+    /// ## `stick_io_bits` Function
+    ///
+    /// Makes the bits of `mask` in IO channel `idx` read as in `value`,
+    /// whatever is written to the channel, until they are released.
+    ///
+    /// ### Result
+    ///
+    ///  - `false` if `IO_STUCK_NUM` channels already have stuck bits
+    ///
+    pub fn stick_io_bits(&mut self, idx: usize, mask: u16, value: u16) -> bool {
+        let (old_mask, old_value) = self.stuck_io.get(&idx).copied().unwrap_or((0, 0));
+        let entry = (old_mask | mask, (old_value & !mask) | (value & mask));
+        match self.stuck_io.insert(idx, entry) {
+            Ok(_) => true,
+            Err(_) => {
+                warn!("Unable to stick bits of channel {:o}, too many stuck channels", idx);
+                false
+            }
+        }
+    }
+
+    ///
+    /// ## `release_io_bits` Function
+    ///
+    /// Releases the stuck bits of `mask` in IO channel `idx`.
+    ///
+    pub fn release_io_bits(&mut self, idx: usize, mask: u16) {
+        if let Some((old_mask, old_value)) = self.stuck_io.get(&idx).copied() {
+            if old_mask & !mask == 0 {
+                self.stuck_io.remove(&idx);
+            } else {
+                let _res = self.stuck_io.insert(idx, (old_mask & !mask, old_value & !mask));
+            }
         }
     }
 
@@ -175,9 +283,9 @@ impl<'a> AgcMemoryMap<'a> {
             }
             memmap::AGC_MM_ERASABLE_START..=memmap::AGC_MM_ERASABLE_END => {
                 if (idx >> 8) == 3 {
-                    self.ram.write(self.regs.ebank, (idx & 0xff) as usize, val)
+                    self.ram.write(self.regs.ebank, (idx & 0xff) as usize, val);
                 } else {
-                    self.ram.write(idx >> 8, (idx & 0xff) as usize, val)
+                    self.ram.write(idx >> 8, (idx & 0xff) as usize, val);
                 }
             }
             memmap::AGC_MM_FIXED_START..=memmap::AGC_MM_FIXED_END => {
@@ -188,9 +296,9 @@ impl<'a> AgcMemoryMap<'a> {
 
                 let bank_idx = idx >> 10;
                 if bank_idx == 1 {
-                    self.rom.write(self.regs.fbank, (idx & 0x3ff) as usize, val)
+                    self.rom.write(self.fixed_window_bank(), (idx & 0x3ff) as usize, val);
                 } else {
-                    self.rom.write(bank_idx, (idx & 0x3ff) as usize, val)
+                    self.rom.write(bank_idx, (idx & 0x3ff) as usize, val);
                 }
             }
             _ => {
@@ -253,6 +361,216 @@ impl<'a> AgcMemoryMap<'a> {
     pub fn check_interrupts(&mut self) -> u16 {
         self.io.check_interrupt()
     }
+
+    fn fixed_window_bank(&self) -> usize {
+        match self.regs.fbank {
+            0o30..=0o33 if self.superbank => self.regs.fbank + 0o10,
+            bank => bank,
+        }
+    }
+
+    ///
+    /// ## `pseudo_address` Function
+    ///
+    /// Returns the pseudo-address of a memory map address, using the current
+    /// EB, FB and superbank bit for the switched banks.
+    ///
+    pub fn pseudo_address(&self, idx: usize) -> usize {
+        match idx {
+            0o1400..=memmap::AGC_MM_ERASABLE_END => (self.regs.ebank << 8) | (idx & 0xff),
+            0o2000..=0o3777 => {
+                memmap::AGC_PSEUDO_FIXED_START + (self.fixed_window_bank() << 10) + (idx & 0x3ff)
+            }
+            0o4000..=memmap::AGC_MM_FIXED_END => memmap::AGC_PSEUDO_FIXED_START + idx,
+            _ => idx,
+        }
+    }
+
+    ///
+    /// ## `read_pseudo` Function
+    ///
+    /// Reads a word by pseudo-address, regardless of the bank registers. The
+    /// registers and counters at the start of bank E0 read as they do for the
+    /// AGC.
+    ///
+    /// ### Result
+    ///
+    ///  - `None` is returned if the pseudo-address is not mapped
+    ///
+    pub fn read_pseudo(&self, addr: usize) -> Option<u16> {
+        match addr {
+            0..=0o60 => Some(self.read(addr)),
+            memmap::AGC_MM_ERASABLE_START..=memmap::AGC_PSEUDO_ERASABLE_END => {
+                Some(self.ram.read(addr >> 8, addr & 0xff))
+            }
+            memmap::AGC_PSEUDO_FIXED_START..=memmap::AGC_PSEUDO_FIXED_END => {
+                let offset = addr - memmap::AGC_PSEUDO_FIXED_START;
+                Some(self.rom.read(offset >> 10, offset & 0x3ff))
+            }
+            _ => None,
+        }
+    }
+
+    ///
+    /// ## `write_pseudo` Function
+    ///
+    /// Writes a word by pseudo-address, regardless of the bank registers.
+    /// Fixed memory is only written once `enable_rom_write` has been called,
+    /// and as long as there is room for `ROM_PATCH_NUM` patched words.
+    ///
+    /// ### Result
+    ///
+    ///  - `bool` - The pseudo-address is mapped and the word was written
+    ///
+    pub fn write_pseudo(&mut self, addr: usize, val: u16) -> bool {
+        match addr {
+            0..=0o60 => {
+                self.write(addr, val);
+                true
+            }
+            memmap::AGC_MM_ERASABLE_START..=memmap::AGC_PSEUDO_ERASABLE_END => {
+                self.ram.write(addr >> 8, addr & 0xff, val)
+            }
+            memmap::AGC_PSEUDO_FIXED_START..=memmap::AGC_PSEUDO_FIXED_END if self.rom_debug => {
+                let offset = addr - memmap::AGC_PSEUDO_FIXED_START;
+                self.rom.write(offset >> 10, offset & 0x3ff, val)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod memory_map_tests {
+    use super::*;
+    use heapless::spsc::Queue;
+
+    ///
+    /// # Description
+    ///
+    /// Tests pseudo-addresses follow the bank registers, and reach erasable
+    /// banks regardless of EB.
+    ///
+    #[test]
+    fn test_pseudo_address() {
+        let mut q = Queue::new();
+        let (rupt_tx, _rupt_rx) = q.split();
+        let mut mm = AgcMemoryMap::new_blank(rupt_tx);
+
+        mm.write(consts::cpu::REG_BB, (0o31 << 10) | 0o5);
+        assert_eq!(mm.pseudo_address(0o1402), 0o2402);
+        assert_eq!(mm.pseudo_address(0o0402), 0o0402);
+        assert_eq!(mm.pseudo_address(0o2001), 0o10000 + 0o31 * 0o2000 + 1);
+        assert_eq!(mm.pseudo_address(0o4000), 0o14000);
+        mm.write_io(consts::io::CHANNEL_SUPERBNK, 0o100);
+        assert_eq!(mm.pseudo_address(0o2001), 0o10000 + 0o41 * 0o2000 + 1);
+
+        // E5 through the window and by pseudo-address
+        mm.write(0o1402, 0o12345);
+        assert_eq!(mm.read_pseudo(0o2402), Some(0o12345));
+        assert!(mm.write_pseudo(0o2403, 0o54321));
+        assert_eq!(mm.read(0o1403), 0o54321);
+        assert!(mm.write_pseudo(consts::cpu::REG_A, 0o177777));
+        assert_eq!(mm.read(consts::cpu::REG_A), 0o177777);
+
+        // Fixed memory is read-only unless ROM writes are enabled
+        assert_eq!(mm.read_pseudo(0o14000), Some(0));
+        assert!(!mm.write_pseudo(0o14000, 1));
+        assert_eq!(mm.read_pseudo(memmap::AGC_PSEUDO_FIXED_END + 1), None);
+        assert!(!mm.write_pseudo(0o4000, 1));
+
+        mm.enable_rom_write();
+        assert!(mm.write_pseudo(0o14000, 0o30001));
+        assert_eq!(mm.read_pseudo(0o14000), Some(0o30001));
+        assert_eq!(mm.read(0o4000), 0o30001);
+
+        // Writes through the window follow the superbank like reads do
+        mm.write(0o2002, 0o30003);
+        assert_eq!(mm.read_pseudo(0o10000 + 0o41 * 0o2000 + 2), Some(0o30003));
+        assert_eq!(mm.read_pseudo(0o10000 + 0o31 * 0o2000 + 2), Some(0));
+        assert_eq!(mm.read(0o2002), 0o30003);
+
+        // Patched words can be written again once the patches are full
+        for addr in 2..ROM_PATCH_NUM {
+            assert!(mm.write_pseudo(0o14000 + addr, 1));
+        }
+        assert!(!mm.write_pseudo(0o14000 + ROM_PATCH_NUM, 1));
+        assert_eq!(mm.read_pseudo(0o14000 + ROM_PATCH_NUM), Some(0));
+        assert!(mm.write_pseudo(0o14000, 0o30002));
+        assert_eq!(mm.read(0o4000), 0o30002);
+    }
+
+    struct ArmedEngine;
+
+    impl AgcIoPeriph for ArmedEngine {
+        fn read(&self, channel_idx: usize) -> u16 {
+            match channel_idx {
+                consts::io::CHANNEL_CHAN30 => 0o77773,
+                _ => 0o77777,
+            }
+        }
+
+        fn write(&mut self, _channel_idx: usize, _value: u16) {}
+
+        fn is_interrupt(&mut self) -> u16 {
+            0
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the channel 30 engine bits only come from the attached
+    /// peripherals.
+    ///
+    #[test]
+    fn test_channel30_periphs() {
+        let mut q = Queue::new();
+        let (rupt_tx, _rupt_rx) = q.split();
+        let mut engine = ArmedEngine;
+        let mut mm = AgcMemoryMap::new_blank(rupt_tx);
+        assert_eq!(mm.read_io(consts::io::CHANNEL_CHAN30), 0o37777);
+
+        assert!(mm.attach_periph(&mut engine).is_ok());
+        assert_eq!(mm.read_io(consts::io::CHANNEL_CHAN30), 0o37773);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests stuck IO bits override the channel value until released, and
+    /// leave the other bits alone.
+    ///
+    #[test]
+    fn test_stuck_io_bits() {
+        let mut q = Queue::new();
+        let (rupt_tx, _rupt_rx) = q.split();
+        let mut mm = AgcMemoryMap::new_blank(rupt_tx);
+
+        // Inverted input channel, idle with all bits set
+        let chan = consts::io::CHANNEL_CHAN30;
+        assert_eq!(mm.read_io(chan), 0o37777);
+        assert!(mm.stick_io_bits(chan, 0o00004, 0));
+        assert!(mm.stick_io_bits(chan, 0o00006, 0o00002));
+        assert_eq!(mm.read_io(chan), 0o37773);
+        mm.release_io_bits(chan, 0o00004);
+        assert_eq!(mm.read_io(chan), 0o37777);
+
+        // Writes do not unstick bits
+        let chan = consts::io::CHANNEL_CHAN12;
+        assert!(mm.stick_io_bits(chan, 0o00200, 0o00200));
+        mm.write_io(chan, 0o00004);
+        assert_eq!(mm.read_io(chan), 0o00204);
+        mm.release_io_bits(chan, 0o00200);
+        assert_eq!(mm.read_io(chan), 0o00004);
+        mm.release_io_bits(consts::io::CHANNEL_CHAN30, 0o77777);
+
+        for idx in 0..IO_STUCK_NUM {
+            assert!(mm.stick_io_bits(0o40 + idx, 1, 1));
+        }
+        assert!(!mm.stick_io_bits(chan, 1, 1));
+        assert!(mm.stick_io_bits(0o40, 2, 2));
+    }
 }
 
 /*
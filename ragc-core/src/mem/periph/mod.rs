@@ -1,4 +1,6 @@
-pub mod engines;
+/// Maximum number of additional peripherals which can be attached to the IO
+/// space on top of the DSKY and DOWNRUPT peripherals.
+pub const MAX_ATTACHED_PERIPHS: usize = 8;
 
 pub trait Peripheral {
     fn is_interrupt(&mut self) -> u16;
@@ -7,4 +9,54 @@ pub trait AgcIoPeriph {
     fn read(&self, _channel_idx: usize) -> u16;
     fn write(&mut self, channel_idx: usize, value: u16);
     fn is_interrupt(&mut self) -> u16;
-}
\ No newline at end of file
+
+    ///
+    /// ## `pump_mcts` Function
+    ///
+    /// Notifies the peripheral that `mcts` Memory Cycle Times (MCTs) have
+    /// elapsed on the AGC. Peripherals which need a notion of time (i.e. delays
+    /// before data is available) use this to stay in lockstep with the CPU.
+    ///
+    fn pump_mcts(&mut self, _mcts: u16) {}
+
+    ///
+    /// ## `counter_update` Function
+    ///
+    /// Allows a peripheral to load a counter cell within the special register
+    /// space (i.e. `SG_RNRAD`). The memory map keeps calling this function
+    /// after every `pump_mcts` until the peripheral returns `None`.
+    ///
+    /// ### Result
+    ///
+    ///  - `(usize, u16)` - Special register address and the value to load
+    ///
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        None
+    }
+
+    ///
+    /// ## `counter_increment` Function
+    ///
+    /// Allows a peripheral to count pulses into a counter cell (i.e. PINC and
+    /// MINC into `SG_PIPAX`, PCDU and MCDU into `SG_CDUX`). The memory map
+    /// keeps calling this function after every `pump_mcts` until the
+    /// peripheral returns `None`.
+    ///
+    /// ### Result
+    ///
+    ///  - `(usize, i16)` - Special register address and the signed number of
+    ///    pulses to count
+    ///
+    fn counter_increment(&mut self) -> Option<(usize, i16)> {
+        None
+    }
+
+    ///
+    /// ## `write_counter` Function
+    ///
+    /// Delivers the contents of an output counter cell (i.e. `SG_THRUST`) to
+    /// the peripheral once the AGC enables the drive for that counter. The
+    /// value is the signed 15-bit ones' complement number of pulses.
+    ///
+    fn write_counter(&mut self, _counter_idx: usize, _value: u16) {}
+}
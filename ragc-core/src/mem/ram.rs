@@ -78,7 +78,7 @@ impl AgcMemType for AgcRam {
     ///  - `bank_offset` - usize - Word offset within a given `bank_idx`
     ///  - `value` - u16 - Value to write to a given RAM address.
     ///
-    fn write(&mut self, bank_idx: usize, bank_offset: usize, value: u16) {
+    fn write(&mut self, bank_idx: usize, bank_offset: usize, value: u16) -> bool {
         trace!(
             "RAM Write: 0x{:x},0x{:x}: 0x{:x}",
             bank_idx,
@@ -93,6 +93,7 @@ impl AgcMemType for AgcRam {
             let a = value & 0x7FFF;
             self.banks[bank_idx][bank_offset] = a;
         }
+        true
     }
 }
 
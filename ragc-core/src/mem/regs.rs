@@ -49,7 +49,7 @@ impl AgcMemType for AgcRegs {
         }
     }
 
-    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) {
+    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) -> bool {
         match bank_offset {
             // BB register contains the bank index for both the Erasable memory
             // and ROM Memory window banks. As such, both this and BB register needs to be
@@ -58,7 +58,7 @@ impl AgcMemType for AgcRegs {
                 self.ebank = (value & 0x7) as usize;
                 self.fbank = ((value & 0x7C00) >> 10) as usize;
                 self.update_bank_registers();
-                return;
+                return true;
             }
 
             // EB register contains the bank index for the Erasable memory
@@ -67,7 +67,7 @@ impl AgcMemType for AgcRegs {
             consts::cpu::REG_FB => {
                 self.fbank = ((value & 0x7C00) >> 10) as usize;
                 self.update_bank_registers();
-                return;
+                return true;
             }
 
             // EB register contains the bank index for the Erasable memory
@@ -76,7 +76,7 @@ impl AgcMemType for AgcRegs {
             consts::cpu::REG_EB => {
                 self.ebank = ((value & 0x0700) >> 8) as usize;
                 self.update_bank_registers();
-                return;
+                return true;
             }
 
             // Per the documentation of the Z register, this register is a
@@ -89,7 +89,7 @@ impl AgcMemType for AgcRegs {
             // Zero register is hardwired to be zero. If there is a write to
             // the zero register, we should atleast warn the user.
             consts::cpu::REG_ZERO => {
-                return;
+                return true;
             }
 
             // All remaining registers are standard 15-bit registers.
@@ -98,6 +98,7 @@ impl AgcMemType for AgcRegs {
             }
         }
         self.regs[bank_offset] = value;
+        true
     }
 }
 
@@ -1,3 +1,4 @@
+use heapless::LinearMap;
 use log::{info, warn};
 
 use crate::mem::AgcMemType;
@@ -9,8 +10,13 @@ const DATA_LINE_NUM_PARTS: usize = 8;
 #[allow(dead_code)]
 const DATA_LINE_PART_LEN: usize = 6;
 
+/// Number of fixed memory words that can be patched once ROM writes are
+/// enabled. The rope itself is borrowed and never modified.
+pub const ROM_PATCH_NUM: usize = 64;
+
 pub struct AgcRom<'a> {
-    program: Option<&'a [[u16; consts::ROM_BANK_NUM_WORDS]; consts::ROM_NUM_BANKS]>
+    program: Option<&'a [[u16; consts::ROM_BANK_NUM_WORDS]; consts::ROM_NUM_BANKS]>,
+    patches: LinearMap<(usize, usize), u16, ROM_PATCH_NUM>,
 }
 
 // ============================================================================
@@ -25,6 +31,15 @@ impl <'a>AgcMemType for AgcRom<'a> {
             );
             return 0x0;
         }
+
+        // Patched words are only looked up when there are any, so ROM
+        // fetches are not slowed down otherwise
+        if !self.patches.is_empty() {
+            if let Some(value) = self.patches.get(&(bank_idx, bank_offset)) {
+                return *value;
+            }
+        }
+
         match self.program {
             Option::Some(program) => {
                 const BANK_IDX_REF: [usize; 36] = [
@@ -43,16 +58,20 @@ impl <'a>AgcMemType for AgcRom<'a> {
 
     }
 
-    fn write(&mut self, bank_idx: usize, bank_offset: usize, value: u16) {
+    fn write(&mut self, bank_idx: usize, bank_offset: usize, value: u16) -> bool {
         if bank_idx >= consts::ROM_NUM_BANKS || bank_offset >= consts::ROM_BANK_NUM_WORDS {
             warn!(
                 "Out of bound indexing into AgcRom {} {}",
                 bank_idx, bank_offset
             );
-            return;
+            return false;
+        }
+        if self.patches.insert((bank_idx, bank_offset), value & 0x7FFF).is_err() {
+            warn!("AGC ROM patches are full. Ignoring write {:03o}{:03o} <= {:05o}",
+                    bank_idx, bank_offset, value);
+            return false;
         }
-        warn!("Attempting to write to AGC ROM. Ignoring write {:03o}{:03o} <= {:05o}",
-                bank_idx, bank_offset, value);
+        true
     }
 }
 
@@ -60,12 +79,14 @@ impl <'a>AgcRom<'a> {
     pub fn new(program: &'a [[u16; consts::ROM_BANK_NUM_WORDS]; consts::ROM_NUM_BANKS]) -> AgcRom {
         AgcRom {
             program: Option::Some(program),
+            patches: LinearMap::new(),
         }
     }
 
     pub fn blank() -> AgcRom<'a> {
         AgcRom {
-            program: Option::None
+            program: Option::None,
+            patches: LinearMap::new(),
         }
     }
 
@@ -1,5 +1,6 @@
 use crate::mem::AgcMemType;
 use crate::consts::special::*;
+use crate::utils::agc_sp_to_cpu;
 use heapless::spsc::Producer;
 use log::{error, warn};
 
@@ -20,6 +21,14 @@ pub struct AgcSpecialRegs {
     // - 0 - Error Recovery
     // - cccccCCCCCccccc - Triply Redundant bit pattern
     pub inlink: u16,
+
+    // Radar data shifted in from the selected radar. A RADARUPT occurs once
+    // the full value is available.
+    pub rnrad: u16,
+
+    // Descent engine throttle increment. Driven out to the engine once
+    // THRUST DRIVE ACTIVITY (channel 14 bit 4) is set.
+    pub thrust: u16,
 }
 
 // =============================================================================
@@ -33,11 +42,94 @@ impl AgcSpecialRegs {
             opt: (0, 0),
             pipa: (0, 0, 0),
             rch: (0, 0, 0),
+            rnrad: 0,
+            thrust: 0,
         }
     }
 
     #[allow(dead_code)]
     pub fn reset(&mut self) {}
+
+    ///
+    /// ## `load_counter` Function
+    ///
+    /// Loads a counter cell with a value provided by a peripheral. Unlike
+    /// `write`, this is the hardware side of the register, so read-only
+    /// counters (from the software point of view) can be updated.
+    ///
+    /// ### Parameters
+    ///
+    ///  - `counter_idx` - Special register address of the counter
+    ///  - `value` - Value to load into the counter
+    ///
+    pub fn load_counter(&mut self, counter_idx: usize, value: u16) {
+        match counter_idx {
+            SG_RNRAD => {
+                self.rnrad = value & 0o77777;
+            }
+            SG_RCHP => {
+                self.rch.0 = value & 0o77777;
+            }
+            SG_RCHY => {
+                self.rch.1 = value & 0o77777;
+            }
+            SG_RCHR => {
+                self.rch.2 = value & 0o77777;
+            }
+            SG_INLINK => {
+                self.inlink = value & 0o77777;
+            }
+            _ => {
+                error!("Unimplemented Counter Load: {:o}", counter_idx);
+            }
+        }
+    }
+
+    ///
+    /// ## `increment_counter` Function
+    ///
+    /// Counts pulses from a peripheral into a counter cell. PIPA counters are
+    /// ones' complement (PINC/MINC) while CDU counters are two's complement
+    /// (PCDU/MCDU) and wrap around at a full revolution.
+    ///
+    /// ### Parameters
+    ///
+    ///  - `counter_idx` - Special register address of the counter
+    ///  - `pulses` - Signed number of pulses to count
+    ///
+    pub fn increment_counter(&mut self, counter_idx: usize, pulses: i16) {
+        let ones_add = |val: u16| {
+            let res = (agc_sp_to_cpu(val) as i32 + pulses as i32).clamp(-0o37777, 0o37777);
+            if res < 0 {
+                !((-res) as u16) & 0o77777
+            } else {
+                res as u16
+            }
+        };
+        let twos_add = |val: u16| (val as i32 + pulses as i32) as u16 & 0o77777;
+
+        match counter_idx {
+            SG_PIPAX => self.pipa.0 = ones_add(self.pipa.0),
+            SG_PIPAY => self.pipa.1 = ones_add(self.pipa.1),
+            SG_PIPAZ => self.pipa.2 = ones_add(self.pipa.2),
+            SG_CDUX => self.cdu.0 = twos_add(self.cdu.0),
+            SG_CDUY => self.cdu.1 = twos_add(self.cdu.1),
+            SG_CDUZ => self.cdu.2 = twos_add(self.cdu.2),
+            _ => {
+                error!("Unimplemented Counter Increment: {:o}", counter_idx);
+            }
+        }
+    }
+
+    ///
+    /// ## `drain_thrust` Function
+    ///
+    /// Returns the pending THRUST pulses and clears the counter, as the
+    /// hardware does once all the pulses have been driven out.
+    ///
+    pub fn drain_thrust(&mut self) -> u16 {
+        core::mem::replace(&mut self.thrust, 0)
+    }
 }
 
 impl AgcMemType for AgcSpecialRegs {
@@ -56,6 +148,11 @@ impl AgcMemType for AgcSpecialRegs {
             SG_PIPAX => self.pipa.0,
             SG_PIPAY => self.pipa.1,
             SG_PIPAZ => self.pipa.2,
+            SG_RNRAD => self.rnrad,
+            SG_RCHP => self.rch.0,
+            SG_RCHY => self.rch.1,
+            SG_RCHR => self.rch.2,
+            SG_THRUST => self.thrust,
 
             // Inlink and Outlink Registers
             SG_INLINK => self.inlink,
@@ -74,15 +171,44 @@ impl AgcMemType for AgcSpecialRegs {
         }
     }
 
-    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) {
+    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) -> bool {
         match bank_offset {
             // Block of Read Only Registers. Send a warning mentioning how the
             // Execution is trying to write to special read only registers
-            SG_CDUX | SG_CDUY | SG_CDUZ | SG_OPTX | SG_OPTY | SG_PIPAX | SG_PIPAY | SG_PIPAZ => {
+            SG_CDUX | SG_CDUY | SG_CDUZ | SG_OPTX | SG_OPTY | SG_RNRAD => {
                 warn!(
                     "Attempting to write to Read-Only Special Registers Address: {:o}",
                     bank_offset
                 );
+                return false;
+            }
+
+            // PIPA counters are cleared by software after each read of the
+            // accumulated velocity
+            SG_PIPAX => {
+                self.pipa.0 = value & 0o77777;
+            }
+            SG_PIPAY => {
+                self.pipa.1 = value & 0o77777;
+            }
+            SG_PIPAZ => {
+                self.pipa.2 = value & 0o77777;
+            }
+
+            // Hand controller counters are cleared by software before
+            // starting a new RHC read
+            SG_RCHP => {
+                self.rch.0 = value & 0o77777;
+            }
+            SG_RCHY => {
+                self.rch.1 = value & 0o77777;
+            }
+            SG_RCHR => {
+                self.rch.2 = value & 0o77777;
+            }
+
+            SG_THRUST => {
+                self.thrust = value & 0o77777;
             }
 
             // Inlink and Outlink Registers
@@ -91,11 +217,14 @@ impl AgcMemType for AgcSpecialRegs {
             }
             SG_OUTLINK => {
                 error!("Writing to outlink, which is known to not be used!");
+                return false;
             }
 
             _ => {
                 error!("Unimplemented Special Write: {:o}", bank_offset);
+                return false;
             }
         }
+        true
     }
 }
@@ -340,7 +340,7 @@ impl AgcMemType for AgcTimers {
         res
     }
 
-    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) {
+    fn write(&mut self, _bank_idx: usize, bank_offset: usize, value: u16) -> bool {
         debug!(
             "Timers: Setting {:x} to bank_offet: {:o}",
             value, bank_offset
@@ -364,8 +364,9 @@ impl AgcMemType for AgcTimers {
             consts::timer::MM_TIME6 => {
                 self.set_time_value(TimerType::TIME6, value);
             }
-            _ => {}
+            _ => return false,
         }
+        true
     }
 }
 
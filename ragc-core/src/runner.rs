@@ -0,0 +1,581 @@
+use core::fmt;
+
+use crate::consts::cpu::REG_Z;
+use crate::consts::memmap::{AGC_PSEUDO_FIXED_END, AGC_PSEUDO_FIXED_START};
+use crate::cpu::AgcCpu;
+
+/// Duration of a memory cycle time (MCT) in nanoseconds.
+pub const MCT_NANOS: u64 = 11700;
+
+/// Number of MCTs in one second of AGC time.
+pub const MCTS_PER_SECOND: u64 = 1000000000 / MCT_NANOS;
+
+/// Number of MCTs run by each `advance` call at maximum speed (about 100ms of
+/// AGC time), so callers keep polling their inputs.
+pub const MAX_SPEED_SLICE_MCTS: u64 = 100000000 / MCT_NANOS;
+
+/// Most AGC time caught up by `advance` after the host fell behind (about
+/// 250ms). Anything older is dropped instead of running the AGC in a burst.
+pub const MAX_CATCHUP_NANOS: u64 = 250000000;
+
+///
+/// ## RunMode Enum
+///
+/// Pacing of an `AgcRunner`.
+///
+///  - `Paused` - The AGC only runs when stepped
+///  - `RealTime` - The AGC runs at the speed of the host clock
+///  - `Scaled` - The AGC runs at the given multiple of real time, such as
+///    `Scaled(10.0)` or `Scaled(0.5)`
+///  - `MaxSpeed` - The AGC runs as fast as the host allows
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunMode {
+    Paused,
+    RealTime,
+    Scaled(f64),
+    MaxSpeed,
+}
+
+impl RunMode {
+    fn speed(&self) -> Option<f64> {
+        match self {
+            RunMode::RealTime => Some(1.0),
+            RunMode::Scaled(x) if *x > 0.0 => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+///
+/// ## MissionTime Structure
+///
+/// Emulated time since the AGC was reset, shown as `HHH:MM:SS.CC`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissionTime {
+    pub hours: u32,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub centiseconds: u8,
+}
+
+impl MissionTime {
+    pub fn from_mcts(mcts: u64) -> Self {
+        let cs = mcts * MCT_NANOS / 10000000;
+        MissionTime {
+            hours: (cs / 360000) as u32,
+            minutes: ((cs / 6000) % 60) as u8,
+            seconds: ((cs / 100) % 60) as u8,
+            centiseconds: (cs % 100) as u8,
+        }
+    }
+}
+
+impl fmt::Display for MissionTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:03}:{:02}:{:02}.{:02}",
+            self.hours, self.minutes, self.seconds, self.centiseconds
+        )
+    }
+}
+
+///
+/// ## FixedAddress Structure
+///
+/// Bank-qualified fixed memory address, written `BB,AAAA` in octal like the
+/// yaAGC listings: the fixed bank (0 to 43, superbanks included) and the
+/// address within the switched window (2000 to 3777). Addresses of the fixed
+/// fixed banks can also be written alone (4000 to 7777).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedAddress {
+    pub bank: usize,
+    pub offset: u16,
+}
+
+impl FixedAddress {
+    ///
+    /// ## `parse` Function
+    ///
+    /// Parses `BB,AAAA` or a fixed fixed `AAAA` address, `None` if it is not a
+    /// valid fixed memory address.
+    ///
+    pub fn parse(text: &str) -> Option<Self> {
+        let octal = |x: &str| u16::from_str_radix(x.trim(), 8).ok();
+        match text.find(',') {
+            Some(idx) => {
+                let bank = octal(&text[..idx])? as usize;
+                let addr = octal(&text[idx + 1..])?;
+                match (bank, addr) {
+                    (0..=0o43, 0o2000..=0o3777) => Some(FixedAddress {
+                        bank,
+                        offset: addr - 0o2000,
+                    }),
+                    _ => None,
+                }
+            }
+            None => match octal(text)? {
+                addr @ 0o4000..=0o7777 => Some(FixedAddress {
+                    bank: (addr >> 10) as usize,
+                    offset: addr & 0o1777,
+                }),
+                _ => None,
+            },
+        }
+    }
+
+    ///
+    /// ## `of_pc` Function
+    ///
+    /// Returns the address of the next instruction of the AGC, `None` when it
+    /// runs from erasable memory.
+    ///
+    pub fn of_pc(cpu: &mut AgcCpu) -> Option<Self> {
+        let z = (cpu.read(REG_Z) & 0o7777) as usize;
+        match cpu.pseudo_address(z) {
+            addr @ AGC_PSEUDO_FIXED_START..=AGC_PSEUDO_FIXED_END => {
+                let offset = addr - AGC_PSEUDO_FIXED_START;
+                Some(FixedAddress {
+                    bank: offset >> 10,
+                    offset: (offset & 0o1777) as u16,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FixedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02o},{:04o}", self.bank, self.offset + 0o2000)
+    }
+}
+
+///
+/// ## StopCondition Enum
+///
+/// Condition ending `AgcRunner::run_until`.
+///
+///  - `Mcts` - The given number of MCTs have gone by
+///  - `Address` - The next instruction is at the given address
+///  - `Restart` - The AGC went through a GOJAM restart
+///  - `Channel` - The IO channel, masked with `mask`, reads `value`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCondition {
+    Mcts(u64),
+    Address(FixedAddress),
+    Restart,
+    Channel { channel: usize, mask: u16, value: u16 },
+}
+
+impl fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopCondition::Mcts(x) => write!(f, "{} MCTs", x),
+            StopCondition::Address(x) => write!(f, "PC {}", x),
+            StopCondition::Restart => write!(f, "restart"),
+            StopCondition::Channel {
+                channel,
+                mask,
+                value,
+            } => write!(f, "channel {:o}={:05o}/{:05o}", channel, value, mask),
+        }
+    }
+}
+
+///
+/// ## RunOutcome Enum
+///
+/// Result of `AgcRunner::run_until`.
+///
+///  - `Stopped` - The condition of the given index was met, after the given
+///    number of MCTs
+///  - `Timeout` - No condition was met before the timeout
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Stopped { condition: usize, mcts: u64 },
+    Timeout,
+}
+
+///
+/// ## AgcRunner Structure
+///
+/// Paces an `AgcCpu` against a host clock. The caller owns the clock and
+/// passes its reading in nanoseconds to `advance`, which runs the AGC for
+/// the time elapsed since the last call, scaled by the `RunMode`.
+/// Instructions overrunning the elapsed time are paid back on the next call,
+/// so the AGC does not drift from the host clock.
+///
+/// `step_instruction` and `step_mcts` run the AGC regardless of the mode,
+/// typically while paused.
+///
+pub struct AgcRunner {
+    mode: RunMode,
+    last_ns: Option<u64>,
+    credit_ns: f64,
+}
+
+impl AgcRunner {
+    pub fn new(mode: RunMode) -> Self {
+        AgcRunner {
+            mode,
+            last_ns: None,
+            credit_ns: 0.0,
+        }
+    }
+
+    pub fn mode(&self) -> RunMode {
+        self.mode
+    }
+
+    ///
+    /// ## `set_mode` Function
+    ///
+    /// Changes the pacing. Time only counts from the next `advance` call, so
+    /// resuming from a pause does not run the AGC in a burst.
+    ///
+    pub fn set_mode(&mut self, mode: RunMode) {
+        self.mode = mode;
+        self.last_ns = None;
+        self.credit_ns = self.credit_ns.min(0.0);
+    }
+
+    ///
+    /// ## `advance` Function
+    ///
+    /// Runs the AGC for the host time elapsed since the last call, or a
+    /// `MAX_SPEED_SLICE_MCTS` slice at maximum speed.
+    ///
+    /// ### Arguments
+    ///
+    ///  - `now_ns` - Host clock reading, in nanoseconds from any origin
+    ///
+    /// ### Result
+    ///
+    ///  - `u64` - Number of MCTs run
+    ///
+    pub fn advance(&mut self, cpu: &mut AgcCpu, now_ns: u64) -> u64 {
+        self.advance_until(cpu, now_ns, |_cpu| false).0
+    }
+
+    ///
+    /// ## `advance_until` Function
+    ///
+    /// Same as `advance`, ending early after an instruction for which `stop`
+    /// returns true, such as a breakpoint. The time left is run by the next
+    /// call.
+    ///
+    /// ### Result
+    ///
+    ///  - `(u64, bool)` - Number of MCTs run, and whether `stop` ended the run
+    ///
+    pub fn advance_until<F: FnMut(&mut AgcCpu) -> bool>(
+        &mut self,
+        cpu: &mut AgcCpu,
+        now_ns: u64,
+        mut stop: F,
+    ) -> (u64, bool) {
+        let elapsed = match self.last_ns {
+            Some(last) => now_ns.saturating_sub(last),
+            None => 0,
+        };
+        self.last_ns = Some(now_ns);
+
+        let speed = match self.mode {
+            RunMode::MaxSpeed => {
+                let mut mcts = 0;
+                while mcts < MAX_SPEED_SLICE_MCTS {
+                    mcts += cpu.step() as u64;
+                    if stop(cpu) {
+                        return (mcts, true);
+                    }
+                }
+                return (mcts, false);
+            }
+            mode => match mode.speed() {
+                Some(x) => x,
+                None => return (0, false),
+            },
+        };
+
+        self.credit_ns = (self.credit_ns + elapsed as f64 * speed).min(MAX_CATCHUP_NANOS as f64);
+        let mut mcts = 0;
+        while self.credit_ns >= MCT_NANOS as f64 {
+            let cycles = cpu.step() as u64;
+            self.credit_ns -= (cycles * MCT_NANOS) as f64;
+            mcts += cycles;
+            if stop(cpu) {
+                return (mcts, true);
+            }
+        }
+        (mcts, false)
+    }
+
+    ///
+    /// ## `idle_ns` Function
+    ///
+    /// Returns the host time, in nanoseconds, before the next instruction is
+    /// due. `None` is returned while paused, as the AGC only runs when
+    /// stepped.
+    ///
+    pub fn idle_ns(&self) -> Option<u64> {
+        match self.mode {
+            RunMode::MaxSpeed => Some(0),
+            mode => mode
+                .speed()
+                .map(|speed| ((MCT_NANOS as f64 - self.credit_ns).max(0.0) / speed) as u64),
+        }
+    }
+
+    ///
+    /// ## `step_instruction` Function
+    ///
+    /// Runs a single instruction, or an interrupt or restart sequence.
+    ///
+    /// ### Result
+    ///
+    ///  - `u16` - Number of MCTs run
+    ///
+    pub fn step_instruction(&mut self, cpu: &mut AgcCpu) -> u16 {
+        cpu.step()
+    }
+
+    ///
+    /// ## `step_mcts` Function
+    ///
+    /// Runs whole instructions until at least `mcts` MCTs have gone by.
+    ///
+    /// ### Result
+    ///
+    ///  - `u64` - Number of MCTs run
+    ///
+    pub fn step_mcts(&mut self, cpu: &mut AgcCpu, mcts: u64) -> u64 {
+        let mut ran = 0;
+        while ran < mcts {
+            ran += cpu.step() as u64;
+        }
+        ran
+    }
+
+    ///
+    /// ## `run_until` Function
+    ///
+    /// Runs the AGC without pacing until one of the conditions is met or
+    /// `timeout_mcts` MCTs have gone by. Conditions are checked after every
+    /// instruction.
+    ///
+    pub fn run_until(
+        &mut self,
+        cpu: &mut AgcCpu,
+        conditions: &[StopCondition],
+        timeout_mcts: u64,
+    ) -> RunOutcome {
+        self.run_until_with(cpu, conditions, timeout_mcts, |_cpu| false)
+    }
+
+    ///
+    /// ## `run_until_with` Function
+    ///
+    /// Same as `run_until`, with an additional condition checked by `extra`
+    /// after the others, such as a condition on a peripheral. It is reported
+    /// with the index `conditions.len()`.
+    ///
+    pub fn run_until_with<F: FnMut(&mut AgcCpu) -> bool>(
+        &mut self,
+        cpu: &mut AgcCpu,
+        conditions: &[StopCondition],
+        timeout_mcts: u64,
+        mut extra: F,
+    ) -> RunOutcome {
+        let restarts = cpu.restarts;
+        let mut mcts = 0;
+        while mcts < timeout_mcts {
+            mcts += cpu.step() as u64;
+            let met = conditions.iter().position(|condition| match *condition {
+                StopCondition::Mcts(x) => mcts >= x,
+                StopCondition::Address(addr) => FixedAddress::of_pc(cpu) == Some(addr),
+                StopCondition::Restart => cpu.restarts != restarts,
+                StopCondition::Channel {
+                    channel,
+                    mask,
+                    value,
+                } => cpu.read_io(channel) & mask == value,
+            });
+            let met = match met {
+                Some(x) => Some(x),
+                None if extra(cpu) => Some(conditions.len()),
+                None => None,
+            };
+            if let Some(condition) = met {
+                return RunOutcome::Stopped { condition, mcts };
+            }
+        }
+        RunOutcome::Timeout
+    }
+
+    ///
+    /// ## `mission_time` Function
+    ///
+    /// Returns the emulated time since the AGC was reset.
+    ///
+    pub fn mission_time(&self, cpu: &AgcCpu) -> MissionTime {
+        MissionTime::from_mcts(cpu.total_cycles as u64)
+    }
+}
+
+#[cfg(test)]
+mod runner_tests {
+    use super::*;
+    use crate::consts::{ROM_BANK_NUM_WORDS, ROM_NUM_BANKS};
+    use crate::mem::periph::AgcIoPeriph;
+    use crate::mem::AgcMemoryMap;
+
+    struct NullPeriph;
+
+    impl AgcIoPeriph for NullPeriph {
+        fn read(&self, _channel_idx: usize) -> u16 {
+            0
+        }
+
+        fn write(&mut self, _channel_idx: usize, _value: u16) {}
+
+        fn is_interrupt(&mut self) -> u16 {
+            0
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the AGC follows the host clock at real time and scaled speeds,
+    /// does not run while paused, and can be single-stepped.
+    ///
+    #[test]
+    fn test_runner_pacing() {
+        let mut q1: heapless::spsc::Queue<u8, 8> = heapless::spsc::Queue::new();
+        let (tx, _rx) = q1.split();
+        let mut cpu = AgcCpu::new(AgcMemoryMap::new_blank(tx));
+        cpu.reset();
+
+        let mut runner = AgcRunner::new(RunMode::RealTime);
+        assert_eq!(runner.advance(&mut cpu, 5000000), 0);
+        let mut now = 5000000;
+        let mut mcts = 0;
+        for _i in 0..100 {
+            now += 1000000;
+            mcts += runner.advance(&mut cpu, now);
+        }
+        // 100ms of real time, within the last instruction
+        let expected = 100000000 / MCT_NANOS;
+        assert!(mcts >= expected && mcts <= expected + 10, "{}", mcts);
+        assert!(runner.idle_ns().unwrap() <= MCT_NANOS);
+
+        runner.set_mode(RunMode::Scaled(10.0));
+        runner.advance(&mut cpu, now);
+        let mcts = runner.advance(&mut cpu, now + 10000000);
+        assert!(mcts >= expected && mcts <= expected + 10, "{}", mcts);
+
+        runner.set_mode(RunMode::Paused);
+        assert_eq!(runner.advance(&mut cpu, now + 20000000), 0);
+        assert_eq!(runner.advance(&mut cpu, now + 90000000), 0);
+        assert_eq!(runner.idle_ns(), None);
+        assert!(runner.step_instruction(&mut cpu) > 0);
+        assert!(runner.step_mcts(&mut cpu, 50) >= 50);
+
+        runner.set_mode(RunMode::MaxSpeed);
+        assert!(runner.advance(&mut cpu, now + 90000000) >= MAX_SPEED_SLICE_MCTS);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the mission time shown from the MCTs since reset.
+    ///
+    #[test]
+    fn test_mission_time() {
+        // MCTS_PER_SECOND is rounded down, so this is just short of 3725s
+        let time = MissionTime::from_mcts(MCTS_PER_SECOND * 3725 + 12);
+        assert_eq!(time.hours, 1);
+        assert_eq!(time.minutes, 2);
+        assert_eq!(time.seconds, 4);
+        let mut buf = heapless::String::<16>::new();
+        core::fmt::write(&mut buf, format_args!("{}", time)).unwrap();
+        assert_eq!(buf.as_str(), "001:02:04.99");
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests bank-qualified addresses are parsed and shown in yaAGC format.
+    ///
+    #[test]
+    fn test_fixed_address() {
+        let addr = FixedAddress::parse("27,2345").unwrap();
+        assert_eq!(addr, FixedAddress { bank: 0o27, offset: 0o345 });
+        let mut buf = heapless::String::<16>::new();
+        core::fmt::write(&mut buf, format_args!("{}", addr)).unwrap();
+        assert_eq!(buf.as_str(), "27,2345");
+
+        assert_eq!(
+            FixedAddress::parse("4000"),
+            Some(FixedAddress { bank: 2, offset: 0 })
+        );
+        assert_eq!(FixedAddress::parse("44,2000"), None);
+        assert_eq!(FixedAddress::parse("27,4000"), None);
+        assert_eq!(FixedAddress::parse("1400"), None);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests running until the PC reaches an address, a channel value or a
+    /// number of MCTs, and the timeout.
+    ///
+    #[test]
+    fn test_run_until() {
+        // TCF 4002, then TCF 4002 forever. Ropes hold big endian words
+        // shifted left by one, with fixed fixed bank 2 first.
+        let mut rope = [[0; ROM_BANK_NUM_WORDS]; ROM_NUM_BANKS];
+        rope[0][0] = (0o14002u16 << 1).to_be();
+        rope[0][2] = (0o14002u16 << 1).to_be();
+
+        let mut q1: heapless::spsc::Queue<u8, 8> = heapless::spsc::Queue::new();
+        let (tx, _rx) = q1.split();
+        let (mut downrupt, mut dsky) = (NullPeriph, NullPeriph);
+        let mut cpu = AgcCpu::new(AgcMemoryMap::new(&rope, &mut downrupt, &mut dsky, tx));
+        cpu.reset();
+
+        let mut runner = AgcRunner::new(RunMode::MaxSpeed);
+        let target = FixedAddress::parse("4002").unwrap();
+        let outcome = runner.run_until(&mut cpu, &[StopCondition::Address(target)], 100);
+        assert!(matches!(outcome, RunOutcome::Stopped { condition: 0, .. }));
+
+        let conditions = [
+            StopCondition::Channel { channel: 0o13, mask: 0o1000, value: 0o1000 },
+            StopCondition::Mcts(20),
+        ];
+        let outcome = runner.run_until(&mut cpu, &conditions, 100);
+        assert!(matches!(outcome, RunOutcome::Stopped { condition: 1, mcts: 20..=21 }));
+
+        assert_eq!(runner.run_until(&mut cpu, &[StopCondition::Restart], 10), RunOutcome::Timeout);
+
+        let mut checks = 0;
+        let outcome = runner.run_until_with(&mut cpu, &conditions[..1], 100, |_cpu| {
+            checks += 1;
+            checks == 3
+        });
+        assert!(matches!(outcome, RunOutcome::Stopped { condition: 1, .. }));
+
+        // Breakpoint on the pseudo-address of 4002, ending a slice early
+        let (mcts, stopped) = runner.advance_until(&mut cpu, 0, |cpu| {
+            let z = cpu.read(REG_Z) as usize;
+            cpu.pseudo_address(z) == 0o14002
+        });
+        assert!(stopped && mcts < MAX_SPEED_SLICE_MCTS);
+    }
+}
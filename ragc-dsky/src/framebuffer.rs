@@ -0,0 +1,280 @@
+use ragc_core::consts::io;
+use ragc_periph::dsky_state::{DskyLamps, DskyState};
+use ragc_periph::utils::get_7seg_value;
+
+/// Number of seven segment digits on the DSKY: PROG, VERB, NOUN and the five
+/// digits of R1, R2 and R3.
+pub const DIGIT_COUNT: usize = 21;
+
+/// Index of the first PROG digit in `DskyFramebuffer::digits`.
+pub const DIGIT_PROG: usize = 0;
+/// Index of the first VERB digit in `DskyFramebuffer::digits`.
+pub const DIGIT_VERB: usize = 2;
+/// Index of the first NOUN digit in `DskyFramebuffer::digits`.
+pub const DIGIT_NOUN: usize = 4;
+/// Index of the first digit of R1, R2 and R3 in `DskyFramebuffer::digits`.
+pub const DIGIT_REGISTERS: [usize; 3] = [6, 11, 16];
+
+/// Segments of a minus sign (segment g).
+pub const SIGN_MINUS: u8 = 0x40;
+/// Segments of a plus sign: segment g along with the vertical bar, wired in
+/// place of the decimal point.
+pub const SIGN_PLUS: u8 = 0xC0;
+
+/// Segments lit on every digit during the lamp test.
+const SEGMENTS_ALL: u8 = 0x7F;
+
+// Digits of the `c` and `d` relays of each row, row 1 first. Row 8 has no
+// `c` digit.
+const ROW_DIGITS: [(Option<usize>, usize); 11] = [
+    (Some(19), 20),
+    (Some(17), 18),
+    (Some(15), 16),
+    (Some(13), 14),
+    (Some(11), 12),
+    (Some(9), 10),
+    (Some(7), 8),
+    (None, 6),
+    (Some(4), 5),
+    (Some(2), 3),
+    (Some(0), 1),
+];
+
+// Relay row holding the sign of a register in bit 11
+const ROW_SIGN_BIT: u16 = 0o02000;
+
+///
+/// ## DskyFramebuffer Structure
+///
+/// What a DSKY with seven segment digits and individual lamps should show.
+/// Values dequeued from the `heapless` DSKY are given to `update`: relay
+/// words and channel 11 from its `(channel, value)` queue, and the already
+/// flashed channel 163 lamps as `(0o163, value)`.
+///
+/// Digits are in `get_7seg` format (bit 0 is segment a, bit 6 segment g),
+/// and lamps are a bit mask indexed like `DskyLamps::NAMES`. VERB and NOUN
+/// are blanked during the off phase of their flash, the whole display when
+/// channel 163 turns it off, and every segment and lamp is lit during the
+/// lamp test.
+///
+pub struct DskyFramebuffer {
+    segments: [u8; DIGIT_COUNT],
+    signs: [(bool, bool); 3],
+    state: DskyState,
+}
+
+impl Default for DskyFramebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DskyFramebuffer {
+    pub fn new() -> Self {
+        DskyFramebuffer {
+            segments: [0; DIGIT_COUNT],
+            signs: [(false, false); 3],
+            state: DskyState::new(),
+        }
+    }
+
+    ///
+    /// ## `state` Function
+    ///
+    /// Returns the decoded DSKY state behind the framebuffer.
+    ///
+    pub fn state(&self) -> &DskyState {
+        &self.state
+    }
+
+    ///
+    /// ## `update` Function
+    ///
+    /// Decodes a value sent by the DSKY on channel 10, 11, 13 or 163.
+    ///
+    /// ### Result
+    ///
+    ///  - `bool` - The framebuffer has changed and should be redrawn
+    ///
+    pub fn update(&mut self, channel_idx: usize, value: u16) -> bool {
+        let mut changed = self.state.update(channel_idx, value);
+        if channel_idx != io::CHANNEL_DSKY {
+            return changed;
+        }
+
+        let row = ((value >> 11) & 0o17) as usize;
+        if row == 0 || row > ROW_DIGITS.len() {
+            return changed;
+        }
+        let (c_idx, d_idx) = ROW_DIGITS[row - 1];
+        let segs = get_7seg_value(((value >> 5) & 0o37) as u8, (value & 0o37) as u8);
+        if let Some(idx) = c_idx {
+            changed |= self.segments[idx] != (segs >> 8) as u8;
+            self.segments[idx] = (segs >> 8) as u8;
+        }
+        changed |= self.segments[d_idx] != segs as u8;
+        self.segments[d_idx] = segs as u8;
+
+        // Plus and minus signs of R1 (rows 7 and 6), R2 (rows 5 and 4) and
+        // R3 (rows 2 and 1)
+        let sign = value & ROW_SIGN_BIT != 0;
+        let sign_bit = match row {
+            7 => Some(&mut self.signs[0].0),
+            6 => Some(&mut self.signs[0].1),
+            5 => Some(&mut self.signs[1].0),
+            4 => Some(&mut self.signs[1].1),
+            2 => Some(&mut self.signs[2].0),
+            1 => Some(&mut self.signs[2].1),
+            _ => None,
+        };
+        if let Some(bit) = sign_bit {
+            changed |= *bit != sign;
+            *bit = sign;
+        }
+        changed
+    }
+
+    fn blanked(&self, idx: usize) -> bool {
+        self.state.display_off
+            || (self.state.flash_off && (DIGIT_VERB..DIGIT_NOUN + 2).contains(&idx))
+    }
+
+    ///
+    /// ## `digit` Function
+    ///
+    /// Returns the segments lit on a digit, see the `DIGIT_*` indexes.
+    ///
+    pub fn digit(&self, idx: usize) -> u8 {
+        if self.state.lamp_test {
+            SEGMENTS_ALL
+        } else if self.blanked(idx) {
+            0
+        } else {
+            self.segments[idx]
+        }
+    }
+
+    ///
+    /// ## `digits` Function
+    ///
+    /// Returns the segments lit on every digit.
+    ///
+    pub fn digits(&self) -> [u8; DIGIT_COUNT] {
+        let mut digits = [0; DIGIT_COUNT];
+        for (idx, digit) in digits.iter_mut().enumerate() {
+            *digit = self.digit(idx);
+        }
+        digits
+    }
+
+    ///
+    /// ## `sign` Function
+    ///
+    /// Returns the segments of the sign of R1, R2 or R3 (0 to 2):
+    /// `SIGN_PLUS`, `SIGN_MINUS` or blank.
+    ///
+    pub fn sign(&self, register: usize) -> u8 {
+        if self.state.lamp_test {
+            return SIGN_PLUS;
+        } else if self.state.display_off {
+            return 0;
+        }
+        match self.signs[register] {
+            (true, false) => SIGN_PLUS,
+            (false, true) => SIGN_MINUS,
+            _ => 0,
+        }
+    }
+
+    ///
+    /// ## `lamps` Function
+    ///
+    /// Returns the lit lamps, bit `n` being lamp `DskyLamps::NAMES[n]`.
+    ///
+    pub fn lamps(&self) -> u32 {
+        let lamps = self.state.lit_lamps();
+        DskyLamps::NAMES
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| lamps.get(name) == Some(true))
+            .fold(0, |mask, (idx, _)| mask | (1 << idx))
+    }
+}
+
+#[cfg(test)]
+mod framebuffer_tests {
+    use super::*;
+
+    // Relay codes of the digits 0 to 9
+    const CODES: [u16; 10] = [21, 3, 25, 27, 15, 30, 28, 19, 29, 31];
+
+    // Segments of the digits 0 to 9
+    const SEGS: [u8; 10] = [0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F];
+
+    fn row(row: u16, sign: bool, c: u16, d: u16) -> u16 {
+        (row << 11) | if sign { ROW_SIGN_BIT } else { 0 } | (c << 5) | d
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests relay words are drawn on the matching digits and signs, with
+    /// VERB and NOUN blanked while they flash off.
+    ///
+    #[test]
+    fn test_framebuffer_digits() {
+        let mut fb = DskyFramebuffer::new();
+        // PROG 63, VERB 06, NOUN 36, R1 +12345
+        assert!(fb.update(io::CHANNEL_DSKY, row(11, false, CODES[6], CODES[3])));
+        assert!(fb.update(io::CHANNEL_DSKY, row(10, false, CODES[0], CODES[6])));
+        assert!(fb.update(io::CHANNEL_DSKY, row(9, false, CODES[3], CODES[6])));
+        assert!(fb.update(io::CHANNEL_DSKY, row(8, false, 0, CODES[1])));
+        assert!(fb.update(io::CHANNEL_DSKY, row(7, true, CODES[2], CODES[3])));
+        assert!(fb.update(io::CHANNEL_DSKY, row(6, false, CODES[4], CODES[5])));
+        assert!(!fb.update(io::CHANNEL_DSKY, row(6, false, CODES[4], CODES[5])));
+
+        let digits = fb.digits();
+        assert_eq!(digits[DIGIT_PROG..DIGIT_PROG + 2], [SEGS[6], SEGS[3]]);
+        assert_eq!(digits[DIGIT_VERB..DIGIT_VERB + 2], [SEGS[0], SEGS[6]]);
+        assert_eq!(digits[DIGIT_NOUN..DIGIT_NOUN + 2], [SEGS[3], SEGS[6]]);
+        assert_eq!(
+            digits[DIGIT_REGISTERS[0]..DIGIT_REGISTERS[0] + 5],
+            [SEGS[1], SEGS[2], SEGS[3], SEGS[4], SEGS[5]]
+        );
+        assert_eq!(digits[DIGIT_REGISTERS[1]], 0);
+        assert_eq!(fb.sign(0), SIGN_PLUS);
+        assert_eq!(fb.sign(1), 0);
+
+        assert!(fb.update(io::CHANNEL_DSKY, row(4, true, CODES[0], CODES[0])));
+        assert_eq!(fb.sign(1), SIGN_MINUS);
+
+        // VERB and NOUN flashing off
+        assert!(fb.update(0o163, 0o00040));
+        assert_eq!(fb.digit(DIGIT_VERB), 0);
+        assert_eq!(fb.digit(DIGIT_NOUN + 1), 0);
+        assert_eq!(fb.digit(DIGIT_PROG), SEGS[6]);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests lamps from channels 11 and 163 and relay row 12, and the lamp
+    /// test lighting everything.
+    ///
+    #[test]
+    fn test_framebuffer_lamps() {
+        let mut fb = DskyFramebuffer::new();
+        let bit = |name: &str| 1 << DskyLamps::NAMES.iter().position(|x| *x == name).unwrap();
+
+        // COMP ACTY, PROG and RESTART
+        fb.update(io::CHANNEL_DSALMOUT, 0o00002);
+        fb.update(io::CHANNEL_DSKY, (12 << 11) | 0o00400);
+        fb.update(0o163, 0o00200);
+        assert_eq!(fb.lamps(), bit("COMP ACTY") | bit("PROG") | bit("RESTART"));
+
+        assert!(fb.update(io::CHANNEL_CHAN13, 0o01000));
+        assert_eq!(fb.lamps(), (1 << DskyLamps::NAMES.len()) - 1);
+        assert_eq!(fb.digits(), [0x7F; DIGIT_COUNT]);
+        assert_eq!(fb.sign(2), SIGN_PLUS);
+    }
+}
@@ -0,0 +1,339 @@
+use heapless::spsc::Producer;
+use ragc_periph::keycodes;
+
+/// Number of keys on the DSKY keyboard.
+pub const KEY_COUNT: usize = 19;
+
+/// Default number of identical scans before a key change is accepted.
+pub const DEBOUNCE_SCANS: u8 = 3;
+
+// Keypress queue values of the PRO key, as produced by
+// `keycodes::from_packet`
+const KEYPRESS_PRO_PRESSED: u16 = 0o40000;
+const KEYPRESS_PRO_RELEASED: u16 = 0o60000;
+
+///
+/// ## DskyKey Enum
+///
+/// Keys of the DSKY keyboard. The value of each key is its bit in the masks
+/// returned by `KeypadScanner::scan`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DskyKey {
+    Digit0,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Verb,
+    Noun,
+    Plus,
+    Minus,
+    Clr,
+    Pro,
+    KeyRel,
+    Entr,
+    Rset,
+}
+
+impl DskyKey {
+    /// Every key, in bit order.
+    pub const ALL: [DskyKey; KEY_COUNT] = [
+        DskyKey::Digit0,
+        DskyKey::Digit1,
+        DskyKey::Digit2,
+        DskyKey::Digit3,
+        DskyKey::Digit4,
+        DskyKey::Digit5,
+        DskyKey::Digit6,
+        DskyKey::Digit7,
+        DskyKey::Digit8,
+        DskyKey::Digit9,
+        DskyKey::Verb,
+        DskyKey::Noun,
+        DskyKey::Plus,
+        DskyKey::Minus,
+        DskyKey::Clr,
+        DskyKey::Pro,
+        DskyKey::KeyRel,
+        DskyKey::Entr,
+        DskyKey::Rset,
+    ];
+
+    ///
+    /// ## `mask` Function
+    ///
+    /// Returns the bit of the key in a scan mask.
+    ///
+    pub fn mask(self) -> u32 {
+        1 << (self as u32)
+    }
+
+    ///
+    /// ## `keycode` Function
+    ///
+    /// Returns the channel 15 keycode, `None` for PRO which is sensed on
+    /// channel 32.
+    ///
+    pub fn keycode(self) -> Option<u16> {
+        let key = match self {
+            DskyKey::Digit0 => '0',
+            DskyKey::Digit1 => '1',
+            DskyKey::Digit2 => '2',
+            DskyKey::Digit3 => '3',
+            DskyKey::Digit4 => '4',
+            DskyKey::Digit5 => '5',
+            DskyKey::Digit6 => '6',
+            DskyKey::Digit7 => '7',
+            DskyKey::Digit8 => '8',
+            DskyKey::Digit9 => '9',
+            DskyKey::Verb => 'V',
+            DskyKey::Noun => 'N',
+            DskyKey::Plus => '+',
+            DskyKey::Minus => '-',
+            DskyKey::Clr => 'C',
+            DskyKey::Pro => return None,
+            DskyKey::KeyRel => 'K',
+            DskyKey::Entr => 'E',
+            DskyKey::Rset => 'R',
+        };
+        keycodes::from_char(key)
+    }
+}
+
+///
+/// ## KeypadScanner Trait
+///
+/// Hardware access of a keypad: returns the keys currently held as a mask of
+/// `DskyKey::mask` bits. Scans are not debounced.
+///
+pub trait KeypadScanner {
+    fn scan(&mut self) -> u32;
+}
+
+/// Rows of the DSKY keyboard matrix.
+pub const MATRIX_ROWS: usize = 3;
+/// Columns of the DSKY keyboard matrix.
+pub const MATRIX_COLUMNS: usize = 7;
+
+/// Keys of the DSKY keyboard matrix as laid out on the DSKY. VERB and NOUN,
+/// and ENTR and RSET, each take a column of their own.
+pub const MATRIX_LAYOUT: [[Option<DskyKey>; MATRIX_COLUMNS]; MATRIX_ROWS] = [
+    [
+        Some(DskyKey::Verb),
+        Some(DskyKey::Plus),
+        Some(DskyKey::Digit7),
+        Some(DskyKey::Digit8),
+        Some(DskyKey::Digit9),
+        Some(DskyKey::Clr),
+        Some(DskyKey::Entr),
+    ],
+    [
+        None,
+        Some(DskyKey::Minus),
+        Some(DskyKey::Digit4),
+        Some(DskyKey::Digit5),
+        Some(DskyKey::Digit6),
+        Some(DskyKey::Pro),
+        None,
+    ],
+    [
+        Some(DskyKey::Noun),
+        Some(DskyKey::Digit0),
+        Some(DskyKey::Digit1),
+        Some(DskyKey::Digit2),
+        Some(DskyKey::Digit3),
+        Some(DskyKey::KeyRel),
+        Some(DskyKey::Rset),
+    ],
+];
+
+///
+/// ## MatrixScanner Structure
+///
+/// `KeypadScanner` of a key matrix wired like `MATRIX_LAYOUT`. The
+/// `read_row` closure drives one row and returns its columns, bit 0 being
+/// the leftmost column.
+///
+pub struct MatrixScanner<F: FnMut(usize) -> u8> {
+    read_row: F,
+}
+
+impl<F: FnMut(usize) -> u8> MatrixScanner<F> {
+    pub fn new(read_row: F) -> Self {
+        MatrixScanner { read_row }
+    }
+}
+
+impl<F: FnMut(usize) -> u8> KeypadScanner for MatrixScanner<F> {
+    fn scan(&mut self) -> u32 {
+        let mut mask = 0;
+        for (row, keys) in MATRIX_LAYOUT.iter().enumerate() {
+            let columns = (self.read_row)(row);
+            for (col, key) in keys.iter().enumerate() {
+                match key {
+                    Some(key) if columns & (1 << col) != 0 => mask |= key.mask(),
+                    _ => {}
+                }
+            }
+        }
+        mask
+    }
+}
+
+///
+/// ## Keypad Structure
+///
+/// Debounces a `KeypadScanner` and turns key presses into keypress queue
+/// values for the `heapless` DSKY: the channel 15 keycode when a key is
+/// pressed, and the PRO key on both press and release.
+///
+pub struct Keypad<S: KeypadScanner> {
+    scanner: S,
+    debounce: u8,
+    stable: u32,
+    candidate: u32,
+    count: u8,
+}
+
+impl<S: KeypadScanner> Keypad<S> {
+    pub fn new(scanner: S) -> Self {
+        Keypad {
+            scanner,
+            debounce: DEBOUNCE_SCANS,
+            stable: 0,
+            candidate: 0,
+            count: 0,
+        }
+    }
+
+    ///
+    /// ## `set_debounce` Function
+    ///
+    /// Sets the number of identical scans before a key change is accepted.
+    ///
+    pub fn set_debounce(&mut self, scans: u8) {
+        self.debounce = scans.max(1);
+    }
+
+    ///
+    /// ## `held` Function
+    ///
+    /// Returns the debounced mask of the keys held.
+    ///
+    pub fn held(&self) -> u32 {
+        self.stable
+    }
+
+    ///
+    /// ## `poll` Function
+    ///
+    /// Scans the keypad once, and queues the keypresses of the keys whose
+    /// change has been debounced. Keypresses not fitting in the queue are
+    /// dropped.
+    ///
+    /// ### Result
+    ///
+    ///  - `usize` - Number of keypresses queued
+    ///
+    pub fn poll<const N: usize>(&mut self, keypress_tx: &mut Producer<'_, u16, N>) -> usize {
+        let scan = self.scanner.scan();
+        if scan != self.candidate {
+            self.candidate = scan;
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+        if self.count < self.debounce || self.candidate == self.stable {
+            return 0;
+        }
+
+        let pressed = self.candidate & !self.stable;
+        let released = self.stable & !self.candidate;
+        self.stable = self.candidate;
+
+        let mut queued = 0;
+        for key in DskyKey::ALL.iter() {
+            let value = match key.keycode() {
+                Some(code) if pressed & key.mask() != 0 => code,
+                None if pressed & key.mask() != 0 => KEYPRESS_PRO_PRESSED,
+                None if released & key.mask() != 0 => KEYPRESS_PRO_RELEASED,
+                _ => continue,
+            };
+            if keypress_tx.enqueue(value).is_ok() {
+                queued += 1;
+            }
+        }
+        queued
+    }
+}
+
+#[cfg(test)]
+mod keypad_tests {
+    use super::*;
+    use heapless::spsc::Queue;
+
+    struct FakeScanner<'a> {
+        scans: &'a [u32],
+        idx: usize,
+    }
+
+    impl KeypadScanner for FakeScanner<'_> {
+        fn scan(&mut self) -> u32 {
+            let scan = self.scans[self.idx.min(self.scans.len() - 1)];
+            self.idx += 1;
+            scan
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests bouncing keys are only queued once they are stable, and PRO is
+    /// queued on both press and release.
+    ///
+    #[test]
+    fn test_keypad_debounce() {
+        let verb = DskyKey::Verb.mask();
+        let pro = DskyKey::Pro.mask();
+        let scans = [verb, 0, verb, verb, verb, verb, 0, 0, 0, pro, pro, pro, 0, 0, 0];
+        let mut keypad = Keypad::new(FakeScanner {
+            scans: &scans,
+            idx: 0,
+        });
+        let mut q: Queue<u16, 8> = Queue::new();
+        let (mut tx, mut rx) = q.split();
+
+        let queued: usize = (0..scans.len()).map(|_| keypad.poll(&mut tx)).sum();
+        assert_eq!(queued, 3);
+        assert_eq!(rx.dequeue(), Some(keycodes::KEY_VERB));
+        assert_eq!(rx.dequeue(), Some(KEYPRESS_PRO_PRESSED));
+        assert_eq!(rx.dequeue(), Some(KEYPRESS_PRO_RELEASED));
+        assert_eq!(keypad.held(), 0);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the matrix scanner maps rows and columns onto the DSKY keys.
+    ///
+    #[test]
+    fn test_matrix_scanner() {
+        // ENTR on row 0, KEY REL and NOUN on row 2
+        let mut scanner = MatrixScanner::new(|row| match row {
+            0 => 0b1000000,
+            2 => 0b0100001,
+            _ => 0,
+        });
+        assert_eq!(
+            scanner.scan(),
+            DskyKey::Entr.mask() | DskyKey::KeyRel.mask() | DskyKey::Noun.mask()
+        );
+        assert_eq!(DskyKey::Rset.keycode(), Some(keycodes::KEY_RSET));
+        assert_eq!(DskyKey::Pro.keycode(), None);
+    }
+}
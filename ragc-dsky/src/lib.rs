@@ -0,0 +1,124 @@
+#![no_std]
+
+pub mod framebuffer;
+pub mod keypad;
+
+use heapless::spsc::{Consumer, Producer};
+
+pub use framebuffer::DskyFramebuffer;
+pub use keypad::{DskyKey, Keypad, KeypadScanner, MatrixScanner};
+
+///
+/// ## DskyFrontend Structure
+///
+/// Reference bare-metal frontend of the `heapless` DSKY of `ragc-periph`. It
+/// takes the other ends of the `DskyDisplay` queues: relay words and lamps
+/// are drawn on a `DskyFramebuffer`, and keys of a `Keypad` are queued as
+/// keypresses.
+///
+/// `poll` is meant to be called from the main loop or a timer, every few
+/// milliseconds for the keypad debounce, and the displays refreshed from
+/// `framebuffer` when it reports a change.
+///
+pub struct DskyFrontend<'a, S: KeypadScanner> {
+    framebuffer: DskyFramebuffer,
+    keypad: Keypad<S>,
+    dsky_rx: Consumer<'a, (usize, u16), 64>,
+    flash_rx: Consumer<'a, u16, 8>,
+    keypress_tx: Producer<'a, u16, 8>,
+}
+
+impl<'a, S: KeypadScanner> DskyFrontend<'a, S> {
+    pub fn new(
+        scanner: S,
+        dsky_rx: Consumer<'a, (usize, u16), 64>,
+        flash_rx: Consumer<'a, u16, 8>,
+        keypress_tx: Producer<'a, u16, 8>,
+    ) -> Self {
+        DskyFrontend {
+            framebuffer: DskyFramebuffer::new(),
+            keypad: Keypad::new(scanner),
+            dsky_rx,
+            flash_rx,
+            keypress_tx,
+        }
+    }
+
+    pub fn framebuffer(&self) -> &DskyFramebuffer {
+        &self.framebuffer
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut Keypad<S> {
+        &mut self.keypad
+    }
+
+    ///
+    /// ## `poll` Function
+    ///
+    /// Draws every value queued by the DSKY, and scans the keypad once.
+    ///
+    /// ### Result
+    ///
+    ///  - `bool` - The framebuffer has changed and should be redrawn
+    ///
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Some((channel_idx, value)) = self.dsky_rx.dequeue() {
+            changed |= self.framebuffer.update(channel_idx, value);
+        }
+        while let Some(value) = self.flash_rx.dequeue() {
+            changed |= self.framebuffer.update(0o163, value);
+        }
+        self.keypad.poll(&mut self.keypress_tx);
+        changed
+    }
+}
+
+#[cfg(test)]
+mod frontend_tests {
+    use super::*;
+    use framebuffer::{DIGIT_VERB, SIGN_MINUS};
+    use heapless::spsc::Queue;
+    use ragc_core::consts::cpu;
+    use ragc_core::mem::periph::AgcIoPeriph;
+    use ragc_periph::dsky::DskyDisplay;
+
+    ///
+    /// # Description
+    ///
+    /// Tests the frontend against the `heapless` DSKY: display writes of the
+    /// AGC reach the framebuffer, and a key held on the matrix reaches the
+    /// AGC with a KEYRUPT1.
+    ///
+    #[test]
+    fn test_frontend_heapless_dsky() {
+        let mut keys: Queue<u16, 8> = Queue::new();
+        let mut out: Queue<(usize, u16), 64> = Queue::new();
+        let mut flash: Queue<u16, 8> = Queue::new();
+        let (keys_tx, keys_rx) = keys.split();
+        let (out_tx, out_rx) = out.split();
+        let (flash_tx, flash_rx) = flash.split();
+        let mut dsky = DskyDisplay::new(keys_rx, out_tx, flash_tx);
+
+        // VERB key held on row 0, column 0
+        let scanner = MatrixScanner::new(|row| if row == 0 { 0b1 } else { 0 });
+        let mut frontend = DskyFrontend::new(scanner, out_rx, flash_rx, keys_tx);
+
+        // VERB 37 and R3 minus sign
+        dsky.write(0o10, (10 << 11) | (27 << 5) | 19);
+        dsky.write(0o10, (1 << 11) | 0o02000);
+        dsky.write(0o11, 0o00020);
+        assert!(frontend.poll());
+        let fb = frontend.framebuffer();
+        assert_eq!(fb.digit(DIGIT_VERB), 0x4F);
+        assert_eq!(fb.digit(DIGIT_VERB + 1), 0x07);
+        assert_eq!(fb.sign(2), SIGN_MINUS);
+        assert!(fb.state().lamps.key_rel);
+
+        frontend.poll();
+        frontend.poll();
+        dsky.pump_mcts(1);
+        assert_eq!(dsky.is_interrupt(), 1 << cpu::RUPT_KEY1);
+        assert_eq!(dsky.read(0o15), 0o21);
+    }
+}
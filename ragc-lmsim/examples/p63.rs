@@ -0,0 +1,36 @@
+//!
+//! Flies the LUMINARY131 P63 braking phase from the powered descent
+//! scenario and writes the recorded trajectory as CSV.
+//!
+//! ```text
+//! cargo run --release --example p63 [SCENARIO] [CSV]
+//! ```
+//!
+use std::fs::File;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let scenario_file = args
+        .next()
+        .unwrap_or_else(|| "scenarios/luminary131_p63.txt".to_string());
+    let csv_file = args.next().unwrap_or_else(|| "p63.csv".to_string());
+
+    let text = std::fs::read_to_string(&scenario_file)?;
+    let scenario = ragc_lmsim::Scenario::parse(&text)
+        .map_err(|e| format!("{}: line {}: {:?}", scenario_file, e.line, e.kind))?;
+
+    let trajectory = ragc_lmsim::fly(ragc_ropes::LUMINARY131_ROPE, &scenario);
+    trajectory.write_csv(File::create(&csv_file)?)?;
+
+    if let Some(last) = trajectory.last() {
+        println!(
+            "{} points to {}, landed: {}, last altitude {:.1} m at {:.1} s",
+            trajectory.points.len(),
+            csv_file,
+            trajectory.landed,
+            last.altitude,
+            last.time
+        );
+    }
+    Ok(())
+}
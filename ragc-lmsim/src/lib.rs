@@ -0,0 +1,192 @@
+pub mod math;
+pub mod scenario;
+pub mod sim;
+pub mod trajectory;
+pub mod vehicle;
+
+use ragc_core::consts::{cpu as cpu_consts, ROM_BANK_NUM_WORDS, ROM_NUM_BANKS};
+use ragc_core::cpu::AgcCpu;
+use ragc_core::mem::periph::AgcIoPeriph;
+use ragc_core::mem::AgcMemoryMap;
+
+pub use scenario::{Scenario, ScenarioAction, ScenarioError, ScenarioErrorKind};
+pub use sim::LmSimulator;
+pub use trajectory::{Trajectory, TrajectoryPoint};
+
+///
+/// ## NullPeriph Peripheral
+///
+/// Stand-in for the DSKY and DOWNRUPT peripherals when flying without any
+/// display attached.
+///
+pub struct NullPeriph;
+
+impl AgcIoPeriph for NullPeriph {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            ragc_core::consts::io::CHANNEL_CHAN32 => 0o77777,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, _channel_idx: usize, _value: u16) {}
+
+    fn is_interrupt(&mut self) -> u16 {
+        0
+    }
+}
+
+///
+/// ## `load_erasable` Function
+///
+/// Presets erasable memory with the `(bank, offset, value)` entries of a
+/// scenario, restoring EBANK afterwards.
+///
+fn load_erasable(cpu: &mut AgcCpu, erasable: &[(usize, usize, u16)]) {
+    let ebank = cpu.read(cpu_consts::REG_EB);
+    for (bank, offset, value) in erasable.iter() {
+        if *bank < 3 {
+            cpu.write(bank * 0o400 + offset, *value);
+        } else {
+            cpu.write(cpu_consts::REG_EB, (*bank as u16) << 8);
+            cpu.write(0o1400 + offset, *value);
+        }
+    }
+    cpu.write(cpu_consts::REG_EB, ebank);
+}
+
+///
+/// ## `fly_with_dsky` Function
+///
+/// Runs the AGC with the given rope coupled to the LM vehicle model until
+/// the scenario duration elapses or the LM touches down, and returns the
+/// recorded trajectory. The DSKY peripheral is used to watch or interact
+/// with the flight.
+///
+pub fn fly_with_dsky(
+    rope: &[[u16; ROM_BANK_NUM_WORDS]; ROM_NUM_BANKS],
+    scenario: &Scenario,
+    dsky: &mut dyn AgcIoPeriph,
+) -> Trajectory {
+    let mut q = heapless::spsc::Queue::new();
+    let (rupt_tx, _rupt_rx) = q.split();
+
+    let mut downrupt = NullPeriph;
+    let mut sim = LmSimulator::new(scenario);
+    let vehicle = sim.vehicle();
+    let end_mcts = (scenario.duration / sim::MCT_SECS) as u64;
+
+    {
+        let mut mm = AgcMemoryMap::new(rope, &mut downrupt, dsky, rupt_tx);
+        if mm.attach_periph(&mut sim).is_err() {
+            unreachable!("First peripheral attached to an empty memory map");
+        }
+
+        let mut cpu = AgcCpu::new(mm);
+        cpu.reset();
+        load_erasable(&mut cpu, &scenario.erasable);
+
+        let mut mcts = 0;
+        while mcts < end_mcts && !vehicle.borrow().state.landed {
+            mcts += cpu.step() as u64;
+        }
+    }
+
+    sim.into_trajectory()
+}
+
+///
+/// ## `fly` Function
+///
+/// Same as `fly_with_dsky` without any DSKY attached. DSKY input comes from
+/// the scenario `keys` lines.
+///
+pub fn fly(rope: &[[u16; ROM_BANK_NUM_WORDS]; ROM_NUM_BANKS], scenario: &Scenario) -> Trajectory {
+    fly_with_dsky(rope, scenario, &mut NullPeriph)
+}
+
+#[cfg(test)]
+mod lmsim_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests the simulator runs in lockstep with the AGC. With a blank rope
+    /// nothing commands the vehicle, so it must be in free fall for the
+    /// whole scenario duration.
+    ///
+    #[test]
+    fn test_fly_free_fall() {
+        let scenario = Scenario::parse(
+            "position 1747400 0 0\n\
+             velocity 0 0 0\n\
+             record 0.5\n\
+             duration 2\n",
+        )
+        .unwrap();
+
+        let trajectory = fly(ragc_ropes::BLANK_ROPE, &scenario);
+        assert!(!trajectory.landed);
+        assert_eq!(trajectory.points.len(), 5);
+
+        let last = trajectory.last().unwrap();
+        assert!((last.time - 2.0).abs() < 0.01);
+        let expected = 10000.0 - 0.5 * 1.6 * last.time * last.time;
+        assert!((last.altitude - expected).abs() < 0.2, "Altitude {}", last.altitude);
+        assert_eq!(last.thrust, 0.0);
+    }
+
+    struct DisplayWatcher {
+        state: ragc_periph::dsky_state::DskyState,
+        progs: Vec<u8>,
+    }
+
+    impl AgcIoPeriph for DisplayWatcher {
+        fn read(&self, channel_idx: usize) -> u16 {
+            NullPeriph.read(channel_idx)
+        }
+
+        fn write(&mut self, channel_idx: usize, value: u16) {
+            self.state.update(channel_idx, value);
+            if let Some(prog) = self.state.prog_value() {
+                if self.progs.last() != Some(&prog) {
+                    self.progs.push(prog);
+                }
+            }
+        }
+
+        fn is_interrupt(&mut self) -> u16 {
+            0
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Flies LUMINARY131 from the powered descent scenario: P63 must be
+    /// selected through the scenario keys, and the trajectory recorded until
+    /// the end of the scenario or touchdown. Ignored as it needs the real
+    /// LUMINARY131 rope and the erasable presets of the scenario, and runs 12
+    /// minutes of flight.
+    ///
+    #[test]
+    #[ignore]
+    fn test_fly_luminary131_p63() {
+        let scenario = Scenario::parse(include_str!("../scenarios/luminary131_p63.txt")).unwrap();
+        let mut dsky = DisplayWatcher {
+            state: ragc_periph::dsky_state::DskyState::new(),
+            progs: Vec::new(),
+        };
+
+        let trajectory = fly_with_dsky(ragc_ropes::LUMINARY131_ROPE, &scenario, &mut dsky);
+        assert!(dsky.progs.contains(&63), "Programs {:?}", dsky.progs);
+
+        let first = &trajectory.points[0];
+        let last = trajectory.last().unwrap();
+        assert!(trajectory.landed || (last.time - scenario.duration).abs() < 1.0);
+        assert!(last.altitude < first.altitude);
+        assert!(trajectory.points.iter().any(|x| x.thrust > 0.0));
+        assert!(last.mass < first.mass);
+    }
+}
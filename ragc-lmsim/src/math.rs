@@ -0,0 +1,227 @@
+use core::ops::{Add, AddAssign, Mul, Neg, Sub};
+
+///
+/// ## `Vec3` Structure
+///
+/// Three component vector used for positions, velocities, forces and body
+/// rates.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vec3 {
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+        Vec3 { x, y, z }
+    }
+
+    pub fn dot(&self, other: Vec3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.dot(*self).sqrt()
+    }
+
+    pub fn unit(&self) -> Vec3 {
+        *self * (1.0 / self.norm())
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl AddAssign for Vec3 {
+    fn add_assign(&mut self, other: Vec3) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<f64> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, k: f64) -> Vec3 {
+        Vec3::new(self.x * k, self.y * k, self.z * k)
+    }
+}
+
+///
+/// ## `Mat3` Structure
+///
+/// Row major 3x3 matrix. Attitude matrices follow the REFSMMAT convention:
+/// the rows are the axes of the destination frame expressed in the source
+/// frame, so `m * v` converts `v` from the source to the destination frame.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat3 {
+    pub rows: [Vec3; 3],
+}
+
+impl Mat3 {
+    pub const IDENTITY: Mat3 = Mat3 {
+        rows: [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ],
+    };
+
+    pub fn from_rows(rows: [Vec3; 3]) -> Self {
+        Mat3 { rows }
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let r = &self.rows;
+        Mat3::from_rows([
+            Vec3::new(r[0].x, r[1].x, r[2].x),
+            Vec3::new(r[0].y, r[1].y, r[2].y),
+            Vec3::new(r[0].z, r[1].z, r[2].z),
+        ])
+    }
+
+    pub fn mul_mat(&self, other: &Mat3) -> Mat3 {
+        let t = other.transpose();
+        let row = |r: Vec3| Vec3::new(r.dot(t.rows[0]), r.dot(t.rows[1]), r.dot(t.rows[2]));
+        Mat3::from_rows([row(self.rows[0]), row(self.rows[1]), row(self.rows[2])])
+    }
+
+    ///
+    /// ## `rot_x` Function
+    ///
+    /// Frame rotation of `angle` radians about the X axis. `rot_y` and `rot_z`
+    /// are the equivalent for the other axes.
+    ///
+    pub fn rot_x(angle: f64) -> Mat3 {
+        let (s, c) = angle.sin_cos();
+        Mat3::from_rows([
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, c, s),
+            Vec3::new(0.0, -s, c),
+        ])
+    }
+
+    pub fn rot_y(angle: f64) -> Mat3 {
+        let (s, c) = angle.sin_cos();
+        Mat3::from_rows([
+            Vec3::new(c, 0.0, -s),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(s, 0.0, c),
+        ])
+    }
+
+    pub fn rot_z(angle: f64) -> Mat3 {
+        let (s, c) = angle.sin_cos();
+        Mat3::from_rows([
+            Vec3::new(c, s, 0.0),
+            Vec3::new(-s, c, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ])
+    }
+
+    ///
+    /// ## `orthonormalize` Function
+    ///
+    /// Removes the numerical drift accumulated while integrating an attitude
+    /// matrix using Gram-Schmidt on the rows.
+    ///
+    pub fn orthonormalize(&self) -> Mat3 {
+        let x = self.rows[0].unit();
+        let y = (self.rows[1] - x * x.dot(self.rows[1])).unit();
+        let z = x.cross(y);
+        Mat3::from_rows([x, y, z])
+    }
+}
+
+impl Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+    fn mul(self, v: Vec3) -> Vec3 {
+        Vec3::new(self.rows[0].dot(v), self.rows[1].dot(v), self.rows[2].dot(v))
+    }
+}
+
+///
+/// ## `gimbal_matrix` Function
+///
+/// Builds the matrix converting stable member coordinates into navigation
+/// base (body) coordinates for the given IMU gimbal angles in radians. The
+/// gimbal order from the stable member out is inner (Y), middle (Z) and
+/// outer (X).
+///
+pub fn gimbal_matrix(outer: f64, inner: f64, middle: f64) -> Mat3 {
+    Mat3::rot_x(outer).mul_mat(&Mat3::rot_z(middle).mul_mat(&Mat3::rot_y(inner)))
+}
+
+///
+/// ## `gimbal_angles` Function
+///
+/// Inverse of `gimbal_matrix`, returning the (outer, inner, middle) gimbal
+/// angles in radians.
+///
+pub fn gimbal_angles(body_from_sm: &Mat3) -> (f64, f64, f64) {
+    let r = &body_from_sm.rows;
+    let middle = r[0].y.clamp(-1.0, 1.0).asin();
+    let inner = (-r[0].z).atan2(r[0].x);
+    let outer = (-r[2].y).atan2(r[1].y);
+    (outer, inner, middle)
+}
+
+#[cfg(test)]
+mod math_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests gimbal angles survive a round trip through the gimbal matrix.
+    ///
+    #[test]
+    fn test_gimbal_angles_round_trip() {
+        let angles = [(0.1, -0.7, 0.3), (2.5, 1.2, -0.4), (-3.0, 0.0, 1.2)];
+        for (outer, inner, middle) in angles.iter() {
+            let m = gimbal_matrix(*outer, *inner, *middle);
+            let (o, i, mi) = gimbal_angles(&m);
+            assert!((o - outer).abs() < 1e-9, "Outer mismatch {} vs {}", o, outer);
+            assert!((i - inner).abs() < 1e-9, "Inner mismatch {} vs {}", i, inner);
+            assert!((mi - middle).abs() < 1e-9, "Middle mismatch {} vs {}", mi, middle);
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that a frame rotation converts vectors into the rotated frame.
+    ///
+    #[test]
+    fn test_frame_rotation() {
+        let v = Mat3::rot_z(core::f64::consts::FRAC_PI_2) * Vec3::new(0.0, 1.0, 0.0);
+        assert!((v - Vec3::new(1.0, 0.0, 0.0)).norm() < 1e-12);
+    }
+}
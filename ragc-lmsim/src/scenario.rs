@@ -0,0 +1,307 @@
+use ragc_periph::keycodes;
+
+use crate::math::{gimbal_matrix, Mat3, Vec3};
+use crate::vehicle::VehicleState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScenarioErrorKind {
+    UnknownCommand,
+    BadArgument,
+    MissingState,
+}
+
+///
+/// ## `ScenarioAction` Enum
+///
+/// DSKY input of a scenario `respond` line.
+///
+///  - `Keys` - DSKY keys, as accepted on a `keys` line
+///  - `Proceed` - A press of the PRO key
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScenarioAction {
+    Keys(String),
+    Proceed,
+}
+
+///
+/// ## `ScenarioError` Structure
+///
+/// Error found while parsing a scenario. `line` starts at 1, and is 0 for
+/// errors which are not tied to a line (i.e. a missing position).
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScenarioError {
+    pub line: usize,
+    pub kind: ScenarioErrorKind,
+}
+
+///
+/// ## `Scenario` Structure
+///
+/// Initial conditions of a simulated flight. A scenario is parsed from a
+/// text file with one setting per line. Anything after a `#` is a comment.
+///
+/// ```text
+/// position  1752000 0 0          # m, moon centered inertial
+/// velocity  0 0 1690             # m/s
+/// attitude  0 -90 0              # IMU gimbal angles outer, inner, middle (deg)
+/// rates     0 0 0                # body rates (deg/s)
+/// mass      15100                # kg
+/// refsmmat  1 0 0 0 1 0 0 0 1    # stable member axes in the inertial frame
+/// armed     yes                  # descent engine arm switch
+/// erasable  5 234 00017          # octal bank, offset and value
+/// keys      2.0 V37E63E          # DSKY keys entered from 2.0 seconds
+/// pro       30.0                 # PRO key pressed at 30.0 seconds
+/// respond   50 18 pro            # answer a flashing V50N18 with PRO
+/// respond   06 33 V34E           # answer a flashing V06N33 with keys
+/// record    0.5                  # trajectory sample interval (s)
+/// duration  720                  # length of the flight (s)
+/// ```
+///
+/// Only `position` and `velocity` are required. A `respond` line answers
+/// every appearance of the flashing VERB and NOUN, with keys or PRO.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub attitude: (f64, f64, f64),
+    pub rates: Vec3,
+    pub mass: f64,
+    pub refsmmat: Mat3,
+    pub armed: bool,
+    pub erasable: Vec<(usize, usize, u16)>,
+    pub keys: Vec<(f64, String)>,
+    pub proceed: Vec<f64>,
+    pub responses: Vec<(u8, u8, ScenarioAction)>,
+    pub record_interval: f64,
+    pub duration: f64,
+}
+
+fn parse_floats<'a, const N: usize>(args: &mut impl Iterator<Item = &'a str>) -> Option<[f64; N]> {
+    let mut res = [0.0; N];
+    for x in res.iter_mut() {
+        *x = args.next()?.parse::<f64>().ok()?;
+    }
+    Some(res)
+}
+
+fn parse_octal<'a>(args: &mut impl Iterator<Item = &'a str>) -> Option<u32> {
+    u32::from_str_radix(args.next()?, 8).ok()
+}
+
+fn parse_pair<'a>(args: &mut impl Iterator<Item = &'a str>) -> Option<u8> {
+    args.next()?.parse::<u8>().ok().filter(|x| *x < 100)
+}
+
+///
+/// ## `valid_keys` Function
+///
+/// Checks the DSKY keys of a `keys` line. See `keycodes::from_char` for the
+/// accepted keys.
+///
+fn valid_keys(keys: &str) -> bool {
+    keys.chars().all(|c| keycodes::from_char(c).is_some())
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+        let mut position = None;
+        let mut velocity = None;
+        let mut scenario = Scenario {
+            position: Vec3::default(),
+            velocity: Vec3::default(),
+            attitude: (0.0, 0.0, 0.0),
+            rates: Vec3::default(),
+            mass: 15100.0,
+            refsmmat: Mat3::IDENTITY,
+            armed: true,
+            erasable: Vec::new(),
+            keys: Vec::new(),
+            proceed: Vec::new(),
+            responses: Vec::new(),
+            record_interval: 1.0,
+            duration: 720.0,
+        };
+
+        for (idx, line) in text.lines().enumerate() {
+            let content = line.split('#').next().unwrap_or("");
+            let mut args = content.split_whitespace();
+            let cmd = match args.next() {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let err = |kind| ScenarioError {
+                line: idx + 1,
+                kind,
+            };
+            let bad = || err(ScenarioErrorKind::BadArgument);
+
+            match cmd {
+                "position" => {
+                    let [x, y, z] = parse_floats(&mut args).ok_or_else(bad)?;
+                    position = Some(Vec3::new(x, y, z));
+                }
+                "velocity" => {
+                    let [x, y, z] = parse_floats(&mut args).ok_or_else(bad)?;
+                    velocity = Some(Vec3::new(x, y, z));
+                }
+                "attitude" => {
+                    let [o, i, m] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.attitude = (o, i, m);
+                }
+                "rates" => {
+                    let [p, q, r] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.rates = Vec3::new(p, q, r);
+                }
+                "mass" => {
+                    let [m] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.mass = m;
+                }
+                "refsmmat" => {
+                    let [a, b, c, d, e, f, g, h, i] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.refsmmat =
+                        Mat3::from_rows([Vec3::new(a, b, c), Vec3::new(d, e, f), Vec3::new(g, h, i)]);
+                }
+                "armed" => {
+                    scenario.armed = match args.next() {
+                        Some("yes") => true,
+                        Some("no") => false,
+                        _ => return Err(bad()),
+                    };
+                }
+                "erasable" => {
+                    let bank = parse_octal(&mut args).filter(|x| *x < 8).ok_or_else(bad)?;
+                    let offset = parse_octal(&mut args).filter(|x| *x < 0o400).ok_or_else(bad)?;
+                    let value = parse_octal(&mut args).filter(|x| *x <= 0o77777).ok_or_else(bad)?;
+                    scenario.erasable.push((bank as usize, offset as usize, value as u16));
+                }
+                "keys" => {
+                    let [time] = parse_floats(&mut args).ok_or_else(bad)?;
+                    let keys = args.next().filter(|x| valid_keys(x)).ok_or_else(bad)?;
+                    scenario.keys.push((time, keys.to_string()));
+                }
+                "pro" => {
+                    let [time] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.proceed.push(time);
+                }
+                "respond" => {
+                    let verb = parse_pair(&mut args).ok_or_else(bad)?;
+                    let noun = parse_pair(&mut args).ok_or_else(bad)?;
+                    let action = match args.next() {
+                        Some("pro") => ScenarioAction::Proceed,
+                        Some(keys) if valid_keys(keys) => ScenarioAction::Keys(keys.to_string()),
+                        _ => return Err(bad()),
+                    };
+                    scenario.responses.push((verb, noun, action));
+                }
+                "record" => {
+                    let [interval] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.record_interval = interval;
+                }
+                "duration" => {
+                    let [duration] = parse_floats(&mut args).ok_or_else(bad)?;
+                    scenario.duration = duration;
+                }
+                _ => return Err(err(ScenarioErrorKind::UnknownCommand)),
+            }
+
+            if args.next().is_some() {
+                return Err(bad());
+            }
+        }
+
+        match (position, velocity) {
+            (Some(p), Some(v)) => {
+                scenario.position = p;
+                scenario.velocity = v;
+                Ok(scenario)
+            }
+            _ => Err(ScenarioError {
+                line: 0,
+                kind: ScenarioErrorKind::MissingState,
+            }),
+        }
+    }
+
+    ///
+    /// ## `initial_state` Function
+    ///
+    /// Returns the initial vehicle state, converting the IMU gimbal angles
+    /// into the vehicle attitude through the REFSMMAT.
+    ///
+    pub fn initial_state(&self) -> VehicleState {
+        let (outer, inner, middle) = self.attitude;
+        let body_from_sm = gimbal_matrix(outer.to_radians(), inner.to_radians(), middle.to_radians());
+        VehicleState {
+            position: self.position,
+            velocity: self.velocity,
+            attitude: body_from_sm.mul_mat(&self.refsmmat),
+            rates: self.rates * (1.0f64).to_radians(),
+            mass: self.mass,
+            landed: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod scenario_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests parsing a scenario with comments and defaults.
+    ///
+    #[test]
+    fn test_scenario_parse() {
+        let scenario = Scenario::parse(
+            "# P63 start\n\
+             position 1752000 0 0\n\
+             velocity 0 0 1690   # m/s\n\
+             erasable 5 234 00017\n\
+             keys 2.0 V37E63E\n\
+             pro 30.5\n\
+             respond 50 18 pro\n\
+             respond 06 33 V34E\n",
+        )
+        .unwrap();
+
+        assert_eq!(scenario.position, Vec3::new(1752000.0, 0.0, 0.0));
+        assert_eq!(scenario.velocity, Vec3::new(0.0, 0.0, 1690.0));
+        assert_eq!(scenario.erasable, vec![(5, 0o234, 0o17)]);
+        assert_eq!(scenario.keys, vec![(2.0, "V37E63E".to_string())]);
+        assert_eq!(scenario.proceed, vec![30.5]);
+        assert_eq!(
+            scenario.responses,
+            vec![
+                (50, 18, ScenarioAction::Proceed),
+                (6, 33, ScenarioAction::Keys("V34E".to_string()))
+            ]
+        );
+        assert_eq!(scenario.refsmmat, Mat3::IDENTITY);
+        assert!(scenario.armed);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the errors reported for malformed scenarios.
+    ///
+    #[test]
+    fn test_scenario_errors() {
+        let err = |text| Scenario::parse(text).err().map(|x| (x.line, x.kind));
+        assert_eq!(err("position 1 2"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("\nfly 1 2 3"), Some((2, ScenarioErrorKind::UnknownCommand)));
+        assert_eq!(err("position 1 2 3"), Some((0, ScenarioErrorKind::MissingState)));
+        assert_eq!(err("erasable 5 400 0"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("keys 1.0 V37X"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("mass 1 2"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("pro"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("respond 50 18"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("respond 50 100 pro"), Some((1, ScenarioErrorKind::BadArgument)));
+        assert_eq!(err("respond 50 18 V3X"), Some((1, ScenarioErrorKind::BadArgument)));
+    }
+}
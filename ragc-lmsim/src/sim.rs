@@ -0,0 +1,568 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use ragc_core::consts::{io, special};
+use ragc_core::mem::periph::AgcIoPeriph;
+use ragc_periph::dsky_keys::{DskyKeyEvent, DskyKeyboard};
+use ragc_periph::dsky_state::DskyState;
+use ragc_periph::engine::{EngineStage, LmEngine, ThrustEvent};
+use ragc_periph::keycodes;
+use ragc_periph::radar::{LmRadar, RadarSample, RadarSelect};
+use ragc_periph::rcs::{JetEvent, RcsJets, RcsVehicle, RCS_JET_COUNT};
+
+use crate::math::{gimbal_angles, Mat3, Vec3};
+use crate::scenario::{Scenario, ScenarioAction};
+use crate::trajectory::{Trajectory, TrajectoryPoint};
+use crate::vehicle::{Actuators, LmVehicle, VehicleState};
+
+/// Duration of a single MCT, in seconds.
+pub const MCT_SECS: f64 = 11.7 / 1000000.0;
+
+/// Number of MCTs between integration steps of the vehicle (about 1ms).
+pub const SIM_STEP_MCTS: u32 = 85;
+
+// Velocity increment for each LM PIPA pulse, in m/s
+const PIPA_SCALE: f64 = 0.01;
+
+// Number of CDU counts for a full revolution of a gimbal
+const CDU_COUNTS: f64 = 32768.0;
+
+// Crew reaction time before answering a flashing VERB/NOUN, in seconds
+const RESPONSE_SECS: f64 = 1.0;
+
+// Channel 32 PRO key bit, owned by the DSKY keyboard
+const CHAN32_PROCEED: u16 = 0o20000;
+
+// Channel 30 bits (inverted) owned by the simulated IMU and LGC
+const CHAN30_IMU_OPERATE: u16 = 0o00400;
+const CHAN30_LGC_CONTROL: u16 = 0o01000;
+
+// Landing radar scaling. The velocity axes of the antenna are assumed to be
+// aligned with the body axes.
+const LR_RANGE_LOW_SCALE_FT: f64 = 1.079;
+const LR_RANGE_HIGH_SCALE_FT: f64 = 5.395;
+const LR_RANGE_LOW_SCALE_LIMIT_FT: f64 = 2500.0;
+const LR_VEL_SCALE_FT: [f64; 3] = [0.6440, 1.212, 0.8668];
+const LR_VEL_BIAS: f64 = 12288.0;
+const LR_MAX_ALTITUDE_FT: f64 = 50000.0;
+const LR_MIN_BEAM_COS: f64 = 0.5;
+
+const FEET: f64 = 0.3048;
+
+fn secs_to_mcts(secs: f64) -> u64 {
+    (secs / MCT_SECS) as u64
+}
+
+///
+/// ## `action_events` Function
+///
+/// Converts the DSKY input of a scenario line into keyboard events.
+///
+fn action_events(action: &ScenarioAction) -> Vec<DskyKeyEvent> {
+    match action {
+        ScenarioAction::Keys(keys) => keys
+            .chars()
+            .filter_map(keycodes::from_char)
+            .map(DskyKeyEvent::Key)
+            .collect(),
+        ScenarioAction::Proceed => vec![DskyKeyEvent::Proceed(true), DskyKeyEvent::Proceed(false)],
+    }
+}
+
+fn ignore_jet_event(_ev: JetEvent) {}
+fn ignore_thrust_event(_ev: ThrustEvent) {}
+
+type RadarFn = Box<dyn FnMut(RadarSelect, u64) -> RadarSample>;
+
+///
+/// ## `radar_sample` Function
+///
+/// Computes the landing radar measurements from the vehicle state. The
+/// rendezvous radar has no target and never reports good data.
+///
+fn radar_sample(state: &VehicleState, select: RadarSelect) -> RadarSample {
+    let no_data = RadarSample {
+        value: 0,
+        data_good: false,
+        low_scale: false,
+    };
+    if select.is_rendezvous_radar() {
+        return no_data;
+    }
+
+    let altitude_ft = state.altitude() / FEET;
+    let beam = -state.attitude.rows[0];
+    let down = -state.position.unit();
+    let beam_cos = beam.dot(down);
+    if altitude_ft > LR_MAX_ALTITUDE_FT || beam_cos < LR_MIN_BEAM_COS || state.landed {
+        return no_data;
+    }
+
+    let counts = |x: f64| x.round().clamp(0.0, 0o77777 as f64) as u16;
+    match select {
+        RadarSelect::LrRange => {
+            let range_ft = altitude_ft.max(0.0) / beam_cos;
+            let low_scale = range_ft < LR_RANGE_LOW_SCALE_LIMIT_FT;
+            let scale = if low_scale {
+                LR_RANGE_LOW_SCALE_FT
+            } else {
+                LR_RANGE_HIGH_SCALE_FT
+            };
+            RadarSample {
+                value: counts(range_ft / scale),
+                data_good: true,
+                low_scale,
+            }
+        }
+        _ => {
+            let vel = state.attitude * state.velocity;
+            let (axis_vel, scale) = match select {
+                RadarSelect::LrVelX => (vel.x, LR_VEL_SCALE_FT[0]),
+                RadarSelect::LrVelY => (vel.y, LR_VEL_SCALE_FT[1]),
+                _ => (vel.z, LR_VEL_SCALE_FT[2]),
+            };
+            RadarSample {
+                value: counts(LR_VEL_BIAS + axis_vel / FEET / scale),
+                data_good: true,
+                low_scale: false,
+            }
+        }
+    }
+}
+
+///
+/// ## LmSimulator Peripheral
+///
+/// The LmSimulator peripheral closes the loop between the AGC and the LM
+/// vehicle model. Once attached to the memory map it is stepped in lockstep
+/// with the CPU through `pump_mcts`, and:
+///
+///  - Fires the RCS jets commanded on channels 5/6 and the engine commanded
+///    through channel 11 and the THRUST counter.
+///  - Counts the IMU gimbal angles into CDUX/CDUY/CDUZ and the sensed
+///    velocity into PIPAX/PIPAY/PIPAZ.
+///  - Answers landing radar reads through RNRAD.
+///  - Plays back the DSKY keys and PRO presses of the scenario through
+///    channels 15 and 32, and answers the flashing VERB/NOUN displays the
+///    scenario has a `respond` line for.
+///  - Records the trajectory at the scenario record interval.
+///
+pub struct LmSimulator {
+    vehicle: Rc<RefCell<LmVehicle>>,
+    refsmmat: Mat3,
+
+    jets: RcsJets<fn(JetEvent)>,
+    engine: LmEngine<fn(ThrustEvent)>,
+    radar: LmRadar<RadarFn>,
+
+    mcts: u64,
+    step_mcts: u32,
+    cdu_counts: [i32; 3],
+    pipa_residual: Vec3,
+    increments: VecDeque<(usize, i16)>,
+
+    inputs: VecDeque<(u64, DskyKeyEvent)>,
+    keyboard: DskyKeyboard,
+    dsky: DskyState,
+    responses: Vec<(u8, u8, Vec<DskyKeyEvent>)>,
+    flashing: Option<(u8, u8)>,
+
+    trajectory: Trajectory,
+    record_mcts: u64,
+    next_record: u64,
+}
+
+impl LmSimulator {
+    pub fn new(scenario: &Scenario) -> Self {
+        let vehicle = Rc::new(RefCell::new(LmVehicle::new(scenario.initial_state())));
+
+        let radar_vehicle = vehicle.clone();
+        let radar_fn: RadarFn =
+            Box::new(move |select, _mcts| radar_sample(&radar_vehicle.borrow().state, select));
+
+        let mut engine = LmEngine::new(EngineStage::Descent, ignore_thrust_event as fn(ThrustEvent));
+        engine.set_armed(scenario.armed);
+
+        let responses = scenario
+            .responses
+            .iter()
+            .map(|(verb, noun, action)| (*verb, *noun, action_events(action)))
+            .collect();
+
+        let mut sim = LmSimulator {
+            vehicle,
+            refsmmat: scenario.refsmmat,
+            jets: RcsJets::new(RcsVehicle::Lm, ignore_jet_event as fn(JetEvent)),
+            engine,
+            radar: LmRadar::new(radar_fn),
+            mcts: 0,
+            step_mcts: 0,
+            cdu_counts: [0; 3],
+            pipa_residual: Vec3::default(),
+            increments: VecDeque::new(),
+            inputs: VecDeque::new(),
+            keyboard: DskyKeyboard::new(),
+            dsky: DskyState::new(),
+            responses,
+            flashing: None,
+            trajectory: Trajectory::default(),
+            record_mcts: secs_to_mcts(scenario.record_interval).max(1),
+            next_record: 0,
+        };
+
+        for (time, keys) in scenario.keys.iter() {
+            sim.schedule(secs_to_mcts(*time), &action_events(&ScenarioAction::Keys(keys.clone())));
+        }
+        for time in scenario.proceed.iter() {
+            sim.schedule(secs_to_mcts(*time), &action_events(&ScenarioAction::Proceed));
+        }
+        sim
+    }
+
+    ///
+    /// ## `schedule` Function
+    ///
+    /// Queues DSKY input to be typed from the given MCT, after any input
+    /// already scheduled for the same time.
+    ///
+    fn schedule(&mut self, at: u64, events: &[DskyKeyEvent]) {
+        let start = self.inputs.partition_point(|(mcts, _)| *mcts <= at);
+        for (idx, event) in events.iter().enumerate() {
+            self.inputs.insert(start + idx, (at, *event));
+        }
+    }
+
+    ///
+    /// ## `check_responses` Function
+    ///
+    /// Schedules the scenario response to a VERB/NOUN pair which just
+    /// started flashing.
+    ///
+    fn check_responses(&mut self) {
+        let flashing = match (self.dsky.flash, self.dsky.verb_value(), self.dsky.noun_value()) {
+            (true, Some(verb), Some(noun)) => Some((verb, noun)),
+            _ => None,
+        };
+        if flashing == self.flashing {
+            return;
+        }
+
+        self.flashing = flashing;
+        let events = self
+            .responses
+            .iter()
+            .find(|(verb, noun, _)| Some((*verb, *noun)) == flashing)
+            .map(|(_, _, events)| events.clone());
+        if let Some(events) = events {
+            self.schedule(self.mcts + secs_to_mcts(RESPONSE_SECS), &events);
+        }
+    }
+
+    ///
+    /// ## `vehicle` Function
+    ///
+    /// Returns a handle to the vehicle model, which stays accessible while
+    /// the simulator is attached to the memory map.
+    ///
+    pub fn vehicle(&self) -> Rc<RefCell<LmVehicle>> {
+        self.vehicle.clone()
+    }
+
+    pub fn trajectory(&self) -> &Trajectory {
+        &self.trajectory
+    }
+
+    pub fn into_trajectory(self) -> Trajectory {
+        self.trajectory
+    }
+
+    fn jets_mask(&self) -> u16 {
+        (1..=RCS_JET_COUNT as u8)
+            .filter(|jet| self.jets.is_firing(*jet))
+            .fold(0, |mask, jet| mask | 1 << (jet - 1))
+    }
+
+    fn step_vehicle(&mut self, mcts: u32) {
+        let act = Actuators {
+            jets: self.jets_mask(),
+            thrust: self.engine.thrust(),
+            gimbal: self.engine.gimbal(),
+        };
+
+        let mut vehicle = self.vehicle.borrow_mut();
+        let sensed = vehicle.step(mcts as f64 * MCT_SECS, &act);
+
+        // Velocity sensed by the PIPAs on the stable member
+        let dv = self.refsmmat * sensed + self.pipa_residual;
+        let mut residual = [dv.x, dv.y, dv.z];
+        let pipas = [special::SG_PIPAX, special::SG_PIPAY, special::SG_PIPAZ];
+        for (axis, counter) in residual.iter_mut().zip(pipas.iter()) {
+            let pulses = (*axis / PIPA_SCALE).trunc();
+            if pulses != 0.0 {
+                *axis -= pulses * PIPA_SCALE;
+                self.increments.push_back((*counter, pulses as i16));
+            }
+        }
+        self.pipa_residual = Vec3::new(residual[0], residual[1], residual[2]);
+
+        // IMU gimbal angles seen by the CDUs
+        let body_from_sm = vehicle.state.attitude.mul_mat(&self.refsmmat.transpose());
+        let (outer, inner, middle) = gimbal_angles(&body_from_sm);
+        let cdus = [
+            (special::SG_CDUX, outer),
+            (special::SG_CDUY, inner),
+            (special::SG_CDUZ, middle),
+        ];
+        for (idx, (counter, angle)) in cdus.iter().enumerate() {
+            let counts = (angle / (2.0 * core::f64::consts::PI) * CDU_COUNTS).round() as i32;
+            let counts = counts.rem_euclid(CDU_COUNTS as i32);
+            let delta = (counts - self.cdu_counts[idx] + 16384).rem_euclid(32768) - 16384;
+            if delta != 0 {
+                self.increments.push_back((*counter, delta as i16));
+                self.cdu_counts[idx] = counts;
+            }
+        }
+    }
+
+    fn record(&mut self) {
+        let state = self.vehicle.borrow().state;
+        let body_from_sm = state.attitude.mul_mat(&self.refsmmat.transpose());
+        let (outer, inner, middle) = gimbal_angles(&body_from_sm);
+
+        self.trajectory.landed = state.landed;
+        self.trajectory.points.push(TrajectoryPoint {
+            time: self.mcts as f64 * MCT_SECS,
+            position: state.position,
+            velocity: state.velocity,
+            altitude: state.altitude(),
+            cdu: (outer.to_degrees(), inner.to_degrees(), middle.to_degrees()),
+            rates: state.rates * (1.0f64).to_degrees(),
+            mass: state.mass,
+            thrust: self.engine.thrust(),
+            jets: self.jets_mask(),
+        });
+    }
+}
+
+impl AgcIoPeriph for LmSimulator {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_MNKEYIN => self.keyboard.keycode(),
+            io::CHANNEL_CHAN30 => {
+                self.jets.read(channel_idx)
+                    & self.engine.read(channel_idx)
+                    & self.radar.read(channel_idx)
+                    & !(CHAN30_IMU_OPERATE | CHAN30_LGC_CONTROL)
+            }
+            io::CHANNEL_CHAN32 => {
+                self.jets.read(channel_idx)
+                    & self.engine.read(channel_idx)
+                    & self.radar.read(channel_idx)
+                    & (!CHAN32_PROCEED | self.keyboard.proceed_flag())
+            }
+            io::CHANNEL_CHAN31 | io::CHANNEL_CHAN33 => {
+                self.jets.read(channel_idx) & self.engine.read(channel_idx) & self.radar.read(channel_idx)
+            }
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        self.jets.write(channel_idx, value);
+        self.engine.write(channel_idx, value);
+        self.radar.write(channel_idx, value);
+        if self.dsky.update(channel_idx, value) {
+            self.check_responses();
+        }
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        self.radar.is_interrupt() | self.keyboard.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.mcts += mcts as u64;
+        self.jets.pump_mcts(mcts);
+        self.engine.pump_mcts(mcts);
+
+        self.step_mcts += mcts as u32;
+        if self.step_mcts >= SIM_STEP_MCTS {
+            let step = self.step_mcts;
+            self.step_mcts = 0;
+            self.step_vehicle(step);
+        }
+
+        // Radar reads sample the vehicle after it has been stepped
+        self.radar.pump_mcts(mcts);
+
+        while let Some((at, event)) = self.inputs.front().copied() {
+            if at > self.mcts || self.keyboard.press(event).is_err() {
+                break;
+            }
+            self.inputs.pop_front();
+        }
+        self.keyboard.pump_mcts(mcts);
+
+        if self.mcts >= self.next_record {
+            self.next_record += self.record_mcts;
+            self.record();
+        }
+    }
+
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        self.radar.counter_update()
+    }
+
+    fn counter_increment(&mut self) -> Option<(usize, i16)> {
+        self.increments.pop_front()
+    }
+
+    fn write_counter(&mut self, counter_idx: usize, value: u16) {
+        self.engine.write_counter(counter_idx, value);
+    }
+}
+
+#[cfg(test)]
+mod sim_tests {
+    use super::*;
+    use ragc_core::consts::cpu;
+
+    fn scenario(text: &str) -> Scenario {
+        Scenario::parse(text).unwrap()
+    }
+
+    fn pump_secs(sim: &mut LmSimulator, secs: f64) {
+        for _ in 0..(secs / MCT_SECS / 100.0) as usize {
+            sim.pump_mcts(100);
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the CDU counters follow the scenario attitude and that the key
+    /// schedule raises KEYRUPT1 with the proper channel 15 code.
+    ///
+    #[test]
+    fn test_cdu_counts_and_keys() {
+        let mut sim = LmSimulator::new(&scenario(
+            "position 1750000 0 0\n\
+             velocity 0 0 0\n\
+             attitude 90 -45 10\n\
+             keys 0.001 V\n",
+        ));
+        sim.pump_mcts(SIM_STEP_MCTS as u16);
+
+        let mut cdus = [0i32; 3];
+        while let Some((counter, pulses)) = sim.counter_increment() {
+            match counter {
+                special::SG_CDUX => cdus[0] += pulses as i32,
+                special::SG_CDUY => cdus[1] += pulses as i32,
+                special::SG_CDUZ => cdus[2] += pulses as i32,
+                _ => {}
+            }
+        }
+        assert_eq!(cdus, [8192, -4096, 910]);
+
+        assert_eq!(sim.is_interrupt(), 1 << cpu::RUPT_KEY1);
+        assert_eq!(sim.read(io::CHANNEL_MNKEYIN), 0o21);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests PRO is held down on channel 32 from a `pro` line, and that
+    /// flashing VERB/NOUN pairs are answered after the crew reaction time.
+    ///
+    #[test]
+    fn test_proceed_and_responses() {
+        let mut sim = LmSimulator::new(&scenario(
+            "position 1750000 0 0\n\
+             velocity 0 0 0\n\
+             pro 0.001\n\
+             respond 50 18 pro\n\
+             respond 06 33 V\n",
+        ));
+        let proceed = |sim: &LmSimulator| sim.read(io::CHANNEL_CHAN32) & CHAN32_PROCEED == 0;
+
+        pump_secs(&mut sim, 0.01);
+        assert!(proceed(&sim));
+        assert_eq!(sim.is_interrupt(), 0);
+        pump_secs(&mut sim, 0.5);
+        assert!(!proceed(&sim));
+
+        // Flashing V50N18, relay codes of 5 0 and 1 8
+        sim.write(io::CHANNEL_DSKY, (10 << 11) | (30 << 5) | 21);
+        sim.write(io::CHANNEL_DSKY, (9 << 11) | (3 << 5) | 29);
+        sim.write(io::CHANNEL_DSALMOUT, 0o00040);
+        pump_secs(&mut sim, 0.9);
+        assert!(!proceed(&sim));
+        pump_secs(&mut sim, 0.2);
+        assert!(proceed(&sim));
+        pump_secs(&mut sim, 1.0);
+        assert!(!proceed(&sim));
+
+        // Flashing V06N33, relay codes of 0 6 and 3 3
+        sim.write(io::CHANNEL_DSALMOUT, 0);
+        sim.write(io::CHANNEL_DSKY, (10 << 11) | (21 << 5) | 28);
+        sim.write(io::CHANNEL_DSKY, (9 << 11) | (27 << 5) | 27);
+        sim.write(io::CHANNEL_DSALMOUT, 0o00040);
+        pump_secs(&mut sim, 1.1);
+        assert_eq!(sim.is_interrupt(), 1 << cpu::RUPT_KEY1);
+        assert_eq!(sim.read(io::CHANNEL_MNKEYIN), keycodes::KEY_VERB);
+        assert!(!proceed(&sim));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the engine thrust is sensed by the PIPAs along the stable member
+    /// axis aligned with the vehicle X axis.
+    ///
+    #[test]
+    fn test_pipa_pulses() {
+        let mut sim = LmSimulator::new(&scenario(
+            "position 1750000 0 0\n\
+             velocity 0 0 0\n\
+             mass 4670.6\n",
+        ));
+        sim.write(io::CHANNEL_DSALMOUT, 0o10000);
+
+        // One second at minimum throttle for a vehicle accelerating at 1 m/s^2
+        let mut pipax = 0;
+        for _ in 0..(1.0 / MCT_SECS / 100.0) as usize {
+            sim.pump_mcts(100);
+            while let Some((counter, pulses)) = sim.counter_increment() {
+                if counter == special::SG_PIPAX {
+                    pipax += pulses as i32;
+                }
+            }
+        }
+        assert!((99..=101).contains(&pipax), "Unexpected PIPAX count {}", pipax);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the landing radar altitude read while descending vertically.
+    ///
+    #[test]
+    fn test_landing_radar_range() {
+        let mut sim = LmSimulator::new(&scenario(
+            "position 1738400 0 0\n\
+             velocity 0 0 0\n",
+        ));
+
+        sim.write(io::CHANNEL_CHAN13, 0o00017);
+        for _ in 0..100 {
+            sim.pump_mcts(100);
+        }
+        let (counter, value) = sim.counter_update().unwrap();
+        assert_eq!(counter, special::SG_RNRAD);
+
+        // 1000m is 3281ft, which is reported on the high scale
+        assert_eq!(value, (1000.0 / FEET / LR_RANGE_HIGH_SCALE_FT).round() as u16);
+        assert_eq!(sim.read(io::CHANNEL_CHAN33) & 0o00020, 0, "LR range data not good");
+    }
+}
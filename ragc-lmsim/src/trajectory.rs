@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use crate::math::Vec3;
+
+///
+/// ## `TrajectoryPoint` Structure
+///
+/// Sample of the simulated flight.
+///
+///  - `time` - Seconds since the start of the flight
+///  - `position`, `velocity` - Moon centered inertial frame, in m and m/s
+///  - `altitude` - Height above the landing site radius, in m
+///  - `cdu` - IMU gimbal angles (outer, inner, middle), in degrees
+///  - `rates` - Body angular rates, in deg/s
+///  - `thrust` - Main engine thrust, in newtons
+///  - `jets` - Bit mask of the firing RCS jets (bit 0 is jet 1)
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryPoint {
+    pub time: f64,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub altitude: f64,
+    pub cdu: (f64, f64, f64),
+    pub rates: Vec3,
+    pub mass: f64,
+    pub thrust: f64,
+    pub jets: u16,
+}
+
+///
+/// ## `Trajectory` Structure
+///
+/// Recording of a simulated flight, sampled at the scenario record interval.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trajectory {
+    pub points: Vec<TrajectoryPoint>,
+    pub landed: bool,
+}
+
+impl Trajectory {
+    pub fn last(&self) -> Option<&TrajectoryPoint> {
+        self.points.last()
+    }
+
+    ///
+    /// ## `write_csv` Function
+    ///
+    /// Writes the trajectory as CSV with a header line.
+    ///
+    pub fn write_csv<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "time,x,y,z,vx,vy,vz,altitude,cdux,cduy,cduz,p,q,r,mass,thrust,jets"
+        )?;
+        for p in self.points.iter() {
+            writeln!(
+                w,
+                "{:.3},{:.1},{:.1},{:.1},{:.3},{:.3},{:.3},{:.1},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.1},{:.1},{:o}",
+                p.time,
+                p.position.x,
+                p.position.y,
+                p.position.z,
+                p.velocity.x,
+                p.velocity.y,
+                p.velocity.z,
+                p.altitude,
+                p.cdu.0,
+                p.cdu.1,
+                p.cdu.2,
+                p.rates.x,
+                p.rates.y,
+                p.rates.z,
+                p.mass,
+                p.thrust,
+                p.jets
+            )?;
+        }
+        Ok(())
+    }
+}
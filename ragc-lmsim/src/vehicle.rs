@@ -0,0 +1,271 @@
+use crate::math::{Mat3, Vec3};
+
+/// Gravitational parameter of the Moon, in m^3/s^2.
+pub const MOON_MU: f64 = 4.9028e12;
+
+/// Mean radius of the Moon, in meters. The landing site is assumed to be at
+/// this radius.
+pub const MOON_RADIUS: f64 = 1737400.0;
+
+/// Thrust of a single RCS jet, in newtons (100 lbf).
+pub const RCS_JET_THRUST: f64 = 445.0;
+
+const G0: f64 = 9.80665;
+const RCS_ISP: f64 = 290.0;
+const DPS_ISP: f64 = 305.0;
+
+// Distance of the RCS quads from the X axis along both Y and Z, in meters
+const QUAD_ARM: f64 = 1.68;
+
+// Distance of the descent engine gimbal point below the center of mass, in
+// meters
+const GIMBAL_ARM: f64 = 2.0;
+
+// RCS quad positions relative to the center of mass
+const QUAD1: Vec3 = Vec3::new(0.0, -QUAD_ARM, QUAD_ARM);
+const QUAD2: Vec3 = Vec3::new(0.0, -QUAD_ARM, -QUAD_ARM);
+const QUAD3: Vec3 = Vec3::new(0.0, QUAD_ARM, -QUAD_ARM);
+const QUAD4: Vec3 = Vec3::new(0.0, QUAD_ARM, QUAD_ARM);
+
+const PLUME_UP: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+const PLUME_DOWN: Vec3 = Vec3::new(-1.0, 0.0, 0.0);
+const PLUME_RIGHT: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+const PLUME_LEFT: Vec3 = Vec3::new(0.0, -1.0, 0.0);
+const PLUME_FORWARD: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+const PLUME_AFT: Vec3 = Vec3::new(0.0, 0.0, -1.0);
+
+// Plume direction and position of the LM jets 1-16, named after the
+// direction their plume points to. The force on the vehicle is opposite to
+// the plume.
+const LM_JETS: [(Vec3, Vec3); 16] = [
+    (PLUME_UP, QUAD4),      // 1  B4U
+    (PLUME_DOWN, QUAD4),    // 2  A4D
+    (PLUME_FORWARD, QUAD4), // 3  B4F
+    (PLUME_RIGHT, QUAD4),   // 4  A4R
+    (PLUME_UP, QUAD3),      // 5  A3U
+    (PLUME_DOWN, QUAD3),    // 6  B3D
+    (PLUME_AFT, QUAD3),     // 7  B3A
+    (PLUME_RIGHT, QUAD3),   // 8  A3R
+    (PLUME_UP, QUAD2),      // 9  B2U
+    (PLUME_DOWN, QUAD2),    // 10 A2D
+    (PLUME_AFT, QUAD2),     // 11 A2A
+    (PLUME_LEFT, QUAD2),    // 12 B2L
+    (PLUME_UP, QUAD1),      // 13 A1U
+    (PLUME_DOWN, QUAD1),    // 14 B1D
+    (PLUME_FORWARD, QUAD1), // 15 A1F
+    (PLUME_LEFT, QUAD1),    // 16 B1L
+];
+
+///
+/// ## `VehicleState` Structure
+///
+/// State of the LM rigid body.
+///
+///  - `position`, `velocity` - Moon centered inertial frame, in m and m/s
+///  - `attitude` - Converts inertial vectors into body (navigation base)
+///    coordinates
+///  - `rates` - Body angular rates, in rad/s
+///  - `mass` - Vehicle mass, in kg
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VehicleState {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub attitude: Mat3,
+    pub rates: Vec3,
+    pub mass: f64,
+    pub landed: bool,
+}
+
+impl VehicleState {
+    pub fn altitude(&self) -> f64 {
+        self.position.norm() - MOON_RADIUS
+    }
+}
+
+///
+/// ## `Actuators` Structure
+///
+/// Commands applied to the vehicle during an integration step.
+///
+///  - `jets` - Bit mask of the firing RCS jets (bit 0 is jet 1)
+///  - `thrust` - Main engine thrust, in newtons
+///  - `gimbal` - Descent engine gimbal angles (pitch, roll), in degrees
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Actuators {
+    pub jets: u16,
+    pub thrust: f64,
+    pub gimbal: (f64, f64),
+}
+
+///
+/// ## `LmVehicle` Structure
+///
+/// Six degrees of freedom model of the LM in lunar gravity. The center of
+/// mass is kept on the X axis and the inertia tensor is diagonal, which is
+/// enough to close the loop with the DAP and guidance but is not a flight
+/// accurate mass properties model.
+///
+pub struct LmVehicle {
+    pub state: VehicleState,
+    inertia: Vec3,
+}
+
+impl LmVehicle {
+    pub fn new(state: VehicleState) -> Self {
+        LmVehicle {
+            state,
+            // Moments of inertia of a fully fueled LM, in kg m^2
+            inertia: Vec3::new(32000.0, 45000.0, 45000.0),
+        }
+    }
+
+    fn forces(&self, act: &Actuators) -> (Vec3, Vec3, f64) {
+        let mut force = Vec3::default();
+        let mut torque = Vec3::default();
+        let mut flow = 0.0;
+
+        for (idx, (plume, pos)) in LM_JETS.iter().enumerate() {
+            if act.jets & (1 << idx) != 0 {
+                let f = -*plume * RCS_JET_THRUST;
+                force += f;
+                torque += pos.cross(f);
+                flow += RCS_JET_THRUST / (RCS_ISP * G0);
+            }
+        }
+
+        if act.thrust > 0.0 {
+            let (pitch, roll) = (act.gimbal.0.to_radians(), act.gimbal.1.to_radians());
+            let f = Vec3::new(1.0, roll.sin(), -pitch.sin()).unit() * act.thrust;
+            force += f;
+            torque += Vec3::new(-GIMBAL_ARM, 0.0, 0.0).cross(f);
+            flow += act.thrust / (DPS_ISP * G0);
+        }
+
+        (force, torque, flow)
+    }
+
+    ///
+    /// ## `step` Function
+    ///
+    /// Integrates the vehicle state by `dt` seconds with the given actuator
+    /// commands.
+    ///
+    /// ### Result
+    ///
+    ///  - `Vec3` - Velocity change due to non gravitational forces in the
+    ///    inertial frame, as sensed by the PIPAs.
+    ///
+    pub fn step(&mut self, dt: f64, act: &Actuators) -> Vec3 {
+        let (force_body, torque, flow) = self.forces(act);
+        let s = &mut self.state;
+        if s.landed {
+            return Vec3::default();
+        }
+
+        // Translational motion
+        let sensed = s.attitude.transpose() * force_body * (dt / s.mass);
+        let r = s.position.norm();
+        let gravity = s.position * (-MOON_MU / (r * r * r));
+        s.velocity += sensed + gravity * dt;
+        s.position += s.velocity * dt;
+        s.mass = (s.mass - flow * dt).max(1.0);
+
+        // Rotational motion using Euler's equations
+        let w = s.rates;
+        let i = self.inertia;
+        let iw = Vec3::new(i.x * w.x, i.y * w.y, i.z * w.z);
+        let gyro = torque - w.cross(iw);
+        s.rates += Vec3::new(gyro.x / i.x, gyro.y / i.y, gyro.z / i.z) * dt;
+
+        let w = s.rates * dt;
+        let delta = Mat3::from_rows([
+            Vec3::new(1.0, w.z, -w.y),
+            Vec3::new(-w.z, 1.0, w.x),
+            Vec3::new(w.y, -w.x, 1.0),
+        ]);
+        s.attitude = delta.mul_mat(&s.attitude).orthonormalize();
+
+        if s.altitude() <= 0.0 {
+            s.landed = true;
+            s.velocity = Vec3::default();
+            s.rates = Vec3::default();
+        }
+
+        sensed
+    }
+}
+
+#[cfg(test)]
+mod vehicle_tests {
+    use super::*;
+
+    fn hovering_state() -> VehicleState {
+        VehicleState {
+            position: Vec3::new(MOON_RADIUS + 1000.0, 0.0, 0.0),
+            velocity: Vec3::default(),
+            attitude: Mat3::IDENTITY,
+            rates: Vec3::default(),
+            mass: 8000.0,
+            landed: false,
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that an unpowered vehicle falls with lunar gravity and senses
+    /// no acceleration.
+    ///
+    #[test]
+    fn test_free_fall() {
+        let mut lm = LmVehicle::new(hovering_state());
+        let mut sensed = Vec3::default();
+        for _ in 0..1000 {
+            sensed += lm.step(0.001, &Actuators::default());
+        }
+
+        let g = MOON_MU / (MOON_RADIUS + 1000.0).powi(2);
+        assert!((lm.state.velocity.x + g).abs() < 1e-3);
+        assert_eq!(sensed, Vec3::default());
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that a pair of opposing jets on opposite quads produces a roll
+    /// rate without any translation.
+    ///
+    #[test]
+    fn test_jet_couple() {
+        let mut lm = LmVehicle::new(hovering_state());
+        // B4F and A2A are a pure couple about X
+        let act = Actuators {
+            jets: (1 << 2) | (1 << 10),
+            ..Default::default()
+        };
+        let sensed = lm.step(1.0, &act);
+
+        assert!(sensed.norm() < 1e-9, "Couple produced translation");
+        assert!(lm.state.rates.x.abs() > 0.0);
+        assert!(lm.state.rates.y.abs() < 1e-12 && lm.state.rates.z.abs() < 1e-12);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the vehicle stops at the surface.
+    ///
+    #[test]
+    fn test_touchdown() {
+        let mut state = hovering_state();
+        state.velocity = Vec3::new(-100.0, 0.0, 0.0);
+        let mut lm = LmVehicle::new(state);
+        for _ in 0..20 {
+            lm.step(1.0, &Actuators::default());
+        }
+        assert!(lm.state.landed);
+        assert_eq!(lm.state.velocity, Vec3::default());
+    }
+}
@@ -0,0 +1,597 @@
+use core::fmt;
+
+use ragc_core::consts::io;
+
+/// Number of words of a complete downlist, including the ID and sync words.
+pub const DOWNLIST_WORDS: usize = 100;
+
+/// Sync word sent along with the list ID at the start of each downlist.
+pub const DOWNLIST_SYNC_WORD: u16 = 0o77340;
+
+// Channel 13 downlink telemetry word order code bit. The bit is clear while
+// the ID and sync words are sent and set for the rest of the list.
+const CHAN13_WORD_ORDER: u16 = 0o00100;
+
+// Scale factors, converting a fraction into SI units
+const SCALE_B29_M: f64 = 536870912.0;
+const SCALE_B24_M: f64 = 16777216.0;
+const SCALE_B7_M_CS: f64 = 128.0 * 100.0;
+const SCALE_B28_CS: f64 = 268435456.0 / 100.0;
+const SCALE_B1: f64 = 2.0;
+
+///
+/// ## `FieldFormat` Enum
+///
+/// Encoding of a downlist field.
+///
+///  - `Octal` - Single word shown as is (flag words, IDs, channels)
+///  - `Sp` - Single precision fraction, times the scale factor
+///  - `Dp` - Double precision fraction (two words), times the scale factor
+///  - `Cdu` - CDU angle, in degrees
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldFormat {
+    Octal,
+    Sp(f64),
+    Dp(f64),
+    Cdu,
+}
+
+///
+/// ## `FieldValue` Enum
+///
+/// Decoded value of a downlist field.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue {
+    Octal(u16),
+    Scaled(f64),
+}
+
+///
+/// ## `DownlistField` Structure
+///
+/// Named field found at `index` words from the start of a downlist.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownlistField {
+    pub name: &'static str,
+    pub index: usize,
+    pub format: FieldFormat,
+}
+
+const fn octal(name: &'static str, index: usize) -> DownlistField {
+    DownlistField {
+        name,
+        index,
+        format: FieldFormat::Octal,
+    }
+}
+
+const fn sp(name: &'static str, index: usize, scale: f64) -> DownlistField {
+    DownlistField {
+        name,
+        index,
+        format: FieldFormat::Sp(scale),
+    }
+}
+
+const fn dp(name: &'static str, index: usize, scale: f64) -> DownlistField {
+    DownlistField {
+        name,
+        index,
+        format: FieldFormat::Dp(scale),
+    }
+}
+
+const fn cdu(name: &'static str, index: usize) -> DownlistField {
+    DownlistField {
+        name,
+        index,
+        format: FieldFormat::Cdu,
+    }
+}
+
+///
+/// ## `ones_complement` Function
+///
+/// Converts a 15-bit ones' complement word into a signed value.
+///
+fn ones_complement(word: u16) -> i32 {
+    let word = word & 0o77777;
+    if word & 0o40000 != 0 {
+        -((!word & 0o77777) as i32)
+    } else {
+        word as i32
+    }
+}
+
+impl DownlistField {
+    ///
+    /// ## `decode` Function
+    ///
+    /// Decodes the field from the words of a downlist.
+    ///
+    pub fn decode(&self, words: &[u16; DOWNLIST_WORDS]) -> FieldValue {
+        let word = words[self.index];
+        match self.format {
+            FieldFormat::Octal => FieldValue::Octal(word),
+            FieldFormat::Sp(scale) => {
+                FieldValue::Scaled(ones_complement(word) as f64 / 16384.0 * scale)
+            }
+            FieldFormat::Dp(scale) => {
+                let lo = words[self.index + 1];
+                let value = ones_complement(word) as f64 * 16384.0 + ones_complement(lo) as f64;
+                FieldValue::Scaled(value / 268435456.0 * scale)
+            }
+            FieldFormat::Cdu => FieldValue::Scaled((word & 0o77777) as f64 * 360.0 / 32768.0),
+        }
+    }
+}
+
+// Example layouts. They are not transcribed from the flight software word
+// maps: each list shares the ID, sync, state vector, time and flag word
+// fields below, followed by a few fields of interest from word 40, so the
+// positions and scalings only illustrate the decoding.
+const COMMON_FIELDS: [DownlistField; 26] = [
+    octal("ID", 0),
+    octal("SYNC", 1),
+    dp("RN_X", 2, SCALE_B29_M),
+    dp("RN_Y", 4, SCALE_B29_M),
+    dp("RN_Z", 6, SCALE_B29_M),
+    dp("VN_X", 8, SCALE_B7_M_CS),
+    dp("VN_Y", 10, SCALE_B7_M_CS),
+    dp("VN_Z", 12, SCALE_B7_M_CS),
+    dp("PIPTIME", 14, SCALE_B28_CS),
+    dp("TIME2", 16, SCALE_B28_CS),
+    cdu("CDUX", 18),
+    cdu("CDUY", 19),
+    cdu("CDUZ", 20),
+    octal("DSPTAB11", 21),
+    octal("FLAGWRD0", 22),
+    octal("FLAGWRD1", 23),
+    octal("FLAGWRD2", 24),
+    octal("FLAGWRD3", 25),
+    octal("FLAGWRD4", 26),
+    octal("FLAGWRD5", 27),
+    octal("FLAGWRD6", 28),
+    octal("FLAGWRD7", 29),
+    octal("FLAGWRD8", 30),
+    octal("FLAGWRD9", 31),
+    octal("FLAGWRD10", 32),
+    octal("FLAGWRD11", 33),
+];
+
+const REFSMMAT_FIELDS: [DownlistField; 9] = [
+    dp("REFSMMAT_0", 40, SCALE_B1),
+    dp("REFSMMAT_1", 42, SCALE_B1),
+    dp("REFSMMAT_2", 44, SCALE_B1),
+    dp("REFSMMAT_3", 46, SCALE_B1),
+    dp("REFSMMAT_4", 48, SCALE_B1),
+    dp("REFSMMAT_5", 50, SCALE_B1),
+    dp("REFSMMAT_6", 52, SCALE_B1),
+    dp("REFSMMAT_7", 54, SCALE_B1),
+    dp("REFSMMAT_8", 56, SCALE_B1),
+];
+
+const MANEUVER_FIELDS: [DownlistField; 5] = [
+    dp("VGTIG_X", 40, SCALE_B7_M_CS),
+    dp("VGTIG_Y", 42, SCALE_B7_M_CS),
+    dp("VGTIG_Z", 44, SCALE_B7_M_CS),
+    dp("TIG", 46, SCALE_B28_CS),
+    octal("CADRFLSH", 48),
+];
+
+const RENDEZVOUS_FIELDS: [DownlistField; 5] = [
+    dp("R-OTHER_X", 40, SCALE_B29_M),
+    dp("R-OTHER_Y", 42, SCALE_B29_M),
+    dp("R-OTHER_Z", 44, SCALE_B29_M),
+    dp("TPASS4", 46, SCALE_B28_CS),
+    octal("TRKMKCNT", 48),
+];
+
+const LM_DESCENT_FIELDS: [DownlistField; 6] = [
+    dp("HCALC", 40, SCALE_B24_M),
+    dp("TTF/8", 42, SCALE_B28_CS),
+    dp("VGU_X", 44, SCALE_B7_M_CS),
+    dp("VGU_Y", 46, SCALE_B7_M_CS),
+    dp("VGU_Z", 48, SCALE_B7_M_CS),
+    sp("THRUST", 50, 1.0),
+];
+
+const LM_AGS_FIELDS: [DownlistField; 2] = [dp("AGSK", 40, SCALE_B28_CS), octal("AGSBUFF", 42)];
+
+const CM_ENTRY_FIELDS: [DownlistField; 3] = [
+    dp("RTGO", 40, SCALE_B29_M),
+    dp("VIO", 42, SCALE_B7_M_CS),
+    sp("ROLLC", 44, 180.0),
+];
+
+///
+/// ## `DownlistVehicle` Enum
+///
+/// Vehicle whose example downlist layouts are used for decoding. The LM and
+/// CM flight software reuse the same list IDs for different lists.
+///
+/// The layouts only name the lists after their IDs: the word positions and
+/// scalings of their fields are examples, and do not follow the word maps of
+/// any flight software release.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownlistVehicle {
+    Lm,
+    Cm,
+}
+
+///
+/// ## `DownlistLayout` Structure
+///
+/// Named fields of the downlist identified by `id`. Only the fields listed
+/// are decoded, the remaining words are left out.
+///
+#[derive(Debug)]
+pub struct DownlistLayout {
+    pub id: u16,
+    pub name: &'static str,
+    sections: &'static [&'static [DownlistField]],
+}
+
+impl DownlistLayout {
+    pub fn fields(&self) -> impl Iterator<Item = &'static DownlistField> {
+        self.sections.iter().flat_map(|x| x.iter())
+    }
+}
+
+const LM_LAYOUTS: [DownlistLayout; 6] = [
+    DownlistLayout {
+        id: 0o77777,
+        name: "LM Orbital Maneuvers",
+        sections: &[&COMMON_FIELDS, &MANEUVER_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77776,
+        name: "LM Coast and Align",
+        sections: &[&COMMON_FIELDS, &REFSMMAT_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77775,
+        name: "LM Rendezvous and Prethrust",
+        sections: &[&COMMON_FIELDS, &RENDEZVOUS_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77774,
+        name: "LM AGS Initialization and Update",
+        sections: &[&COMMON_FIELDS, &LM_AGS_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77773,
+        name: "LM Descent and Ascent",
+        sections: &[&COMMON_FIELDS, &LM_DESCENT_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77772,
+        name: "LM Lunar Surface Align",
+        sections: &[&COMMON_FIELDS, &REFSMMAT_FIELDS],
+    },
+];
+
+const CM_LAYOUTS: [DownlistLayout; 5] = [
+    DownlistLayout {
+        id: 0o77777,
+        name: "CM Powered",
+        sections: &[&COMMON_FIELDS, &MANEUVER_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77776,
+        name: "CM Coast and Align",
+        sections: &[&COMMON_FIELDS, &REFSMMAT_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77775,
+        name: "CM Rendezvous and Prethrust",
+        sections: &[&COMMON_FIELDS, &RENDEZVOUS_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77774,
+        name: "CM Entry and Update",
+        sections: &[&COMMON_FIELDS, &CM_ENTRY_FIELDS],
+    },
+    DownlistLayout {
+        id: 0o77773,
+        name: "CM Program 22",
+        sections: &[&COMMON_FIELDS, &RENDEZVOUS_FIELDS],
+    },
+];
+
+impl DownlistVehicle {
+    pub fn layouts(&self) -> &'static [DownlistLayout] {
+        match self {
+            DownlistVehicle::Lm => &LM_LAYOUTS,
+            DownlistVehicle::Cm => &CM_LAYOUTS,
+        }
+    }
+
+    pub fn layout(&self, id: u16) -> Option<&'static DownlistLayout> {
+        self.layouts().iter().find(|x| x.id == id)
+    }
+}
+
+///
+/// ## `Downlist` Structure
+///
+/// Complete downlist, starting with the list ID and the sync word.
+///
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Downlist {
+    pub words: [u16; DOWNLIST_WORDS],
+}
+
+impl fmt::Debug for Downlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Downlist({:05o})", self.id())
+    }
+}
+
+impl Downlist {
+    pub fn id(&self) -> u16 {
+        self.words[0]
+    }
+
+    ///
+    /// ## `decode` Function
+    ///
+    /// Decodes the named fields of the list with the example layouts of the
+    /// given vehicle.
+    ///
+    /// ### Result
+    ///
+    ///  - `None` if the list ID is not known for the vehicle
+    ///
+    pub fn decode(
+        &self,
+        vehicle: DownlistVehicle,
+    ) -> Option<impl Iterator<Item = (&'static str, FieldValue)> + '_> {
+        let layout = vehicle.layout(self.id())?;
+        Some(layout.fields().map(move |x| (x.name, x.decode(&self.words))))
+    }
+
+    ///
+    /// ## `json` Function
+    ///
+    /// Returns a value which formats the list as a single line JSON object.
+    /// Octal fields are written as strings of octal digits, and lists with an
+    /// unknown ID are written with their raw words.
+    ///
+    pub fn json(&self, vehicle: DownlistVehicle) -> DownlistJson<'_> {
+        DownlistJson { list: self, vehicle }
+    }
+}
+
+///
+/// ## `DownlistJson` Structure
+///
+/// JSON line formatting of a `Downlist`, see `Downlist::json`.
+///
+pub struct DownlistJson<'a> {
+    list: &'a Downlist,
+    vehicle: DownlistVehicle,
+}
+
+impl fmt::Display for DownlistJson<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"id\":\"{:05o}\"", self.list.id())?;
+        match self.vehicle.layout(self.list.id()) {
+            Some(layout) => {
+                write!(f, ",\"list\":\"{}\"", layout.name)?;
+                for field in layout.fields() {
+                    match field.decode(&self.list.words) {
+                        FieldValue::Octal(x) => write!(f, ",\"{}\":\"{:05o}\"", field.name, x)?,
+                        FieldValue::Scaled(x) => write!(f, ",\"{}\":{}", field.name, x)?,
+                    }
+                }
+            }
+            None => {
+                write!(f, ",\"list\":null,\"words\":[")?;
+                for (idx, word) in self.list.words.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "\"{:05o}\"", word)?;
+                }
+                write!(f, "]")?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+///
+/// ## `DownlistAssembler` Structure
+///
+/// Reassembles complete downlists from the DOWNRUPT words written to
+/// channels 34 and 35. A new list starts with the word pair sent while the
+/// channel 13 word order code bit is clear. Pairs received before the start
+/// of a list are dropped, and a list interrupted by a new ID is discarded.
+///
+pub struct DownlistAssembler {
+    word_order: bool,
+    first: Option<u16>,
+    words: [u16; DOWNLIST_WORDS],
+    len: usize,
+}
+
+impl Default for DownlistAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DownlistAssembler {
+    pub fn new() -> Self {
+        DownlistAssembler {
+            word_order: false,
+            first: None,
+            words: [0; DOWNLIST_WORDS],
+            len: 0,
+        }
+    }
+
+    ///
+    /// ## `write` Function
+    ///
+    /// Handles an AGC channel write. Channel 13 writes update the word order
+    /// code, while channel 34 and 35 writes carry the downlist words.
+    ///
+    /// ### Result
+    ///
+    ///  - `Some(Downlist)` once the last word pair of a list is written
+    ///
+    pub fn write(&mut self, channel_idx: usize, value: u16) -> Option<Downlist> {
+        match channel_idx {
+            io::CHANNEL_CHAN13 => {
+                self.word_order = value & CHAN13_WORD_ORDER != 0;
+                None
+            }
+            io::CHANNEL_CHAN34 => {
+                self.first = Some(value & 0o77777);
+                None
+            }
+            io::CHANNEL_CHAN35 => {
+                let first = self.first.take()?;
+                self.push_pair(first, value & 0o77777)
+            }
+            _ => None,
+        }
+    }
+
+    fn push_pair(&mut self, first: u16, second: u16) -> Option<Downlist> {
+        if !self.word_order {
+            self.words[0] = first;
+            self.words[1] = second;
+            self.len = 2;
+            return None;
+        }
+
+        if self.len == 0 {
+            return None;
+        }
+
+        self.words[self.len] = first;
+        self.words[self.len + 1] = second;
+        self.len += 2;
+        if self.len == DOWNLIST_WORDS {
+            self.len = 0;
+            Some(Downlist { words: self.words })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod downlist_tests {
+    extern crate std;
+
+    use super::*;
+    use std::format;
+
+    fn send_list(asm: &mut DownlistAssembler, words: &[u16; DOWNLIST_WORDS]) -> Option<Downlist> {
+        let mut res = None;
+        for (idx, pair) in words.chunks_exact(2).enumerate() {
+            let order = if idx == 0 { 0 } else { CHAN13_WORD_ORDER };
+            asm.write(io::CHANNEL_CHAN13, order);
+            asm.write(io::CHANNEL_CHAN34, pair[0]);
+            res = asm.write(io::CHANNEL_CHAN35, pair[1]);
+        }
+        res
+    }
+
+    fn descent_list() -> [u16; DOWNLIST_WORDS] {
+        let mut words = [0; DOWNLIST_WORDS];
+        words[0] = 0o77773;
+        words[1] = DOWNLIST_SYNC_WORD;
+        // RN_X = 0.5 of B29 meters
+        words[2] = 0o20000;
+        // VN_X = -0.25 of B7 m/cs
+        words[8] = !0o10000 & 0o77777;
+        words[9] = 0o77777;
+        words[18] = 0o20000;
+        words[22] = 0o12345;
+        words
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests a list is only complete once all its word pairs are sent after
+    /// the ID, and that pairs sent before the ID are dropped.
+    ///
+    #[test]
+    fn test_downlist_assembly() {
+        let mut asm = DownlistAssembler::new();
+
+        // Tail of a list started before the decoder was attached
+        asm.write(io::CHANNEL_CHAN13, CHAN13_WORD_ORDER);
+        asm.write(io::CHANNEL_CHAN34, 1);
+        assert_eq!(asm.write(io::CHANNEL_CHAN35, 2), None);
+
+        let words = descent_list();
+        let list = send_list(&mut asm, &words).unwrap();
+        assert_eq!(list.id(), 0o77773);
+        assert_eq!(list.words, words);
+
+        // A new ID in the middle of a list restarts the assembly
+        asm.write(io::CHANNEL_CHAN13, 0);
+        asm.write(io::CHANNEL_CHAN34, 0o77776);
+        asm.write(io::CHANNEL_CHAN35, DOWNLIST_SYNC_WORD);
+        assert_eq!(send_list(&mut asm, &words), Some(list));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests decoding the fields of an LM descent list.
+    ///
+    #[test]
+    fn test_downlist_decode() {
+        let list = Downlist {
+            words: descent_list(),
+        };
+        let fields: std::vec::Vec<_> = list.decode(DownlistVehicle::Lm).unwrap().collect();
+        let get = |name| fields.iter().find(|x| x.0 == name).unwrap().1;
+
+        assert_eq!(get("ID"), FieldValue::Octal(0o77773));
+        assert_eq!(get("RN_X"), FieldValue::Scaled(0.5 * SCALE_B29_M));
+        match get("VN_X") {
+            FieldValue::Scaled(x) => assert!((x + 0.25 * SCALE_B7_M_CS).abs() < 1.0, "{}", x),
+            x => panic!("Unexpected VN_X {:?}", x),
+        }
+        assert_eq!(get("CDUX"), FieldValue::Scaled(90.0));
+        assert_eq!(get("FLAGWRD0"), FieldValue::Octal(0o12345));
+        assert!(fields.iter().any(|x| x.0 == "HCALC"));
+
+        assert_eq!(DownlistVehicle::Cm.layout(0o77773).unwrap().name, "CM Program 22");
+        assert!(list.decode(DownlistVehicle::Cm).is_some());
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the JSON line output of known and unknown lists.
+    ///
+    #[test]
+    fn test_downlist_json() {
+        let mut list = Downlist {
+            words: descent_list(),
+        };
+        let line = format!("{}", list.json(DownlistVehicle::Lm));
+        assert!(line.starts_with("{\"id\":\"77773\",\"list\":\"LM Descent and Ascent\",\"ID\":\"77773\""));
+        assert!(line.contains(",\"CDUX\":90,"));
+        assert!(line.ends_with('}'));
+        assert!(!line.contains('\n'));
+
+        list.words[0] = 0o00001;
+        let line = format!("{}", list.json(DownlistVehicle::Lm));
+        assert!(line.starts_with("{\"id\":\"00001\",\"list\":null,\"words\":[\"00001\",\"77340\","));
+    }
+}
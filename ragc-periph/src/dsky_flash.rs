@@ -0,0 +1,169 @@
+/// Number of MCTs of a full DSKY flash cycle (about 1 second).
+pub const FLASH_PERIOD_MCTS: u32 = 1000000000 / 11700;
+
+/// Number of MCTs of a flash cycle with the flashing displays and lamps lit
+/// (about 750ms), the rest of the cycle being the off phase.
+pub const FLASH_ON_MCTS: u32 = 750000000 / 11700;
+
+// Channel 163 bits blinked by the flash: KEY REL and OPR ERR lamps, and the
+// VERB/NOUN off phase
+const CHAN163_KEY_REL: u16 = 0o00020;
+const CHAN163_FLASH: u16 = 0o00040;
+const CHAN163_OPR_ERR: u16 = 0o00100;
+
+///
+/// ## `DskyFlash` Structure
+///
+/// Flashes the DSKY from emulated time, so the display only depends on the
+/// MCTs the AGC has run through and stays deterministic when the emulation
+/// is paused, single-stepped or fast-forwarded.
+///
+/// The commanded channel 163 lamps are given with `set_lamps`, where bit 6
+/// requests the VERB and NOUN flash (mirrored from channel 11). During the
+/// off phase of each cycle the KEY REL and OPR ERR lamps are turned off, and
+/// bit 6 is set to blank VERB and NOUN if they flash. Bit 6 is cleared during
+/// the on phase.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DskyFlash {
+    lamps: u16,
+    cycle_mcts: u32,
+    shown: Option<u16>,
+}
+
+impl DskyFlash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// ## `set_lamps` Function
+    ///
+    /// Sets the commanded channel 163 lamps.
+    ///
+    pub fn set_lamps(&mut self, value: u16) {
+        self.lamps = value;
+    }
+
+    ///
+    /// ## `pump_mcts` Function
+    ///
+    /// Moves the flash cycle forward by the elapsed MCTs.
+    ///
+    pub fn pump_mcts(&mut self, mcts: u16) {
+        self.cycle_mcts = (self.cycle_mcts + mcts as u32) % FLASH_PERIOD_MCTS;
+    }
+
+    ///
+    /// ## `off_phase` Function
+    ///
+    /// Returns whether the flash cycle is in its off phase.
+    ///
+    pub fn off_phase(&self) -> bool {
+        self.cycle_mcts >= FLASH_ON_MCTS
+    }
+
+    ///
+    /// ## `flash_off` Function
+    ///
+    /// Returns whether VERB and NOUN are flashing and currently blanked.
+    ///
+    pub fn flash_off(&self) -> bool {
+        self.off_phase() && self.lamps & CHAN163_FLASH != 0
+    }
+
+    ///
+    /// ## `displayed` Function
+    ///
+    /// Returns the channel 163 value to show at this point of the cycle.
+    ///
+    pub fn displayed(&self) -> u16 {
+        if self.off_phase() {
+            (self.lamps & !(CHAN163_KEY_REL | CHAN163_OPR_ERR | CHAN163_FLASH))
+                | (self.lamps & CHAN163_FLASH)
+        } else {
+            self.lamps & !CHAN163_FLASH
+        }
+    }
+
+    ///
+    /// ## `update` Function
+    ///
+    /// Returns the channel 163 value to send to the display if it differs
+    /// from the last value returned.
+    ///
+    pub fn update(&mut self) -> Option<u16> {
+        let value = self.displayed();
+        if self.shown == Some(value) {
+            None
+        } else {
+            self.shown = Some(value);
+            Some(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod dsky_flash_tests {
+    use super::*;
+
+    fn pump(flash: &mut DskyFlash, mcts: u32) {
+        for _i in 0..mcts {
+            flash.pump_mcts(1);
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the VERB/NOUN flash and the KEY REL blink follow the elapsed
+    /// MCTs, and values are only reported when they change.
+    ///
+    #[test]
+    fn test_dsky_flash_cycle() {
+        let mut flash = DskyFlash::new();
+        assert_eq!(flash.update(), Some(0o00000));
+        assert_eq!(flash.update(), None);
+
+        // KEY REL lit, VERB/NOUN flashing and the RESTART lamp
+        flash.set_lamps(CHAN163_KEY_REL | CHAN163_FLASH | 0o00200);
+        assert_eq!(flash.update(), Some(CHAN163_KEY_REL | 0o00200));
+        assert!(!flash.flash_off());
+
+        pump(&mut flash, FLASH_ON_MCTS - 1);
+        assert_eq!(flash.update(), None);
+        pump(&mut flash, 1);
+        assert!(flash.off_phase() && flash.flash_off());
+        assert_eq!(flash.update(), Some(CHAN163_FLASH | 0o00200));
+
+        pump(&mut flash, FLASH_PERIOD_MCTS - FLASH_ON_MCTS);
+        assert!(!flash.off_phase());
+        assert_eq!(flash.update(), Some(CHAN163_KEY_REL | 0o00200));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the flash does not depend on how the MCTs are pumped, and that
+    /// VERB and NOUN are not blanked when they do not flash.
+    ///
+    #[test]
+    fn test_dsky_flash_deterministic() {
+        let mut single = DskyFlash::new();
+        let mut bulk = DskyFlash::new();
+        single.set_lamps(CHAN163_OPR_ERR);
+        bulk.set_lamps(CHAN163_OPR_ERR);
+
+        pump(&mut single, 3 * FLASH_PERIOD_MCTS + FLASH_ON_MCTS);
+        let mut remaining = 3 * FLASH_PERIOD_MCTS + FLASH_ON_MCTS;
+        while remaining > 0 {
+            let mcts = remaining.min(0o7777);
+            bulk.pump_mcts(mcts as u16);
+            remaining -= mcts;
+        }
+
+        assert_eq!(single, bulk);
+        assert!(single.off_phase() && !single.flash_off());
+        assert_eq!(single.displayed(), 0o00000);
+    }
+}
@@ -0,0 +1,266 @@
+use ragc_core::consts::cpu;
+
+use crate::headless_dsky::KeyError;
+
+/// Number of key events that can be queued on a `DskyKeyboard`.
+pub const DSKY_KEY_QUEUE_LEN: usize = 16;
+
+/// Default number of MCTs a key is held down (about 250ms). PRO is sensed by
+/// the flight software every T4RUPT, so it must stay down longer than that.
+pub const KEY_DOWN_MCTS: u32 = 250000000 / 11700;
+
+/// Default number of MCTs between the release of a key and the next key
+/// (about 100ms).
+pub const KEY_UP_MCTS: u32 = 100000000 / 11700;
+
+// Channel 32 PRO key bit, cleared while the key is pressed
+const CHAN32_PROCEED: u16 = 0o20000;
+
+// Flag set by `keycodes::from_packet` on PRO key packets
+const KEYPRESS_PROCEED: u16 = 0o40000;
+
+///
+/// ## `DskyKeyEvent` Enum
+///
+/// Input from the DSKY keyboard.
+///
+///  - `Key` - A key sensed on channel 15, see `keycodes`
+///  - `Proceed` - The PRO key is pressed (`true`) or released (`false`)
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DskyKeyEvent {
+    Key(u16),
+    Proceed(bool),
+}
+
+impl DskyKeyEvent {
+    ///
+    /// ## `from_keypress` Function
+    ///
+    /// Converts a keypress returned by `keycodes::from_packet`, where PRO key
+    /// packets have bit 15 set.
+    ///
+    pub fn from_keypress(value: u16) -> Self {
+        if value & KEYPRESS_PROCEED != 0 {
+            DskyKeyEvent::Proceed(value & CHAN32_PROCEED == 0)
+        } else {
+            DskyKeyEvent::Key(value & 0o37)
+        }
+    }
+}
+
+///
+/// ## `DskyKeyboard` Structure
+///
+/// Bounded queue of DSKY key events played back with realistic timing, so
+/// keys typed or scripted faster than the AGC reads them are not lost.
+///
+/// Each key is held down for `key_down` MCTs and followed by `key_up` MCTs
+/// before the next event. A key sets channel 15 and raises one KEYRUPT1 when
+/// pressed, and channel 15 keeps its code until the next key. PRO does not
+/// raise any interrupt: it clears channel 32 bit 14 from its press until its
+/// release, which comes at least `key_down` MCTs later.
+///
+pub struct DskyKeyboard {
+    queue: [DskyKeyEvent; DSKY_KEY_QUEUE_LEN],
+    head: usize,
+    len: usize,
+
+    key_down: u32,
+    key_up: u32,
+    wait: u32,
+
+    keycode: u16,
+    proceed: bool,
+    rupt: bool,
+}
+
+impl Default for DskyKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DskyKeyboard {
+    pub fn new() -> Self {
+        DskyKeyboard {
+            queue: [DskyKeyEvent::Key(0); DSKY_KEY_QUEUE_LEN],
+            head: 0,
+            len: 0,
+            key_down: KEY_DOWN_MCTS,
+            key_up: KEY_UP_MCTS,
+            wait: 0,
+            keycode: 0,
+            proceed: false,
+            rupt: false,
+        }
+    }
+
+    ///
+    /// ## `set_timing` Function
+    ///
+    /// Sets the number of MCTs keys are held down, and the number of MCTs
+    /// between the release of a key and the next one.
+    ///
+    pub fn set_timing(&mut self, key_down: u32, key_up: u32) {
+        self.key_down = key_down;
+        self.key_up = key_up;
+    }
+
+    ///
+    /// ## `pending` Function
+    ///
+    /// Returns the number of events not played back yet.
+    ///
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    ///
+    /// ## `press` Function
+    ///
+    /// Queues a key event.
+    ///
+    pub fn press(&mut self, event: DskyKeyEvent) -> Result<(), KeyError> {
+        if self.len == DSKY_KEY_QUEUE_LEN {
+            return Err(KeyError::QueueFull);
+        }
+        self.queue[(self.head + self.len) % DSKY_KEY_QUEUE_LEN] = event;
+        self.len += 1;
+        Ok(())
+    }
+
+    ///
+    /// ## `keycode` Function
+    ///
+    /// Returns the channel 15 keycode.
+    ///
+    pub fn keycode(&self) -> u16 {
+        self.keycode
+    }
+
+    ///
+    /// ## `proceed_flag` Function
+    ///
+    /// Returns channel 32 bit 14, cleared while PRO is pressed.
+    ///
+    pub fn proceed_flag(&self) -> u16 {
+        if self.proceed {
+            0o00000
+        } else {
+            CHAN32_PROCEED
+        }
+    }
+
+    ///
+    /// ## `is_interrupt` Function
+    ///
+    /// Returns the KEYRUPT1 bit once for each key pressed.
+    ///
+    pub fn is_interrupt(&mut self) -> u16 {
+        if self.rupt {
+            self.rupt = false;
+            1 << cpu::RUPT_KEY1
+        } else {
+            0
+        }
+    }
+
+    ///
+    /// ## `pump_mcts` Function
+    ///
+    /// Moves the playback forward by the elapsed MCTs, and returns the event
+    /// played back if any.
+    ///
+    pub fn pump_mcts(&mut self, mcts: u16) -> Option<DskyKeyEvent> {
+        self.wait = self.wait.saturating_sub(mcts as u32);
+        if self.wait > 0 || self.len == 0 {
+            return None;
+        }
+
+        let event = self.queue[self.head];
+        self.head = (self.head + 1) % DSKY_KEY_QUEUE_LEN;
+        self.len -= 1;
+        match event {
+            DskyKeyEvent::Key(code) => {
+                self.keycode = code;
+                self.rupt = true;
+                self.wait = self.key_down + self.key_up;
+            }
+            DskyKeyEvent::Proceed(true) => {
+                self.proceed = true;
+                self.wait = self.key_down;
+            }
+            DskyKeyEvent::Proceed(false) => {
+                self.proceed = false;
+                self.wait = self.key_up;
+            }
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod dsky_keys_tests {
+    use super::*;
+    use crate::keycodes;
+
+    ///
+    /// # Description
+    ///
+    /// Tests keys typed at once are each delivered with their own KEYRUPT1,
+    /// spaced by the key down and key up times.
+    ///
+    #[test]
+    fn test_dsky_keyboard_keys() {
+        let mut keys = DskyKeyboard::new();
+        keys.set_timing(10, 5);
+        for code in [keycodes::KEY_VERB, 3, 7, keycodes::KEY_ENTR].iter() {
+            keys.press(DskyKeyEvent::Key(*code)).unwrap();
+        }
+
+        let mut delivered = [(0, 0); 4];
+        let mut count = 0;
+        for mct in 0..100 {
+            keys.pump_mcts(1);
+            if keys.is_interrupt() != 0 {
+                delivered[count] = (mct, keys.keycode());
+                count += 1;
+            }
+            assert_eq!(keys.is_interrupt(), 0);
+        }
+        assert_eq!(
+            delivered,
+            [(0, keycodes::KEY_VERB), (15, 3), (30, 7), (45, keycodes::KEY_ENTR)]
+        );
+        assert_eq!(keys.keycode(), keycodes::KEY_ENTR);
+
+        for _i in 0..DSKY_KEY_QUEUE_LEN {
+            keys.press(DskyKeyEvent::Key(1)).unwrap();
+        }
+        assert_eq!(keys.press(DskyKeyEvent::Key(1)), Err(KeyError::QueueFull));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests PRO is held down on channel 32 for at least the key down time
+    /// without raising KEYRUPT1, even when released right away.
+    ///
+    #[test]
+    fn test_dsky_keyboard_proceed() {
+        let mut keys = DskyKeyboard::new();
+        keys.set_timing(10, 5);
+        keys.press(DskyKeyEvent::from_keypress(0o40000)).unwrap();
+        keys.press(DskyKeyEvent::from_keypress(0o60000)).unwrap();
+        assert_eq!(keys.proceed_flag(), 0o20000);
+
+        assert_eq!(keys.pump_mcts(1), Some(DskyKeyEvent::Proceed(true)));
+        assert_eq!(keys.proceed_flag(), 0o00000);
+        assert_eq!(keys.pump_mcts(9), None);
+        assert_eq!(keys.proceed_flag(), 0o00000);
+        assert_eq!(keys.pump_mcts(1), Some(DskyKeyEvent::Proceed(false)));
+        assert_eq!(keys.proceed_flag(), 0o20000);
+        assert_eq!(keys.is_interrupt(), 0);
+    }
+}
@@ -0,0 +1,488 @@
+use core::cell::Cell;
+use core::str::Lines;
+
+use ragc_core::mem::periph::AgcIoPeriph;
+
+use crate::dsky_state::DskyState;
+use crate::headless_dsky::HeadlessDsky;
+use crate::keycodes;
+
+/// Number of MCTs in one second of emulated time.
+pub const MCTS_PER_SECOND: f64 = 1000000.0 / 11.7;
+
+// Time a `wait_display` waits for the display when no timeout is given, in
+// seconds
+const DEFAULT_TIMEOUT: f64 = 10.0;
+
+// Display fields checked by `wait_display`, in the order of `DisplayValues`
+const DISPLAY_FIELDS: [&str; 6] = ["PROG", "VERB", "NOUN", "R1", "R2", "R3"];
+
+type DisplayValues = [Option<i32>; 6];
+
+fn secs_to_mcts(secs: f64) -> u64 {
+    (secs * MCTS_PER_SECOND) as u64
+}
+
+fn display_values(state: &DskyState) -> DisplayValues {
+    [
+        state.prog_value().map(i32::from),
+        state.verb_value().map(i32::from),
+        state.noun_value().map(i32::from),
+        state.registers[0].value(),
+        state.registers[1].value(),
+        state.registers[2].value(),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+    UnknownCommand,
+    BadArgument,
+    UnknownLamp,
+}
+
+///
+/// ## `ScriptError` Structure
+///
+/// Error found while parsing a DSKY script. `line` starts at 1.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+}
+
+///
+/// ## `ScriptStep` Enum
+///
+/// One line of a DSKY script.
+///
+///  - `Keys` - DSKY keys entered one after the other, such as `V37E 63E`
+///  - `Proceed` - The PRO key is pressed and released
+///  - `Wait` - Emulated time is left to run for the given number of MCTs
+///  - `WaitDisplay` - Waits up to `timeout` MCTs for the PROG, VERB, NOUN,
+///    R1, R2 and R3 fields which are not `None` to show the given values
+///  - `ExpectLamp` - The lamp must be lit (`lit`) or off right away
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptStep<'a> {
+    Keys(&'a str),
+    Proceed,
+    Wait(u64),
+    WaitDisplay { fields: DisplayValues, timeout: u64 },
+    ExpectLamp { name: &'a str, lit: bool },
+}
+
+fn parse_secs(arg: &str) -> Result<u64, ScriptErrorKind> {
+    match arg.trim().parse::<f64>() {
+        Ok(secs) if secs >= 0.0 => Ok(secs_to_mcts(secs)),
+        _ => Err(ScriptErrorKind::BadArgument),
+    }
+}
+
+fn parse_wait_display(args: &str) -> Result<ScriptStep<'_>, ScriptErrorKind> {
+    let mut fields = [None; 6];
+    let mut timeout = secs_to_mcts(DEFAULT_TIMEOUT);
+    for arg in args.split(',') {
+        let mut parts = arg.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().ok_or(ScriptErrorKind::BadArgument)?.trim();
+        if name.eq_ignore_ascii_case("timeout") {
+            timeout = parse_secs(value)?;
+            continue;
+        }
+
+        let idx = DISPLAY_FIELDS
+            .iter()
+            .position(|x| x.eq_ignore_ascii_case(name))
+            .ok_or(ScriptErrorKind::BadArgument)?;
+        fields[idx] = Some(value.parse::<i32>().map_err(|_| ScriptErrorKind::BadArgument)?);
+    }
+
+    if fields.iter().all(|x| x.is_none()) {
+        return Err(ScriptErrorKind::BadArgument);
+    }
+    Ok(ScriptStep::WaitDisplay { fields, timeout })
+}
+
+fn parse_expect_lamp(args: &str) -> Result<ScriptStep<'_>, ScriptErrorKind> {
+    let mut args = args.split(',').map(|x| x.trim());
+    let name = args.next().unwrap_or("");
+    DskyState::new()
+        .lamps
+        .get(name)
+        .ok_or(ScriptErrorKind::UnknownLamp)?;
+    let lit = match args.next() {
+        None | Some("on") => true,
+        Some("off") => false,
+        _ => return Err(ScriptErrorKind::BadArgument),
+    };
+    if args.next().is_some() {
+        return Err(ScriptErrorKind::BadArgument);
+    }
+    Ok(ScriptStep::ExpectLamp { name, lit })
+}
+
+///
+/// ## `parse_step` Function
+///
+/// Parses one line of a DSKY script, returning `None` for blank and comment
+/// lines.
+///
+fn parse_step(line: &str) -> Result<Option<ScriptStep<'_>>, ScriptErrorKind> {
+    let content = line.split('#').next().unwrap_or("").trim();
+    if content.is_empty() {
+        return Ok(None);
+    }
+    if content == "PRO" {
+        return Ok(Some(ScriptStep::Proceed));
+    }
+
+    if let Some(open) = content.find('(') {
+        let args = content[open + 1..]
+            .strip_suffix(')')
+            .ok_or(ScriptErrorKind::BadArgument)?;
+        let step = match content[..open].trim() {
+            "wait" => ScriptStep::Wait(parse_secs(args)?),
+            "wait_display" => parse_wait_display(args)?,
+            "expect_lamp" => parse_expect_lamp(args)?,
+            _ => return Err(ScriptErrorKind::UnknownCommand),
+        };
+        return Ok(Some(step));
+    }
+
+    let keys = content
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .all(|c| keycodes::from_char(c).is_some());
+    if keys {
+        Ok(Some(ScriptStep::Keys(content)))
+    } else {
+        Err(ScriptErrorKind::UnknownCommand)
+    }
+}
+
+///
+/// ## `DskyScript` Structure
+///
+/// A DSKY script, checked when parsed. Each line holds one step, and anything
+/// after a `#` is a comment.
+///
+/// ```text
+/// V37E 63E                            # keys, see `keycodes::from_char`
+/// wait(2.5)                           # let 2.5 seconds of emulated time run
+/// wait_display(VERB=06, NOUN=63)      # PROG, VERB, NOUN, R1, R2 and R3
+/// wait_display(R1=-00042, timeout=30) # waits up to 10 seconds by default
+/// PRO                                 # presses and releases PRO
+/// expect_lamp(PROG)                   # the lamp must be lit
+/// expect_lamp(KEY REL, off)           # the lamp must be off
+/// ```
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DskyScript<'a> {
+    text: &'a str,
+}
+
+impl<'a> DskyScript<'a> {
+    pub fn parse(text: &'a str) -> Result<DskyScript<'a>, ScriptError> {
+        for (idx, line) in text.lines().enumerate() {
+            parse_step(line).map_err(|kind| ScriptError {
+                line: idx + 1,
+                kind,
+            })?;
+        }
+        Ok(DskyScript { text })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptFailureKind {
+    DisplayTimeout,
+    LampMismatch,
+}
+
+///
+/// ## `ScriptStatus` Enum
+///
+/// Progress of a script run by a `ScriptedDsky`. A failure holds the script
+/// line of the failed step, starting at 1.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptStatus {
+    Running,
+    Passed,
+    Failed { line: usize, kind: ScriptFailureKind },
+}
+
+///
+/// ## ScriptedDsky Peripheral
+///
+/// The ScriptedDsky peripheral is a `HeadlessDsky` entering the keys of a
+/// `DskyScript` and checking its display assertions as emulated time goes
+/// by. Keys are delivered through channel 15 and KEYRUPT1, and PRO through
+/// channel 32, with the key timing of the `HeadlessDsky`: a key or PRO step
+/// completes once its last key has been played back. The script progress is
+/// published to a `Cell` so the caller can stop stepping the CPU once the
+/// script has passed or failed.
+///
+pub struct ScriptedDsky<'a> {
+    dsky: HeadlessDsky,
+    lines: Lines<'a>,
+    line: usize,
+    status: &'a Cell<ScriptStatus>,
+
+    step: Option<ScriptStep<'a>>,
+    deadline: Option<u64>,
+    until: u64,
+    mcts: u64,
+}
+
+impl<'a> ScriptedDsky<'a> {
+    pub fn new(script: &DskyScript<'a>, status: &'a Cell<ScriptStatus>) -> Self {
+        status.set(ScriptStatus::Running);
+        ScriptedDsky {
+            dsky: HeadlessDsky::new(),
+            lines: script.text.lines(),
+            line: 0,
+            status,
+            step: None,
+            deadline: None,
+            until: 0,
+            mcts: 0,
+        }
+    }
+
+    ///
+    /// ## `state` Function
+    ///
+    /// Returns what is currently shown on the DSKY.
+    ///
+    pub fn state(&self) -> &DskyState {
+        self.dsky.state()
+    }
+
+    ///
+    /// ## `mcts` Function
+    ///
+    /// Returns the emulated time elapsed since the script started, in MCTs.
+    ///
+    pub fn mcts(&self) -> u64 {
+        self.mcts
+    }
+
+    fn next_step(&mut self) -> Option<ScriptStep<'a>> {
+        for line in &mut self.lines {
+            self.line += 1;
+            if let Ok(Some(step)) = parse_step(line) {
+                return Some(step);
+            }
+        }
+        None
+    }
+
+    fn fail(&mut self, kind: ScriptFailureKind) -> Option<ScriptStep<'a>> {
+        self.status.set(ScriptStatus::Failed {
+            line: self.line,
+            kind,
+        });
+        None
+    }
+
+    ///
+    /// ## `execute` Function
+    ///
+    /// Carries on with a step, returning the step again while it has not
+    /// completed.
+    ///
+    fn execute(&mut self, step: ScriptStep<'a>) -> Option<ScriptStep<'a>> {
+        match step {
+            ScriptStep::Keys(keys) => {
+                // Queue as many keys as fit, then wait for the playback
+                let mut keys = keys.trim_start();
+                while let Some(key) = keys.chars().next() {
+                    let code = keycodes::from_char(key)?;
+                    if self.dsky.press_key(code).is_err() {
+                        break;
+                    }
+                    keys = keys[key.len_utf8()..].trim_start();
+                }
+                if keys.is_empty() && self.dsky.pending_keys() == 0 {
+                    None
+                } else {
+                    Some(ScriptStep::Keys(keys))
+                }
+            }
+            ScriptStep::Proceed => {
+                // Once queued, wait for the playback as for any key
+                if self.dsky.press_proceed().is_ok() {
+                    Some(ScriptStep::Keys(""))
+                } else {
+                    Some(step)
+                }
+            }
+            ScriptStep::Wait(mcts) => {
+                self.until = self.mcts + mcts;
+                None
+            }
+            ScriptStep::WaitDisplay { fields, timeout } => {
+                let values = display_values(self.dsky.state());
+                let matched = fields
+                    .iter()
+                    .zip(values.iter())
+                    .all(|(field, value)| field.is_none() || field == value);
+                let deadline = *self.deadline.get_or_insert(self.mcts + timeout);
+                if matched {
+                    self.deadline = None;
+                    None
+                } else if self.mcts >= deadline {
+                    self.fail(ScriptFailureKind::DisplayTimeout)
+                } else {
+                    Some(step)
+                }
+            }
+            ScriptStep::ExpectLamp { name, lit } => {
+                if self.dsky.state().lit_lamps().get(name) == Some(lit) {
+                    None
+                } else {
+                    self.fail(ScriptFailureKind::LampMismatch)
+                }
+            }
+        }
+    }
+
+    fn run(&mut self) {
+        while self.status.get() == ScriptStatus::Running && self.mcts >= self.until {
+            let step = match self.step.take().or_else(|| self.next_step()) {
+                Some(x) => x,
+                None => {
+                    self.status.set(ScriptStatus::Passed);
+                    return;
+                }
+            };
+            self.step = self.execute(step);
+            if self.step.is_some() {
+                return;
+            }
+        }
+    }
+}
+
+impl<'a> AgcIoPeriph for ScriptedDsky<'a> {
+    fn read(&self, channel_idx: usize) -> u16 {
+        self.dsky.read(channel_idx)
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        self.dsky.write(channel_idx, value);
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        self.dsky.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.dsky.pump_mcts(mcts);
+        self.mcts += mcts as u64;
+        self.run();
+    }
+}
+
+#[cfg(test)]
+mod dsky_script_tests {
+    use super::*;
+    use crate::dsky_keys::{KEY_DOWN_MCTS, KEY_UP_MCTS};
+    use ragc_core::consts::{cpu, io};
+
+    ///
+    /// # Description
+    ///
+    /// Tests the parsing of each step and the reported error lines.
+    ///
+    #[test]
+    fn test_dsky_script_parse() {
+        assert_eq!(parse_step("  # comment"), Ok(None));
+        assert_eq!(parse_step("V37E 63E"), Ok(Some(ScriptStep::Keys("V37E 63E"))));
+        assert_eq!(parse_step("PRO # proceed"), Ok(Some(ScriptStep::Proceed)));
+        assert_eq!(parse_step("wait(1)"), Ok(Some(ScriptStep::Wait(85470))));
+        assert_eq!(
+            parse_step("wait_display(VERB=06, r1=-00042, timeout=0)"),
+            Ok(Some(ScriptStep::WaitDisplay {
+                fields: [None, Some(6), None, Some(-42), None, None],
+                timeout: 0,
+            }))
+        );
+        assert_eq!(
+            parse_step("expect_lamp(KEY REL, off)"),
+            Ok(Some(ScriptStep::ExpectLamp {
+                name: "KEY REL",
+                lit: false,
+            }))
+        );
+        assert_eq!(parse_step("expect_lamp(FOO)"), Err(ScriptErrorKind::UnknownLamp));
+        assert_eq!(parse_step("wait_display(timeout=2)"), Err(ScriptErrorKind::BadArgument));
+        assert_eq!(parse_step("wait(-1)"), Err(ScriptErrorKind::BadArgument));
+        assert_eq!(parse_step("V37X"), Err(ScriptErrorKind::UnknownCommand));
+
+        assert_eq!(
+            DskyScript::parse("V37E\n\nbeep()\n"),
+            Err(ScriptError {
+                line: 3,
+                kind: ScriptErrorKind::UnknownCommand,
+            })
+        );
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests a script against a stand-in for the AGC answering V37E with
+    /// VERB 37 and PRO with the PROG lamp: keys must come with a KEYRUPT1
+    /// each, spaced out in emulated time, and the assertions must pass or
+    /// fail on the right line.
+    ///
+    #[test]
+    fn test_scripted_dsky() {
+        let script = DskyScript::parse(
+            "V37E\n\
+             wait_display(VERB=37, timeout=1)\n\
+             PRO\n\
+             expect_lamp(PROG)\n\
+             wait_display(NOUN=63, timeout=1)\n",
+        )
+        .unwrap();
+        let status = Cell::new(ScriptStatus::Passed);
+        let mut dsky = ScriptedDsky::new(&script, &status);
+        assert_eq!(status.get(), ScriptStatus::Running);
+
+        let mut keys = [0; 4];
+        let mut count = 0;
+        let mut last_key = 0;
+        while status.get() == ScriptStatus::Running {
+            dsky.pump_mcts(1);
+            if dsky.is_interrupt() == 1 << cpu::RUPT_KEY1 {
+                let spacing = (KEY_DOWN_MCTS + KEY_UP_MCTS) as u64;
+                assert!(count == 0 || dsky.mcts() - last_key >= spacing);
+                keys[count] = dsky.read(io::CHANNEL_MNKEYIN);
+                count += 1;
+                last_key = dsky.mcts();
+                if count == 4 {
+                    // VERB 37
+                    dsky.write(io::CHANNEL_DSKY, (10 << 11) | (27 << 5) | 19);
+                }
+            }
+            if dsky.read(io::CHANNEL_CHAN32) & 0o20000 == 0 {
+                dsky.write(io::CHANNEL_DSKY, (12 << 11) | 0o400);
+            }
+        }
+
+        assert_eq!(keys, [keycodes::KEY_VERB, 3, 7, keycodes::KEY_ENTR]);
+        assert!(dsky.state().lamps.prog);
+        assert_eq!(
+            status.get(),
+            ScriptStatus::Failed {
+                line: 5,
+                kind: ScriptFailureKind::DisplayTimeout,
+            }
+        );
+    }
+}
@@ -0,0 +1,536 @@
+use core::fmt;
+
+use ragc_core::consts::io;
+
+use crate::utils::get_digit;
+
+/// Number of digits of each DSKY data register.
+pub const DSKY_REGISTER_DIGITS: usize = 5;
+
+// Relay row holding the sign of a register in bit 11
+const ROW_SIGN_BIT: u16 = 0o02000;
+
+// Channel 11 lamps
+const CHAN11_ISS_WARNING: u16 = 0o00001;
+const CHAN11_COMP_ACTY: u16 = 0o00002;
+const CHAN11_UPLINK_ACTY: u16 = 0o00004;
+const CHAN11_TEMP: u16 = 0o00010;
+const CHAN11_KEY_REL: u16 = 0o00020;
+const CHAN11_FLASH: u16 = 0o00040;
+const CHAN11_OPR_ERR: u16 = 0o00100;
+
+// Channel 13 lamp test, lighting every lamp
+const CHAN13_LAMP_TEST: u16 = 0o01000;
+
+// Channel 163 lamps
+const CHAN163_AGC_WARNING: u16 = 0o00001;
+const CHAN163_TEMP: u16 = 0o00010;
+const CHAN163_KEY_REL: u16 = 0o00020;
+const CHAN163_FLASH_OFF: u16 = 0o00040;
+const CHAN163_OPR_ERR: u16 = 0o00100;
+const CHAN163_RESTART: u16 = 0o00200;
+const CHAN163_STBY: u16 = 0o00400;
+const CHAN163_DISPLAY_OFF: u16 = 0o01000;
+
+// Relay row 12 lamps
+const ROW12_PRIO_DISP: u16 = 0o00001;
+const ROW12_NO_DAP: u16 = 0o00002;
+const ROW12_VEL: u16 = 0o00004;
+const ROW12_NO_ATT: u16 = 0o00010;
+const ROW12_ALT: u16 = 0o00020;
+const ROW12_GIMBAL_LOCK: u16 = 0o00040;
+const ROW12_TRACKER: u16 = 0o00200;
+const ROW12_PROG: u16 = 0o00400;
+
+// Lamp bits of channel 11, relay row 12 and channel 163 lit by the lamp test
+const LAMP_TEST_CHAN11: u16 = CHAN11_ISS_WARNING
+    | CHAN11_COMP_ACTY
+    | CHAN11_UPLINK_ACTY
+    | CHAN11_TEMP
+    | CHAN11_KEY_REL
+    | CHAN11_OPR_ERR;
+const LAMP_TEST_ROW12: u16 = ROW12_PRIO_DISP
+    | ROW12_NO_DAP
+    | ROW12_VEL
+    | ROW12_NO_ATT
+    | ROW12_ALT
+    | ROW12_GIMBAL_LOCK
+    | ROW12_TRACKER
+    | ROW12_PROG;
+const LAMP_TEST_CHAN163: u16 = CHAN163_AGC_WARNING
+    | CHAN163_TEMP
+    | CHAN163_KEY_REL
+    | CHAN163_OPR_ERR
+    | CHAN163_RESTART
+    | CHAN163_STBY;
+
+///
+/// ## `lamp_test_value` Function
+///
+/// Returns the value to send to a display for a channel 10, 11 or 163 write
+/// while the lamp test is on, with every lamp bit of the channel set. Relay
+/// rows other than row 12 and other channels are returned unchanged.
+///
+pub fn lamp_test_value(channel_idx: usize, value: u16) -> u16 {
+    match channel_idx {
+        io::CHANNEL_DSKY if (value >> 11) & 0o17 == 12 => value | LAMP_TEST_ROW12,
+        io::CHANNEL_DSALMOUT => value | LAMP_TEST_CHAN11,
+        0o163 => value | LAMP_TEST_CHAN163,
+        _ => value,
+    }
+}
+
+///
+/// ## `DskyRegister` Structure
+///
+/// One of the R1, R2 and R3 data registers.
+///
+///  - `plus` / `minus` - State of the sign segments. Octal values are shown
+///    without any sign.
+///  - `digits` - Digits from left to right, `None` when blank
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DskyRegister {
+    pub plus: bool,
+    pub minus: bool,
+    pub digits: [Option<u8>; DSKY_REGISTER_DIGITS],
+}
+
+impl DskyRegister {
+    ///
+    /// ## `value` Function
+    ///
+    /// Returns the displayed decimal value, negative when only the minus sign
+    /// is lit, or `None` if any digit is blank.
+    ///
+    pub fn value(&self) -> Option<i32> {
+        let mut value = 0;
+        for digit in self.digits.iter() {
+            value = value * 10 + (*digit)? as i32;
+        }
+        if self.minus && !self.plus {
+            Some(-value)
+        } else {
+            Some(value)
+        }
+    }
+}
+
+impl fmt::Display for DskyRegister {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match (self.plus, self.minus) {
+            (true, false) => '+',
+            (false, true) => '-',
+            _ => ' ',
+        };
+        write!(f, "{}", sign)?;
+        for digit in self.digits.iter() {
+            match digit {
+                Some(x) => write!(f, "{}", x)?,
+                None => write!(f, " ")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// ## `DskyLamps` Structure
+///
+/// State of each status and caution lamp of the DSKY, along with the ISS
+/// and AGC warnings shown on the caution and warning panel.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DskyLamps {
+    pub comp_acty: bool,
+    pub uplink_acty: bool,
+    pub temp: bool,
+    pub key_rel: bool,
+    pub opr_err: bool,
+    pub restart: bool,
+    pub stby: bool,
+    pub no_att: bool,
+    pub gimbal_lock: bool,
+    pub prog: bool,
+    pub tracker: bool,
+    pub alt: bool,
+    pub vel: bool,
+    pub prio_disp: bool,
+    pub no_dap: bool,
+    pub iss_warning: bool,
+    pub agc_warning: bool,
+}
+
+impl DskyLamps {
+    /// Labels of the lamps, as accepted by `get`.
+    pub const NAMES: [&'static str; 17] = [
+        "COMP ACTY",
+        "UPLINK ACTY",
+        "TEMP",
+        "KEY REL",
+        "OPR ERR",
+        "RESTART",
+        "STBY",
+        "NO ATT",
+        "GIMBAL LOCK",
+        "PROG",
+        "TRACKER",
+        "ALT",
+        "VEL",
+        "PRIO DISP",
+        "NO DAP",
+        "ISS WARNING",
+        "AGC WARNING",
+    ];
+
+    ///
+    /// ## `all_lit` Function
+    ///
+    /// Returns every lamp lit, as shown by the lamp test.
+    ///
+    pub fn all_lit() -> Self {
+        DskyLamps {
+            comp_acty: true,
+            uplink_acty: true,
+            temp: true,
+            key_rel: true,
+            opr_err: true,
+            restart: true,
+            stby: true,
+            no_att: true,
+            gimbal_lock: true,
+            prog: true,
+            tracker: true,
+            alt: true,
+            vel: true,
+            prio_disp: true,
+            no_dap: true,
+            iss_warning: true,
+            agc_warning: true,
+        }
+    }
+
+    fn lamps(&self) -> [bool; 17] {
+        [
+            self.comp_acty,
+            self.uplink_acty,
+            self.temp,
+            self.key_rel,
+            self.opr_err,
+            self.restart,
+            self.stby,
+            self.no_att,
+            self.gimbal_lock,
+            self.prog,
+            self.tracker,
+            self.alt,
+            self.vel,
+            self.prio_disp,
+            self.no_dap,
+            self.iss_warning,
+            self.agc_warning,
+        ]
+    }
+
+    ///
+    /// ## `get` Function
+    ///
+    /// Returns the state of a lamp from its label on the DSKY, such as
+    /// `KEY REL`. The label is not case sensitive and underscores can be
+    /// used in place of spaces.
+    ///
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let idx = Self::NAMES.iter().position(|label| {
+            label.len() == name.len()
+                && label.bytes().zip(name.bytes()).all(|(a, b)| match b {
+                    b'_' => a == b' ',
+                    _ => a == b.to_ascii_uppercase(),
+                })
+        })?;
+        Some(self.lamps()[idx])
+    }
+}
+
+///
+/// ## `DskyState` Structure
+///
+/// What the astronaut sees on the DSKY, decoded from the channel 10 relay
+/// rows, the channel 11 lamps, the channel 13 lamp test and the yaAGC
+/// channel 163 lamps.
+///
+///  - `prog` / `verb` / `noun` - Two digit displays, `None` when blank
+///  - `registers` - R1, R2 and R3
+///  - `lamps` - Status and caution lamps as commanded, see `lit_lamps` for
+///    the lamps actually lit
+///  - `flash` - VERB and NOUN flashing is commanded by channel 11 bit 6
+///  - `flash_off` - The flashing displays are in their off phase (channel
+///    163 bit 6)
+///  - `lamp_test` - Every lamp is lit by channel 13 bit 10
+///  - `display_off` - The electroluminescent displays are turned off
+///    (channel 163 bit 10)
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DskyState {
+    pub prog: [Option<u8>; 2],
+    pub verb: [Option<u8>; 2],
+    pub noun: [Option<u8>; 2],
+    pub registers: [DskyRegister; 3],
+    pub lamps: DskyLamps,
+    pub flash: bool,
+    pub flash_off: bool,
+    pub lamp_test: bool,
+    pub display_off: bool,
+}
+
+fn pair_value(pair: &[Option<u8>; 2]) -> Option<u8> {
+    Some(pair[0]? * 10 + pair[1]?)
+}
+
+impl DskyState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// ## `prog_value` / `verb_value` / `noun_value` Functions
+    ///
+    /// Return the two digit displays as numbers, `None` if a digit is blank.
+    ///
+    pub fn prog_value(&self) -> Option<u8> {
+        pair_value(&self.prog)
+    }
+
+    pub fn verb_value(&self) -> Option<u8> {
+        pair_value(&self.verb)
+    }
+
+    pub fn noun_value(&self) -> Option<u8> {
+        pair_value(&self.noun)
+    }
+
+    ///
+    /// ## `lit_lamps` Function
+    ///
+    /// Returns the lamps actually lit, which are all of them during the lamp
+    /// test.
+    ///
+    pub fn lit_lamps(&self) -> DskyLamps {
+        if self.lamp_test {
+            DskyLamps::all_lit()
+        } else {
+            self.lamps
+        }
+    }
+
+    fn update_row(&mut self, value: u16) {
+        let row = (value >> 11) & 0o17;
+        let sign = value & ROW_SIGN_BIT != 0;
+        let c = get_digit(((value >> 5) & 0o37) as u8);
+        let d = get_digit((value & 0o37) as u8);
+
+        let r = &mut self.registers;
+        match row {
+            1 => {
+                r[2].minus = sign;
+                r[2].digits[3] = c;
+                r[2].digits[4] = d;
+            }
+            2 => {
+                r[2].plus = sign;
+                r[2].digits[1] = c;
+                r[2].digits[2] = d;
+            }
+            3 => {
+                r[1].digits[4] = c;
+                r[2].digits[0] = d;
+            }
+            4 => {
+                r[1].minus = sign;
+                r[1].digits[2] = c;
+                r[1].digits[3] = d;
+            }
+            5 => {
+                r[1].plus = sign;
+                r[1].digits[0] = c;
+                r[1].digits[1] = d;
+            }
+            6 => {
+                r[0].minus = sign;
+                r[0].digits[3] = c;
+                r[0].digits[4] = d;
+            }
+            7 => {
+                r[0].plus = sign;
+                r[0].digits[1] = c;
+                r[0].digits[2] = d;
+            }
+            8 => {
+                r[0].digits[0] = d;
+            }
+            9 => self.noun = [c, d],
+            10 => self.verb = [c, d],
+            11 => self.prog = [c, d],
+            12 => {
+                let l = &mut self.lamps;
+                l.prio_disp = value & ROW12_PRIO_DISP != 0;
+                l.no_dap = value & ROW12_NO_DAP != 0;
+                l.vel = value & ROW12_VEL != 0;
+                l.no_att = value & ROW12_NO_ATT != 0;
+                l.alt = value & ROW12_ALT != 0;
+                l.gimbal_lock = value & ROW12_GIMBAL_LOCK != 0;
+                l.tracker = value & ROW12_TRACKER != 0;
+                l.prog = value & ROW12_PROG != 0;
+            }
+            _ => {}
+        }
+    }
+
+    ///
+    /// ## `update` Function
+    ///
+    /// Decodes a value written to channel 10, 11, 13 or 163. Writes to any
+    /// other channel are ignored.
+    ///
+    /// ### Result
+    ///
+    ///  - `bool` - The state has changed
+    ///
+    pub fn update(&mut self, channel_idx: usize, value: u16) -> bool {
+        let old = *self;
+        match channel_idx {
+            io::CHANNEL_DSKY => self.update_row(value),
+            io::CHANNEL_DSALMOUT => {
+                let l = &mut self.lamps;
+                l.iss_warning = value & CHAN11_ISS_WARNING != 0;
+                l.comp_acty = value & CHAN11_COMP_ACTY != 0;
+                l.uplink_acty = value & CHAN11_UPLINK_ACTY != 0;
+                l.temp = value & CHAN11_TEMP != 0;
+                l.key_rel = value & CHAN11_KEY_REL != 0;
+                l.opr_err = value & CHAN11_OPR_ERR != 0;
+                self.flash = value & CHAN11_FLASH != 0;
+            }
+            io::CHANNEL_CHAN13 => self.lamp_test = value & CHAN13_LAMP_TEST != 0,
+            0o163 => {
+                let l = &mut self.lamps;
+                l.agc_warning = value & CHAN163_AGC_WARNING != 0;
+                l.temp = value & CHAN163_TEMP != 0;
+                l.key_rel = value & CHAN163_KEY_REL != 0;
+                l.opr_err = value & CHAN163_OPR_ERR != 0;
+                l.restart = value & CHAN163_RESTART != 0;
+                l.stby = value & CHAN163_STBY != 0;
+                self.flash_off = value & CHAN163_FLASH_OFF != 0;
+                self.display_off = value & CHAN163_DISPLAY_OFF != 0;
+            }
+            _ => {}
+        }
+        old != *self
+    }
+}
+
+#[cfg(test)]
+mod dsky_state_tests {
+    extern crate std;
+
+    use super::*;
+    use std::format;
+
+    // Relay codes of the digits 0 to 9
+    const CODES: [u16; 10] = [21, 3, 25, 27, 15, 30, 28, 19, 29, 31];
+
+    fn row(row: u16, sign: bool, c: u16, d: u16) -> u16 {
+        (row << 11) | if sign { ROW_SIGN_BIT } else { 0 } | (c << 5) | d
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the decoding of the two digit displays and of the registers,
+    /// including the digits shared by R2 and R3 and the sign bits.
+    ///
+    #[test]
+    fn test_dsky_state_digits() {
+        let mut state = DskyState::new();
+        assert_eq!(state.verb_value(), None);
+
+        let rows = [
+            row(11, false, CODES[6], CODES[3]),
+            row(10, false, CODES[0], CODES[6]),
+            row(9, false, CODES[6], CODES[3]),
+            row(8, false, 0, CODES[1]),
+            row(7, true, CODES[2], CODES[3]),
+            row(6, false, CODES[4], CODES[5]),
+            row(5, false, CODES[0], CODES[0]),
+            row(4, true, CODES[0], CODES[4]),
+            row(3, false, CODES[2], CODES[9]),
+            row(2, false, CODES[8], CODES[7]),
+            row(1, false, CODES[6], CODES[5]),
+        ];
+        for value in rows.iter() {
+            assert!(state.update(io::CHANNEL_DSKY, *value));
+        }
+        assert!(!state.update(io::CHANNEL_DSKY, rows[0]));
+
+        assert_eq!(state.prog_value(), Some(63));
+        assert_eq!(state.verb_value(), Some(6));
+        assert_eq!(state.noun_value(), Some(63));
+        assert_eq!(state.registers[0].value(), Some(12345));
+        assert_eq!(state.registers[1].value(), Some(-42));
+        assert_eq!(format!("{}", state.registers[2]), " 98765");
+
+        state.update(io::CHANNEL_DSKY, row(1, false, 0, 0));
+        assert_eq!(state.registers[2].value(), None);
+        assert_eq!(format!("{}", state.registers[2]), " 987  ");
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the lamps decoded from channels 11 and 163 and relay row 12, and
+    /// their lookup by label.
+    ///
+    #[test]
+    fn test_dsky_state_lamps() {
+        let mut state = DskyState::new();
+        state.update(io::CHANNEL_DSALMOUT, CHAN11_COMP_ACTY | CHAN11_KEY_REL | CHAN11_FLASH);
+        state.update(io::CHANNEL_DSKY, (12 << 11) | ROW12_PROG | ROW12_NO_ATT);
+        assert!(state.lamps.comp_acty && state.lamps.key_rel && state.flash);
+        assert!(state.lamps.prog && state.lamps.no_att && !state.lamps.tracker);
+
+        state.update(0o163, CHAN163_RESTART | CHAN163_FLASH_OFF);
+        assert!(state.lamps.restart && state.flash_off);
+        assert!(!state.lamps.key_rel);
+
+        assert_eq!(state.lamps.get("PROG"), Some(true));
+        assert_eq!(state.lamps.get("restart"), Some(true));
+        assert_eq!(state.lamps.get("GIMBAL_LOCK"), Some(false));
+        assert_eq!(state.lamps.get("GIMBAL"), None);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the lamp test lights every lamp without changing the commanded
+    /// lamps, and the lamp bits sent to displays during the test.
+    ///
+    #[test]
+    fn test_dsky_state_lamp_test() {
+        let mut state = DskyState::new();
+        state.update(io::CHANNEL_DSALMOUT, CHAN11_ISS_WARNING);
+        state.update(0o163, CHAN163_AGC_WARNING | CHAN163_DISPLAY_OFF);
+        assert!(state.lamps.iss_warning && state.lamps.agc_warning);
+        assert!(state.display_off);
+        assert_eq!(state.lit_lamps(), state.lamps);
+
+        assert!(state.update(io::CHANNEL_CHAN13, CHAN13_LAMP_TEST));
+        assert!(state.lamp_test);
+        assert_eq!(state.lit_lamps(), DskyLamps::all_lit());
+        assert!(!state.lamps.prog);
+        state.update(io::CHANNEL_CHAN13, 0);
+        assert_eq!(state.lit_lamps(), state.lamps);
+
+        assert_eq!(lamp_test_value(io::CHANNEL_DSKY, 12 << 11), (12 << 11) | 0o00677);
+        assert_eq!(lamp_test_value(io::CHANNEL_DSKY, 11 << 11), 11 << 11);
+        assert_eq!(lamp_test_value(io::CHANNEL_DSALMOUT, 0o20000), 0o20137);
+        assert_eq!(lamp_test_value(0o163, 0o00040), 0o00771);
+    }
+}
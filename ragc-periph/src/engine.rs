@@ -0,0 +1,399 @@
+use ragc_core::consts::{io, special};
+use ragc_core::mem::periph::AgcIoPeriph;
+
+/// Maximum thrust of the descent engine, in newtons (10500 lbf).
+pub const DPS_MAX_THRUST: f64 = 46706.0;
+
+/// Minimum throttle setting of the descent engine, in newtons (10%).
+pub const DPS_MIN_THRUST: f64 = 4670.6;
+
+/// Thrust change for each THRUST counter pulse, in newtons (2.8 lbf).
+pub const DPS_THRUST_PER_PULSE: f64 = 12.455;
+
+/// Fixed thrust of the ascent engine, in newtons (3500 lbf).
+pub const APS_THRUST: f64 = 15569.0;
+
+/// Rate at which the descent engine gimbal trims, in degrees per second.
+pub const DPS_GIMBAL_RATE: f64 = 0.2;
+
+/// Travel limit of the descent engine gimbal, in degrees.
+pub const DPS_GIMBAL_LIMIT: f64 = 6.0;
+
+const MCT_SECS: f64 = 11.7 / 1000000.0;
+
+// Channel 11 engine commands
+const CHAN11_ENGINE_ON: u16 = 0o10000;
+const CHAN11_ENGINE_OFF: u16 = 0o20000;
+
+// Channel 12 descent engine gimbal trim commands
+const CHAN12_PITCH_TRIM_POS: u16 = 0o00400;
+const CHAN12_PITCH_TRIM_NEG: u16 = 0o01000;
+const CHAN12_ROLL_TRIM_POS: u16 = 0o02000;
+const CHAN12_ROLL_TRIM_NEG: u16 = 0o04000;
+
+// Channel 30 bits (inverted) reported by the engine
+const CHAN30_ENGINE_ARMED: u16 = 0o00004;
+const CHAN30_AUTO_THROTTLE: u16 = 0o00020;
+
+///
+/// ## `EngineStage` Enum
+///
+/// Selects which LM engine is modelled. Only the descent engine can be
+/// throttled and gimballed.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineStage {
+    Descent,
+    Ascent,
+}
+
+///
+/// ## `ThrustEvent` Structure
+///
+/// Reported every time the thrust produced by the engine changes. `mcts` is
+/// the number of MCTs elapsed since the peripheral was created and `thrust`
+/// is in newtons.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrustEvent {
+    pub mcts: u64,
+    pub thrust: f64,
+}
+
+///
+/// ## LmEngine Peripheral
+///
+/// The LmEngine peripheral models the LM descent or ascent engine as seen by
+/// the AGC.
+///
+///  - Channel 11 bits 13/14 turn the engine on and off. The engine only
+///    ignites while armed.
+///  - THRUST counter pulses, sent when channel 14 bit 4 (THRUST DRIVE
+///    ACTIVITY) is set, move the descent engine throttle while auto throttle
+///    is selected.
+///  - Channel 30 reports the ENGINE ARMED and AUTO THROTTLE discretes.
+///  - Channel 12 bits 9-12 trim the descent engine gimbal in pitch and roll.
+///
+/// Every change in thrust is reported to the `on_change` callback, and the
+/// total impulse delivered is accumulated.
+///
+pub struct LmEngine<F>
+where
+    F: FnMut(ThrustEvent),
+{
+    stage: EngineStage,
+    on_change: F,
+    mcts: u64,
+
+    armed: bool,
+    auto_throttle: bool,
+    firing: bool,
+    throttle: f64,
+
+    trim_cmd: u16,
+    gimbal: (f64, f64),
+    impulse: f64,
+}
+
+impl<F> LmEngine<F>
+where
+    F: FnMut(ThrustEvent),
+{
+    pub fn new(stage: EngineStage, on_change: F) -> Self {
+        LmEngine {
+            stage,
+            on_change,
+            mcts: 0,
+            armed: false,
+            auto_throttle: true,
+            firing: false,
+            throttle: DPS_MIN_THRUST,
+            trim_cmd: 0,
+            gimbal: (0.0, 0.0),
+            impulse: 0.0,
+        }
+    }
+
+    ///
+    /// ## `set_armed` Function
+    ///
+    /// Sets the state of the engine arm switch. Disarming the engine shuts
+    /// it down.
+    ///
+    pub fn set_armed(&mut self, armed: bool) {
+        self.armed = armed;
+        if !armed {
+            self.set_firing(false);
+        }
+    }
+
+    ///
+    /// ## `set_auto_throttle` Function
+    ///
+    /// Selects between computer (auto) and manual control of the descent
+    /// engine throttle.
+    ///
+    pub fn set_auto_throttle(&mut self, auto: bool) {
+        self.auto_throttle = auto;
+    }
+
+    ///
+    /// ## `set_manual_thrust` Function
+    ///
+    /// Sets the descent engine throttle, in newtons, while under manual
+    /// control.
+    ///
+    pub fn set_manual_thrust(&mut self, thrust: f64) {
+        if !self.auto_throttle {
+            self.set_throttle(thrust);
+        }
+    }
+
+    pub fn is_firing(&self) -> bool {
+        self.firing
+    }
+
+    ///
+    /// ## `thrust` Function
+    ///
+    /// Returns the thrust currently produced by the engine, in newtons.
+    ///
+    pub fn thrust(&self) -> f64 {
+        match (self.firing, self.stage) {
+            (false, _) => 0.0,
+            (true, EngineStage::Descent) => self.throttle,
+            (true, EngineStage::Ascent) => APS_THRUST,
+        }
+    }
+
+    ///
+    /// ## `commanded_thrust` Function
+    ///
+    /// Returns the throttle setting of the engine, in newtons, regardless of
+    /// whether the engine is firing.
+    ///
+    pub fn commanded_thrust(&self) -> f64 {
+        match self.stage {
+            EngineStage::Descent => self.throttle,
+            EngineStage::Ascent => APS_THRUST,
+        }
+    }
+
+    ///
+    /// ## `gimbal` Function
+    ///
+    /// Returns the descent engine gimbal angles (pitch, roll) in degrees.
+    ///
+    pub fn gimbal(&self) -> (f64, f64) {
+        self.gimbal
+    }
+
+    ///
+    /// ## `total_impulse` Function
+    ///
+    /// Returns the total impulse delivered by the engine, in newton seconds.
+    ///
+    pub fn total_impulse(&self) -> f64 {
+        self.impulse
+    }
+
+    pub fn mcts(&self) -> u64 {
+        self.mcts
+    }
+
+    fn notify(&mut self, old_thrust: f64) {
+        let thrust = self.thrust();
+        if thrust != old_thrust {
+            (self.on_change)(ThrustEvent {
+                mcts: self.mcts,
+                thrust,
+            });
+        }
+    }
+
+    fn set_firing(&mut self, firing: bool) {
+        let old = self.thrust();
+        self.firing = firing;
+        self.notify(old);
+    }
+
+    fn set_throttle(&mut self, thrust: f64) {
+        let old = self.thrust();
+        self.throttle = thrust.clamp(DPS_MIN_THRUST, DPS_MAX_THRUST);
+        self.notify(old);
+    }
+
+    fn trim_axis(angle: f64, cmd: u16, pos_bit: u16, neg_bit: u16, secs: f64) -> f64 {
+        let rate = match (cmd & pos_bit != 0, cmd & neg_bit != 0) {
+            (true, false) => DPS_GIMBAL_RATE,
+            (false, true) => -DPS_GIMBAL_RATE,
+            _ => 0.0,
+        };
+        (angle + rate * secs).clamp(-DPS_GIMBAL_LIMIT, DPS_GIMBAL_LIMIT)
+    }
+}
+
+impl<F> AgcIoPeriph for LmEngine<F>
+where
+    F: FnMut(ThrustEvent),
+{
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_CHAN30 => {
+                let mut val = 0o77777;
+                if self.armed {
+                    val &= !CHAN30_ENGINE_ARMED;
+                }
+                if self.auto_throttle && self.stage == EngineStage::Descent {
+                    val &= !CHAN30_AUTO_THROTTLE;
+                }
+                val
+            }
+            io::CHANNEL_CHAN31 | io::CHANNEL_CHAN32 | io::CHANNEL_CHAN33 => 0o77777,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        match channel_idx {
+            io::CHANNEL_DSALMOUT => {
+                if value & CHAN11_ENGINE_OFF != 0 {
+                    self.set_firing(false);
+                } else if value & CHAN11_ENGINE_ON != 0 && self.armed {
+                    self.set_firing(true);
+                }
+            }
+            io::CHANNEL_CHAN12 => {
+                self.trim_cmd = value;
+            }
+            _ => {}
+        }
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        0
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.mcts += mcts as u64;
+
+        let secs = mcts as f64 * MCT_SECS;
+        self.impulse += self.thrust() * secs;
+
+        if self.stage == EngineStage::Descent {
+            let (pitch, roll) = self.gimbal;
+            self.gimbal = (
+                Self::trim_axis(pitch, self.trim_cmd, CHAN12_PITCH_TRIM_POS, CHAN12_PITCH_TRIM_NEG, secs),
+                Self::trim_axis(roll, self.trim_cmd, CHAN12_ROLL_TRIM_POS, CHAN12_ROLL_TRIM_NEG, secs),
+            );
+        }
+    }
+
+    fn write_counter(&mut self, counter_idx: usize, value: u16) {
+        if counter_idx != special::SG_THRUST
+            || self.stage != EngineStage::Descent
+            || !self.auto_throttle
+        {
+            return;
+        }
+
+        let pulses = if value & 0o40000 != 0 {
+            -((!value & 0o37777) as f64)
+        } else {
+            (value & 0o37777) as f64
+        };
+        self.set_throttle(self.throttle + pulses * DPS_THRUST_PER_PULSE);
+    }
+}
+
+#[cfg(test)]
+mod engine_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests the engine only fires through channel 11 while armed, and that
+    /// the engine discretes are reported on channel 30.
+    ///
+    #[test]
+    fn test_engine_on_off() {
+        let mut engine = LmEngine::new(EngineStage::Descent, |_| {});
+        assert_eq!(engine.read(io::CHANNEL_CHAN30), 0o77777 & !CHAN30_AUTO_THROTTLE);
+
+        engine.write(io::CHANNEL_DSALMOUT, CHAN11_ENGINE_ON);
+        assert!(!engine.is_firing(), "Engine fired while not armed");
+
+        engine.set_armed(true);
+        assert_eq!(engine.read(io::CHANNEL_CHAN30) & CHAN30_ENGINE_ARMED, 0);
+        engine.write(io::CHANNEL_DSALMOUT, CHAN11_ENGINE_ON);
+        assert!(engine.is_firing());
+        assert_eq!(engine.thrust(), DPS_MIN_THRUST);
+
+        engine.write(io::CHANNEL_DSALMOUT, CHAN11_ENGINE_OFF);
+        assert!(!engine.is_firing());
+        assert_eq!(engine.thrust(), 0.0);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests THRUST counter pulses move the throttle only under auto
+    /// throttle, and that thrust changes are reported with their MCT time.
+    ///
+    #[test]
+    fn test_thrust_pulses() {
+        let mut last = None;
+        {
+            let mut engine = LmEngine::new(EngineStage::Descent, |ev| last = Some(ev));
+            engine.set_armed(true);
+            engine.write(io::CHANNEL_DSALMOUT, CHAN11_ENGINE_ON);
+            engine.pump_mcts(100);
+
+            engine.write_counter(special::SG_THRUST, 1000);
+            let expected = DPS_MIN_THRUST + 1000.0 * DPS_THRUST_PER_PULSE;
+            assert!((engine.thrust() - expected).abs() < 1e-6);
+
+            // Negative pulses below minimum throttle are limited
+            engine.write_counter(special::SG_THRUST, !2000 & 0o77777);
+            assert_eq!(engine.thrust(), DPS_MIN_THRUST);
+
+            engine.set_auto_throttle(false);
+            engine.write_counter(special::SG_THRUST, 1000);
+            assert_eq!(engine.thrust(), DPS_MIN_THRUST, "Pulses used under manual throttle");
+
+            engine.set_manual_thrust(100000.0);
+            assert_eq!(engine.thrust(), DPS_MAX_THRUST);
+        }
+        assert_eq!(last, Some(ThrustEvent { mcts: 100, thrust: DPS_MAX_THRUST }));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the descent engine gimbal trims at the gimbal rate and stops at
+    /// its travel limit, and that the impulse accumulates over time.
+    ///
+    #[test]
+    fn test_gimbal_trim_and_impulse() {
+        let mut engine = LmEngine::new(EngineStage::Descent, |_| {});
+        let half_sec = (0.5 / MCT_SECS) as u16;
+
+        engine.write(io::CHANNEL_CHAN12, CHAN12_PITCH_TRIM_POS | CHAN12_ROLL_TRIM_NEG);
+        engine.pump_mcts(half_sec);
+        engine.pump_mcts(half_sec);
+        let (pitch, roll) = engine.gimbal();
+        assert!((pitch - DPS_GIMBAL_RATE).abs() < 1e-3);
+        assert!((roll + DPS_GIMBAL_RATE).abs() < 1e-3);
+
+        for _ in 0..60 {
+            engine.pump_mcts(half_sec);
+        }
+        assert_eq!(engine.gimbal(), (DPS_GIMBAL_LIMIT, -DPS_GIMBAL_LIMIT));
+
+        engine.set_armed(true);
+        engine.write(io::CHANNEL_DSALMOUT, CHAN11_ENGINE_ON);
+        engine.pump_mcts(half_sec);
+        assert!((engine.total_impulse() - 0.5 * DPS_MIN_THRUST).abs() < 1.0);
+    }
+}
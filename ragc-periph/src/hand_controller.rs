@@ -0,0 +1,555 @@
+use ragc_core::consts::{cpu, io, special};
+use ragc_core::mem::periph::AgcIoPeriph;
+
+// Channel 13 bits controlling the hand controller counters and traps
+const CHAN13_RHC_COUNTER_ENABLE: u16 = 0o00200;
+const CHAN13_RHC_START_READ: u16 = 0o00400;
+const CHAN13_RESET_TRAP_31A: u16 = 0o04000;
+const CHAN13_RESET_TRAP_31B: u16 = 0o10000;
+
+// Channel 31 bits (inverted) owned by the hand controllers
+const CHAN31_TRAP_31A_MASK: u16 = 0o00077;
+const CHAN31_TRAP_31B_MASK: u16 = 0o07700;
+const CHAN31_ATTITUDE_HOLD: u16 = 0o10000;
+const CHAN31_AUTO_STABILIZATION: u16 = 0o20000;
+const CHAN31_OUT_OF_DETENT: u16 = 0o40000;
+
+/// Fictitious input channels used by yaACA to send the RHC deflection counts
+/// for pitch, yaw and roll.
+pub const YAACA_CHANNEL_PITCH: u16 = 0o166;
+pub const YAACA_CHANNEL_YAW: u16 = 0o167;
+pub const YAACA_CHANNEL_ROLL: u16 = 0o170;
+
+///
+/// ## `ScsMode` Enum
+///
+/// Position of the SCS mode control switch, reported on channel 31 bits 13
+/// and 14.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScsMode {
+    Off,
+    AttitudeHold,
+    Auto,
+}
+
+///
+/// ## `HandEvent` Enum
+///
+/// A change in the state of the hand controllers. Values for the discrete
+/// inputs (minimum impulse and translation) are -1, 0 or 1 per axis.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandEvent {
+    Rhc(i16, i16, i16),
+    MinImpulse(i8, i8, i8),
+    Thc(i8, i8, i8),
+    Mode(ScsMode),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+    BadTime,
+    OutOfOrder,
+    UnknownCommand,
+    BadArgument,
+}
+
+///
+/// ## `ScriptError` Structure
+///
+/// Error found while parsing a hand controller script. `line` starts at 1.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+}
+
+fn secs_to_mcts(secs: f64) -> u64 {
+    (secs * 1000000.0 / 11.7) as u64
+}
+
+fn parse_discrete<'a>(args: &mut impl Iterator<Item = &'a str>) -> Option<i8> {
+    match args.next()?.parse::<i8>().ok()? {
+        x @ -1..=1 => Some(x),
+        _ => None,
+    }
+}
+
+fn parse_discretes<'a>(args: &mut impl Iterator<Item = &'a str>) -> Option<(i8, i8, i8)> {
+    Some((parse_discrete(args)?, parse_discrete(args)?, parse_discrete(args)?))
+}
+
+fn parse_event<'a>(cmd: &str, args: &mut impl Iterator<Item = &'a str>) -> Result<HandEvent, ScriptErrorKind> {
+    let event = match cmd {
+        "rhc" => {
+            let mut axis = || args.next().and_then(|x| x.parse::<i16>().ok());
+            match (axis(), axis(), axis()) {
+                (Some(p), Some(y), Some(r)) => Some(HandEvent::Rhc(p, y, r)),
+                _ => None,
+            }
+        }
+        "impulse" => parse_discretes(args).map(|(p, y, r)| HandEvent::MinImpulse(p, y, r)),
+        "thc" => parse_discretes(args).map(|(x, y, z)| HandEvent::Thc(x, y, z)),
+        "mode" => match args.next() {
+            Some("off") => Some(HandEvent::Mode(ScsMode::Off)),
+            Some("hold") => Some(HandEvent::Mode(ScsMode::AttitudeHold)),
+            Some("auto") => Some(HandEvent::Mode(ScsMode::Auto)),
+            _ => None,
+        },
+        _ => return Err(ScriptErrorKind::UnknownCommand),
+    };
+
+    match (event, args.next()) {
+        (Some(x), None) => Ok(x),
+        _ => Err(ScriptErrorKind::BadArgument),
+    }
+}
+
+///
+/// ## `HandControllerScript` Structure
+///
+/// Time tagged list of hand controller events. Each line of the script has
+/// the time in seconds since the start of the run, a command and its
+/// arguments. Anything after a `#` is a comment.
+///
+/// ```text
+/// # time  command  arguments
+/// 1.0     rhc      10 0 -5      # pitch, yaw, roll deflection counts
+/// 1.5     impulse  1 0 0        # minimum impulse pitch, yaw, roll
+/// 2.0     thc      0 0 1        # translation x, y, z
+/// 3.0     mode     hold         # off, hold or auto
+/// ```
+///
+/// The whole script is validated when created, and events are parsed as
+/// they become due so no allocation is needed.
+///
+pub struct HandControllerScript<'a> {
+    lines: core::iter::Enumerate<core::str::Lines<'a>>,
+}
+
+impl<'a> HandControllerScript<'a> {
+    pub fn new(text: &'a str) -> Result<Self, ScriptError> {
+        let mut script = HandControllerScript {
+            lines: text.lines().enumerate(),
+        };
+
+        let mut last_time = 0;
+        while let Some((line, time, _event)) = script.parse_next()? {
+            if time < last_time {
+                return Err(ScriptError {
+                    line,
+                    kind: ScriptErrorKind::OutOfOrder,
+                });
+            }
+            last_time = time;
+        }
+
+        Ok(HandControllerScript {
+            lines: text.lines().enumerate(),
+        })
+    }
+
+    fn parse_next(&mut self) -> Result<Option<(usize, u64, HandEvent)>, ScriptError> {
+        for (idx, line) in &mut self.lines {
+            let line_no = idx + 1;
+            let content = line.split('#').next().unwrap_or("");
+            let mut fields = content.split_whitespace();
+            let time = match fields.next() {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let time = match time.parse::<f64>() {
+                Ok(x) if x >= 0.0 => secs_to_mcts(x),
+                _ => {
+                    return Err(ScriptError {
+                        line: line_no,
+                        kind: ScriptErrorKind::BadTime,
+                    })
+                }
+            };
+            let cmd = fields.next().ok_or(ScriptError {
+                line: line_no,
+                kind: ScriptErrorKind::UnknownCommand,
+            })?;
+            let event = parse_event(cmd, &mut fields).map_err(|kind| ScriptError {
+                line: line_no,
+                kind,
+            })?;
+            return Ok(Some((line_no, time, event)));
+        }
+        Ok(None)
+    }
+
+    ///
+    /// ## `next_event` Function
+    ///
+    /// Returns the next event of the script with its time in MCTs.
+    ///
+    pub fn next_event(&mut self) -> Option<(u64, HandEvent)> {
+        // The script was fully validated when it was created.
+        self.parse_next().ok().flatten().map(|(_, time, event)| (time, event))
+    }
+}
+
+///
+/// ## HandController Peripheral
+///
+/// The HandController peripheral models the LM Rotational Hand Controller
+/// (RHC) and Translational Hand Controller (THC).
+///
+///  - Channel 31 reports the minimum impulse, translation, SCS mode and out
+///    of detent discretes.
+///  - When the AGC enables the RHC counters (channel 13 bit 8) and starts a
+///    read (channel 13 bit 9), RCHP, RCHY and RCHR are loaded with the
+///    current RHC deflection.
+///  - A HANDRUPT is generated when trap 31A (channel 31 bits 1-6) or trap
+///    31B (channel 31 bits 7-12) sees a new signal. A trap is re-armed by
+///    writing its reset bit in channel 13.
+///
+/// Input is provided through the setter functions, a `HandControllerScript`
+/// or yaAGC packets as sent by yaACA.
+///
+pub struct HandController<'a> {
+    rhc: (i16, i16, i16),
+    chan31: u16,
+
+    mcts: u64,
+    script: Option<HandControllerScript<'a>>,
+    next_event: Option<(u64, HandEvent)>,
+
+    counter_enable: bool,
+    start_read: bool,
+    counters: [Option<u16>; 3],
+
+    trap_31a: bool,
+    trap_31b: bool,
+    rupt: bool,
+}
+
+fn axis_bits(val: i8, pos_bit: u16) -> u16 {
+    match val {
+        x if x > 0 => pos_bit,
+        x if x < 0 => pos_bit << 1,
+        _ => 0,
+    }
+}
+
+fn to_ones_complement(val: i16) -> u16 {
+    if val < 0 {
+        !(val.unsigned_abs()) & 0o77777
+    } else {
+        val as u16 & 0o77777
+    }
+}
+
+impl<'a> HandController<'a> {
+    pub fn new() -> Self {
+        HandController {
+            rhc: (0, 0, 0),
+            chan31: 0o77777,
+            mcts: 0,
+            script: None,
+            next_event: None,
+            counter_enable: false,
+            start_read: false,
+            counters: [None; 3],
+            trap_31a: true,
+            trap_31b: true,
+            rupt: false,
+        }
+    }
+
+    ///
+    /// ## `with_script` Function
+    ///
+    /// Drives the hand controllers from the given script. Event times are
+    /// relative to the creation of the peripheral.
+    ///
+    pub fn with_script(mut self, mut script: HandControllerScript<'a>) -> Self {
+        self.next_event = script.next_event();
+        self.script = Some(script);
+        self
+    }
+
+    ///
+    /// ## `get_channel31` Function
+    ///
+    /// Returns the current channel 31 value as sensed by the AGC (inverted).
+    ///
+    pub fn get_channel31(&self) -> u16 {
+        self.chan31
+    }
+
+    fn update_channel31(&mut self, value: u16, mask: u16) {
+        let old = self.chan31;
+        self.chan31 = (self.chan31 & !mask) | (value & mask);
+
+        // Bits going from 1 to 0 are new signals for the traps
+        let new_signals = old & !self.chan31;
+        if self.trap_31a && new_signals & CHAN31_TRAP_31A_MASK != 0 {
+            self.trap_31a = false;
+            self.rupt = true;
+        }
+        if self.trap_31b && new_signals & CHAN31_TRAP_31B_MASK != 0 {
+            self.trap_31b = false;
+            self.rupt = true;
+        }
+    }
+
+    ///
+    /// ## `handle_event` Function
+    ///
+    /// Applies a hand controller event to the peripheral.
+    ///
+    pub fn handle_event(&mut self, event: HandEvent) {
+        match event {
+            HandEvent::Rhc(pitch, yaw, roll) => {
+                self.rhc = (pitch, yaw, roll);
+                let detent = if pitch != 0 || yaw != 0 || roll != 0 {
+                    0
+                } else {
+                    CHAN31_OUT_OF_DETENT
+                };
+                self.update_channel31(detent, CHAN31_OUT_OF_DETENT);
+            }
+            HandEvent::MinImpulse(pitch, yaw, roll) => {
+                let bits = axis_bits(pitch, 0o00001) | axis_bits(yaw, 0o00004) | axis_bits(roll, 0o00020);
+                self.update_channel31(!bits, CHAN31_TRAP_31A_MASK);
+            }
+            HandEvent::Thc(x, y, z) => {
+                let bits = axis_bits(x, 0o00100) | axis_bits(y, 0o00400) | axis_bits(z, 0o02000);
+                self.update_channel31(!bits, CHAN31_TRAP_31B_MASK);
+            }
+            HandEvent::Mode(mode) => {
+                let bits = match mode {
+                    ScsMode::Off => 0,
+                    ScsMode::AttitudeHold => CHAN31_ATTITUDE_HOLD,
+                    ScsMode::Auto => CHAN31_AUTO_STABILIZATION,
+                };
+                self.update_channel31(!bits, CHAN31_ATTITUDE_HOLD | CHAN31_AUTO_STABILIZATION);
+            }
+        }
+    }
+
+    pub fn set_rhc(&mut self, pitch: i16, yaw: i16, roll: i16) {
+        self.handle_event(HandEvent::Rhc(pitch, yaw, roll));
+    }
+
+    pub fn set_thc(&mut self, x: i8, y: i8, z: i8) {
+        self.handle_event(HandEvent::Thc(x, y, z));
+    }
+
+    pub fn set_min_impulse(&mut self, pitch: i8, yaw: i8, roll: i8) {
+        self.handle_event(HandEvent::MinImpulse(pitch, yaw, roll));
+    }
+
+    pub fn set_mode(&mut self, mode: ScsMode) {
+        self.handle_event(HandEvent::Mode(mode));
+    }
+
+    ///
+    /// ## `handle_yaagc_input` Function
+    ///
+    /// Handles an input packet as sent by yaACA. The RHC deflection is sent
+    /// through the fictitious channels 166-170 (octal), while the discretes
+    /// are sent on channel 31 with `mask` selecting the bits being changed.
+    ///
+    pub fn handle_yaagc_input(&mut self, channel: u16, value: u16, mask: u16) {
+        let counts = if value & 0o40000 != 0 {
+            -((!value & 0o37777) as i16)
+        } else {
+            (value & 0o37777) as i16
+        };
+
+        match channel {
+            YAACA_CHANNEL_PITCH => self.set_rhc(counts, self.rhc.1, self.rhc.2),
+            YAACA_CHANNEL_YAW => self.set_rhc(self.rhc.0, counts, self.rhc.2),
+            YAACA_CHANNEL_ROLL => self.set_rhc(self.rhc.0, self.rhc.1, counts),
+            0o31 => self.update_channel31(value, mask & 0o77777),
+            _ => {}
+        }
+    }
+
+    fn load_counters(&mut self) {
+        self.counters = [
+            Some(to_ones_complement(self.rhc.0)),
+            Some(to_ones_complement(self.rhc.1)),
+            Some(to_ones_complement(self.rhc.2)),
+        ];
+    }
+}
+
+impl Default for HandController<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AgcIoPeriph for HandController<'_> {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_CHAN31 => self.get_channel31(),
+            io::CHANNEL_CHAN30 | io::CHANNEL_CHAN32 | io::CHANNEL_CHAN33 => 0o77777,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        if channel_idx != io::CHANNEL_CHAN13 {
+            return;
+        }
+
+        if value & CHAN13_RESET_TRAP_31A != 0 {
+            self.trap_31a = true;
+        }
+        if value & CHAN13_RESET_TRAP_31B != 0 {
+            self.trap_31b = true;
+        }
+
+        self.counter_enable = value & CHAN13_RHC_COUNTER_ENABLE != 0;
+        let start_read = value & CHAN13_RHC_START_READ != 0;
+        if self.counter_enable && start_read && !self.start_read {
+            self.load_counters();
+        }
+        self.start_read = start_read;
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        if self.rupt {
+            self.rupt = false;
+            1 << cpu::RUPT_HANDRUPT
+        } else {
+            0
+        }
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.mcts += mcts as u64;
+
+        while let Some((time, event)) = self.next_event {
+            if time > self.mcts {
+                break;
+            }
+            self.handle_event(event);
+            self.next_event = self.script.as_mut().and_then(|x| x.next_event());
+        }
+    }
+
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        const COUNTERS: [usize; 3] = [special::SG_RCHP, special::SG_RCHY, special::SG_RCHR];
+        for (idx, counter) in self.counters.iter_mut().enumerate() {
+            if let Some(value) = counter.take() {
+                return Some((COUNTERS[idx], value));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod hand_controller_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests that the RHC counters are only loaded once the AGC enables the
+    /// counters and starts a read through channel 13.
+    ///
+    #[test]
+    fn test_rhc_counter_read() {
+        let mut hc = HandController::new();
+        hc.set_rhc(10, -3, 0);
+        assert_eq!(hc.get_channel31() & CHAN31_OUT_OF_DETENT, 0);
+
+        hc.write(io::CHANNEL_CHAN13, CHAN13_RHC_START_READ);
+        assert_eq!(hc.counter_update(), None, "Counters loaded while disabled");
+
+        hc.write(io::CHANNEL_CHAN13, 0);
+        hc.write(io::CHANNEL_CHAN13, CHAN13_RHC_COUNTER_ENABLE | CHAN13_RHC_START_READ);
+        assert_eq!(hc.counter_update(), Some((special::SG_RCHP, 10)));
+        assert_eq!(hc.counter_update(), Some((special::SG_RCHY, 0o77774)));
+        assert_eq!(hc.counter_update(), Some((special::SG_RCHR, 0)));
+        assert_eq!(hc.counter_update(), None);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that a translation command generates a single HANDRUPT until
+    /// trap 31B is reset through channel 13.
+    ///
+    #[test]
+    fn test_trap_31b_handrupt() {
+        let mut hc = HandController::new();
+
+        hc.set_thc(1, 0, 0);
+        assert_eq!(hc.get_channel31() & CHAN31_TRAP_31B_MASK, 0o07600);
+        assert_eq!(hc.is_interrupt(), 1 << cpu::RUPT_HANDRUPT);
+
+        hc.set_thc(0, 0, -1);
+        assert_eq!(hc.get_channel31() & CHAN31_TRAP_31B_MASK, 0o03700);
+        assert_eq!(hc.is_interrupt(), 0, "Trap fired without being reset");
+
+        hc.write(io::CHANNEL_CHAN13, CHAN13_RESET_TRAP_31B);
+        hc.set_thc(0, 1, 0);
+        assert_eq!(hc.is_interrupt(), 1 << cpu::RUPT_HANDRUPT);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that scripted events are applied once their time has elapsed.
+    ///
+    #[test]
+    fn test_hand_controller_script() {
+        let script = HandControllerScript::new(
+            "# Scripted input\n\
+             0.0 mode auto\n\
+             \n\
+             0.01 impulse 0 -1 0  # negative yaw\n",
+        )
+        .unwrap();
+        let mut hc = HandController::new().with_script(script);
+
+        hc.pump_mcts(1);
+        assert_eq!(hc.get_channel31(), 0o77777 & !CHAN31_AUTO_STABILIZATION);
+
+        hc.pump_mcts(secs_to_mcts(0.01) as u16);
+        assert_eq!(hc.get_channel31() & CHAN31_TRAP_31A_MASK, 0o00067);
+        assert_eq!(hc.is_interrupt(), 1 << cpu::RUPT_HANDRUPT);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the errors reported for malformed scripts.
+    ///
+    #[test]
+    fn test_hand_controller_script_errors() {
+        let err = |text| HandControllerScript::new(text).err().map(|x| (x.line, x.kind));
+        assert_eq!(err("1.0 rhc 1 2"), Some((1, ScriptErrorKind::BadArgument)));
+        assert_eq!(err("\nx rhc 1 2 3"), Some((2, ScriptErrorKind::BadTime)));
+        assert_eq!(err("1.0 jump"), Some((1, ScriptErrorKind::UnknownCommand)));
+        assert_eq!(err("1.0 thc 2 0 0"), Some((1, ScriptErrorKind::BadArgument)));
+        assert_eq!(err("2.0 mode off\n1.0 mode off"), Some((2, ScriptErrorKind::OutOfOrder)));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the yaACA input packets update the RHC and channel 31.
+    ///
+    #[test]
+    fn test_yaaca_input() {
+        let mut hc = HandController::new();
+        hc.handle_yaagc_input(YAACA_CHANNEL_ROLL, 0o77770, 0o77777);
+        assert_eq!(hc.rhc, (0, 0, -7));
+
+        hc.handle_yaagc_input(0o31, 0o00000, CHAN31_ATTITUDE_HOLD);
+        assert_eq!(hc.get_channel31() & CHAN31_ATTITUDE_HOLD, 0);
+    }
+}
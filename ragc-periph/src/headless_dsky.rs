@@ -0,0 +1,261 @@
+use ragc_core::consts::io;
+use ragc_core::mem::periph::AgcIoPeriph;
+
+use crate::dsky_flash::DskyFlash;
+use crate::dsky_keys::{DskyKeyEvent, DskyKeyboard, DSKY_KEY_QUEUE_LEN};
+use crate::dsky_state::DskyState;
+use crate::keycodes;
+
+// Channel 32 PRO key bit, cleared while the key is pressed
+const CHAN32_PROCEED: u16 = 0o20000;
+
+// Channel 163 bits kept by the DSKY, and the RESTART lamp reset by RSET
+const CHAN163_MASK: u16 = 0o01771;
+const CHAN163_RESTART: u16 = 0o00200;
+
+///
+/// ## `KeyError` Enum
+///
+/// Errors returned when queueing DSKY keys.
+///
+///  - `QueueFull` - The keys do not fit in the key queue
+///  - `InvalidKey` - The character is not a DSKY key
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyError {
+    QueueFull,
+    InvalidKey(char),
+}
+
+///
+/// ## HeadlessDsky Peripheral
+///
+/// The HeadlessDsky peripheral is a DSKY without any display or network
+/// connection. The display is only kept as a `DskyState` for programs and
+/// tests to inspect, and keys are queued by the caller. Queued keys and PRO
+/// are played back by a `DskyKeyboard` as the AGC runs, with the same key
+/// down and key up times as the other DSKYs. VERB and NOUN flash as the AGC
+/// runs, see `DskyFlash`.
+///
+pub struct HeadlessDsky {
+    state: DskyState,
+    chan163: u16,
+    flash: DskyFlash,
+    keys: DskyKeyboard,
+}
+
+impl Default for HeadlessDsky {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeadlessDsky {
+    pub fn new() -> Self {
+        HeadlessDsky {
+            state: DskyState::new(),
+            chan163: 0,
+            flash: DskyFlash::new(),
+            keys: DskyKeyboard::new(),
+        }
+    }
+
+    ///
+    /// ## `state` Function
+    ///
+    /// Returns what is currently shown on the DSKY.
+    ///
+    pub fn state(&self) -> &DskyState {
+        &self.state
+    }
+
+    ///
+    /// ## `pending_keys` Function
+    ///
+    /// Returns the number of key events not played back yet.
+    ///
+    pub fn pending_keys(&self) -> usize {
+        self.keys.pending()
+    }
+
+    ///
+    /// ## `set_key_timing` Function
+    ///
+    /// Sets the number of MCTs keys are held down, and the number of MCTs
+    /// between the release of a key and the next one. See `DskyKeyboard`.
+    ///
+    pub fn set_key_timing(&mut self, key_down: u32, key_up: u32) {
+        self.keys.set_timing(key_down, key_up);
+    }
+
+    ///
+    /// ## `press` Function
+    ///
+    /// Queues a key event, such as a PRO press or release.
+    ///
+    pub fn press(&mut self, event: DskyKeyEvent) -> Result<(), KeyError> {
+        self.keys.press(event)
+    }
+
+    ///
+    /// ## `press_key` Function
+    ///
+    /// Queues a DSKY keycode, see `keycodes`.
+    ///
+    pub fn press_key(&mut self, keycode: u16) -> Result<(), KeyError> {
+        self.keys.press(DskyKeyEvent::Key(keycode & 0o37))
+    }
+
+    ///
+    /// ## `press_keys` Function
+    ///
+    /// Queues a sequence of DSKY keys such as `V37E63E`. See
+    /// `keycodes::from_char` for the accepted characters. Nothing is queued if
+    /// any of the keys is invalid or the whole sequence does not fit.
+    ///
+    pub fn press_keys(&mut self, keys: &str) -> Result<(), KeyError> {
+        let mut count = 0;
+        for key in keys.chars() {
+            keycodes::from_char(key).ok_or(KeyError::InvalidKey(key))?;
+            count += 1;
+        }
+        if self.keys.pending() + count > DSKY_KEY_QUEUE_LEN {
+            return Err(KeyError::QueueFull);
+        }
+
+        for key in keys.chars() {
+            if let Some(code) = keycodes::from_char(key) {
+                self.press_key(code)?;
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// ## `press_proceed` Function
+    ///
+    /// Queues a press and release of the PRO key.
+    ///
+    pub fn press_proceed(&mut self) -> Result<(), KeyError> {
+        if self.keys.pending() + 2 > DSKY_KEY_QUEUE_LEN {
+            return Err(KeyError::QueueFull);
+        }
+        self.keys.press(DskyKeyEvent::Proceed(true))?;
+        self.keys.press(DskyKeyEvent::Proceed(false))
+    }
+
+    fn set_chan163(&mut self, value: u16) {
+        self.chan163 = value & CHAN163_MASK;
+        self.flash.set_lamps(self.chan163);
+        self.state.update(0o163, self.chan163);
+        self.state.flash_off = self.flash.flash_off();
+    }
+}
+
+impl AgcIoPeriph for HeadlessDsky {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_MNKEYIN => self.keys.keycode(),
+            io::CHANNEL_CHAN30 | io::CHANNEL_CHAN31 | io::CHANNEL_CHAN33 => 0o77777,
+            io::CHANNEL_CHAN32 => (0o77777 & !CHAN32_PROCEED) | self.keys.proceed_flag(),
+            0o163 => self.chan163,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        match channel_idx {
+            io::CHANNEL_DSKY | io::CHANNEL_CHAN13 => {
+                self.state.update(channel_idx, value);
+            }
+            io::CHANNEL_DSALMOUT => {
+                // Channel 11 lamps are mirrored on channel 163 like yaAGC
+                self.state.update(channel_idx, value);
+                self.set_chan163((self.chan163 & !0o00170) | (value & 0o00170));
+            }
+            0o163 => {
+                self.set_chan163(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        self.keys.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        if let Some(DskyKeyEvent::Key(keycodes::KEY_RSET)) = self.keys.pump_mcts(mcts) {
+            self.set_chan163(self.chan163 & !CHAN163_RESTART);
+        }
+        self.flash.pump_mcts(mcts);
+        self.state.flash_off = self.flash.flash_off();
+    }
+}
+
+#[cfg(test)]
+mod headless_dsky_tests {
+    use super::*;
+    use ragc_core::consts::cpu;
+
+    ///
+    /// # Description
+    ///
+    /// Tests queued keys are played back one per KEYRUPT1 with the key
+    /// timing, PRO is held on channel 32, and RSET turns off the RESTART
+    /// lamp.
+    ///
+    #[test]
+    fn test_headless_dsky_keys() {
+        let mut dsky = HeadlessDsky::new();
+        dsky.set_key_timing(10, 5);
+        dsky.press_keys("V37E").unwrap();
+        assert_eq!(dsky.press_keys("V3X"), Err(KeyError::InvalidKey('X')));
+        assert_eq!(dsky.pending_keys(), 4);
+
+        let mut keys = [(0, 0); 4];
+        let mut count = 0;
+        for mct in 0..60 {
+            dsky.pump_mcts(1);
+            if dsky.is_interrupt() == 1 << cpu::RUPT_KEY1 {
+                keys[count] = (mct, dsky.read(io::CHANNEL_MNKEYIN));
+                count += 1;
+            }
+        }
+        assert_eq!(
+            keys,
+            [(0, keycodes::KEY_VERB), (15, 3), (30, 7), (45, keycodes::KEY_ENTR)]
+        );
+        assert_eq!(dsky.pending_keys(), 0);
+
+        dsky.write(0o163, CHAN163_RESTART);
+        assert!(dsky.state().lamps.restart);
+        dsky.press_key(keycodes::KEY_RSET).unwrap();
+        dsky.pump_mcts(15);
+        assert_eq!(dsky.is_interrupt(), 1 << cpu::RUPT_KEY1);
+        assert!(!dsky.state().lamps.restart);
+
+        dsky.press_proceed().unwrap();
+        dsky.pump_mcts(15);
+        assert_eq!(dsky.read(io::CHANNEL_CHAN32) & CHAN32_PROCEED, 0);
+        dsky.pump_mcts(10);
+        assert_eq!(dsky.read(io::CHANNEL_CHAN32), 0o77777);
+        assert_eq!(dsky.is_interrupt(), 0);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the display state follows the channel 10 and 11 writes.
+    ///
+    #[test]
+    fn test_headless_dsky_display() {
+        let mut dsky = HeadlessDsky::new();
+        // VERB 06, with KEY REL lit
+        dsky.write(io::CHANNEL_DSKY, (10 << 11) | (21 << 5) | 28);
+        dsky.write(io::CHANNEL_DSALMOUT, 0o00020);
+        assert_eq!(dsky.state().verb_value(), Some(6));
+        assert!(dsky.state().lamps.key_rel);
+        assert_eq!(dsky.read(0o163), 0o00020);
+    }
+}
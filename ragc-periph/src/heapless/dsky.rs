@@ -1,21 +1,31 @@
 use heapless::spsc::{Consumer, Producer};
 use ragc_core;
-use crate::utils::{get_7seg, get_7seg_value};
+use yaagc_protocol::stream::{PacketDecoder, StreamError};
+use crate::keycodes;
+use crate::dsky_flash::DskyFlash;
+use crate::dsky_keys::{DskyKeyEvent, DskyKeyboard, DSKY_KEY_QUEUE_LEN};
+use crate::dsky_state::{lamp_test_value, DskyState};
 
+///
+/// ## DskyDisplay Peripheral
+///
+/// DSKY driven through spsc queues. Relay words and channel 11 are queued on
+/// `dsky_tx` as `(channel, value)`, and keypresses are taken from the
+/// keypress queue. The channel 163 lamps are queued on `flash_tx` as they
+/// should be shown, already flashed from emulated time (see `DskyFlash`).
+///
 pub struct DskyDisplay<'a> {
-    digit: [u8; 15],
-    noun: u16,
-    verb: u16,
-    prog: u16,
-    proceed: u16,
+    state: DskyState,
     output_flags: u16,
     keypress: Consumer<'a, u16, 8>,
-    keypress_val: u16,
+    keys: DskyKeyboard,
     dsky_tx: Producer<'a, (usize, u16), 64>,
     flash_tx: Producer<'a, u16, 8>,
+    flash: DskyFlash,
 
     last_dsalmout: u16,
     last_dskyval: u16,
+    last_row12: u16,
 }
 
 impl<'a> DskyDisplay<'a> {
@@ -25,80 +35,92 @@ impl<'a> DskyDisplay<'a> {
         flash_tx: Producer<'a, u16, 8>,
     ) -> Self {
         Self {
-            digit: [0; 15],
-            noun: 0,
-            verb: 0,
-            prog: 0,
+            state: DskyState::new(),
             keypress: keypress_rx,
             dsky_tx: dsky_tx,
-            keypress_val: 0,
-            proceed: 0o20000,
+            keys: DskyKeyboard::new(),
             flash_tx: flash_tx,
+            flash: DskyFlash::new(),
             output_flags: 0x0,
             last_dsalmout: 0x0,
             last_dskyval: 0x0,
+            last_row12: 12 << 11,
         }
     }
 
     ///
-    /// # Description
-    ///
-    /// The function parses the CHANNEL_DSKY IO channel value into multiple parts
-    /// that is used to capture the display nibbles and rows in which it is being
-    /// displayed on.
+    /// ## `state` Function
     ///
-    /// # Return Value
+    /// Returns what is currently shown on the DSKY.
     ///
-    /// - (u8 a, bool b, u8 c, u8 d)
-    ///   - `a` - Represents the rown in which the decoding is being performed on
-    ///   - `b` - Represents the boolean flag for some rows which determines the
-    ///           positive or negative display flags for a given DSKY value
-    ///   - `c` - Upper nibble of a given value on the DSKY
-    ///   - `d` - Lower nibble of a given value on the DSKY
-    ///
-    fn parse_fields(&self, val: u16) -> (u8, bool, u8, u8) {
-        let a: u8 = ((val >> 11) & 0xF) as u8;
-        let c: u8 = ((val >> 5) & 0x1F) as u8;
-        let d: u8 = (val & 0x1F) as u8;
-        let b: bool = if val & (1 << 10) == (1 << 10) {
-            true
-        } else {
-            false
-        };
-        (a, b, c, d)
+    pub fn state(&self) -> &DskyState {
+        &self.state
     }
 
     pub fn read_keypress(&self) -> u16 {
-        //debug!("DSKY: Reading keypress: {:?}", self.keypress_val);
-        self.keypress_val & 0x1F
+        //debug!("DSKY: Reading keypress: {:?}", self.keys.keycode());
+        self.keys.keycode()
     }
 
     pub fn set_channel_value(&mut self, channel_idx: usize, value: u16) {
         match channel_idx {
             0o13 => {
-                if value & 0o01000 != 0o00000 {
-                    self.output_flags |= 0o00400;
-                } else {
-                    self.output_flags &= 0o77377;
-                }
-                match self.flash_tx.enqueue(self.output_flags) {
-                    Err(x) => {
-                        //warn!("Unable to push to DSKY Flashing queue");
-                    }
-                    _ => {}
+                let changed = self.state.update(ragc_core::consts::io::CHANNEL_CHAN13, value);
+                if changed {
+                    self.send_lamps();
                 }
             }
             0o163 => {
                 self.output_flags = value;
-                match self.flash_tx.enqueue(self.output_flags) {
-                    Err(x) => {
-                        //warn!("Unable to push to DSKY Flashing queue");
-                    }
-                    _ => {}
-                }
             }
             _ => {}
         }
+        self.flash_lamps();
+    }
+
+    ///
+    /// ## `displayed` Function
+    ///
+    /// Returns the value of a lamp channel as queued for the display, with
+    /// every lamp lit during the lamp test.
+    ///
+    fn displayed(&self, channel_idx: usize, value: u16) -> u16 {
+        if self.state.lamp_test {
+            lamp_test_value(channel_idx, value)
+        } else {
+            value
+        }
+    }
+
+    ///
+    /// ## `send_lamps` Function
+    ///
+    /// Queues the channel 11 and relay row 12 lamps again for the display,
+    /// when the lamp test starts or ends.
+    ///
+    fn send_lamps(&mut self) {
+        let _res = self.dsky_tx.enqueue((0o11, self.displayed(0o11, self.last_dsalmout)));
+        let _res = self.dsky_tx.enqueue((0o10, self.displayed(0o10, self.last_row12)));
+    }
+
+    ///
+    /// ## `flash_lamps` Function
+    ///
+    /// Hands the channel 163 lamps over to the flash, and queues them for the
+    /// display if the flash shows them differently.
+    ///
+    fn flash_lamps(&mut self) {
+        self.flash.set_lamps(self.displayed(0o163, self.get_channel_value(0o163)));
+        self.send_flash();
+        self.state.update(0o163, self.get_channel_value(0o163));
+        self.state.flash_off = self.flash.flash_off();
+    }
+
+    fn send_flash(&mut self) {
+        if let Some(value) = self.flash.update() {
+            let _res = self.flash_tx.enqueue(value);
+            self.state.flash_off = self.flash.flash_off();
+        }
     }
 
     pub fn get_channel_value(&self, channel_idx: usize) -> u16 {
@@ -109,7 +131,17 @@ impl<'a> DskyDisplay<'a> {
     }
 
     pub fn read_proceed_flag(&self) -> u16 {
-        self.proceed
+        self.keys.proceed_flag()
+    }
+
+    ///
+    /// ## `set_key_timing` Function
+    ///
+    /// Sets the number of MCTs keys are held down, and the number of MCTs
+    /// between the release of a key and the next one. See `DskyKeyboard`.
+    ///
+    pub fn set_key_timing(&mut self, key_down: u32, key_up: u32) {
+        self.keys.set_timing(key_down, key_up);
     }
 
     ///
@@ -122,16 +154,12 @@ impl<'a> DskyDisplay<'a> {
             //debug!("DSKY: Setting CHANNEL_DSALMOUT Flags: {}", flags);
             self.last_dsalmout = flags;
             self.dsky_tx
-                .enqueue((0o11, flags))
+                .enqueue((0o11, self.displayed(0o11, flags)))
                 .unwrap();
 
+            self.state.update(ragc_core::consts::io::CHANNEL_DSALMOUT, flags);
             self.output_flags = (self.output_flags & 0o77607) | (flags & 0o00170);
-            match self.flash_tx.enqueue(self.output_flags) {
-                Err(_x) => {
-                    //warn!("Unable to push to DSKY Flashing queue");
-                }
-                _ => {}
-            }
+            self.flash_lamps();
         }
     }
 
@@ -140,16 +168,19 @@ impl<'a> DskyDisplay<'a> {
     ///
     /// This function is to handle row value 12 (decimal) for CHANNEL_DSKY which
     /// is different than all the other rows. This row handles specific light
-    /// indicators within the DSKYr
+    /// indicators within the DSKY: PRIO DISP, NO DAP, VEL, NO ATT, ALT,
+    /// GIMBAL LOCK, TRACKER and PROG.
     ///
     /// # Arguments
     ///
     /// - `flags` - Bitfield for row 12 which represents the specific indicators
     ///             for that row.
     ///
-    pub fn set_adv_flags(&mut self, _flags: u16) {
-        //println!("DSKY: Setting Adv Flags: {:x}", flags);
-        //self.lamps = (self.lamps & 0xFF) | ((flags as u32) << 8);
+    pub fn set_adv_flags(&mut self, flags: u16) {
+        let val = (12 << 11) | (flags & 0o03777);
+        self.last_row12 = val;
+        let _res = self.dsky_tx.enqueue((0o10, self.displayed(0o10, val)));
+        self.state.update(ragc_core::consts::io::CHANNEL_DSKY, val);
     }
 
     ///
@@ -170,56 +201,14 @@ impl<'a> DskyDisplay<'a> {
 
         //println!("DSKY: Setting CHANNEL_DSKY Value: {:x}", val);
         self.last_dskyval = val;
+        if (val >> 11) & 0o17 == 12 {
+            self.set_adv_flags(val);
+            return;
+        }
 
         let r = self.dsky_tx.enqueue((0o10, val));
-        let (a, _b, c, d) = self.parse_fields(val);
-        match a {
-            1 => {
-                self.digit[13] = get_7seg(c);
-                self.digit[14] = get_7seg(d);
-            }
-            2 => {
-                self.digit[11] = get_7seg(c);
-                self.digit[12] = get_7seg(d);
-            }
-            3 => {
-                self.digit[9] = get_7seg(c);
-                self.digit[10] = get_7seg(d);
-            }
-            4 => {
-                self.digit[7] = get_7seg(c);
-                self.digit[8] = get_7seg(d);
-            }
-            5 => {
-                self.digit[5] = get_7seg(c);
-                self.digit[6] = get_7seg(d);
-            }
-            6 => {
-                self.digit[3] = get_7seg(c);
-                self.digit[4] = get_7seg(d);
-            }
-            7 => {
-                self.digit[1] = get_7seg(c);
-                self.digit[2] = get_7seg(d);
-            }
-            8 => {
-                self.digit[0] = get_7seg(d);
-            }
-            11 => {
-                self.prog = get_7seg_value(c, d);
-            }
-            10 => {
-                self.verb = get_7seg_value(c, d);
-            }
-            9 => {
-                self.noun = get_7seg_value(c, d);
-            }
-            12 => {
-                self.set_adv_flags(val & 0x7FF);
-            }
-            _ => {}
-        };
-        //debug!("DSKY: {:?}", self.digit);
+        self.state.update(ragc_core::consts::io::CHANNEL_DSKY, val);
+        //debug!("DSKY: {:?}", self.state);
     }
 }
 
@@ -255,24 +244,243 @@ impl ragc_core::mem::periph::AgcIoPeriph for DskyDisplay<'_> {
     }
 
     fn is_interrupt(&mut self) -> u16 {
-        if self.keypress.len() > 0 {
-            let val = self.keypress.dequeue().unwrap();
-            //info!("DSKY: Recv'd {}", val);
-            match val & 0o40000 {
-                0o40000 => {
-                    self.proceed = val & 0o37777;
+        self.keys.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        // Keys stay in the keypress queue while the key queue is full
+        while self.keys.pending() < DSKY_KEY_QUEUE_LEN {
+            match self.keypress.dequeue() {
+                Some(val) => {
+                    let _res = self.keys.press(DskyKeyEvent::from_keypress(val));
                 }
-                _ => {
-                    self.keypress_val = val;
-                    if self.keypress_val == 0o22 {
-                        let io_val = self.get_channel_value(0o163);
-                        self.set_channel_value(0o163, io_val & !0o00200);
-                    }
+                None => break,
+            }
+        }
+        if let Some(DskyKeyEvent::Key(keycodes::KEY_RSET)) = self.keys.pump_mcts(mcts) {
+            let io_val = self.get_channel_value(0o163);
+            self.set_channel_value(0o163, io_val & !0o00200);
+        }
+
+        self.flash.pump_mcts(mcts);
+        self.send_flash();
+    }
+}
+
+///
+/// ## DskyInput Module
+///
+/// The DskyInput module decodes the yaAGC packet stream coming from a DSKY
+/// (i.e. over a UART) and queues the keypresses for the `DskyDisplay`. Bytes
+/// can be pushed one at a time as they are received.
+///
+pub struct DskyInput<'a> {
+    decoder: PacketDecoder,
+    keypress_tx: Producer<'a, u16, 8>,
+}
+
+impl<'a> DskyInput<'a> {
+    pub fn new(keypress_tx: Producer<'a, u16, 8>) -> Self {
+        Self {
+            decoder: PacketDecoder::new(),
+            keypress_tx,
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Pushes a byte of the DSKY stream. Keypresses are dropped if the
+    /// keypress queue is full.
+    ///
+    /// # Return Value
+    ///
+    /// - `Err(StreamError)` - The stream had to be resynchronised or carried
+    ///   an invalid packet
+    ///
+    pub fn push_byte(&mut self, byte: u8) -> Result<(), StreamError> {
+        match self.decoder.push(byte) {
+            Some(Ok(packet)) => {
+                if let Some(key) = keycodes::from_packet(&packet) {
+                    let _res = self.keypress_tx.enqueue(key);
                 }
+                Ok(())
             }
-            (1 << ragc_core::consts::cpu::RUPT_KEY1) as u16
-        } else {
-            0
+            Some(Err(x)) => Err(x),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod dsky_tests {
+    use super::*;
+    use heapless::spsc::Queue;
+    use crate::dsky_flash::{FLASH_ON_MCTS, FLASH_PERIOD_MCTS};
+    use ragc_core::mem::periph::AgcIoPeriph;
+    use yaagc_protocol::agc::generate_yaagc_packet;
+
+    ///
+    /// # Description
+    ///
+    /// Tests keypresses are queued from the DSKY stream, including after a
+    /// lost byte.
+    ///
+    #[test]
+    fn test_dsky_input() {
+        let mut q: Queue<u16, 8> = Queue::new();
+        let (tx, mut rx) = q.split();
+        let mut input = DskyInput::new(tx);
+
+        let verb = generate_yaagc_packet(0o15, 0o21);
+        let pro = generate_yaagc_packet(0o32, 0o00000);
+        // Tail of a packet received before the start of the stream
+        for byte in verb[1..].iter() {
+            assert_eq!(input.push_byte(*byte), Ok(()));
+        }
+        assert_eq!(input.push_byte(verb[0]), Err(StreamError::Resync(3)));
+        for byte in verb[1..].iter().chain(pro.iter()) {
+            assert_eq!(input.push_byte(*byte), Ok(()));
+        }
+
+        assert_eq!(rx.dequeue(), Some(0o21));
+        assert_eq!(rx.dequeue(), Some(0o40000));
+        assert_eq!(rx.dequeue(), None);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the decoded state follows the display writes, while the relay
+    /// words are still queued for the display.
+    ///
+    #[test]
+    fn test_dsky_state() {
+        let mut keys: Queue<u16, 8> = Queue::new();
+        let mut out: Queue<(usize, u16), 64> = Queue::new();
+        let mut flash: Queue<u16, 8> = Queue::new();
+        let (_keys_tx, keys_rx) = keys.split();
+        let (out_tx, mut out_rx) = out.split();
+        let (flash_tx, _flash_rx) = flash.split();
+        let mut dsky = DskyDisplay::new(keys_rx, out_tx, flash_tx);
+
+        // NOUN 63 and the COMP ACTY lamp
+        dsky.set_channel_dsky_value((9 << 11) | (28 << 5) | 27);
+        dsky.set_dsalmout_flags(0o00002);
+        assert_eq!(dsky.state().noun_value(), Some(63));
+        assert!(dsky.state().lamps.comp_acty);
+        assert_eq!(out_rx.dequeue(), Some((0o10, (9 << 11) | (28 << 5) | 27)));
+        assert_eq!(out_rx.dequeue(), Some((0o11, 0o00002)));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the lamp test lights every lamp on the display, and restores the
+    /// commanded lamps when it ends.
+    ///
+    #[test]
+    fn test_dsky_lamp_test() {
+        let mut keys: Queue<u16, 8> = Queue::new();
+        let mut out: Queue<(usize, u16), 64> = Queue::new();
+        let mut flash: Queue<u16, 8> = Queue::new();
+        let (_keys_tx, keys_rx) = keys.split();
+        let (out_tx, mut out_rx) = out.split();
+        let (flash_tx, mut flash_rx) = flash.split();
+        let mut dsky = DskyDisplay::new(keys_rx, out_tx, flash_tx);
+
+        // PROG lamp
+        dsky.set_channel_dsky_value((12 << 11) | 0o00400);
+        assert_eq!(out_rx.dequeue(), Some((0o10, (12 << 11) | 0o00400)));
+
+        dsky.set_channel_value(0o13, 0o01000);
+        assert!(dsky.state().lamp_test);
+        assert!(dsky.state().lit_lamps().key_rel);
+        assert!(!dsky.state().lamps.key_rel);
+        assert_eq!(out_rx.dequeue(), Some((0o11, 0o00137)));
+        assert_eq!(out_rx.dequeue(), Some((0o10, (12 << 11) | 0o00677)));
+        assert_eq!(flash_rx.dequeue(), Some(0o00731));
+
+        dsky.set_channel_value(0o13, 0o00000);
+        assert_eq!(out_rx.dequeue(), Some((0o11, 0o00000)));
+        assert_eq!(out_rx.dequeue(), Some((0o10, (12 << 11) | 0o00400)));
+        assert_eq!(flash_rx.dequeue(), Some(0o00000));
+        assert_eq!(dsky.read(0o163), 0o00000);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests VERB and NOUN flash and KEY REL blinks as the AGC runs, and
+    /// nothing changes while no MCTs go by.
+    ///
+    #[test]
+    fn test_dsky_flashing() {
+        let mut keys: Queue<u16, 8> = Queue::new();
+        let mut out: Queue<(usize, u16), 64> = Queue::new();
+        let mut flash: Queue<u16, 8> = Queue::new();
+        let (_keys_tx, keys_rx) = keys.split();
+        let (out_tx, _out_rx) = out.split();
+        let (flash_tx, mut flash_rx) = flash.split();
+        let mut dsky = DskyDisplay::new(keys_rx, out_tx, flash_tx);
+
+        // KEY REL lit with VERB and NOUN flashing
+        dsky.set_dsalmout_flags(0o00060);
+        assert_eq!(flash_rx.dequeue(), Some(0o00020));
+        assert!(dsky.state().flash && !dsky.state().flash_off);
+
+        for _i in 0..FLASH_ON_MCTS {
+            dsky.pump_mcts(1);
+        }
+        assert_eq!(flash_rx.dequeue(), Some(0o00040));
+        assert!(dsky.state().flash_off && dsky.state().lamps.key_rel);
+        assert_eq!(flash_rx.dequeue(), None);
+
+        for _i in FLASH_ON_MCTS..FLASH_PERIOD_MCTS {
+            dsky.pump_mcts(1);
         }
+        assert_eq!(flash_rx.dequeue(), Some(0o00020));
+        assert!(!dsky.state().flash_off);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests keys sent at once each reach the AGC with their own KEYRUPT1,
+    /// PRO is held on channel 32 without any interrupt, and RSET turns off
+    /// the RESTART lamp.
+    ///
+    #[test]
+    fn test_dsky_key_queue() {
+        let mut keys: Queue<u16, 8> = Queue::new();
+        let mut out: Queue<(usize, u16), 64> = Queue::new();
+        let mut flash: Queue<u16, 8> = Queue::new();
+        let (mut keys_tx, keys_rx) = keys.split();
+        let (out_tx, _out_rx) = out.split();
+        let (flash_tx, _flash_rx) = flash.split();
+        let mut dsky = DskyDisplay::new(keys_rx, out_tx, flash_tx);
+        dsky.set_key_timing(10, 5);
+
+        dsky.set_channel_value(0o163, 0o00200);
+        for key in [0o21, 0o40000, 0o60000, 0o22].iter() {
+            keys_tx.enqueue(*key).unwrap();
+        }
+
+        dsky.pump_mcts(1);
+        assert_eq!(dsky.is_interrupt(), 1 << ragc_core::consts::cpu::RUPT_KEY1);
+        assert_eq!(dsky.read(ragc_core::consts::io::CHANNEL_MNKEYIN), 0o21);
+        assert_eq!(dsky.read(ragc_core::consts::io::CHANNEL_CHAN32), 0o20000);
+
+        dsky.pump_mcts(15);
+        assert_eq!(dsky.is_interrupt(), 0);
+        assert_eq!(dsky.read(ragc_core::consts::io::CHANNEL_CHAN32), 0o00000);
+        dsky.pump_mcts(10);
+        assert_eq!(dsky.read(ragc_core::consts::io::CHANNEL_CHAN32), 0o20000);
+        assert_eq!(dsky.read(0o163), 0o00200);
+
+        dsky.pump_mcts(5);
+        assert_eq!(dsky.is_interrupt(), 1 << ragc_core::consts::cpu::RUPT_KEY1);
+        assert_eq!(dsky.read(ragc_core::consts::io::CHANNEL_MNKEYIN), 0o22);
+        assert_eq!(dsky.read(0o163), 0o00000);
     }
 }
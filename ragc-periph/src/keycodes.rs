@@ -0,0 +1,57 @@
+// DSKY keycodes as read by the AGC from channel 15 and sent through the
+// uplink
+
+use yaagc_protocol::agc::AGCPacket;
+
+pub const KEY_0: u16 = 0o20;
+pub const KEY_VERB: u16 = 0o21;
+pub const KEY_RSET: u16 = 0o22;
+pub const KEY_KEY_REL: u16 = 0o31;
+pub const KEY_PLUS: u16 = 0o32;
+pub const KEY_MINUS: u16 = 0o33;
+pub const KEY_ENTR: u16 = 0o34;
+pub const KEY_CLR: u16 = 0o36;
+pub const KEY_NOUN: u16 = 0o37;
+
+///
+/// ## `from_char` Function
+///
+/// Converts a key written as a single character into its keycode. Digits,
+/// `V` (VERB), `N` (NOUN), `E` (ENTR), `R` (RSET), `C` (CLR), `K` (KEY REL),
+/// `+` and `-` are accepted.
+///
+/// ### Result
+///
+///  - `Option<u16>` - Keycode of the key, `None` if the character is not a
+///    DSKY key.
+///
+pub fn from_char(key: char) -> Option<u16> {
+    match key {
+        '0' => Some(KEY_0),
+        '1'..='9' => Some(key as u16 - '0' as u16),
+        'V' => Some(KEY_VERB),
+        'N' => Some(KEY_NOUN),
+        'E' => Some(KEY_ENTR),
+        'R' => Some(KEY_RSET),
+        'C' => Some(KEY_CLR),
+        'K' => Some(KEY_KEY_REL),
+        '+' => Some(KEY_PLUS),
+        '-' => Some(KEY_MINUS),
+        _ => None,
+    }
+}
+
+///
+/// ## `from_packet` Function
+///
+/// Converts a yaAGC DSKY input packet into a keypress. Keys come in on
+/// channel 15, while the PRO key state comes in on channel 32 and is returned
+/// with bit 15 (0o40000) set to tell it apart.
+///
+pub fn from_packet(packet: &AGCPacket) -> Option<u16> {
+    match *packet {
+        AGCPacket::ChannelWrite { channel: 0o15, value } => Some(value),
+        AGCPacket::ChannelWrite { channel: 0o32, value } => Some(value | 0o40000),
+        _ => None,
+    }
+}
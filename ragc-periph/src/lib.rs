@@ -9,10 +9,27 @@ mod heapless;
 #[cfg(feature = "heapless-periph")]
 pub use crate::heapless::*;
 
+#[cfg(feature = "std")]
+pub mod telemetry;
+
 #[cfg(feature = "vagc-periph")]
 mod vagc;
 
 #[cfg(feature = "vagc-periph")]
 pub use vagc::*;
 
-mod utils;
+pub mod downlist;
+pub mod dsky_flash;
+pub mod dsky_keys;
+pub mod dsky_script;
+pub mod dsky_state;
+pub mod engine;
+pub mod hand_controller;
+pub mod headless_dsky;
+pub mod keycodes;
+pub mod nav_keys;
+pub mod radar;
+pub mod rcs;
+pub mod uplink;
+
+pub mod utils;
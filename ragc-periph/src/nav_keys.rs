@@ -0,0 +1,201 @@
+use ragc_core::consts::{cpu, io};
+use ragc_core::mem::periph::AgcIoPeriph;
+
+use crate::headless_dsky::KeyError;
+use crate::keycodes;
+
+/// Number of inputs that can be queued on `NavKeys`.
+pub const NAV_KEYS_QUEUE_LEN: usize = 32;
+
+/// Minimum number of MCTs between two inputs delivered to the AGC (about
+/// 20ms), so each KEYRUPT2 is serviced before channel 16 changes again.
+pub const NAV_KEYS_MIN_MCTS: u32 = 20000000 / 11700;
+
+/// CM channel 16 optics MARK button.
+pub const NAV_CM_MARK: u16 = 0o00040;
+/// CM channel 16 optics MARK REJECT button.
+pub const NAV_CM_MARK_REJECT: u16 = 0o00100;
+
+/// LM channel 16 AOT X-axis mark.
+pub const NAV_LM_MARK_X: u16 = 0o00004;
+/// LM channel 16 AOT Y-axis mark.
+pub const NAV_LM_MARK_Y: u16 = 0o00010;
+/// LM channel 16 MARK REJECT button.
+pub const NAV_LM_MARK_REJECT: u16 = 0o00020;
+/// LM channel 16 rate of descent switch, DESCENT+ (slower descent).
+pub const NAV_LM_DESCENT_PLUS: u16 = 0o00040;
+/// LM channel 16 rate of descent switch, DESCENT- (faster descent).
+pub const NAV_LM_DESCENT_MINUS: u16 = 0o00100;
+
+// Channel 16 bits used by the AGC
+const CHAN16_MASK: u16 = 0o00177;
+
+///
+/// ## NavKeys Peripheral
+///
+/// The NavKeys peripheral drives the NAVKEYIN channel (16), sensed by the AGC
+/// on KEYRUPT2 (called MARKRUPT in the LM flight software). It carries the
+/// keys of the CM navigation bay DSKY along with the optics MARK and MARK
+/// REJECT buttons, or the LM AOT marks, MARK REJECT and rate of descent
+/// switch. See the `NAV_CM_*` and `NAV_LM_*` bits.
+///
+/// Inputs are queued and delivered one at a time, at least
+/// `NAV_KEYS_MIN_MCTS` apart, each one followed by a KEYRUPT2. Channel 16
+/// keeps the last input until the next one.
+///
+pub struct NavKeys {
+    queue: [u16; NAV_KEYS_QUEUE_LEN],
+    head: usize,
+    len: usize,
+
+    since_last: u32,
+    value: u16,
+    rupt: bool,
+}
+
+impl Default for NavKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NavKeys {
+    pub fn new() -> Self {
+        NavKeys {
+            queue: [0; NAV_KEYS_QUEUE_LEN],
+            head: 0,
+            len: 0,
+            since_last: NAV_KEYS_MIN_MCTS,
+            value: 0,
+            rupt: false,
+        }
+    }
+
+    ///
+    /// ## `pending` Function
+    ///
+    /// Returns the number of inputs not delivered to the AGC yet.
+    ///
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    ///
+    /// ## `press` Function
+    ///
+    /// Queues a raw channel 16 input, such as `NAV_CM_MARK` or a navigation
+    /// DSKY keycode. Only bits 1-7 are kept.
+    ///
+    pub fn press(&mut self, value: u16) -> Result<(), KeyError> {
+        if self.len == NAV_KEYS_QUEUE_LEN {
+            return Err(KeyError::QueueFull);
+        }
+        self.queue[(self.head + self.len) % NAV_KEYS_QUEUE_LEN] = value & CHAN16_MASK;
+        self.len += 1;
+        Ok(())
+    }
+
+    ///
+    /// ## `press_keys` Function
+    ///
+    /// Queues a sequence of navigation DSKY keys such as `V37E`. See
+    /// `keycodes::from_char` for the accepted characters. Nothing is queued if
+    /// any of the keys is invalid or the whole sequence does not fit.
+    ///
+    pub fn press_keys(&mut self, keys: &str) -> Result<(), KeyError> {
+        let mut count = 0;
+        for key in keys.chars() {
+            keycodes::from_char(key).ok_or(KeyError::InvalidKey(key))?;
+            count += 1;
+        }
+        if self.len + count > NAV_KEYS_QUEUE_LEN {
+            return Err(KeyError::QueueFull);
+        }
+
+        for key in keys.chars() {
+            if let Some(code) = keycodes::from_char(key) {
+                self.press(code)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AgcIoPeriph for NavKeys {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_NAVKEYIN => self.value,
+            io::CHANNEL_CHAN30 | io::CHANNEL_CHAN31 | io::CHANNEL_CHAN32 | io::CHANNEL_CHAN33 => {
+                0o77777
+            }
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, _channel_idx: usize, _value: u16) {}
+
+    fn is_interrupt(&mut self) -> u16 {
+        if self.rupt {
+            self.rupt = false;
+            1 << cpu::RUPT_KEY2
+        } else {
+            0
+        }
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.since_last = self.since_last.saturating_add(mcts as u32);
+        if self.len == 0 || self.since_last < NAV_KEYS_MIN_MCTS {
+            return;
+        }
+
+        self.value = self.queue[self.head];
+        self.head = (self.head + 1) % NAV_KEYS_QUEUE_LEN;
+        self.len -= 1;
+        self.since_last = 0;
+        self.rupt = true;
+    }
+}
+
+#[cfg(test)]
+mod nav_keys_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests navigation DSKY keys and marks are delivered on channel 16 one
+    /// at a time, spaced out and each followed by a KEYRUPT2.
+    ///
+    #[test]
+    fn test_nav_keys() {
+        let mut nav = NavKeys::new();
+        assert_eq!(nav.read(io::CHANNEL_NAVKEYIN), 0);
+        nav.press_keys("V3").unwrap();
+        nav.press(NAV_CM_MARK).unwrap();
+        assert_eq!(nav.press_keys("V3X"), Err(KeyError::InvalidKey('X')));
+        assert_eq!(nav.pending(), 3);
+
+        let mut inputs = [0; 3];
+        let mut count = 0;
+        let mut mcts = 0;
+        let mut last = 0;
+        while count < 3 {
+            nav.pump_mcts(1);
+            mcts += 1;
+            if nav.is_interrupt() == 1 << cpu::RUPT_KEY2 {
+                assert!(count == 0 || mcts - last >= NAV_KEYS_MIN_MCTS);
+                inputs[count] = nav.read(io::CHANNEL_NAVKEYIN);
+                count += 1;
+                last = mcts;
+            }
+        }
+        assert_eq!(inputs, [keycodes::KEY_VERB, 3, NAV_CM_MARK]);
+
+        for _i in 0..2 * NAV_KEYS_MIN_MCTS {
+            nav.pump_mcts(1);
+            assert_eq!(nav.is_interrupt(), 0);
+        }
+        assert_eq!(nav.read(io::CHANNEL_NAVKEYIN), NAV_CM_MARK);
+    }
+}
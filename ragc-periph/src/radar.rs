@@ -0,0 +1,353 @@
+use ragc_core::consts::{cpu, io, special};
+use ragc_core::mem::periph::AgcIoPeriph;
+
+/// Number of MCTs it takes for a radar to shift a full value into RNRAD
+/// after the AGC sets the RADAR ACTIVITY bit (about 80ms).
+pub const RADAR_READ_DELAY_MCTS: u32 = 80000000 / 11700;
+
+// Channel 13 bits used for selecting and starting a radar read
+const CHAN13_RADAR_SELECT_MASK: u16 = 0o00007;
+const CHAN13_RADAR_ACTIVITY: u16 = 0o00010;
+
+// Channel 12 bit used to command the landing radar antenna to position 2
+const CHAN12_LR_POS2_CMD: u16 = 0o10000;
+
+// Channel 33 bits (inverted) reported by the radars
+const CHAN33_RR_AUTO_POWER: u16 = 0o00002;
+const CHAN33_RR_RANGE_LOW_SCALE: u16 = 0o00004;
+const CHAN33_RR_DATA_GOOD: u16 = 0o00010;
+const CHAN33_LR_RANGE_DATA_GOOD: u16 = 0o00020;
+const CHAN33_LR_POS1: u16 = 0o00040;
+const CHAN33_LR_POS2: u16 = 0o00100;
+const CHAN33_LR_VEL_DATA_GOOD: u16 = 0o00200;
+const CHAN33_LR_RANGE_LOW_SCALE: u16 = 0o00400;
+
+///
+/// ## `RadarSelect` Enum
+///
+/// Radar parameters which can be selected through bits 1-3 (RADAR C, B, A) of
+/// channel 13 before a radar read is started.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadarSelect {
+    RrRangeRate,
+    RrRange,
+    LrVelX,
+    LrVelY,
+    LrVelZ,
+    LrRange,
+}
+
+impl RadarSelect {
+    ///
+    /// ## `from_select_bits` Function
+    ///
+    /// Converts the channel 13 radar select bits into the radar parameter
+    /// being requested.
+    ///
+    /// ### Result
+    ///
+    ///  - `None` if the select code does not map to a radar parameter
+    ///
+    pub fn from_select_bits(bits: u16) -> Option<RadarSelect> {
+        match bits & CHAN13_RADAR_SELECT_MASK {
+            1 => Some(RadarSelect::RrRangeRate),
+            2 => Some(RadarSelect::RrRange),
+            4 => Some(RadarSelect::LrVelX),
+            5 => Some(RadarSelect::LrVelY),
+            6 => Some(RadarSelect::LrVelZ),
+            7 => Some(RadarSelect::LrRange),
+            _ => None,
+        }
+    }
+
+    pub fn is_rendezvous_radar(&self) -> bool {
+        matches!(self, RadarSelect::RrRangeRate | RadarSelect::RrRange)
+    }
+}
+
+///
+/// ## `RadarSample` Structure
+///
+/// Value returned by the trajectory callback for a given radar read.
+///
+///  - `value` - Raw 15-bit radar counts to shift into RNRAD
+///  - `data_good` - Whether the radar reports the data as good
+///  - `low_scale` - Whether the range is reported on the low scale. This is
+///    only used for RR and LR range reads.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RadarSample {
+    pub value: u16,
+    pub data_good: bool,
+    pub low_scale: bool,
+}
+
+///
+/// ## LmRadar Peripheral
+///
+/// The LmRadar peripheral models the LM rendezvous and landing radars. When
+/// the AGC sets the RADAR ACTIVITY bit of channel 13, the radar selected by
+/// the channel 13 select bits is sampled through the user provided
+/// `trajectory` callback. After `RADAR_READ_DELAY_MCTS`, the value is loaded
+/// into RNRAD and a RADARUPT is generated.
+///
+/// The trajectory callback is given the selected radar parameter and the
+/// number of MCTs elapsed since the peripheral was created.
+///
+pub struct LmRadar<F>
+where
+    F: FnMut(RadarSelect, u64) -> RadarSample,
+{
+    trajectory: F,
+    read_delay: u32,
+    mcts: u64,
+
+    activity: bool,
+    pending: Option<(RadarSelect, u32)>,
+    rnrad: Option<u16>,
+    rupt: bool,
+
+    lr_pos2: bool,
+    rr_auto_power: bool,
+    chan33: u16,
+}
+
+impl<F> LmRadar<F>
+where
+    F: FnMut(RadarSelect, u64) -> RadarSample,
+{
+    pub fn new(trajectory: F) -> Self {
+        LmRadar {
+            trajectory,
+            read_delay: RADAR_READ_DELAY_MCTS,
+            mcts: 0,
+            activity: false,
+            pending: None,
+            rnrad: None,
+            rupt: false,
+            lr_pos2: false,
+            rr_auto_power: false,
+            chan33: 0o77777,
+        }
+    }
+
+    ///
+    /// ## `with_read_delay` Function
+    ///
+    /// Overrides the number of MCTs between the start of a radar read and the
+    /// RADARUPT.
+    ///
+    pub fn with_read_delay(mut self, mcts: u32) -> Self {
+        self.read_delay = mcts;
+        self
+    }
+
+    ///
+    /// ## `set_rr_auto_power` Function
+    ///
+    /// Sets the state of the RR AUTO-POWER ON discrete (channel 33 bit 2).
+    ///
+    pub fn set_rr_auto_power(&mut self, enable: bool) {
+        self.rr_auto_power = enable;
+    }
+
+    ///
+    /// ## `mcts` Function
+    ///
+    /// Returns the number of MCTs elapsed since the peripheral was created.
+    ///
+    pub fn mcts(&self) -> u64 {
+        self.mcts
+    }
+
+    fn start_read(&mut self, select_bits: u16) {
+        // Select codes which do not map to a radar never complete, just as the
+        // hardware would never shift in any data.
+        self.pending = RadarSelect::from_select_bits(select_bits)
+            .map(|select| (select, self.read_delay));
+    }
+
+    fn complete_read(&mut self, select: RadarSelect) {
+        let sample = (self.trajectory)(select, self.mcts);
+
+        let (good_bit, low_scale_bit) = match select {
+            RadarSelect::RrRangeRate => (CHAN33_RR_DATA_GOOD, 0),
+            RadarSelect::RrRange => (CHAN33_RR_DATA_GOOD, CHAN33_RR_RANGE_LOW_SCALE),
+            RadarSelect::LrVelX | RadarSelect::LrVelY | RadarSelect::LrVelZ => {
+                (CHAN33_LR_VEL_DATA_GOOD, 0)
+            }
+            RadarSelect::LrRange => (CHAN33_LR_RANGE_DATA_GOOD, CHAN33_LR_RANGE_LOW_SCALE),
+        };
+
+        // Channel 33 is inverted, so a signal which is present is a zero bit
+        if sample.data_good {
+            self.chan33 &= !good_bit;
+        } else {
+            self.chan33 |= good_bit;
+        }
+        if sample.low_scale {
+            self.chan33 &= !low_scale_bit;
+        } else {
+            self.chan33 |= low_scale_bit;
+        }
+
+        self.rnrad = Some(sample.value & 0o77777);
+        self.rupt = true;
+    }
+
+    fn get_channel33(&self) -> u16 {
+        let mut val = self.chan33;
+        if self.rr_auto_power {
+            val &= !CHAN33_RR_AUTO_POWER;
+        }
+        if self.lr_pos2 {
+            val &= !CHAN33_LR_POS2;
+        } else {
+            val &= !CHAN33_LR_POS1;
+        }
+        val
+    }
+}
+
+impl<F> AgcIoPeriph for LmRadar<F>
+where
+    F: FnMut(RadarSelect, u64) -> RadarSample,
+{
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_CHAN33 => self.get_channel33(),
+            io::CHANNEL_CHAN30 | io::CHANNEL_CHAN31 | io::CHANNEL_CHAN32 => 0o77777,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        match channel_idx {
+            io::CHANNEL_CHAN12 => {
+                self.lr_pos2 = value & CHAN12_LR_POS2_CMD != 0;
+            }
+            io::CHANNEL_CHAN13 => {
+                let activity = value & CHAN13_RADAR_ACTIVITY != 0;
+                if activity && !self.activity {
+                    self.start_read(value);
+                } else if !activity {
+                    // Clearing the activity bit aborts any read in progress
+                    self.pending = None;
+                }
+                self.activity = activity;
+            }
+            _ => {}
+        }
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        if self.rupt {
+            self.rupt = false;
+            1 << cpu::RUPT_RADAR
+        } else {
+            0
+        }
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.mcts += mcts as u64;
+
+        if let Some((select, remaining)) = self.pending {
+            if remaining <= mcts as u32 {
+                self.pending = None;
+                self.complete_read(select);
+            } else {
+                self.pending = Some((select, remaining - mcts as u32));
+            }
+        }
+    }
+
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        self.rnrad.take().map(|value| (special::SG_RNRAD, value))
+    }
+}
+
+#[cfg(test)]
+mod radar_tests {
+    use super::*;
+
+    fn constant_trajectory(select: RadarSelect, _mcts: u64) -> RadarSample {
+        match select {
+            RadarSelect::LrRange => RadarSample {
+                value: 0o12345,
+                data_good: true,
+                low_scale: false,
+            },
+            _ => RadarSample {
+                value: 0o00100,
+                data_good: false,
+                low_scale: false,
+            },
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that a landing radar range read loads RNRAD and generates a
+    /// RADARUPT only after the read delay has elapsed.
+    ///
+    #[test]
+    fn test_radar_read_delay() {
+        let mut radar = LmRadar::new(constant_trajectory).with_read_delay(100);
+
+        radar.write(io::CHANNEL_CHAN13, CHAN13_RADAR_ACTIVITY | 7);
+        radar.pump_mcts(99);
+        assert_eq!(radar.is_interrupt(), 0, "RADARUPT before read delay");
+        assert_eq!(radar.counter_update(), None);
+
+        radar.pump_mcts(1);
+        assert_eq!(radar.counter_update(), Some((special::SG_RNRAD, 0o12345)));
+        assert_eq!(radar.counter_update(), None);
+        assert_eq!(radar.is_interrupt(), 1 << cpu::RUPT_RADAR);
+        assert_eq!(radar.is_interrupt(), 0, "RADARUPT should only occur once");
+
+        assert_eq!(radar.read(io::CHANNEL_CHAN33) & CHAN33_LR_RANGE_DATA_GOOD, 0);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that clearing the RADAR ACTIVITY bit aborts a pending read, and
+    /// that a bad sample reports the data good bit as not present.
+    ///
+    #[test]
+    fn test_radar_read_abort_and_data_good() {
+        let mut radar = LmRadar::new(constant_trajectory).with_read_delay(10);
+
+        radar.write(io::CHANNEL_CHAN13, CHAN13_RADAR_ACTIVITY | 2);
+        radar.write(io::CHANNEL_CHAN13, 0);
+        radar.pump_mcts(20);
+        assert_eq!(radar.is_interrupt(), 0, "Aborted read generated RADARUPT");
+
+        radar.write(io::CHANNEL_CHAN13, CHAN13_RADAR_ACTIVITY | 2);
+        radar.pump_mcts(10);
+        assert_eq!(radar.counter_update(), Some((special::SG_RNRAD, 0o00100)));
+        assert_eq!(
+            radar.read(io::CHANNEL_CHAN33) & CHAN33_RR_DATA_GOOD,
+            CHAN33_RR_DATA_GOOD
+        );
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the landing radar antenna position discretes follow the
+    /// channel 12 position 2 command.
+    ///
+    #[test]
+    fn test_lr_position() {
+        let mut radar = LmRadar::new(constant_trajectory);
+        assert_eq!(radar.read(io::CHANNEL_CHAN33) & CHAN33_LR_POS1, 0);
+
+        radar.write(io::CHANNEL_CHAN12, CHAN12_LR_POS2_CMD);
+        assert_eq!(radar.read(io::CHANNEL_CHAN33) & CHAN33_LR_POS2, 0);
+        assert_eq!(radar.read(io::CHANNEL_CHAN33) & CHAN33_LR_POS1, CHAN33_LR_POS1);
+    }
+}
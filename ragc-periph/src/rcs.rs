@@ -0,0 +1,358 @@
+use ragc_core::consts::io;
+use ragc_core::mem::periph::AgcIoPeriph;
+
+/// Number of RCS jets commanded through channels 5 and 6.
+pub const RCS_JET_COUNT: usize = 16;
+
+// LM jet numbers driven by channel 5 (pitch/yaw) bits 1-8:
+//   B4U, A4D, A3U, B3D, B2U, A2D, A1U, B1D
+const LM_CHAN5_JETS: [u8; 8] = [1, 2, 5, 6, 9, 10, 13, 14];
+
+// LM jet numbers driven by channel 6 (roll) bits 1-8:
+//   B3A, B4F, A1F, A2A, B2L, A3R, A4R, B1L
+const LM_CHAN6_JETS: [u8; 8] = [7, 3, 15, 11, 12, 8, 4, 16];
+
+// LM jet pairs disabled by the crew through channel 32 bits 1-8
+const LM_CHAN32_DISABLE_PAIRS: [(u8, u8); 8] = [
+    (2, 4),
+    (5, 8),
+    (1, 3),
+    (6, 7),
+    (14, 16),
+    (13, 15),
+    (9, 12),
+    (10, 11),
+];
+
+///
+/// ## `RcsVehicle` Enum
+///
+/// Selects how channels 5 and 6 map onto jets.
+///
+///  - `Lm` - LM jets numbered 1-16 as in LUMINARY, with crew disable through
+///    channel 32 bits 1-8.
+///  - `Cm` - Channel 5 bits 1-8 are jets 1-8 and channel 6 bits 1-8 are jets
+///    9-16. Channel 32 has no crew disable bits.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RcsVehicle {
+    Lm,
+    Cm,
+}
+
+///
+/// ## `JetEvent` Structure
+///
+/// Reported every time a jet starts or stops firing. `mcts` is the number of
+/// MCTs elapsed since the peripheral was created.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JetEvent {
+    pub mcts: u64,
+    pub jet: u8,
+    pub firing: bool,
+}
+
+///
+/// ## RcsJets Peripheral
+///
+/// The RcsJets peripheral decodes the jet commands written by the DAP to
+/// channels 5 (PYJETS) and 6 (ROLLJETS) into individual jets. A jet fires
+/// when it is commanded and not disabled by the crew. Every change is
+/// reported to the `on_event` callback, and the total firing time per jet is
+/// accumulated in MCTs.
+///
+pub struct RcsJets<F>
+where
+    F: FnMut(JetEvent),
+{
+    vehicle: RcsVehicle,
+    on_event: F,
+    mcts: u64,
+
+    commanded: u16,
+    disabled: u16,
+    chan32_disable: u16,
+    firing_mcts: [u64; RCS_JET_COUNT],
+}
+
+fn jet_mask(jet: u8) -> u16 {
+    match jet as usize {
+        x @ 1..=RCS_JET_COUNT => 1 << (x - 1),
+        _ => 0,
+    }
+}
+
+impl<F> RcsJets<F>
+where
+    F: FnMut(JetEvent),
+{
+    pub fn new(vehicle: RcsVehicle, on_event: F) -> Self {
+        RcsJets {
+            vehicle,
+            on_event,
+            mcts: 0,
+            commanded: 0,
+            disabled: 0,
+            chan32_disable: 0,
+            firing_mcts: [0; RCS_JET_COUNT],
+        }
+    }
+
+    ///
+    /// ## `set_crew_disable` Function
+    ///
+    /// Sets the crew jet disable switches. `bits` uses the channel 32 bit
+    /// layout (bits 1-8), with a one meaning the pair of jets is disabled.
+    /// This has no effect for the CM.
+    ///
+    pub fn set_crew_disable(&mut self, bits: u16) {
+        if self.vehicle != RcsVehicle::Lm {
+            return;
+        }
+
+        self.chan32_disable = bits & 0o377;
+        self.disabled = LM_CHAN32_DISABLE_PAIRS
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| self.chan32_disable & (1 << idx) != 0)
+            .fold(0, |mask, (_, (a, b))| mask | jet_mask(*a) | jet_mask(*b));
+        self.update_firing(self.commanded);
+    }
+
+    ///
+    /// ## `is_firing` Function
+    ///
+    /// Returns whether the given jet (1-16) is currently firing, `false` for
+    /// any other jet number.
+    ///
+    pub fn is_firing(&self, jet: u8) -> bool {
+        self.firing() & jet_mask(jet) != 0
+    }
+
+    ///
+    /// ## `firing_mcts` Function
+    ///
+    /// Returns the accumulated firing time of the given jet (1-16) in MCTs,
+    /// 0 for any other jet number.
+    ///
+    pub fn firing_mcts(&self, jet: u8) -> u64 {
+        match jet as usize {
+            x @ 1..=RCS_JET_COUNT => self.firing_mcts[x - 1],
+            _ => 0,
+        }
+    }
+
+    ///
+    /// ## `firing_secs` Function
+    ///
+    /// Returns the accumulated firing time of the given jet (1-16) in
+    /// seconds.
+    ///
+    pub fn firing_secs(&self, jet: u8) -> f64 {
+        self.firing_mcts(jet) as f64 * 11.7 / 1000000.0
+    }
+
+    pub fn mcts(&self) -> u64 {
+        self.mcts
+    }
+
+    fn firing(&self) -> u16 {
+        self.commanded & !self.disabled
+    }
+
+    fn decode(&self, channel_idx: usize, value: u16) -> u16 {
+        match self.vehicle {
+            RcsVehicle::Lm => {
+                let table = if channel_idx == io::CHANNEL_PYJETS {
+                    &LM_CHAN5_JETS
+                } else {
+                    &LM_CHAN6_JETS
+                };
+                table
+                    .iter()
+                    .enumerate()
+                    .filter(|(bit, _)| value & (1 << bit) != 0)
+                    .fold(0, |mask, (_, jet)| mask | jet_mask(*jet))
+            }
+            RcsVehicle::Cm => {
+                if channel_idx == io::CHANNEL_PYJETS {
+                    value & 0o377
+                } else {
+                    (value & 0o377) << 8
+                }
+            }
+        }
+    }
+
+    fn update_firing(&mut self, commanded: u16) {
+        let old = self.firing();
+        self.commanded = commanded;
+        let new = self.firing();
+
+        for jet in 1..=RCS_JET_COUNT as u8 {
+            if (old ^ new) & jet_mask(jet) != 0 {
+                (self.on_event)(JetEvent {
+                    mcts: self.mcts,
+                    jet,
+                    firing: new & jet_mask(jet) != 0,
+                });
+            }
+        }
+    }
+}
+
+impl<F> AgcIoPeriph for RcsJets<F>
+where
+    F: FnMut(JetEvent),
+{
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            // Channel 32 is inverted, so a disabled pair is a zero bit
+            io::CHANNEL_CHAN32 => 0o77777 & !self.chan32_disable,
+            io::CHANNEL_CHAN30 | io::CHANNEL_CHAN31 | io::CHANNEL_CHAN33 => 0o77777,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        match channel_idx {
+            io::CHANNEL_PYJETS => {
+                let other = self.commanded & !self.decode(io::CHANNEL_PYJETS, 0o377);
+                let commanded = other | self.decode(io::CHANNEL_PYJETS, value);
+                self.update_firing(commanded);
+            }
+            io::CHANNEL_ROLLJETS => {
+                let other = self.commanded & !self.decode(io::CHANNEL_ROLLJETS, 0o377);
+                let commanded = other | self.decode(io::CHANNEL_ROLLJETS, value);
+                self.update_firing(commanded);
+            }
+            _ => {}
+        }
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        0
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.mcts += mcts as u64;
+
+        let firing = self.firing();
+        for (idx, total) in self.firing_mcts.iter_mut().enumerate() {
+            if firing & (1 << idx) != 0 {
+                *total += mcts as u64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rcs_tests {
+    use super::*;
+    use core::cell::RefCell;
+
+    ///
+    /// # Description
+    ///
+    /// Tests the LM channel 5 and 6 bits are decoded into the proper jets,
+    /// with events timestamped in MCTs.
+    ///
+    #[test]
+    fn test_lm_jet_decoding() {
+        let events = RefCell::new([None; 4]);
+        let count = RefCell::new(0);
+        {
+            let mut rcs = RcsJets::new(RcsVehicle::Lm, |ev| {
+                events.borrow_mut()[*count.borrow()] = Some(ev);
+                *count.borrow_mut() += 1;
+            });
+
+            // A3U from channel 5 and B1L from channel 6
+            rcs.pump_mcts(10);
+            rcs.write(io::CHANNEL_PYJETS, 0o00004);
+            rcs.write(io::CHANNEL_ROLLJETS, 0o00200);
+            assert!(rcs.is_firing(5));
+            assert!(rcs.is_firing(16));
+
+            rcs.pump_mcts(20);
+            rcs.write(io::CHANNEL_PYJETS, 0o00000);
+            assert!(!rcs.is_firing(5));
+            assert!(rcs.is_firing(16), "Channel 5 write stopped a roll jet");
+        }
+
+        let events = events.into_inner();
+        assert_eq!(*count.borrow(), 3);
+        assert_eq!(events[0], Some(JetEvent { mcts: 10, jet: 5, firing: true }));
+        assert_eq!(events[1], Some(JetEvent { mcts: 10, jet: 16, firing: true }));
+        assert_eq!(events[2], Some(JetEvent { mcts: 30, jet: 5, firing: false }));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that jets disabled by the crew through channel 32 never fire
+    /// and are reported to the AGC.
+    ///
+    #[test]
+    fn test_lm_crew_disable() {
+        let mut rcs = RcsJets::new(RcsVehicle::Lm, |_| {});
+
+        // Commanding B4U and A4D, then disabling thrusters 2 & 4
+        rcs.write(io::CHANNEL_PYJETS, 0o00003);
+        rcs.set_crew_disable(0o00001);
+        assert!(rcs.is_firing(1));
+        assert!(!rcs.is_firing(2));
+        assert_eq!(rcs.read(io::CHANNEL_CHAN32), 0o77776);
+
+        rcs.pump_mcts(100);
+        assert_eq!(rcs.firing_mcts(1), 100);
+        assert_eq!(rcs.firing_mcts(2), 0);
+
+        rcs.set_crew_disable(0);
+        rcs.pump_mcts(50);
+        assert_eq!(rcs.firing_mcts(2), 50);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the CM jets map directly onto channel bits and accumulate
+    /// firing time.
+    ///
+    #[test]
+    fn test_cm_jet_firing_time() {
+        let mut rcs = RcsJets::new(RcsVehicle::Cm, |_| {});
+
+        rcs.write(io::CHANNEL_ROLLJETS, 0o00001);
+        rcs.set_crew_disable(0o00377);
+        rcs.pump_mcts(1000);
+        rcs.write(io::CHANNEL_ROLLJETS, 0o00000);
+        rcs.pump_mcts(1000);
+
+        assert_eq!(rcs.firing_mcts(9), 1000);
+        assert!((rcs.firing_secs(9) - 0.0117).abs() < 1e-9);
+        assert_eq!(rcs.read(io::CHANNEL_CHAN32), 0o77777);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests jet numbers outside of 1-16 are never firing and have no firing
+    /// time, instead of panicking.
+    ///
+    #[test]
+    fn test_jet_out_of_range() {
+        let mut rcs = RcsJets::new(RcsVehicle::Lm, |_| {});
+        rcs.write(io::CHANNEL_PYJETS, 0o00377);
+        rcs.write(io::CHANNEL_ROLLJETS, 0o00377);
+        rcs.pump_mcts(100);
+
+        for jet in [0, 17, 255].iter() {
+            assert!(!rcs.is_firing(*jet));
+            assert_eq!(rcs.firing_mcts(*jet), 0);
+            assert_eq!(rcs.firing_secs(*jet), 0.0);
+        }
+        assert_eq!(rcs.firing_mcts(16), 100);
+    }
+}
@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::string::String;
+
+/// Magic bytes at the start of a binary telemetry recording.
+pub const TELEMETRY_MAGIC: [u8; 8] = *b"RAGCTLM1";
+
+/// Header line of a CSV telemetry recording.
+pub const TELEMETRY_CSV_HEADER: &str = "mcts,word_order,word1,word2";
+
+// Size of a binary record: MCTs, word order and both downlink words
+const RECORD_SIZE: usize = 14;
+
+///
+/// ## `TelemetryFormat` Enum
+///
+/// File format of a telemetry recording.
+///
+///  - `Binary` - `TELEMETRY_MAGIC` followed by little endian records of the
+///    MCT timestamp (u64), the word order bit (u16) and both words (u16)
+///  - `Csv` - `TELEMETRY_CSV_HEADER` followed by one line per word pair, with
+///    the words written in octal
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryFormat {
+    Binary,
+    Csv,
+}
+
+impl TelemetryFormat {
+    ///
+    /// ## `from_path` Function
+    ///
+    /// Selects the CSV format for `.csv` files and the binary format for
+    /// anything else.
+    ///
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|x| x.to_str()) {
+            Some(x) if x.eq_ignore_ascii_case("csv") => TelemetryFormat::Csv,
+            _ => TelemetryFormat::Binary,
+        }
+    }
+}
+
+///
+/// ## `TelemetryRecord` Structure
+///
+/// DOWNRUPT word pair written to channels 34 and 35.
+///
+///  - `mcts` - MCTs elapsed since the start of the run when the second word
+///    was written
+///  - `word_order` - Channel 13 word order code bit at the time of the write
+///  - `words` - Channel 34 and 35 words
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelemetryRecord {
+    pub mcts: u64,
+    pub word_order: bool,
+    pub words: [u16; 2],
+}
+
+///
+/// ## `TelemetryRecorder` Structure
+///
+/// Writes telemetry records to a file or any other writer.
+///
+pub struct TelemetryRecorder<W: Write> {
+    writer: W,
+    format: TelemetryFormat,
+}
+
+impl TelemetryRecorder<BufWriter<File>> {
+    ///
+    /// ## `create` Function
+    ///
+    /// Creates a recording at `path`, in the format matching its extension.
+    ///
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let format = TelemetryFormat::from_path(&path);
+        TelemetryRecorder::new(BufWriter::new(File::create(path)?), format)
+    }
+}
+
+impl<W: Write> TelemetryRecorder<W> {
+    ///
+    /// ## `new` Function
+    ///
+    /// Starts a recording on the given writer by writing the format header.
+    ///
+    pub fn new(mut writer: W, format: TelemetryFormat) -> io::Result<Self> {
+        match format {
+            TelemetryFormat::Binary => writer.write_all(&TELEMETRY_MAGIC)?,
+            TelemetryFormat::Csv => writeln!(writer, "{}", TELEMETRY_CSV_HEADER)?,
+        }
+        Ok(TelemetryRecorder { writer, format })
+    }
+
+    pub fn record(&mut self, record: &TelemetryRecord) -> io::Result<()> {
+        match self.format {
+            TelemetryFormat::Binary => {
+                let mut buf = [0; RECORD_SIZE];
+                buf[0..8].copy_from_slice(&record.mcts.to_le_bytes());
+                buf[8..10].copy_from_slice(&(record.word_order as u16).to_le_bytes());
+                buf[10..12].copy_from_slice(&record.words[0].to_le_bytes());
+                buf[12..14].copy_from_slice(&record.words[1].to_le_bytes());
+                self.writer.write_all(&buf)
+            }
+            TelemetryFormat::Csv => writeln!(
+                self.writer,
+                "{},{},{:05o},{:05o}",
+                record.mcts, record.word_order as u8, record.words[0], record.words[1]
+            ),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+///
+/// ## `TelemetryReader` Structure
+///
+/// Reads back the records of a telemetry recording. The format is detected
+/// from the start of the recording. Records are returned by iterating over
+/// the reader.
+///
+pub struct TelemetryReader<R: BufRead> {
+    reader: R,
+    format: TelemetryFormat,
+    line: usize,
+}
+
+impl TelemetryReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        TelemetryReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: BufRead> TelemetryReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic == TELEMETRY_MAGIC {
+            return Ok(TelemetryReader {
+                reader,
+                format: TelemetryFormat::Binary,
+                line: 0,
+            });
+        }
+
+        let mut header = String::from_utf8_lossy(&magic).into_owned();
+        reader.read_line(&mut header)?;
+        if header.trim_end() != TELEMETRY_CSV_HEADER {
+            return Err(invalid_data("Not a telemetry recording".into()));
+        }
+        Ok(TelemetryReader {
+            reader,
+            format: TelemetryFormat::Csv,
+            line: 1,
+        })
+    }
+
+    pub fn format(&self) -> TelemetryFormat {
+        self.format
+    }
+
+    fn read_binary(&mut self) -> io::Result<Option<TelemetryRecord>> {
+        let mut buf = [0; RECORD_SIZE];
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match self.reader.read(&mut buf[len..])? {
+                0 if len == 0 => return Ok(None),
+                0 => return Err(invalid_data("Truncated telemetry record".into())),
+                x => len += x,
+            }
+        }
+
+        let u16_at = |idx: usize| u16::from_le_bytes([buf[idx], buf[idx + 1]]);
+        let mut mcts = [0; 8];
+        mcts.copy_from_slice(&buf[0..8]);
+        Ok(Some(TelemetryRecord {
+            mcts: u64::from_le_bytes(mcts),
+            word_order: u16_at(8) != 0,
+            words: [u16_at(10), u16_at(12)],
+        }))
+    }
+
+    fn read_csv(&mut self) -> io::Result<Option<TelemetryRecord>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let bad = || invalid_data(std::format!("Invalid telemetry record on line {}", self.line));
+        let mut fields = line.trim().split(',');
+        let mut next = || fields.next().ok_or_else(bad);
+        let mcts = next()?.parse::<u64>().map_err(|_| bad())?;
+        let word_order = match next()? {
+            "0" => false,
+            "1" => true,
+            _ => return Err(bad()),
+        };
+        let word1 = u16::from_str_radix(next()?, 8).map_err(|_| bad())?;
+        let word2 = u16::from_str_radix(next()?, 8).map_err(|_| bad())?;
+        if next().is_ok() {
+            return Err(bad());
+        }
+
+        Ok(Some(TelemetryRecord {
+            mcts,
+            word_order,
+            words: [word1, word2],
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for TelemetryReader<R> {
+    type Item = io::Result<TelemetryRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = match self.format {
+            TelemetryFormat::Binary => self.read_binary(),
+            TelemetryFormat::Csv => self.read_csv(),
+        };
+        res.transpose()
+    }
+}
+
+#[cfg(test)]
+mod telemetry_tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn records() -> Vec<TelemetryRecord> {
+        std::vec![
+            TelemetryRecord {
+                mcts: 1709,
+                word_order: false,
+                words: [0o77773, 0o77340],
+            },
+            TelemetryRecord {
+                mcts: 3418,
+                word_order: true,
+                words: [0o12345, 0o54321],
+            },
+        ]
+    }
+
+    fn round_trip(format: TelemetryFormat) -> Vec<u8> {
+        let mut rec = TelemetryRecorder::new(Vec::new(), format).unwrap();
+        for x in records().iter() {
+            rec.record(x).unwrap();
+        }
+        let data = rec.into_inner();
+
+        let reader = TelemetryReader::new(&data[..]).unwrap();
+        assert_eq!(reader.format(), format);
+        let read: Vec<_> = reader.map(|x| x.unwrap()).collect();
+        assert_eq!(read, records());
+        data
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests writing and reading back a binary recording.
+    ///
+    #[test]
+    fn test_telemetry_binary() {
+        let data = round_trip(TelemetryFormat::Binary);
+        assert_eq!(data.len(), 8 + 2 * RECORD_SIZE);
+
+        let truncated = &data[..data.len() - 1];
+        let res: Vec<_> = TelemetryReader::new(truncated).unwrap().collect();
+        assert!(res[1].is_err());
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests writing and reading back a CSV recording, and the errors reported
+    /// for malformed lines.
+    ///
+    #[test]
+    fn test_telemetry_csv() {
+        let data = round_trip(TelemetryFormat::Csv);
+        let text = String::from_utf8(data).unwrap();
+        assert_eq!(text, "mcts,word_order,word1,word2\n1709,0,77773,77340\n3418,1,12345,54321\n");
+
+        let bad = "mcts,word_order,word1,word2\n1,0,77773,99999\n";
+        let res: Vec<_> = TelemetryReader::new(bad.as_bytes()).unwrap().collect();
+        assert_eq!(res[0].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert!(TelemetryReader::new("time,x\n".as_bytes()).is_err());
+        assert_eq!(TelemetryFormat::from_path("run.CSV"), TelemetryFormat::Csv);
+        assert_eq!(TelemetryFormat::from_path("run.tlm"), TelemetryFormat::Binary);
+    }
+}
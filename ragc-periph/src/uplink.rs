@@ -0,0 +1,335 @@
+use ragc_core::consts::{cpu, io, special};
+use ragc_core::mem::periph::AgcIoPeriph;
+
+use crate::keycodes;
+
+/// Number of MCTs between two uplink words sent by the ground station
+/// (about 20ms, one word per telemetry frame).
+pub const UPLINK_WORD_MCTS: u32 = 20000000 / 11700;
+
+/// Minimum number of MCTs between two uplink words. Words arriving sooner
+/// than this after the previous one set the UPLINK TOO FAST discrete. The
+/// uplink shifts 16 bits at 1kbps per word.
+pub const UPLINK_MIN_WORD_MCTS: u32 = 16000000 / 11700;
+
+/// Number of uplink words that can be queued while waiting to be shifted
+/// into INLINK.
+pub const UPLINK_QUEUE_LEN: usize = 64;
+
+// Channel 33 bits (inverted) reported by the uplink
+const CHAN33_BLOCK_UPLINK: u16 = 0o01000;
+const CHAN33_UPLINK_TOO_FAST: u16 = 0o02000;
+
+///
+/// ## `UplinkError` Enum
+///
+/// Errors returned when queueing uplink words.
+///
+///  - `QueueFull` - The words do not fit in the uplink queue
+///  - `InvalidKey` - The character is not a DSKY key
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UplinkError {
+    QueueFull,
+    InvalidKey(char),
+}
+
+///
+/// ## `uplink_word` Function
+///
+/// Builds the triply redundant `cccccCCCCCccccc` uplink word of a keycode,
+/// where `C` is the ones' complement of the 5-bit keycode `c`.
+///
+pub fn uplink_word(keycode: u16) -> u16 {
+    let c = keycode & 0o37;
+    (c << 10) | ((!c & 0o37) << 5) | c
+}
+
+///
+/// ## Uplink Peripheral
+///
+/// The Uplink peripheral models the digital uplink from the ground station.
+/// Queued words are shifted into INLINK one at a time, `word_interval` MCTs
+/// apart, each one followed by an UPRUPT.
+///
+/// The crew UPLINK switch in the BLOCK position is reported on channel 33 bit
+/// 10. While blocked, words are still clocked out of the queue but never
+/// reach the AGC. Words delivered less than `UPLINK_MIN_WORD_MCTS` apart set
+/// the UPLINK TOO FAST discrete (channel 33 bit 11), which is reset when the
+/// AGC writes channel 33.
+///
+pub struct Uplink {
+    queue: [u16; UPLINK_QUEUE_LEN],
+    head: usize,
+    len: usize,
+
+    word_interval: u32,
+    since_last: u32,
+    blocked: bool,
+    too_fast: bool,
+
+    inlink: Option<u16>,
+    rupt: bool,
+}
+
+impl Default for Uplink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Uplink {
+    pub fn new() -> Self {
+        Uplink {
+            queue: [0; UPLINK_QUEUE_LEN],
+            head: 0,
+            len: 0,
+            word_interval: UPLINK_WORD_MCTS,
+            since_last: u32::MAX,
+            blocked: false,
+            too_fast: false,
+            inlink: None,
+            rupt: false,
+        }
+    }
+
+    ///
+    /// ## `with_word_interval` Function
+    ///
+    /// Overrides the number of MCTs between two uplink words. Intervals below
+    /// `UPLINK_MIN_WORD_MCTS` are accepted to exercise the UPLINK TOO FAST
+    /// handling of the flight software.
+    ///
+    pub fn with_word_interval(mut self, mcts: u32) -> Self {
+        self.word_interval = mcts;
+        self
+    }
+
+    ///
+    /// ## `set_block` Function
+    ///
+    /// Sets the crew UPLINK switch to BLOCK (`true`) or ACCEPT (`false`).
+    ///
+    pub fn set_block(&mut self, block: bool) {
+        self.blocked = block;
+    }
+
+    ///
+    /// ## `pending` Function
+    ///
+    /// Returns the number of words waiting to be shifted into INLINK.
+    ///
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    ///
+    /// ## `send_word` Function
+    ///
+    /// Queues a raw 15-bit uplink word.
+    ///
+    pub fn send_word(&mut self, word: u16) -> Result<(), UplinkError> {
+        if self.len == UPLINK_QUEUE_LEN {
+            return Err(UplinkError::QueueFull);
+        }
+        self.queue[(self.head + self.len) % UPLINK_QUEUE_LEN] = word & 0o77777;
+        self.len += 1;
+        Ok(())
+    }
+
+    ///
+    /// ## `send_keycode` Function
+    ///
+    /// Queues a DSKY keycode in its triply redundant form.
+    ///
+    pub fn send_keycode(&mut self, keycode: u16) -> Result<(), UplinkError> {
+        self.send_word(uplink_word(keycode))
+    }
+
+    ///
+    /// ## `send_keys` Function
+    ///
+    /// Queues a sequence of DSKY keys such as `V37E63E`. See
+    /// `keycodes::from_char` for the accepted characters. Nothing is queued if
+    /// any of the keys is invalid or the whole sequence does not fit.
+    ///
+    pub fn send_keys(&mut self, keys: &str) -> Result<(), UplinkError> {
+        let mut count = 0;
+        for key in keys.chars() {
+            keycodes::from_char(key).ok_or(UplinkError::InvalidKey(key))?;
+            count += 1;
+        }
+        if self.len + count > UPLINK_QUEUE_LEN {
+            return Err(UplinkError::QueueFull);
+        }
+
+        for key in keys.chars() {
+            if let Some(code) = keycodes::from_char(key) {
+                self.send_keycode(code)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pop_word(&mut self) -> Option<u16> {
+        if self.len == 0 {
+            return None;
+        }
+        let word = self.queue[self.head];
+        self.head = (self.head + 1) % UPLINK_QUEUE_LEN;
+        self.len -= 1;
+        Some(word)
+    }
+
+    fn get_channel33(&self) -> u16 {
+        let mut val = 0o77777;
+        if self.blocked {
+            val &= !CHAN33_BLOCK_UPLINK;
+        }
+        if self.too_fast {
+            val &= !CHAN33_UPLINK_TOO_FAST;
+        }
+        val
+    }
+}
+
+impl AgcIoPeriph for Uplink {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_CHAN30 | io::CHANNEL_CHAN31 | io::CHANNEL_CHAN32 => 0o77777,
+            io::CHANNEL_CHAN33 => self.get_channel33(),
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, channel_idx: usize, _value: u16) {
+        // Any write to channel 33 resets the channel 33 flip-flops
+        if channel_idx == io::CHANNEL_CHAN33 {
+            self.too_fast = false;
+        }
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        if self.rupt {
+            self.rupt = false;
+            1 << cpu::RUPT_UPRUPT
+        } else {
+            0
+        }
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.since_last = self.since_last.saturating_add(mcts as u32);
+        if self.since_last < self.word_interval {
+            return;
+        }
+
+        if let Some(word) = self.pop_word() {
+            if !self.blocked {
+                if self.since_last < UPLINK_MIN_WORD_MCTS {
+                    self.too_fast = true;
+                }
+                self.inlink = Some(word);
+                self.rupt = true;
+            }
+            self.since_last = 0;
+        }
+    }
+
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        self.inlink.take().map(|value| (special::SG_INLINK, value))
+    }
+}
+
+#[cfg(test)]
+mod uplink_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests the triply redundant encoding of DSKY keycodes.
+    ///
+    #[test]
+    fn test_uplink_word() {
+        assert_eq!(uplink_word(keycodes::KEY_VERB), 0b10001_01110_10001);
+        assert_eq!(uplink_word(keycodes::KEY_0), 0b10000_01111_10000);
+        assert_eq!(uplink_word(7), 0b00111_11000_00111);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that queued keys are shifted into INLINK one word interval apart,
+    /// each followed by a single UPRUPT.
+    ///
+    #[test]
+    fn test_uplink_keys() {
+        let mut uplink = Uplink::new();
+        uplink.send_keys("V3").unwrap();
+        assert_eq!(uplink.pending(), 2);
+
+        uplink.pump_mcts(1);
+        assert_eq!(
+            uplink.counter_update(),
+            Some((special::SG_INLINK, uplink_word(keycodes::KEY_VERB)))
+        );
+        assert_eq!(uplink.is_interrupt(), 1 << cpu::RUPT_UPRUPT);
+        assert_eq!(uplink.is_interrupt(), 0, "UPRUPT should only occur once");
+
+        uplink.pump_mcts((UPLINK_WORD_MCTS - 1) as u16);
+        assert_eq!(uplink.counter_update(), None);
+        uplink.pump_mcts(1);
+        assert_eq!(uplink.counter_update(), Some((special::SG_INLINK, uplink_word(3))));
+        assert_eq!(uplink.pending(), 0);
+        assert_eq!(uplink.read(io::CHANNEL_CHAN33), 0o77777);
+
+        assert_eq!(uplink.send_keys("V3X"), Err(UplinkError::InvalidKey('X')));
+        assert_eq!(uplink.pending(), 0);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests that words are discarded while the uplink is blocked.
+    ///
+    #[test]
+    fn test_uplink_block() {
+        let mut uplink = Uplink::new();
+        uplink.set_block(true);
+        assert_eq!(uplink.read(io::CHANNEL_CHAN33) & CHAN33_BLOCK_UPLINK, 0);
+
+        uplink.send_word(0o12345).unwrap();
+        uplink.pump_mcts(1);
+        assert_eq!(uplink.counter_update(), None);
+        assert_eq!(uplink.is_interrupt(), 0);
+        assert_eq!(uplink.pending(), 0);
+
+        uplink.set_block(false);
+        assert_eq!(uplink.read(io::CHANNEL_CHAN33), 0o77777);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the UPLINK TOO FAST discrete is set by words sent too close to
+    /// each other and reset by a channel 33 write.
+    ///
+    #[test]
+    fn test_uplink_too_fast() {
+        let mut uplink = Uplink::new().with_word_interval(100);
+        uplink.send_word(1).unwrap();
+        uplink.send_word(2).unwrap();
+
+        uplink.pump_mcts(1);
+        assert_eq!(uplink.counter_update(), Some((special::SG_INLINK, 1)));
+        assert_eq!(uplink.read(io::CHANNEL_CHAN33), 0o77777);
+
+        uplink.pump_mcts(100);
+        assert_eq!(uplink.counter_update(), Some((special::SG_INLINK, 2)));
+        assert_eq!(uplink.read(io::CHANNEL_CHAN33) & CHAN33_UPLINK_TOO_FAST, 0);
+
+        uplink.write(io::CHANNEL_CHAN33, 0);
+        assert_eq!(uplink.read(io::CHANNEL_CHAN33), 0o77777);
+    }
+}
@@ -19,21 +19,42 @@ const SEVEN_SEG_TABLE: [u8; 11] = [
 ///  - 7 Segment value code
 ///
 pub fn get_7seg(agc_val: u8) -> u8 {
+    match get_digit(agc_val) {
+        Some(x) => SEVEN_SEG_TABLE[x as usize],
+        None => SEVEN_SEG_TABLE[10],
+    }
+}
+
+///
+/// # Description
+///
+/// The function is to convert the bits being set by the AGC code within a given
+/// IO channel to the decimal digit being displayed.
+///
+/// # Arguments
+///
+///  - `agc_val` - Typical 5 bit code value that is used in CHANNEL_DSKY.
+///
+/// # Return Value
+///
+///  - `Some(digit)` - Digit from 0 to 9
+///  - `None` - The digit is blank
+///
+pub fn get_digit(agc_val: u8) -> Option<u8> {
     // This match is to convert the agc_val bits that is being fed into the
-    // IO channel to the corresponding 7 Segment Display Value.
+    // IO channel to the corresponding digit.
     match agc_val {
-        0 => SEVEN_SEG_TABLE[10],
-        21 => SEVEN_SEG_TABLE[0],
-        3 => SEVEN_SEG_TABLE[1],
-        25 => SEVEN_SEG_TABLE[2],
-        27 => SEVEN_SEG_TABLE[3],
-        15 => SEVEN_SEG_TABLE[4],
-        30 => SEVEN_SEG_TABLE[5],
-        28 => SEVEN_SEG_TABLE[6],
-        19 => SEVEN_SEG_TABLE[7],
-        29 => SEVEN_SEG_TABLE[8],
-        31 => SEVEN_SEG_TABLE[9],
-        _ => SEVEN_SEG_TABLE[10],
+        21 => Some(0),
+        3 => Some(1),
+        25 => Some(2),
+        27 => Some(3),
+        15 => Some(4),
+        30 => Some(5),
+        28 => Some(6),
+        19 => Some(7),
+        29 => Some(8),
+        31 => Some(9),
+        _ => None,
     }
 }
 
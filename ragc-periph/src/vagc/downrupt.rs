@@ -1,39 +1,33 @@
 use yaagc_protocol::agc::generate_yaagc_packet;
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crate::downlist::{DownlistAssembler, DownlistVehicle};
+use crate::telemetry::{TelemetryRecord, TelemetryRecorder};
+
+use super::server::YaAgcServer;
+
+use crossbeam_channel::Sender;
+use log::error;
+use std::boxed::Box;
 use std::io::Write;
-use std::net::TcpListener;
 
 use ragc_core::mem::periph::AgcIoPeriph;
 
-pub struct DownruptPeriph {
-    tx: Sender<[u8; 4]>,
-    word_order: bool
+/// Address served by `new`, the default port of yaTelemetry for a LM AGC.
+pub const DOWNRUPT_DEFAULT_ADDR: &str = "127.0.0.1:19800";
+
+struct DownlistOutput {
+    assembler: DownlistAssembler,
+    vehicle: DownlistVehicle,
+    writer: Box<dyn Write + Send>,
 }
 
-fn downrupt_thread(rx: Receiver<[u8; 4]>, addr: &str) {
-    // accept connections and process them serially
-    let listener = TcpListener::bind(addr).unwrap();
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut xa) => loop {
-                let msg = match rx.recv() {
-                    Ok(x) => x,
-                    _ => {
-                        break;
-                    }
-                };
-
-                match xa.write_all(&msg) {
-                    Ok(_x) => {}
-                    _ => {
-                        break;
-                    }
-                }
-            },
-            _ => {}
-        };
-    }
+pub struct DownruptPeriph {
+    tx: Sender<[u8; 4]>,
+    word_order: bool,
+    downlist: Option<DownlistOutput>,
+    recorder: Option<TelemetryRecorder<Box<dyn Write + Send>>>,
+    first_word: Option<u16>,
+    mcts: u64,
 }
 
 ///
@@ -46,15 +40,91 @@ fn downrupt_thread(rx: Receiver<[u8; 4]>, addr: &str) {
 /// the start of the message.
 ///
 impl DownruptPeriph {
+    ///
+    /// ## `new` Function
+    ///
+    /// Creates the peripheral served on `DOWNRUPT_DEFAULT_ADDR`. If the address
+    /// can not be bound, the error is logged and the peripheral runs without
+    /// any client. Use `bind` to handle the error instead.
+    ///
     pub fn new() -> Self {
-        let (tx, rx) = unbounded();
+        match Self::bind(DOWNRUPT_DEFAULT_ADDR) {
+            Ok(x) => x,
+            Err(x) => {
+                error!("Downrupt: Unable to bind: {}", x);
+                Self::with_server(&YaAgcServer::new())
+            }
+        }
+    }
 
-        std::thread::spawn(move || downrupt_thread(rx, "127.0.0.1:19800"));
+    ///
+    /// ## `bind` Function
+    ///
+    /// Creates the peripheral served on its own yaAGC server listening on
+    /// `addr`, returning the bind error if the address is not available.
+    ///
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        YaAgcServer::bind(&[addr]).map(|x| Self::with_server(&x))
+    }
+
+    ///
+    /// ## `with_server` Function
+    ///
+    /// Creates the peripheral broadcasting the DOWNRUPT words to every client
+    /// of a shared yaAGC server.
+    ///
+    pub fn with_server(server: &YaAgcServer) -> Self {
         DownruptPeriph {
-            tx: tx,
-            word_order: false
+            tx: server.output(),
+            word_order: false,
+            downlist: None,
+            recorder: None,
+            first_word: None,
+            mcts: 0,
         }
     }
+
+    ///
+    /// ## `with_recorder` Function
+    ///
+    /// Records every DOWNRUPT word pair with its MCT timestamp.
+    ///
+    pub fn with_recorder(mut self, recorder: TelemetryRecorder<Box<dyn Write + Send>>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    fn record_pair(&mut self, word2: u16) {
+        let (recorder, word1) = match (&mut self.recorder, self.first_word.take()) {
+            (Some(r), Some(w)) => (r, w),
+            _ => return,
+        };
+
+        let record = TelemetryRecord {
+            mcts: self.mcts,
+            word_order: self.word_order,
+            words: [word1, word2],
+        };
+        if let Err(x) = recorder.record(&record) {
+            error!("Unable to record telemetry, stopping recording: {:?}", x);
+            self.recorder = None;
+        }
+    }
+
+    ///
+    /// ## `with_downlist_json` Function
+    ///
+    /// Decodes every complete downlist with the example layouts of the given
+    /// vehicle and writes it to `writer` as a JSON line.
+    ///
+    pub fn with_downlist_json(mut self, vehicle: DownlistVehicle, writer: Box<dyn Write + Send>) -> Self {
+        self.downlist = Some(DownlistOutput {
+            assembler: DownlistAssembler::new(),
+            vehicle,
+            writer,
+        });
+        self
+    }
 }
 
 impl AgcIoPeriph for DownruptPeriph {
@@ -92,6 +162,14 @@ impl AgcIoPeriph for DownruptPeriph {
     /// Assumption: Code does not write to DOWNRUPT2 before DOWNRUPT1 word
     ///
     fn write(&mut self, channel_idx: usize, value: u16) {
+        if let Some(out) = &mut self.downlist {
+            if let Some(list) = out.assembler.write(channel_idx, value) {
+                if let Err(x) = writeln!(out.writer, "{}", list.json(out.vehicle)) {
+                    error!("Unable to write downlist: {:?}", x);
+                }
+            }
+        }
+
         match channel_idx {
             ragc_core::consts::io::CHANNEL_CHAN13 => {
                 if value & (1 << 6) != 0o00000 {
@@ -102,10 +180,12 @@ impl AgcIoPeriph for DownruptPeriph {
                 }
             },
             ragc_core::consts::io::CHANNEL_CHAN34 => {
+                self.first_word = Some(value);
                 let packet = generate_yaagc_packet(channel_idx, value);
                 self.tx.send(packet).unwrap();
             }
             ragc_core::consts::io::CHANNEL_CHAN35 => {
+                self.record_pair(value);
                 let packet = generate_yaagc_packet(channel_idx, value);
                 self.tx.send(packet).unwrap();
             }
@@ -116,4 +196,16 @@ impl AgcIoPeriph for DownruptPeriph {
     fn is_interrupt(&mut self) -> u16 {
         0
     }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        self.mcts += mcts as u64;
+    }
+}
+
+impl Drop for DownruptPeriph {
+    fn drop(&mut self) {
+        if let Some(recorder) = &mut self.recorder {
+            let _res = recorder.flush();
+        }
+    }
 }
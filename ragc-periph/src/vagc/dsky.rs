@@ -1,204 +1,156 @@
-use yaagc_protocol::agc::{generate_yaagc_packet, parse_yaagc_packet};
-use crate::utils::{get_7seg, get_7seg_value};
+use yaagc_protocol::agc::{generate_yaagc_packet, AGCPacket};
+use crate::keycodes;
+use crate::dsky_flash::DskyFlash;
+use crate::dsky_keys::{DskyKeyEvent, DskyKeyboard};
+use crate::dsky_state::{lamp_test_value, DskyState};
+use super::server::YaAgcServer;
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use log::{debug, warn};
+use crossbeam_channel::{Receiver, Sender};
+use log::{debug, error, warn};
 
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::println;
+/// Address served by `new`, the default port of yaDSKY2 for a CM AGC.
+pub const DSKY_DEFAULT_ADDR: &str = "127.0.0.1:19697";
 
 pub struct DskyDisplay {
-    digit: [u8; 15],
-    noun: u16,
-    verb: u16,
-    prog: u16,
-    proceed: u16,
+    state: DskyState,
     output_flags: u16,
-    keypress: Receiver<u16>,
-    keypress_val: u16,
+    keypress: Receiver<AGCPacket>,
+    keys: DskyKeyboard,
     dsky_tx: Sender<[u8; 4]>,
-    flash_tx: Sender<u16>,
+    flash: DskyFlash,
     last_dsalmout: u16,
     last_dskyval: u16,
+    last_row12: u16,
 }
 
-fn handle_stream_input(stream: &mut TcpStream, keypress_tx: &Sender<u16>) {
-    loop {
-        let mut buf = [0; 4];
-        match stream.read_exact(&mut buf) {
-            Ok(_x) => match parse_yaagc_packet(buf) {
-                Some(res) => match res.0 {
-                    0o15 => {
-                        debug!("Keypress: {:o}", res.1);
-                        let _res = keypress_tx.send(res.1);
-                    }
-                    0o32 => {
-                        debug!("Keypress (Proceed): {:o}", res.1);
-                        let _res = keypress_tx.send(res.1 | 0o40000);
-                    }
-                    _ => {
-                        warn!("Unimplemented keypress: {:?}", res);
-                    }
-                },
-                _ => {}
-            },
-            _ => {
-                break;
-            }
-        }
-    }
-    println!("Stream Input: Disconnecting from stream session");
-}
-
-fn handle_steam_output(stream: &mut TcpStream, dsky_rx: &Receiver<[u8; 4]>) {
-    loop {
-        let msg = match dsky_rx.recv() {
+impl DskyDisplay {
+    ///
+    /// ## `new` Function
+    ///
+    /// Creates the peripheral served on `DSKY_DEFAULT_ADDR`. If the address
+    /// can not be bound, the error is logged and the peripheral runs without
+    /// any client. Use `bind` to handle the error instead.
+    ///
+    pub fn new() -> Self {
+        match Self::bind(DSKY_DEFAULT_ADDR) {
             Ok(x) => x,
-            _ => {
-                break;
-            }
-        };
-
-        match stream.write_all(&msg) {
-            Ok(_x) => {}
-            _ => {
-                break;
+            Err(x) => {
+                error!("DSKY: Unable to bind: {}", x);
+                Self::with_server(&YaAgcServer::new())
             }
         }
     }
-    println!("Stream Output: Disconnecting from stream session");
-}
-
-fn flashing_thread(flash_rx: Receiver<u16>, dsky_tx: Sender<[u8; 4]>) {
-    let mut channel_value = 0o00000;
-    let start_time = std::time::SystemTime::now();
-
-    loop {
-        while !flash_rx.is_empty() {
-            channel_value = flash_rx.recv().unwrap();
-        }
-
-        let elapsed = start_time.elapsed().unwrap().as_millis();
-        if elapsed % 1000 < 750 {
-            let mut value = channel_value;
-            if channel_value & 0o00040 == 0o00040 {
-                value &= !0o00040;
-            }
-            dsky_tx.send(generate_yaagc_packet(0o0163, value)).unwrap();
-        } else {
-            if channel_value != 0o00000 {
-                let mut value = channel_value & !0o00160;
-                if channel_value & 0o00040 == 0o00040 {
-                    value |= 0o00040;
-                }
-                dsky_tx.send(generate_yaagc_packet(0o0163, value)).unwrap();
-            }
-        }
-
-        std::thread::sleep(std::time::Duration::new(0, 10000000));
-    }
-}
 
-fn dsky_network_thread(keypress_tx: Sender<u16>, dsky_rx: Receiver<[u8; 4]>) {
-    // accept connections and process them serially
-    let listener = TcpListener::bind("127.0.0.1:19697").unwrap();
-    for stream in listener.incoming() {
-        println!("Connecting to new stream");
-        match stream {
-            Ok(mut xa) => {
-                match xa.try_clone() {
-                    Ok(mut x) => {
-                        let keypresstx = keypress_tx.clone();
-                        std::thread::spawn(move || handle_stream_input(&mut x, &keypresstx));
-                    }
-                    _ => {
-                        continue;
-                    }
-                }
-                handle_steam_output(&mut xa, &dsky_rx);
-            }
-            _ => {}
-        };
-        println!("Disconnecting");
+    ///
+    /// ## `bind` Function
+    ///
+    /// Creates the peripheral served on its own yaAGC server listening on
+    /// `addr`, returning the bind error if the address is not available.
+    ///
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        YaAgcServer::bind(&[addr]).map(|x| Self::with_server(&x))
     }
-}
-
-impl DskyDisplay {
-    pub fn new() -> Self {
-        let (keypress_tx, keypress_rx) = unbounded();
-        let (dsky_tx, dsky_rx) = unbounded();
-        let (flash_tx, flash_rx) = unbounded();
 
-        let flash_dsky_tx = dsky_tx.clone();
-        std::thread::spawn(move || flashing_thread(flash_rx, flash_dsky_tx));
-        std::thread::spawn(move || dsky_network_thread(keypress_tx, dsky_rx));
+    ///
+    /// # Description
+    ///
+    /// Creates a DSKY served by a shared yaAGC server. Display updates are
+    /// broadcast to every client of the server, and keypresses are taken from
+    /// channel 15 and 32 packets sent by any of them.
+    ///
+    pub fn with_server(server: &YaAgcServer) -> Self {
+        let keypress_rx = server.subscribe(&[0o15, 0o32]);
+        let dsky_tx = server.output();
 
         Self {
-            digit: [0; 15],
-            noun: 0,
-            verb: 0,
-            prog: 0,
+            state: DskyState::new(),
             keypress: keypress_rx,
-            keypress_val: 0,
-            proceed: 0o20000,
+            keys: DskyKeyboard::new(),
             dsky_tx: dsky_tx,
-            flash_tx: flash_tx,
+            flash: DskyFlash::new(),
             output_flags: 0x0,
             last_dsalmout: 0x0,
             last_dskyval: 0x0,
+            last_row12: 12 << 11,
         }
     }
 
     ///
-    /// # Description
+    /// ## `state` Function
     ///
-    /// The function parses the CHANNEL_DSKY IO channel value into multiple parts
-    /// that is used to capture the display nibbles and rows in which it is being
-    /// displayed on.
-    ///
-    /// # Return Value
-    ///
-    /// - (u8 a, bool b, u8 c, u8 d)
-    ///   - `a` - Represents the rown in which the decoding is being performed on
-    ///   - `b` - Represents the boolean flag for some rows which determines the
-    ///           positive or negative display flags for a given DSKY value
-    ///   - `c` - Upper nibble of a given value on the DSKY
-    ///   - `d` - Lower nibble of a given value on the DSKY
-    ///
-    fn parse_fields(&self, val: u16) -> (u8, bool, u8, u8) {
-        let a: u8 = ((val >> 11) & 0xF) as u8;
-        let c: u8 = ((val >> 5) & 0x1F) as u8;
-        let d: u8 = (val & 0x1F) as u8;
-        let b: bool = if val & (1 << 10) == (1 << 10) {
-            true
-        } else {
-            false
-        };
-        (a, b, c, d)
+    /// Returns what is currently shown on the DSKY.
+    ///
+    pub fn state(&self) -> &DskyState {
+        &self.state
     }
 
     pub fn read_keypress(&self) -> u16 {
-        debug!("DSKY: Reading keypress: {:?}", self.keypress_val);
-        self.keypress_val & 0x1F
+        debug!("DSKY: Reading keypress: {:?}", self.keys.keycode());
+        self.keys.keycode()
     }
 
     pub fn set_channel_value(&mut self, channel_idx: usize, value: u16) {
         match channel_idx {
             0o13 => {
-                if value & 0o01000 != 0o00000 {
-                    self.output_flags |= 0o00400;
-                } else {
-                    self.output_flags &= 0o77377;
+                let changed = self.state.update(ragc_core::consts::io::CHANNEL_CHAN13, value);
+                if changed {
+                    debug!("DSKY: Lamp test {}", self.state.lamp_test);
+                    self.send_lamps();
                 }
-                self.flash_tx.send(self.output_flags).unwrap();
             }
             0o163 => {
                 self.output_flags = value;
-                self.flash_tx.send(self.output_flags).unwrap();
             }
             _ => {}
         }
+        self.flash_lamps();
+    }
+
+    ///
+    /// ## `displayed` Function
+    ///
+    /// Returns the value of a lamp channel as sent to the clients, with every
+    /// lamp lit during the lamp test.
+    ///
+    fn displayed(&self, channel_idx: usize, value: u16) -> u16 {
+        if self.state.lamp_test {
+            lamp_test_value(channel_idx, value)
+        } else {
+            value
+        }
+    }
+
+    ///
+    /// ## `send_lamps` Function
+    ///
+    /// Sends the channel 11 and relay row 12 lamps again to the clients, when
+    /// the lamp test starts or ends.
+    ///
+    fn send_lamps(&mut self) {
+        let dsalmout = self.displayed(0o11, self.last_dsalmout);
+        let row12 = self.displayed(0o10, self.last_row12);
+        self.dsky_tx.send(generate_yaagc_packet(0o11, dsalmout)).unwrap();
+        self.dsky_tx.send(generate_yaagc_packet(0o10, row12)).unwrap();
+    }
+
+    ///
+    /// ## `flash_lamps` Function
+    ///
+    /// Hands the channel 163 lamps over to the flash, and sends them to the
+    /// clients if the flash shows them differently.
+    ///
+    fn flash_lamps(&mut self) {
+        self.flash.set_lamps(self.displayed(0o163, self.get_channel_value(0o163)));
+        self.send_flash();
+        self.state.update(0o163, self.get_channel_value(0o163));
+        self.state.flash_off = self.flash.flash_off();
+    }
+
+    fn send_flash(&mut self) {
+        if let Some(value) = self.flash.update() {
+            self.dsky_tx.send(generate_yaagc_packet(0o163, value)).unwrap();
+            self.state.flash_off = self.flash.flash_off();
+        }
     }
 
     pub fn get_channel_value(&self, channel_idx: usize) -> u16 {
@@ -209,7 +161,17 @@ impl DskyDisplay {
     }
 
     pub fn read_proceed_flag(&self) -> u16 {
-        self.proceed
+        self.keys.proceed_flag()
+    }
+
+    ///
+    /// ## `set_key_timing` Function
+    ///
+    /// Sets the number of MCTs keys are held down, and the number of MCTs
+    /// between the release of a key and the next one. See `DskyKeyboard`.
+    ///
+    pub fn set_key_timing(&mut self, key_down: u32, key_up: u32) {
+        self.keys.set_timing(key_down, key_up);
     }
 
     ///
@@ -222,11 +184,12 @@ impl DskyDisplay {
             debug!("DSKY: Setting CHANNEL_DSALMOUT Flags: {:o}", flags);
             self.last_dsalmout = flags;
             self.dsky_tx
-                .send(generate_yaagc_packet(0o11, flags))
+                .send(generate_yaagc_packet(0o11, self.displayed(0o11, flags)))
                 .unwrap();
 
+            self.state.update(ragc_core::consts::io::CHANNEL_DSALMOUT, flags);
             self.output_flags = (self.output_flags & 0o77607) | (flags & 0o00170);
-            self.flash_tx.send(self.output_flags).unwrap();
+            self.flash_lamps();
         }
     }
 
@@ -235,16 +198,21 @@ impl DskyDisplay {
     ///
     /// This function is to handle row value 12 (decimal) for CHANNEL_DSKY which
     /// is different than all the other rows. This row handles specific light
-    /// indicators within the DSKYr
+    /// indicators within the DSKY: PRIO DISP, NO DAP, VEL, NO ATT, ALT,
+    /// GIMBAL LOCK, TRACKER and PROG.
     ///
     /// # Arguments
     ///
     /// - `flags` - Bitfield for row 12 which represents the specific indicators
     ///             for that row.
     ///
-    pub fn set_adv_flags(&mut self, _flags: u16) {
-        //println!("DSKY: Setting Adv Flags: {:x}", flags);
-        //self.lamps = (self.lamps & 0xFF) | ((flags as u32) << 8);
+    pub fn set_adv_flags(&mut self, flags: u16) {
+        let val = (12 << 11) | (flags & 0o03777);
+        self.last_row12 = val;
+        self.dsky_tx
+            .send(generate_yaagc_packet(0o10, self.displayed(0o10, val)))
+            .unwrap();
+        self.state.update(ragc_core::consts::io::CHANNEL_DSKY, val);
     }
 
     ///
@@ -265,56 +233,14 @@ impl DskyDisplay {
 
         //println!("DSKY: Setting CHANNEL_DSKY Value: {:x}", val);
         self.last_dskyval = val;
+        if (val >> 11) & 0o17 == 12 {
+            self.set_adv_flags(val);
+            return;
+        }
         self.dsky_tx.send(generate_yaagc_packet(0o10, val)).unwrap();
 
-        let (a, _b, c, d) = self.parse_fields(val);
-        match a {
-            1 => {
-                self.digit[13] = get_7seg(c);
-                self.digit[14] = get_7seg(d);
-            }
-            2 => {
-                self.digit[11] = get_7seg(c);
-                self.digit[12] = get_7seg(d);
-            }
-            3 => {
-                self.digit[9] = get_7seg(c);
-                self.digit[10] = get_7seg(d);
-            }
-            4 => {
-                self.digit[7] = get_7seg(c);
-                self.digit[8] = get_7seg(d);
-            }
-            5 => {
-                self.digit[5] = get_7seg(c);
-                self.digit[6] = get_7seg(d);
-            }
-            6 => {
-                self.digit[3] = get_7seg(c);
-                self.digit[4] = get_7seg(d);
-            }
-            7 => {
-                self.digit[1] = get_7seg(c);
-                self.digit[2] = get_7seg(d);
-            }
-            8 => {
-                self.digit[0] = get_7seg(d);
-            }
-            11 => {
-                self.prog = get_7seg_value(c, d);
-            }
-            10 => {
-                self.verb = get_7seg_value(c, d);
-            }
-            9 => {
-                self.noun = get_7seg_value(c, d);
-            }
-            12 => {
-                self.set_adv_flags(val & 0x7FF);
-            }
-            _ => {}
-        };
-        debug!("DSKY: {:?}", self.digit);
+        self.state.update(ragc_core::consts::io::CHANNEL_DSKY, val);
+        debug!("DSKY: {:?}", self.state);
     }
 }
 
@@ -355,30 +281,70 @@ impl ragc_core::mem::periph::AgcIoPeriph for DskyDisplay {
     }
 
     fn is_interrupt(&mut self) -> u16 {
-        if self.keypress.len() > 0 {
-            let val = self.keypress.recv().unwrap();
-            match val & 0o40000 {
-                0o40000 => {
-                    self.proceed = val & 0o37777;
-                }
-                _ => {
-                    self.keypress_val = val;
-                    if self.keypress_val == 0o22 {
-                        let io_val = self.get_channel_value(0o163);
-                        self.set_channel_value(0o163, io_val & !0o00200);
-                    }
-                }
+        self.keys.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        let pressed = self
+            .keypress
+            .try_iter()
+            .filter_map(|packet| keycodes::from_packet(&packet));
+        for val in pressed {
+            if self.keys.press(DskyKeyEvent::from_keypress(val)).is_err() {
+                warn!("DSKY: Key queue full, dropping {:o}", val);
             }
-            (1 << ragc_core::consts::cpu::RUPT_KEY1) as u16
-        } else {
-            0
         }
+        if let Some(DskyKeyEvent::Key(keycodes::KEY_RSET)) = self.keys.pump_mcts(mcts) {
+            let io_val = self.get_channel_value(0o163);
+            self.set_channel_value(0o163, io_val & !0o00200);
+        }
+
+        self.flash.pump_mcts(mcts);
+        self.send_flash();
     }
 }
 
 #[cfg(test)]
 mod dsky_unittests {
     use super::DskyDisplay;
+    use super::YaAgcServer;
+    use crate::dsky_flash::FLASH_ON_MCTS;
+    use crate::vagc::server::LocalClient;
+    use std::time::Duration;
+    use ragc_core::mem::periph::AgcIoPeriph;
+    use yaagc_protocol::agc::AGCPacket;
+
+    ///
+    /// # Description
+    ///
+    /// Tests the clients see VERB and NOUN flash as the AGC runs, with the
+    /// channel 163 lamps only sent when the flash changes them.
+    ///
+    #[test]
+    fn test_dsky_flash_from_mcts() {
+        let server = YaAgcServer::new();
+        let mut dsky = DskyDisplay::with_server(&server);
+        let client = server.connect_local();
+        let chan163 = |client: &LocalClient| {
+            // Packets are broadcast by the server thread, so wait for them
+            std::iter::from_fn(|| client.output().recv_timeout(Duration::from_millis(200)).ok())
+                .filter_map(|msg| match AGCPacket::deserialize(&msg) {
+                    Ok(AGCPacket::ChannelWrite { channel: 0o163, value }) => Some(value),
+                    _ => None,
+                })
+                .collect::<std::vec::Vec<u16>>()
+        };
+
+        dsky.set_dsalmout_flags(0o00040);
+        assert_eq!(chan163(&client), [0o00000]);
+        for _i in 0..FLASH_ON_MCTS - 1 {
+            dsky.pump_mcts(1);
+        }
+        assert_eq!(chan163(&client), []);
+        dsky.pump_mcts(1);
+        assert_eq!(chan163(&client), [0o00040]);
+        assert!(dsky.state().flash_off);
+    }
 
     const AGC_SEG_TABLE: [u8; 11] = [
         // 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, Blank
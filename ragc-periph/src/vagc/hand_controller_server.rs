@@ -0,0 +1,136 @@
+use yaagc_protocol::agc::AGCPacket;
+
+use crate::hand_controller::{HandController, YAACA_CHANNEL_PITCH, YAACA_CHANNEL_ROLL, YAACA_CHANNEL_YAW};
+use super::server::YaAgcServer;
+
+use crossbeam_channel::Receiver;
+use log::{debug, warn};
+
+use ragc_core::mem::periph::AgcIoPeriph;
+
+/// yaAGC channel of the hand controller discretes, as sent by yaACA.
+pub const YAAGC_CHANNEL_CHAN31: u8 = 0o31;
+
+pub struct HandControllerPeriph {
+    hc: HandController<'static>,
+    packets: Receiver<AGCPacket>,
+    chan31_mask: u16,
+}
+
+///
+/// ## HandControllerPeriph Module
+///
+/// The HandControllerPeriph module feeds the `HandController` peripheral from
+/// yaACA, or any yaAGC client sending the RHC deflection on the fictitious
+/// channels 166-170 (octal) and the discretes on channel 31. A mask packet on
+/// channel 31 selects the bits changed by the following channel 31 writes.
+///
+impl HandControllerPeriph {
+    ///
+    /// ## `bind` Function
+    ///
+    /// Creates the peripheral served on its own yaAGC server listening on
+    /// `addr`, returning the bind error if the address is not available.
+    ///
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        YaAgcServer::bind(&[addr]).map(|x| Self::with_server(&x))
+    }
+
+    ///
+    /// ## `with_server` Function
+    ///
+    /// Creates the peripheral taking yaACA packets sent by any client of a
+    /// shared yaAGC server.
+    ///
+    pub fn with_server(server: &YaAgcServer) -> Self {
+        HandControllerPeriph {
+            hc: HandController::new(),
+            packets: server.subscribe(&[
+                YAAGC_CHANNEL_CHAN31,
+                YAACA_CHANNEL_PITCH as u8,
+                YAACA_CHANNEL_YAW as u8,
+                YAACA_CHANNEL_ROLL as u8,
+            ]),
+            chan31_mask: 0o77777,
+        }
+    }
+}
+
+impl AgcIoPeriph for HandControllerPeriph {
+    fn read(&self, channel_idx: usize) -> u16 {
+        self.hc.read(channel_idx)
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        self.hc.write(channel_idx, value);
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        self.hc.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        for packet in self.packets.try_iter() {
+            match packet {
+                AGCPacket::ChannelMask { channel: YAAGC_CHANNEL_CHAN31, mask } => {
+                    self.chan31_mask = mask;
+                }
+                AGCPacket::ChannelWrite { channel, value } => {
+                    debug!("yaACA: {:03o} {:05o}", channel, value);
+                    self.hc.handle_yaagc_input(channel as u16, value, self.chan31_mask);
+                }
+                _ => {
+                    warn!("Unexpected yaACA packet: {:?}", packet);
+                }
+            }
+        }
+        self.hc.pump_mcts(mcts);
+    }
+
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        self.hc.counter_update()
+    }
+}
+
+#[cfg(test)]
+mod hand_controller_server_tests {
+    use super::*;
+    use ragc_core::consts::{cpu, io, special};
+
+    ///
+    /// # Description
+    ///
+    /// Tests yaACA packets reach the hand controller: the RHC counters, and
+    /// the masked channel 31 discretes with a HANDRUPT.
+    ///
+    #[test]
+    fn test_hand_controller_server() {
+        let server = YaAgcServer::new();
+        let mut hc = HandControllerPeriph::with_server(&server);
+        let client = server.connect_local();
+        let packets = [
+            AGCPacket::ChannelWrite {
+                channel: YAACA_CHANNEL_PITCH as u8,
+                value: 0o00012,
+            },
+            AGCPacket::ChannelMask {
+                channel: YAAGC_CHANNEL_CHAN31,
+                mask: 0o00003,
+            },
+            AGCPacket::ChannelWrite {
+                channel: YAAGC_CHANNEL_CHAN31,
+                value: 0o00002,
+            },
+        ];
+        for packet in packets.iter() {
+            client.send(*packet);
+        }
+
+        hc.pump_mcts(1);
+        assert_eq!(hc.read(io::CHANNEL_CHAN31), 0o77777 & !0o40001);
+        assert_eq!(hc.is_interrupt(), 1 << cpu::RUPT_HANDRUPT);
+
+        hc.write(io::CHANNEL_CHAN13, 0o00600);
+        assert_eq!(hc.counter_update(), Some((special::SG_RCHP, 0o12)));
+    }
+}
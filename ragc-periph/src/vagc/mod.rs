@@ -1,2 +1,6 @@
 pub mod dsky;
 pub mod downrupt;
+pub mod hand_controller_server;
+pub mod nav_keys_server;
+pub mod server;
+pub mod uplink_server;
@@ -0,0 +1,116 @@
+use yaagc_protocol::agc::AGCPacket;
+
+use crate::nav_keys::NavKeys;
+use super::server::YaAgcServer;
+
+use crossbeam_channel::Receiver;
+use log::{debug, warn};
+
+use ragc_core::mem::periph::AgcIoPeriph;
+
+/// yaAGC channel of the navigation DSKY keys, marks and rate of descent
+/// switch.
+pub const YAAGC_CHANNEL_NAVKEYIN: u8 = 0o16;
+
+pub struct NavKeysPeriph {
+    keys: NavKeys,
+    packets: Receiver<AGCPacket>,
+}
+
+///
+/// ## NavKeysPeriph Module
+///
+/// The NavKeysPeriph module feeds the `NavKeys` peripheral from yaAGC clients
+/// sending channel 16 packets: a yaDSKY2 acting as the CM navigation bay
+/// DSKY, or any tool pressing the MARK, MARK REJECT or rate of descent
+/// inputs. Packets without any bit set only report a release and are
+/// ignored.
+///
+impl NavKeysPeriph {
+    ///
+    /// ## `bind` Function
+    ///
+    /// Creates the peripheral served on its own yaAGC server listening on
+    /// `addr`, returning the bind error if the address is not available.
+    ///
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        YaAgcServer::bind(&[addr]).map(|x| Self::with_server(&x))
+    }
+
+    ///
+    /// ## `with_server` Function
+    ///
+    /// Creates the peripheral taking channel 16 packets sent by any client of
+    /// a shared yaAGC server.
+    ///
+    pub fn with_server(server: &YaAgcServer) -> Self {
+        NavKeysPeriph {
+            keys: NavKeys::new(),
+            packets: server.subscribe(&[YAAGC_CHANNEL_NAVKEYIN]),
+        }
+    }
+}
+
+impl AgcIoPeriph for NavKeysPeriph {
+    fn read(&self, channel_idx: usize) -> u16 {
+        self.keys.read(channel_idx)
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        self.keys.write(channel_idx, value);
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        self.keys.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        for packet in self.packets.try_iter() {
+            match packet {
+                AGCPacket::ChannelWrite { value: 0, .. } => {}
+                AGCPacket::ChannelWrite { value, .. } => {
+                    debug!("NAVKEYIN: {:03o}", value);
+                    if self.keys.press(value).is_err() {
+                        warn!("NAVKEYIN queue full, dropping {:03o}", value);
+                    }
+                }
+                _ => {
+                    warn!("Unexpected NAVKEYIN packet: {:?}", packet);
+                }
+            }
+        }
+        self.keys.pump_mcts(mcts);
+    }
+}
+
+#[cfg(test)]
+mod nav_keys_server_tests {
+    use super::*;
+    use crate::nav_keys::{NAV_KEYS_MIN_MCTS, NAV_LM_DESCENT_PLUS};
+    use ragc_core::consts::{cpu, io};
+
+    ///
+    /// # Description
+    ///
+    /// Tests channel 16 packets of a yaAGC client reach the AGC with a
+    /// KEYRUPT2, and releases are ignored.
+    ///
+    #[test]
+    fn test_nav_keys_server() {
+        let server = YaAgcServer::new();
+        let mut nav = NavKeysPeriph::with_server(&server);
+        let client = server.connect_local();
+        for value in [NAV_LM_DESCENT_PLUS, 0].iter() {
+            client.send(AGCPacket::ChannelWrite {
+                channel: YAAGC_CHANNEL_NAVKEYIN,
+                value: *value,
+            });
+        }
+
+        nav.pump_mcts(1);
+        assert_eq!(nav.is_interrupt(), 1 << cpu::RUPT_KEY2);
+        assert_eq!(nav.read(io::CHANNEL_NAVKEYIN), NAV_LM_DESCENT_PLUS);
+        nav.pump_mcts(NAV_KEYS_MIN_MCTS as u16);
+        assert_eq!(nav.is_interrupt(), 0);
+    }
+}
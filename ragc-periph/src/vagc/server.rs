@@ -0,0 +1,441 @@
+use yaagc_protocol::agc::AGCPacket;
+use yaagc_protocol::stream::PacketDecoder;
+
+use crossbeam_channel::{bounded, never, select, unbounded, Receiver, Sender, TrySendError};
+use log::{debug, warn};
+
+use std::format;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::println;
+use std::string::String;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::vec::Vec;
+
+/// First port of the range yaAGC serves for a CM AGC.
+pub const YAAGC_CM_BASE_PORT: u16 = 19697;
+
+/// First port of the range yaAGC serves for a LM AGC.
+pub const YAAGC_LM_BASE_PORT: u16 = 19797;
+
+/// Number of ports in each yaAGC port range.
+pub const YAAGC_PORTS_PER_RANGE: u16 = 10;
+
+/// Number of packets queued for each client. A client falling further
+/// behind the broadcast is disconnected.
+pub const YAAGC_CLIENT_QUEUE_LEN: usize = 8192;
+
+/// Number of output packets queued for the broadcast.
+pub const YAAGC_OUTPUT_QUEUE_LEN: usize = 8192;
+
+// Number of yaAGC channels
+const NUM_CHANNELS: usize = 128;
+
+// Time a socket client has to accept a packet before being disconnected
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Routes = Arc<Mutex<Vec<(u8, Sender<AGCPacket>)>>>;
+
+// Client receiving the broadcast packets through its own queue, emptied by a
+// writer thread for socket clients or by the client itself within the
+// process. Sending never blocks: a client with a full queue is dropped.
+struct Client {
+    queue: Sender<[u8; 4]>,
+}
+
+impl Client {
+    fn send(&self, msg: &[u8; 4]) -> bool {
+        match self.queue.try_send(*msg) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("yaAGC server: Disconnecting client falling behind");
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+fn route(routes: &Routes, packet: AGCPacket) {
+    let routes = routes.lock().unwrap();
+    let mut routed = false;
+    for (channel, tx) in routes.iter() {
+        if *channel == packet.channel() {
+            let _res = tx.send(packet);
+            routed = true;
+        }
+    }
+    if !routed {
+        debug!("yaAGC server: Unrouted packet {:?}", packet);
+    }
+}
+
+///
+/// ## `default_addrs` Function
+///
+/// Returns the local addresses of both the CM and LM yaAGC port ranges, which
+/// cover the ports used by yaDSKY2, yaTelemetry and yaACA.
+///
+pub fn default_addrs() -> Vec<String> {
+    [YAAGC_CM_BASE_PORT, YAAGC_LM_BASE_PORT]
+        .iter()
+        .flat_map(|base| *base..*base + YAAGC_PORTS_PER_RANGE)
+        .map(|port| format!("127.0.0.1:{}", port))
+        .collect()
+}
+
+///
+/// ## YaAgcServer Module
+///
+/// The YaAgcServer module serves the yaAGC socket protocol to any number of
+/// simultaneous clients, on any number of ports. Output packets are broadcast
+/// to every client, and new clients are sent the last value written to each
+/// channel so they start with the current state. Each client has its own
+/// queue of `YAAGC_CLIENT_QUEUE_LEN` packets, so a stalled client never holds
+/// back the others: it is disconnected once its queue is full. Input packets
+/// are routed to the peripherals subscribed to their channel.
+///
+pub struct YaAgcServer {
+    output: Sender<[u8; 4]>,
+    clients: Sender<Client>,
+    routes: Routes,
+    local_addrs: Vec<SocketAddr>,
+}
+
+///
+/// ## `LocalClient` Structure
+///
+/// Client of a `YaAgcServer` living in the same process, such as a terminal
+/// DSKY. It receives the same packets as the socket clients, and its packets
+/// are routed to the peripherals the same way.
+///
+pub struct LocalClient {
+    output: Receiver<[u8; 4]>,
+    routes: Routes,
+}
+
+impl LocalClient {
+    ///
+    /// ## `output` Function
+    ///
+    /// Returns the receiver of the serialized packets broadcast by the server,
+    /// starting with the last value of each channel. The receiver is
+    /// disconnected if more than `YAAGC_CLIENT_QUEUE_LEN` packets are left
+    /// unread.
+    ///
+    pub fn output(&self) -> &Receiver<[u8; 4]> {
+        &self.output
+    }
+
+    ///
+    /// ## `send` Function
+    ///
+    /// Sends a packet to the peripherals subscribed to its channel.
+    ///
+    pub fn send(&self, packet: AGCPacket) {
+        route(&self.routes, packet);
+    }
+}
+
+fn broadcast_thread(output_rx: Receiver<[u8; 4]>, mut client_rx: Receiver<Client>) {
+    let mut clients: Vec<Client> = Vec::new();
+    let mut last: [Option<[u8; 4]>; NUM_CHANNELS] = [None; NUM_CHANNELS];
+
+    loop {
+        select! {
+            recv(output_rx) -> msg => {
+                let msg = match msg {
+                    Ok(x) => x,
+                    _ => break,
+                };
+                if let Ok(AGCPacket::ChannelWrite { channel, .. }) = AGCPacket::deserialize(&msg) {
+                    last[channel as usize] = Some(msg);
+                }
+                clients.retain(|x| x.send(&msg));
+            }
+            recv(client_rx) -> client => {
+                let client = match client {
+                    Ok(x) => x,
+                    _ => {
+                        // Server and listeners gone, keep serving the output
+                        client_rx = never();
+                        continue;
+                    }
+                };
+                if last.iter().flatten().all(|x| client.send(x)) {
+                    clients.push(client);
+                }
+            }
+        }
+    }
+}
+
+fn writer_thread(mut stream: TcpStream, queue: Receiver<[u8; 4]>) {
+    for msg in queue.iter() {
+        if stream.write_all(&msg).is_err() {
+            break;
+        }
+    }
+    // Dropped by the broadcast or gone, make sure the reader ends as well
+    let _res = stream.shutdown(std::net::Shutdown::Both);
+}
+
+fn client_thread(mut stream: TcpStream, routes: Routes) {
+    let mut decoder = PacketDecoder::new();
+    let mut buf = [0; 64];
+    loop {
+        let len = match stream.read(&mut buf) {
+            Ok(0) | Err(_) => {
+                break;
+            }
+            Ok(x) => x,
+        };
+
+        for res in decoder.decode(&buf[..len]) {
+            match res {
+                Ok(packet) => {
+                    route(&routes, packet);
+                }
+                Err(x) => {
+                    warn!("yaAGC server: {}", x);
+                }
+            }
+        }
+    }
+    println!("yaAGC server: Disconnecting from stream session");
+}
+
+fn listener_thread(listener: TcpListener, client_tx: Sender<Client>, routes: Routes) {
+    for stream in listener.incoming().flatten() {
+        println!("yaAGC server: Connecting to new stream");
+        let writer = match stream.try_clone() {
+            Ok(x) => x,
+            _ => {
+                continue;
+            }
+        };
+        let _res = writer.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT));
+        let (queue, queue_rx) = bounded(YAAGC_CLIENT_QUEUE_LEN);
+        if client_tx.send(Client { queue }).is_err() {
+            break;
+        }
+        std::thread::spawn(move || writer_thread(writer, queue_rx));
+        let client_routes = routes.clone();
+        std::thread::spawn(move || client_thread(stream, client_routes));
+    }
+}
+
+impl YaAgcServer {
+    ///
+    /// ## `new` Function
+    ///
+    /// Creates a server without any listening socket. Peripherals can still
+    /// be attached to it, but no client is able to connect.
+    ///
+    pub fn new() -> Self {
+        let (output, output_rx) = bounded(YAAGC_OUTPUT_QUEUE_LEN);
+        let (clients, client_rx) = unbounded();
+        std::thread::spawn(move || broadcast_thread(output_rx, client_rx));
+
+        YaAgcServer {
+            output,
+            clients,
+            routes: Arc::new(Mutex::new(Vec::new())),
+            local_addrs: Vec::new(),
+        }
+    }
+
+    ///
+    /// ## `bind` Function
+    ///
+    /// Creates a server listening on all the given addresses. Either all the
+    /// addresses are bound, or the first bind error is returned along with
+    /// the failing address.
+    ///
+    pub fn bind<A: AsRef<str>>(addrs: &[A]) -> io::Result<Self> {
+        let mut listeners = Vec::new();
+        for addr in addrs.iter() {
+            let listener = TcpListener::bind(addr.as_ref())
+                .map_err(|x| io::Error::new(x.kind(), format!("{}: {}", addr.as_ref(), x)))?;
+            listeners.push(listener);
+        }
+        let local_addrs = listeners
+            .iter()
+            .map(|x| x.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (output, output_rx) = bounded(YAAGC_OUTPUT_QUEUE_LEN);
+        let (client_tx, client_rx) = unbounded();
+        let routes: Routes = Arc::new(Mutex::new(Vec::new()));
+
+        std::thread::spawn(move || broadcast_thread(output_rx, client_rx));
+        for listener in listeners {
+            let tx = client_tx.clone();
+            let listener_routes = routes.clone();
+            std::thread::spawn(move || listener_thread(listener, tx, listener_routes));
+        }
+
+        Ok(YaAgcServer {
+            output,
+            clients: client_tx,
+            routes,
+            local_addrs,
+        })
+    }
+
+    ///
+    /// ## `local_addrs` Function
+    ///
+    /// Returns the addresses the server is listening on.
+    ///
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    ///
+    /// ## `output` Function
+    ///
+    /// Returns a sender of serialized packets to broadcast to every client.
+    ///
+    pub fn output(&self) -> Sender<[u8; 4]> {
+        self.output.clone()
+    }
+
+    ///
+    /// ## `connect_local` Function
+    ///
+    /// Connects a client living in the same process to the server.
+    ///
+    pub fn connect_local(&self) -> LocalClient {
+        let (queue, output) = bounded(YAAGC_CLIENT_QUEUE_LEN);
+        let _res = self.clients.send(Client { queue });
+        LocalClient {
+            output,
+            routes: self.routes.clone(),
+        }
+    }
+
+    ///
+    /// ## `subscribe` Function
+    ///
+    /// Returns a receiver of every packet sent by the clients on the given
+    /// channels. Counter increment packets are routed by counter address.
+    ///
+    pub fn subscribe(&self, channels: &[u8]) -> Receiver<AGCPacket> {
+        let (tx, rx) = unbounded();
+        let mut routes = self.routes.lock().unwrap();
+        for channel in channels.iter() {
+            routes.push((*channel, tx.clone()));
+        }
+        rx
+    }
+}
+
+impl Default for YaAgcServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use std::time::Duration;
+    use yaagc_protocol::agc::generate_yaagc_packet;
+
+    fn read_packet(stream: &mut TcpStream) -> [u8; 4] {
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests output packets reach every client, including the last channel
+    /// values for late and local clients, and input packets are routed by
+    /// channel.
+    ///
+    #[test]
+    fn test_server_clients() {
+        let server = YaAgcServer::bind(&["127.0.0.1:0", "127.0.0.1:0"]).unwrap();
+        let keys = server.subscribe(&[0o15]);
+        let addrs = server.local_addrs().to_vec();
+
+        let mut a = TcpStream::connect(addrs[0]).unwrap();
+        let mut b = TcpStream::connect(addrs[1]).unwrap();
+        a.set_read_timeout(Some(Duration::new(5, 0))).unwrap();
+        b.set_read_timeout(Some(Duration::new(5, 0))).unwrap();
+
+        let packet = generate_yaagc_packet(0o10, 0o12345);
+        server.output().send(packet).unwrap();
+        assert_eq!(read_packet(&mut a), packet);
+        assert_eq!(read_packet(&mut b), packet);
+
+        let mut c = TcpStream::connect(addrs[0]).unwrap();
+        c.set_read_timeout(Some(Duration::new(5, 0))).unwrap();
+        assert_eq!(read_packet(&mut c), packet);
+        let local = server.connect_local();
+        assert_eq!(local.output().recv_timeout(Duration::new(5, 0)), Ok(packet));
+
+        b.write_all(&generate_yaagc_packet(0o15, 0o21)).unwrap();
+        a.write_all(&generate_yaagc_packet(0o16, 0o21)).unwrap();
+        c.write_all(&generate_yaagc_packet(0o15, 0o34)).unwrap();
+        local.send(AGCPacket::ChannelWrite {
+            channel: 0o15,
+            value: 0o36,
+        });
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(keys.recv_timeout(Duration::new(5, 0)).unwrap());
+        }
+        received.sort_by_key(|x| match x {
+            AGCPacket::ChannelWrite { value, .. } => *value,
+            _ => 0,
+        });
+        assert_eq!(
+            received,
+            std::vec![
+                AGCPacket::ChannelWrite {
+                    channel: 0o15,
+                    value: 0o21
+                },
+                AGCPacket::ChannelWrite {
+                    channel: 0o15,
+                    value: 0o34
+                },
+                AGCPacket::ChannelWrite {
+                    channel: 0o15,
+                    value: 0o36
+                },
+            ]
+        );
+        assert!(keys.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests a client which stops reading is disconnected once its queue is
+    /// full, without holding back the other clients.
+    ///
+    #[test]
+    fn test_server_slow_client() {
+        let server = YaAgcServer::new();
+        let stalled = server.connect_local();
+        let reader = server.connect_local();
+
+        let output = server.output();
+        for value in 0..=YAAGC_CLIENT_QUEUE_LEN as u16 {
+            output.send(generate_yaagc_packet(0o10, value)).unwrap();
+            let msg = reader.output().recv_timeout(Duration::new(5, 0)).unwrap();
+            assert_eq!(msg, generate_yaagc_packet(0o10, value));
+        }
+
+        assert_eq!(stalled.output().try_iter().count(), YAAGC_CLIENT_QUEUE_LEN);
+        let res = stalled.output().recv_timeout(Duration::from_millis(100));
+        assert!(res.unwrap_err().is_disconnected());
+    }
+}
@@ -0,0 +1,170 @@
+use yaagc_protocol::agc::AGCPacket;
+
+use crate::keycodes;
+use crate::uplink::{uplink_word, Uplink, UplinkError};
+use super::server::YaAgcServer;
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{debug, error, warn};
+
+use ragc_core::mem::periph::AgcIoPeriph;
+
+/// yaAGC channel used by ground stations to send uplink words.
+pub const YAAGC_CHANNEL_INLINK: u8 = 0o173;
+
+/// Address served by `new`, the default port of uplink clients.
+pub const UPLINK_DEFAULT_ADDR: &str = "127.0.0.1:19801";
+
+///
+/// ## `UplinkSender` Structure
+///
+/// Handle used to send uplink words to an `UplinkPeriph` from other threads.
+///
+#[derive(Clone)]
+pub struct UplinkSender {
+    tx: Sender<u16>,
+}
+
+impl UplinkSender {
+    ///
+    /// ## `send_word` Function
+    ///
+    /// Sends a raw 15-bit uplink word.
+    ///
+    pub fn send_word(&self, word: u16) {
+        let _res = self.tx.send(word & 0o77777);
+    }
+
+    ///
+    /// ## `send_keys` Function
+    ///
+    /// Sends a sequence of DSKY keys such as `V37E63E` as triply redundant
+    /// uplink words. Nothing is sent if any of the keys is invalid.
+    ///
+    pub fn send_keys(&self, keys: &str) -> Result<(), UplinkError> {
+        let mut words = std::vec::Vec::new();
+        for key in keys.chars() {
+            let code = keycodes::from_char(key).ok_or(UplinkError::InvalidKey(key))?;
+            words.push(uplink_word(code));
+        }
+        for word in words {
+            self.send_word(word);
+        }
+        Ok(())
+    }
+}
+
+pub struct UplinkPeriph {
+    uplink: Uplink,
+    rx: Receiver<u16>,
+    tx: Sender<u16>,
+    packets: Receiver<AGCPacket>,
+}
+
+///
+/// ## UplinkPeriph Module
+///
+/// The UplinkPeriph module feeds the `Uplink` peripheral from ground
+/// stations connected over TCP, which send yaAGC packets on the INLINK
+/// channel (0o173), and from `UplinkSender` handles given to the rest of the
+/// application.
+///
+impl UplinkPeriph {
+    ///
+    /// ## `new` Function
+    ///
+    /// Creates the peripheral served on `UPLINK_DEFAULT_ADDR`. If the address
+    /// can not be bound, the error is logged and the peripheral runs without
+    /// any client. Use `bind` to handle the error instead.
+    ///
+    pub fn new() -> Self {
+        match Self::bind(UPLINK_DEFAULT_ADDR) {
+            Ok(x) => x,
+            Err(x) => {
+                error!("Uplink: Unable to bind: {}", x);
+                Self::with_server(&YaAgcServer::new())
+            }
+        }
+    }
+
+    ///
+    /// ## `bind` Function
+    ///
+    /// Creates the peripheral served on its own yaAGC server listening on
+    /// `addr`, returning the bind error if the address is not available.
+    ///
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        YaAgcServer::bind(&[addr]).map(|x| Self::with_server(&x))
+    }
+
+    ///
+    /// ## `with_server` Function
+    ///
+    /// Creates the peripheral taking uplink words from the INLINK packets
+    /// sent by any client of a shared yaAGC server.
+    ///
+    pub fn with_server(server: &YaAgcServer) -> Self {
+        let (tx, rx) = unbounded();
+        UplinkPeriph {
+            uplink: Uplink::new(),
+            rx,
+            tx,
+            packets: server.subscribe(&[YAAGC_CHANNEL_INLINK]),
+        }
+    }
+
+    pub fn sender(&self) -> UplinkSender {
+        UplinkSender {
+            tx: self.tx.clone(),
+        }
+    }
+
+    pub fn set_block(&mut self, block: bool) {
+        self.uplink.set_block(block);
+    }
+}
+
+impl Default for UplinkPeriph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AgcIoPeriph for UplinkPeriph {
+    fn read(&self, channel_idx: usize) -> u16 {
+        self.uplink.read(channel_idx)
+    }
+
+    fn write(&mut self, channel_idx: usize, value: u16) {
+        self.uplink.write(channel_idx, value);
+    }
+
+    fn is_interrupt(&mut self) -> u16 {
+        self.uplink.is_interrupt()
+    }
+
+    fn pump_mcts(&mut self, mcts: u16) {
+        for packet in self.packets.try_iter() {
+            match packet {
+                AGCPacket::ChannelWrite { value, .. } => {
+                    debug!("Uplink word: {:05o}", value);
+                    let _res = self.tx.send(value);
+                }
+                _ => {
+                    warn!("Unexpected uplink packet: {:?}", packet);
+                }
+            }
+        }
+
+        while let Ok(word) = self.rx.try_recv() {
+            if self.uplink.send_word(word).is_err() {
+                warn!("Uplink queue full, dropping word {:05o}", word);
+            }
+        }
+        self.uplink.pump_mcts(mcts);
+    }
+
+    fn counter_update(&mut self) -> Option<(usize, u16)> {
+        self.uplink.counter_update()
+    }
+}
@@ -0,0 +1,578 @@
+// GDB remote serial protocol stub, debugging the AGC from any GDB front-end.
+//
+// Memory is the flat space of `AgcMemoryMap` pseudo-addresses, two bytes per
+// word: erasable bank E at byte E * 0o1000, and fixed bank F at byte
+// 0o20000 + F * 0o4000. Words and registers are sent little endian.
+//
+// GDB has no AGC architecture, so the target description only names the
+// registers and cannot give an `<architecture>`. A stock GDB keeps its own
+// default architecture and rejects the description; clients have to take the
+// registers from the description as-is, or speak the protocol directly.
+
+use gdbstub::arch::{Arch, Registers};
+use gdbstub::common::Signal;
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::{run_blocking, DisconnectReason, GdbStub, SingleThreadStopReason};
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep,
+    SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::breakpoints::{Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps};
+use gdbstub::target::{Target, TargetError, TargetResult};
+use log::info;
+
+use ragc_core::consts::cpu::*;
+use ragc_core::cpu::AgcCpu;
+use ragc_core::runner::AgcRunner;
+
+use std::marker::PhantomData;
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+/// AGC registers sent to GDB, in target description order.
+pub const REGISTERS: [(&str, usize); 15] = [
+    ("a", REG_A),
+    ("l", REG_L),
+    ("q", REG_Q),
+    ("eb", REG_EB),
+    ("fb", REG_FB),
+    ("z", REG_Z),
+    ("bb", REG_BB),
+    ("a_shadow", REG_A_SHADOW),
+    ("b_shadow", REG_B_SHADOW),
+    ("lr_shadow", REG_LR_SHADOW),
+    ("eb_shadow", REG_EB_SHADOW),
+    ("fb_shadow", REG_FB_SHADOW),
+    ("z_shadow", REG_PC_SHADOW),
+    ("bb_shadow", REG_BB_SHADOW),
+    ("brupt", REG_IR),
+];
+
+// Size of the register block: the 16 bit registers, then the 32 bit byte
+// address of the next instruction
+const REGISTERS_LEN: usize = REGISTERS.len() * 2 + 4;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.ragc.agc">
+    <reg name="a" bitsize="16" type="uint16"/>
+    <reg name="l" bitsize="16" type="uint16"/>
+    <reg name="q" bitsize="16" type="uint16"/>
+    <reg name="eb" bitsize="16" type="uint16"/>
+    <reg name="fb" bitsize="16" type="uint16"/>
+    <reg name="z" bitsize="16" type="uint16"/>
+    <reg name="bb" bitsize="16" type="uint16"/>
+    <reg name="a_shadow" bitsize="16" type="uint16"/>
+    <reg name="b_shadow" bitsize="16" type="uint16"/>
+    <reg name="lr_shadow" bitsize="16" type="uint16"/>
+    <reg name="eb_shadow" bitsize="16" type="uint16"/>
+    <reg name="fb_shadow" bitsize="16" type="uint16"/>
+    <reg name="z_shadow" bitsize="16" type="uint16"/>
+    <reg name="bb_shadow" bitsize="16" type="uint16"/>
+    <reg name="brupt" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// Host time slept between slices of the running AGC, while polling GDB for
+// an interrupt
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+///
+/// ## AgcArch Enum
+///
+/// GDB architecture of the AGC, described to GDB by a target description.
+///
+pub enum AgcArch {}
+
+impl Arch for AgcArch {
+    type Usize = u32;
+    type Registers = AgcRegisters;
+    type BreakpointKind = usize;
+    type RegId = ();
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
+
+///
+/// ## AgcRegisters Structure
+///
+/// Registers of `REGISTERS`, and `pc`, the byte address of the next
+/// instruction in the flat memory space. `pc` is read-only: the AGC is moved
+/// by writing Z and the bank registers.
+///
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AgcRegisters {
+    regs: [u16; REGISTERS.len()],
+    pc: u32,
+}
+
+impl Registers for AgcRegisters {
+    type ProgramCounter = u32;
+
+    fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for reg in self.regs.iter() {
+            reg.to_le_bytes().iter().for_each(|x| write_byte(Some(*x)));
+        }
+        self.pc.to_le_bytes().iter().for_each(|x| write_byte(Some(*x)));
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() != REGISTERS_LEN {
+            return Err(());
+        }
+        for (reg, x) in self.regs.iter_mut().zip(bytes.chunks(2)) {
+            *reg = u16::from_le_bytes([x[0], x[1]]);
+        }
+        let pc = &bytes[REGISTERS_LEN - 4..];
+        self.pc = u32::from_le_bytes([pc[0], pc[1], pc[2], pc[3]]);
+        Ok(())
+    }
+}
+
+// Byte address of the next instruction
+fn pc_address(cpu: &mut AgcCpu) -> u32 {
+    let z = (cpu.read(REG_Z) & 0o7777) as usize;
+    (cpu.pseudo_address(z) * 2) as u32
+}
+
+enum ExecMode {
+    Step,
+    Continue,
+}
+
+///
+/// ## AgcTarget Structure
+///
+/// GDB target running an `AgcCpu`, paced by an `AgcRunner` when continued.
+/// Breakpoints are kept by byte address and checked after every
+/// instruction, so fixed memory is never patched.
+///
+pub struct AgcTarget<'a, 'b> {
+    cpu: &'b mut AgcCpu<'a>,
+    runner: &'b mut AgcRunner,
+    clock: Instant,
+    breakpoints: Vec<u32>,
+    exec_mode: ExecMode,
+}
+
+impl<'a, 'b> AgcTarget<'a, 'b> {
+    pub fn new(cpu: &'b mut AgcCpu<'a>, runner: &'b mut AgcRunner, clock: Instant) -> Self {
+        AgcTarget {
+            cpu,
+            runner,
+            clock,
+            breakpoints: vec![],
+            exec_mode: ExecMode::Continue,
+        }
+    }
+}
+
+impl Target for AgcTarget<'_, '_> {
+    type Arch = AgcArch;
+    type Error = &'static str;
+
+    #[inline(always)]
+    fn base_ops(&mut self) -> BaseOps<'_, AgcArch, Self::Error> {
+        BaseOps::SingleThread(self)
+    }
+
+    #[inline(always)]
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for AgcTarget<'_, '_> {
+    fn read_registers(&mut self, regs: &mut AgcRegisters) -> TargetResult<(), Self> {
+        for (reg, (_, idx)) in regs.regs.iter_mut().zip(REGISTERS.iter()) {
+            *reg = self.cpu.read(*idx);
+        }
+        regs.pc = pc_address(self.cpu);
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &AgcRegisters) -> TargetResult<(), Self> {
+        // Only the registers GDB changed are written, so a new EB or FB is not
+        // undone by the old BB sent along with it
+        let changed: Vec<_> = REGISTERS
+            .iter()
+            .zip(regs.regs.iter())
+            .filter(|((_, idx), value)| self.cpu.read(*idx) != **value)
+            .collect();
+        for ((_, idx), value) in changed {
+            self.cpu.write(*idx, *value);
+        }
+        Ok(())
+    }
+
+    fn read_addrs(&mut self, start_addr: u32, data: &mut [u8]) -> TargetResult<usize, Self> {
+        for (idx, byte) in data.iter_mut().enumerate() {
+            let addr = start_addr as usize + idx;
+            match self.cpu.read_pseudo(addr / 2) {
+                Some(word) => *byte = word.to_le_bytes()[addr % 2],
+                None if idx == 0 => return Err(TargetError::NonFatal),
+                None => return Ok(idx),
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn write_addrs(&mut self, start_addr: u32, data: &[u8]) -> TargetResult<(), Self> {
+        for (idx, byte) in data.iter().enumerate() {
+            let addr = start_addr as usize + idx;
+            let mut word = match self.cpu.read_pseudo(addr / 2) {
+                Some(x) => x.to_le_bytes(),
+                None => return Err(TargetError::NonFatal),
+            };
+            word[addr % 2] = *byte;
+            if !self.cpu.write_pseudo(addr / 2, u16::from_le_bytes(word)) {
+                return Err(TargetError::NonFatal);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadResume for AgcTarget<'_, '_> {
+    fn resume(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+        if signal.is_some() {
+            return Err("no support for continuing with signal");
+        }
+        // Restart the pacing, so the time spent stopped is not caught up
+        self.runner.set_mode(self.runner.mode());
+        self.exec_mode = ExecMode::Continue;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for AgcTarget<'_, '_> {
+    fn step(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+        if signal.is_some() {
+            return Err("no support for stepping with signal");
+        }
+        self.exec_mode = ExecMode::Step;
+        Ok(())
+    }
+}
+
+impl Breakpoints for AgcTarget<'_, '_> {
+    #[inline(always)]
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for AgcTarget<'_, '_> {
+    fn add_sw_breakpoint(&mut self, addr: u32, _kind: usize) -> TargetResult<bool, Self> {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u32, _kind: usize) -> TargetResult<bool, Self> {
+        match self.breakpoints.iter().position(|x| *x == addr) {
+            Some(idx) => {
+                self.breakpoints.remove(idx);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+struct AgcEventLoop<'a, 'b>(PhantomData<AgcTarget<'a, 'b>>);
+
+impl<'a, 'b> run_blocking::BlockingEventLoop for AgcEventLoop<'a, 'b> {
+    type Target = AgcTarget<'a, 'b>;
+    type Connection = TcpStream;
+    type StopReason = SingleThreadStopReason<u32>;
+
+    #[allow(clippy::type_complexity)]
+    fn wait_for_stop_reason(
+        target: &mut AgcTarget<'a, 'b>,
+        conn: &mut TcpStream,
+    ) -> Result<
+        run_blocking::Event<SingleThreadStopReason<u32>>,
+        run_blocking::WaitForStopReasonError<&'static str, std::io::Error>,
+    > {
+        if let ExecMode::Step = target.exec_mode {
+            target.runner.step_instruction(target.cpu);
+            return Ok(run_blocking::Event::TargetStopped(SingleThreadStopReason::DoneStep));
+        }
+
+        let AgcTarget {
+            cpu,
+            runner,
+            clock,
+            breakpoints,
+            ..
+        } = target;
+        loop {
+            // GDB sends a byte to interrupt the AGC
+            if conn.peek().map(|x| x.is_some()).unwrap_or(true) {
+                let byte = conn
+                    .read()
+                    .map_err(run_blocking::WaitForStopReasonError::Connection)?;
+                return Ok(run_blocking::Event::IncomingData(byte));
+            }
+
+            let now = clock.elapsed().as_nanos() as u64;
+            let (_, stopped) = runner.advance_until(cpu, now, |cpu| {
+                !breakpoints.is_empty() && breakpoints.contains(&pc_address(cpu))
+            });
+            if stopped {
+                let reason = SingleThreadStopReason::SwBreak(());
+                return Ok(run_blocking::Event::TargetStopped(reason));
+            }
+            match runner.idle_ns() {
+                Some(0) => {}
+                Some(x) => std::thread::sleep(Duration::from_nanos(x).max(POLL_INTERVAL)),
+                None => std::thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    fn on_interrupt(
+        _target: &mut AgcTarget<'a, 'b>,
+    ) -> Result<Option<SingleThreadStopReason<u32>>, &'static str> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+///
+/// ## `serve` Function
+///
+/// Waits for a GDB connection on `addr`, and debugs the AGC until GDB
+/// disconnects. The AGC is stopped until GDB continues it.
+///
+/// ### Result
+///
+///  - `Ok(reason)` - GDB detached or killed the AGC
+///  - `Err(x)` - The connection or the GDB session failed
+///
+pub fn serve(
+    cpu: &mut AgcCpu,
+    runner: &mut AgcRunner,
+    clock: Instant,
+    addr: &str,
+) -> Result<DisconnectReason, String> {
+    let listener = TcpListener::bind(addr).map_err(|x| format!("{}: {}", addr, x))?;
+    println!("Waiting for a GDB connection on {}", addr);
+    serve_listener(cpu, runner, clock, &listener)
+}
+
+///
+/// ## `serve_listener` Function
+///
+/// Same as `serve`, on a socket already listening.
+///
+fn serve_listener(
+    cpu: &mut AgcCpu,
+    runner: &mut AgcRunner,
+    clock: Instant,
+    listener: &TcpListener,
+) -> Result<DisconnectReason, String> {
+    let (stream, peer) = listener.accept().map_err(|x| x.to_string())?;
+    info!("GDB connected from {}", peer);
+
+    let mut target = AgcTarget::new(cpu, runner, clock);
+    GdbStub::new(stream)
+        .run_blocking::<AgcEventLoop>(&mut target)
+        .map_err(|x| x.to_string())
+}
+
+#[cfg(test)]
+mod gdb_tests {
+    use super::*;
+    use crate::script::NoDownrupt;
+    use ragc_core::mem::AgcMemoryMap;
+    use ragc_core::runner::RunMode;
+    use std::io::{Read, Write};
+
+    ///
+    /// Sends a remote serial protocol packet and returns the reply, playing
+    /// the GDB side of the acknowledgements and expanding run-length encoded
+    /// bytes.
+    ///
+    fn packet(stream: &mut TcpStream, data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, x| sum.wrapping_add(x));
+        write!(stream, "${}#{:02x}", data, sum).unwrap();
+
+        let mut reply = vec![];
+        let mut byte = [0u8];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => {}
+                b'#' => break,
+                b'*' => {
+                    stream.read_exact(&mut byte).unwrap();
+                    let last = *reply.last().unwrap();
+                    reply.extend((0..byte[0] - 29).map(|_| last));
+                }
+                x => reply.push(x),
+            }
+        }
+        let mut sum = [0u8; 2];
+        stream.read_exact(&mut sum).unwrap();
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply[1..].to_vec()).unwrap()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the registers survive a round trip through the GDB register
+    /// block, little endian with `pc` last, and blocks of the wrong size are
+    /// rejected.
+    ///
+    #[test]
+    fn test_registers_round_trip() {
+        let mut regs = AgcRegisters::default();
+        for (idx, reg) in regs.regs.iter_mut().enumerate() {
+            *reg = 0o70000 + idx as u16;
+        }
+        regs.pc = 0x00012345;
+
+        let mut bytes = vec![];
+        regs.gdb_serialize(|x| bytes.push(x.unwrap()));
+        assert_eq!(bytes.len(), REGISTERS_LEN);
+        assert_eq!(bytes[..2], 0o70000u16.to_le_bytes());
+        assert_eq!(bytes[REGISTERS_LEN - 4..], [0x45, 0x23, 0x01, 0x00]);
+
+        let mut res = AgcRegisters::default();
+        assert_eq!(res.gdb_deserialize(&bytes), Ok(()));
+        assert_eq!(res, regs);
+        assert_eq!(res.gdb_deserialize(&bytes[1..]), Err(()));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests bytes map onto the little endian words of the pseudo-address
+    /// space, including reads and writes starting on the high byte of a
+    /// word, and reads stopping at the end of erasable memory.
+    ///
+    #[test]
+    fn test_read_write_addrs() {
+        let mut q = heapless::spsc::Queue::new();
+        let (rupt_tx, _rupt_rx) = q.split();
+        let mut downrupt = NoDownrupt;
+        let mut dsky = NoDownrupt;
+        let mm = AgcMemoryMap::new(ragc_ropes::BLANK_ROPE, &mut downrupt, &mut dsky, rupt_tx);
+        let mut cpu = AgcCpu::new(mm);
+        let mut runner = AgcRunner::new(RunMode::MaxSpeed);
+        let mut target = AgcTarget::new(&mut cpu, &mut runner, Instant::now());
+
+        // E3 word 100 high byte, then word 101 low byte
+        let addr = (0o1400 + 0o100) * 2 + 1;
+        assert!(target.write_addrs(addr, &[0x12, 0x34]).is_ok());
+        assert_eq!(target.cpu.read_pseudo(0o1500), Some(0x1200));
+        assert_eq!(target.cpu.read_pseudo(0o1501), Some(0x0034));
+
+        let mut data = [0; 3];
+        assert_eq!(target.read_addrs(addr - 1, &mut data).ok(), Some(3));
+        assert_eq!(data, [0x00, 0x12, 0x34]);
+
+        assert!(target.write_addrs(0o1502 * 2, &[0x21, 0x43]).is_ok());
+        assert_eq!(target.cpu.read_pseudo(0o1502), Some(0x4321));
+
+        // Erasable memory ends at pseudo-address 3777
+        let end = 0o3777 * 2 + 1;
+        let mut data = [0; 4];
+        assert_eq!(target.read_addrs(end, &mut data).ok(), Some(1));
+        assert!(matches!(target.read_addrs(end + 1, &mut data), Err(TargetError::NonFatal)));
+        assert!(matches!(target.write_addrs(end, &[0, 0]), Err(TargetError::NonFatal)));
+
+        // Fixed memory is read-only without --rom-write
+        assert!(matches!(target.write_addrs(0o10000 * 2, &[1]), Err(TargetError::NonFatal)));
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests a session over loopback the way a GDB client drives it: the
+    /// target description, the register block, memory reads and writes,
+    /// breakpoints and detaching.
+    ///
+    #[test]
+    fn test_serve_session() {
+        let mut q = heapless::spsc::Queue::new();
+        let (rupt_tx, _rupt_rx) = q.split();
+        let mut downrupt = NoDownrupt;
+        let mut dsky = NoDownrupt;
+        let mm = AgcMemoryMap::new(ragc_ropes::BLANK_ROPE, &mut downrupt, &mut dsky, rupt_tx);
+        let mut cpu = AgcCpu::new(mm);
+        cpu.reset();
+        cpu.write(REG_IR, 0o1234);
+        let mut runner = AgcRunner::new(RunMode::MaxSpeed);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut replies = vec![packet(&mut stream, "qSupported:multiprocess+;xmlRegisters=i386")];
+
+            // The target description comes in chunks, the last one with `l`
+            let mut xml = String::new();
+            loop {
+                let reply = packet(&mut stream, &format!("qXfer:features:read:target.xml:{:x},100", xml.len()));
+                xml.push_str(&reply[1..]);
+                if !reply.starts_with('m') {
+                    break;
+                }
+            }
+            replies.push(xml);
+
+            for data in [
+                "g",
+                "M3c0,2:3412",
+                "m3c0,2",
+                "Z0,4000,0",
+                "D",
+            ]
+            .iter()
+            {
+                replies.push(packet(&mut stream, data));
+            }
+            replies
+        });
+
+        let reason = serve_listener(&mut cpu, &mut runner, Instant::now(), &listener);
+        assert!(matches!(reason, Ok(DisconnectReason::Disconnect)));
+        assert_eq!(cpu.read_pseudo(0o740), Some(0x1234));
+
+        let replies = client.join().unwrap();
+        assert!(replies[0].contains("qXfer:features:read+"), "{}", replies[0]);
+        assert!(replies[1].trim_end().ends_with("</target>"), "{}", replies[1]);
+        assert!(replies[1].contains(r#"<reg name="brupt""#));
+        assert!(!replies[1].contains("<architecture>"));
+        assert_eq!(replies[2].len(), REGISTERS_LEN * 2);
+        assert_eq!(&replies[2][REGISTERS.len() * 4 - 4..REGISTERS.len() * 4], "9c02");
+        assert_eq!(replies[3], "OK");
+        assert_eq!(replies[4], "3412");
+        assert_eq!(replies[5], "OK");
+        assert_eq!(replies[6], "OK");
+    }
+}
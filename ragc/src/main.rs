@@ -5,7 +5,8 @@ use ctrlc;
 use env_logger;
 use log::error;
 
-use ragc_core::{cpu, mem};
+use ragc_core::fault::FaultInjector;
+use ragc_core::{cpu, mem, runner};
 use ragc_ropes;
 use ragc_periph;
 
@@ -15,12 +16,104 @@ pub const ROM_BANK_NUM_WORDS: usize = 1024;
 use std::io::Read;
 use std::fs::File;
 
+mod gdb;
+mod monitor;
+mod run;
+mod script;
+mod tui;
+
 fn fetch_config<'a>() -> clap::ArgMatches<'a> {
     let about =
         "RAGC is a Block-II Apollo Guidance Computer functional emulator written entirely in Rust";
     let c = clap::App::new("Rust Apollo Guidance Computer (RAGC)")
         .version("0.1")
         .about(about)
+        .arg(clap::Arg::with_name("downlist_json")
+            .long("downlist-json")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write decoded downlists to FILE as JSON lines")
+        )
+        .arg(clap::Arg::with_name("downlist_layout")
+            .long("downlist-layout")
+            .takes_value(true)
+            .possible_values(&["lm", "cm"])
+            .default_value("lm")
+            .help("Vehicle of the example downlist layouts used for decoding")
+        )
+        .arg(clap::Arg::with_name("record_telemetry")
+            .long("record-telemetry")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Record DOWNRUPT words to FILE, as CSV for .csv files and binary otherwise")
+        )
+        .arg(clap::Arg::with_name("yaagc_addr")
+            .long("yaagc-addr")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("ADDR")
+            .help("Serve yaAGC clients on ADDR or PORT instead of the CM and LM port ranges")
+        )
+        .arg(clap::Arg::with_name("speed")
+            .long("speed")
+            .takes_value(true)
+            .value_name("SPEED")
+            .default_value("1")
+            .help("Run the AGC at SPEED times real time, or as fast as possible with max")
+        )
+        .arg(clap::Arg::with_name("tui")
+            .long("tui")
+            .help("Show the DSKY in the terminal and take keypresses from the keyboard")
+        )
+        .arg(clap::Arg::with_name("script")
+            .long("script")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("tui")
+            .help("Run the DSKY script FILE headless and exit with 0 if it passes, 1 if it fails")
+        )
+        .arg(clap::Arg::with_name("dsky_addr")
+            .long("dsky-addr")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Serve the DSKY alone on ADDR or PORT, outside of the yaAGC ports")
+        )
+        .arg(clap::Arg::with_name("downrupt_addr")
+            .long("downrupt-addr")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Serve the DOWNRUPT telemetry alone on ADDR or PORT, outside of the yaAGC ports")
+        )
+        .arg(clap::Arg::with_name("uplink_addr")
+            .long("uplink-addr")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Serve the uplink alone on ADDR or PORT, outside of the yaAGC ports")
+        )
+        .arg(clap::Arg::with_name("gdb")
+            .long("gdb")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Wait for GDB on ADDR or PORT, and run the AGC once it detaches")
+        )
+        .arg(clap::Arg::with_name("monitor")
+            .long("monitor")
+            .conflicts_with("tui")
+            .help("Read monitor commands from the console to pause, examine and step the AGC")
+        )
+        .arg(clap::Arg::with_name("rom_write")
+            .long("rom-write")
+            .help("Allow the monitor and GDB to write fixed memory")
+        )
+        .arg(clap::Arg::with_name("faults")
+            .long("faults")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("script")
+            .help("Inject the faults of the scenario FILE, see ragc_core::fault::FaultInjector")
+        )
+        .subcommand(run::subcommand())
         .subcommand(
             clap::SubCommand::with_name("retread50")
                 .help("Run AGC with RETREAD50 ROM and Configuration")
@@ -82,13 +175,78 @@ fn load_agcbin_file(filename: &str) -> Option<[[u16; ROM_BANK_NUM_WORDS]; ROM_BA
     Some(banks)
 }
 
+///
+/// ## `load_rope` Function
+///
+/// Returns one of the ropes of `ragc_ropes` by name, or loads the agcbin file
+/// at the given path.
+///
+fn load_rope(name: &str) -> Option<[[u16; ROM_BANK_NUM_WORDS]; ROM_BANKS_NUM]> {
+    match name {
+        "retread50" => Some(*ragc_ropes::RETREAD50_ROPE),
+        "luminary131" => Some(*ragc_ropes::LUMINARY131_ROPE),
+        "validation" => Some(*ragc_ropes::VALIDATION_ROPE),
+        _ => load_agcbin_file(name),
+    }
+}
+
+///
+/// ## `load_faults` Function
+///
+/// Reads the fault scenario of `filename`, logging why it could not be.
+///
+fn load_faults(filename: &str) -> Option<FaultInjector> {
+    let text = match std::fs::read_to_string(filename) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("Unable to read faults {:?}: {}", filename, x);
+            return None;
+        }
+    };
+    match FaultInjector::parse(&text) {
+        Ok(x) => Some(x),
+        Err(x) => {
+            error!("{}:{}: {:?}", filename, x.line, x.kind);
+            None
+        }
+    }
+}
+
+fn run_mode(value: &str) -> Option<runner::RunMode> {
+    match value {
+        "max" => Some(runner::RunMode::MaxSpeed),
+        _ => match value.parse::<f64>() {
+            Ok(1.0) => Some(runner::RunMode::RealTime),
+            Ok(x) if x > 0.0 => Some(runner::RunMode::Scaled(x)),
+            _ => None,
+        },
+    }
+}
+
+fn bind_addr(value: &str) -> String {
+    // A bare port number is served on the local interface only
+    match value.parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        _ => value.to_string(),
+    }
+}
+
 fn main() {
-    env_logger::init();
+    let matches = fetch_config();
+
+    // Log records would be drawn over the terminal DSKY, so only show them
+    // when explicitly asked for through RUST_LOG
+    if matches.is_present("tui") && std::env::var_os("RUST_LOG").is_none() {
+        env_logger::Builder::new().filter_level(log::LevelFilter::Off).init();
+    } else {
+        env_logger::init();
+    }
 
     // Register for a ctrlc handler which will push a signal to the application.
     // If the signal handler is pushed multiple times without closing, then force
     // closing the application and lose any close-ups of
     let (ctrlc_tx, ctrlc_rx) = bounded(1);
+    let quit_tx = ctrlc_tx.clone();
     let res = ctrlc::set_handler(move || {
         if ctrlc_tx.is_full() == true {
             std::process::exit(-1);
@@ -104,56 +262,218 @@ fn main() {
         _ => {}
     }
 
-    let matches = fetch_config();
     let rope = match matches.subcommand_name() {
-        Some("retread50") => {
-            *ragc_ropes::RETREAD50_ROPE
-        }
-        Some("luminary131") => {
-            *ragc_ropes::LUMINARY131_ROPE
-        }
-        Some("validation") => {
-            *ragc_ropes::VALIDATION_ROPE
+        Some("run") => {
+            std::process::exit(run::run(matches.subcommand_matches("run").unwrap()));
         }
         Some("file") => {
             let sub_matches = matches.subcommand_matches("file").unwrap();
             let filename = sub_matches.value_of("filename").unwrap();
             load_agcbin_file(&filename).unwrap()
         }
+        Some(x) => load_rope(x).unwrap(),
         _ => {
             error!("Invalid subcommand. Exiting");
             return
         }
     };
 
+    let mode = match run_mode(matches.value_of("speed").unwrap()) {
+        Some(x) => x,
+        None => {
+            error!("Invalid speed: {}", matches.value_of("speed").unwrap());
+            return;
+        }
+    };
+
+    let mut faults = match matches.value_of("faults") {
+        Some(filename) => match load_faults(filename) {
+            Some(x) => x,
+            None => return,
+        },
+        None => FaultInjector::new(),
+    };
+
+    // Scripts drive their own DSKY, without pacing or any yaAGC client
+    if let Some(filename) = matches.value_of("script") {
+        std::process::exit(script::run(&rope, filename));
+    }
+
     let mut q1 = heapless::spsc::Queue::new();
     let (rupt_tx, _rupt_rx) = q1.split();
 
-    let mut dsky = ragc_periph::dsky::DskyDisplay::new();
-    let mut downrupt = ragc_periph::downrupt::DownruptPeriph::new();
+    // Serve yaDSKY2, yaTelemetry and any other yaAGC client on the CM and LM
+    // port ranges, all peripherals sharing the same clients.
+    let addrs = match matches.values_of("yaagc_addr") {
+        Some(x) => x.map(bind_addr).collect(),
+        None => ragc_periph::server::default_addrs(),
+    };
+    let server = match ragc_periph::server::YaAgcServer::bind(&addrs) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("Unable to start yaAGC server: {}", x);
+            return;
+        }
+    };
 
-    let mm = mem::AgcMemoryMap::new(&rope, &mut downrupt, &mut dsky, rupt_tx);
+    // Peripherals given their own address are not served on the shared ports.
+    let dsky_server = match matches.value_of("dsky_addr") {
+        Some(addr) => match ragc_periph::server::YaAgcServer::bind(&[bind_addr(addr)]) {
+            Ok(x) => Some(x),
+            Err(x) => {
+                error!("Unable to start DSKY: {}", x);
+                return;
+            }
+        },
+        None => None,
+    };
+    let dsky_server = dsky_server.as_ref().unwrap_or(&server);
+    let mut dsky = ragc_periph::dsky::DskyDisplay::with_server(dsky_server);
+    let downrupt = match matches.value_of("downrupt_addr") {
+        Some(addr) => ragc_periph::downrupt::DownruptPeriph::bind(&bind_addr(addr)),
+        None => Ok(ragc_periph::downrupt::DownruptPeriph::with_server(&server)),
+    };
+    let mut downrupt = match downrupt {
+        Ok(x) => x,
+        Err(x) => {
+            error!("Unable to start DOWNRUPT telemetry: {}", x);
+            return;
+        }
+    };
+    if let Some(filename) = matches.value_of("downlist_json") {
+        let layout = match matches.value_of("downlist_layout") {
+            Some("cm") => ragc_periph::downlist::DownlistVehicle::Cm,
+            _ => ragc_periph::downlist::DownlistVehicle::Lm,
+        };
+        let f = match File::create(filename) {
+            Ok(f) => f,
+            _ => {
+                error!("Unable to create file: {:?}", filename);
+                return;
+            }
+        };
+        downrupt = downrupt.with_downlist_json(layout, Box::new(f));
+    }
+    if let Some(filename) = matches.value_of("record_telemetry") {
+        let format = ragc_periph::telemetry::TelemetryFormat::from_path(filename);
+        let recorder = File::create(filename).and_then(|f| {
+            let writer: Box<dyn std::io::Write + Send> = Box::new(std::io::BufWriter::new(f));
+            ragc_periph::telemetry::TelemetryRecorder::new(writer, format)
+        });
+        match recorder {
+            Ok(r) => {
+                downrupt = downrupt.with_recorder(r);
+            }
+            Err(x) => {
+                error!("Unable to create telemetry recording {:?}: {:?}", filename, x);
+                return;
+            }
+        }
+    }
+    let uplink = match matches.value_of("uplink_addr") {
+        Some(addr) => ragc_periph::uplink_server::UplinkPeriph::bind(&bind_addr(addr)),
+        None => Ok(ragc_periph::uplink_server::UplinkPeriph::with_server(&server)),
+    };
+    let mut uplink = match uplink {
+        Ok(x) => x,
+        Err(x) => {
+            error!("Unable to start uplink: {}", x);
+            return;
+        }
+    };
+
+    // The navigation DSKY shows the same display, so it is served along with
+    // the main DSKY
+    let mut nav_keys = ragc_periph::nav_keys_server::NavKeysPeriph::with_server(dsky_server);
+
+    // yaACA connects to the yaAGC ports like any other client
+    let mut hand_controller = ragc_periph::hand_controller_server::HandControllerPeriph::with_server(&server);
+
+    let mut mm = mem::AgcMemoryMap::new(&rope, &mut downrupt, &mut dsky, rupt_tx);
+    if mm.attach_periph(&mut uplink).is_err() {
+        error!("Unable to attach uplink peripheral");
+        return;
+    }
+    if mm.attach_periph(&mut nav_keys).is_err() {
+        error!("Unable to attach navigation keys peripheral");
+        return;
+    }
+    if mm.attach_periph(&mut hand_controller).is_err() {
+        error!("Unable to attach hand controller peripheral");
+        return;
+    }
+    if matches.is_present("rom_write") {
+        mm.enable_rom_write();
+    }
     let mut _cpu = cpu::AgcCpu::new(mm);
 
+    // Started last, so no early exit leaves the terminal in raw mode
+    let tui = if matches.is_present("tui") {
+        let client = dsky_server.connect_local();
+        Some(std::thread::spawn(move || tui::run(client, quit_tx)))
+    } else {
+        None
+    };
+
     _cpu.reset();
-    let mut last_timestamp = std::time::Instant::now();
+    let mut agc_runner = runner::AgcRunner::new(mode);
+    let clock = std::time::Instant::now();
+
+    // The AGC is held at reset until GDB continues it, and runs on its own
+    // once GDB detaches
+    let mut quit = false;
+    if let Some(addr) = matches.value_of("gdb") {
+        match gdb::serve(&mut _cpu, &mut agc_runner, clock, &bind_addr(addr)) {
+            Ok(gdbstub::stub::DisconnectReason::Kill) => quit = true,
+            Ok(_) => agc_runner.set_mode(mode),
+            Err(x) => {
+                error!("GDB session failed: {}", x);
+                quit = true;
+            }
+        }
+    }
+
+    let mut monitor = if matches.is_present("monitor") {
+        Some(monitor::Monitor::new(mode))
+    } else {
+        None
+    };
+
     loop {
         // Check to see if we received a ctrlc signal. If we have, we need to
         // exit out of the loop and exit the application.
-        if ctrlc_rx.len() > 0 {
+        if quit || !ctrlc_rx.is_empty() {
             break;
         }
 
-        if last_timestamp.elapsed().as_millis() == 0 {
-            std::thread::sleep(std::time::Duration::new(0, 5000000));
-            continue;
+        let now = clock.elapsed().as_nanos() as u64;
+        match monitor.as_mut() {
+            Some(m) => {
+                if !m.poll(&mut _cpu, &mut agc_runner) {
+                    break;
+                }
+                m.advance(&mut _cpu, &mut agc_runner, now, &mut faults);
+            }
+            None => {
+                agc_runner.advance_until(&mut _cpu, now, |cpu| {
+                    faults.pump(cpu);
+                    false
+                });
+            }
         }
 
-        let mut cycle_counter = 0;
-        let expected_cycles = ((last_timestamp.elapsed().as_micros() as f64) / 11.7) as i64;
-        while cycle_counter < expected_cycles {
-            cycle_counter += _cpu.step() as i64;
+        // Run the AGC in slices of at least 1ms rather than spinning on the
+        // host clock
+        match agc_runner.idle_ns() {
+            Some(0) => {}
+            Some(x) => std::thread::sleep(std::time::Duration::from_nanos(x.max(1000000))),
+            None => std::thread::sleep(std::time::Duration::from_millis(5)),
+        }
+    }
+
+    if let Some(handle) = tui {
+        if let Ok(Err(x)) = handle.join() {
+            error!("Terminal DSKY failed: {}", x);
         }
-        last_timestamp = std::time::Instant::now();
     }
 }
@@ -0,0 +1,366 @@
+// Interactive monitor, pausing, examining and modifying the AGC from the
+// console while it runs.
+//
+// Addresses are octal, and written like the yaAGC listings: `E5,1400` for an
+// erasable bank, `23,2000` for a fixed bank, a bare address as the AGC sees
+// it through the current bank registers, or `@` and a pseudo-address.
+
+use crossbeam_channel::{unbounded, Receiver, TryRecvError};
+
+use ragc_core::consts::cpu::REG_Z;
+use ragc_core::consts::memmap::{
+    AGC_PSEUDO_ERASABLE_END, AGC_PSEUDO_FIXED_END, AGC_PSEUDO_FIXED_START,
+};
+use ragc_core::cpu::AgcCpu;
+use ragc_core::fault::FaultInjector;
+use ragc_core::mem::ROM_PATCH_NUM;
+use ragc_core::runner::{AgcRunner, FixedAddress, RunMode};
+
+use std::io::{self, BufRead, Write};
+
+use crate::gdb::REGISTERS;
+
+const HELP: &str = "\
+  pause                 Pause the AGC
+  resume                Resume the AGC at the --speed pace
+  step [N]              Run N instructions (1 by default) and pause
+  regs                  Show the registers
+  x ADDR [N]            Show N words (1 by default) from ADDR
+  set ADDR|REG VALUE    Write a word or a register
+  io CH [VALUE]         Read or write an IO channel
+  break [ADDR]          Break at ADDR, or list the breakpoints
+  delete [ADDR]         Delete the breakpoint at ADDR, or all of them
+  quit                  Exit ragc
+Values and addresses are octal, and N decimal. ADDR is E5,1400 (erasable
+bank), 23,2000 (fixed bank), 1400 (through the current banks) or @2400
+(pseudo-address).";
+
+fn octal(text: &str) -> Option<usize> {
+    usize::from_str_radix(text.trim(), 8).ok()
+}
+
+///
+/// ## `parse_address` Function
+///
+/// Returns the pseudo-address of a monitor address, `None` if it is not
+/// mapped.
+///
+fn parse_address(cpu: &AgcCpu, text: &str) -> Option<usize> {
+    let addr = if let Some(x) = text.strip_prefix('@') {
+        octal(x)?
+    } else if let Some(x) = text.strip_prefix(|c| c == 'E' || c == 'e') {
+        let idx = x.find(',')?;
+        match (octal(&x[..idx])?, octal(&x[idx + 1..])?) {
+            (bank @ 0..=7, addr @ 0o1400..=0o1777) => (bank << 8) + addr - 0o1400,
+            _ => return None,
+        }
+    } else if text.contains(',') {
+        let addr = FixedAddress::parse(text)?;
+        AGC_PSEUDO_FIXED_START + (addr.bank << 10) + addr.offset as usize
+    } else {
+        match octal(text)? {
+            x @ 0..=0o7777 => cpu.pseudo_address(x),
+            _ => return None,
+        }
+    };
+    cpu.read_pseudo(addr).map(|_| addr)
+}
+
+///
+/// ## `location` Function
+///
+/// Returns a pseudo-address written as a monitor address, along with the
+/// pseudo-address itself.
+///
+fn location(addr: usize) -> String {
+    match addr {
+        0..=0o1377 => format!("{:04o} (@{:06o})", addr, addr),
+        0o1400..=AGC_PSEUDO_ERASABLE_END => {
+            format!("E{},{:04o} (@{:06o})", addr >> 8, (addr & 0o377) + 0o1400, addr)
+        }
+        AGC_PSEUDO_FIXED_START..=AGC_PSEUDO_FIXED_END => {
+            let offset = addr - AGC_PSEUDO_FIXED_START;
+            let fixed = FixedAddress {
+                bank: offset >> 10,
+                offset: (offset & 0o1777) as u16,
+            };
+            format!("{} (@{:06o})", fixed, addr)
+        }
+        _ => format!("@{:06o}", addr),
+    }
+}
+
+fn pc_address(cpu: &mut AgcCpu) -> usize {
+    let z = (cpu.read(REG_Z) & 0o7777) as usize;
+    cpu.pseudo_address(z)
+}
+
+fn register(name: &str) -> Option<usize> {
+    REGISTERS
+        .iter()
+        .find(|(x, _)| x.eq_ignore_ascii_case(name))
+        .map(|(_, idx)| *idx)
+}
+
+///
+/// ## Monitor Structure
+///
+/// Console debugger of the AGC. Commands are read from stdin by a thread of
+/// their own, and run by `poll` between slices of the AGC. `advance` runs the
+/// AGC in place of `AgcRunner::advance`, pausing it on breakpoints.
+///
+pub struct Monitor {
+    lines: Receiver<String>,
+    breakpoints: Vec<usize>,
+    resume_mode: RunMode,
+}
+
+impl Monitor {
+    pub fn new(resume_mode: RunMode) -> Self {
+        let (tx, rx) = unbounded();
+        std::thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("ragc monitor, `help` for the commands");
+        prompt();
+        Monitor {
+            lines: rx,
+            breakpoints: vec![],
+            resume_mode,
+        }
+    }
+
+    ///
+    /// ## `poll` Function
+    ///
+    /// Runs the commands entered since the last call.
+    ///
+    /// ### Result
+    ///
+    ///  - `bool` - `false` once `quit` has been entered
+    ///
+    pub fn poll(&mut self, cpu: &mut AgcCpu, runner: &mut AgcRunner) -> bool {
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => {
+                    if !self.execute(cpu, runner, line.trim()) {
+                        return false;
+                    }
+                    prompt();
+                }
+                Err(TryRecvError::Empty) => return true,
+                // stdin closed, the AGC keeps running without a monitor
+                Err(TryRecvError::Disconnected) => return true,
+            }
+        }
+    }
+
+    ///
+    /// ## `advance` Function
+    ///
+    /// Runs the AGC like `AgcRunner::advance`, injecting the due `faults`,
+    /// and pauses it when the next instruction is on a breakpoint.
+    ///
+    pub fn advance(&mut self, cpu: &mut AgcCpu, runner: &mut AgcRunner, now_ns: u64, faults: &mut FaultInjector) {
+        let breakpoints = &self.breakpoints;
+        let (_, stopped) = runner.advance_until(cpu, now_ns, |cpu| {
+            faults.pump(cpu);
+            !breakpoints.is_empty() && breakpoints.contains(&pc_address(cpu))
+        });
+        if stopped {
+            runner.set_mode(RunMode::Paused);
+            println!();
+            print!("Breakpoint, ");
+            show_pc(cpu, runner);
+            prompt();
+        }
+    }
+
+    fn execute(&mut self, cpu: &mut AgcCpu, runner: &mut AgcRunner, line: &str) -> bool {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            [] => {}
+            ["help"] => println!("{}", HELP),
+            ["quit"] => return false,
+            ["pause"] => {
+                runner.set_mode(RunMode::Paused);
+                show_pc(cpu, runner);
+            }
+            ["resume"] => runner.set_mode(self.resume_mode),
+            ["step"] | ["step", _] => {
+                let count = match args.get(1).map(|x| x.parse::<u32>()) {
+                    None => 1,
+                    Some(Ok(x)) => x,
+                    Some(Err(_)) => return invalid(line),
+                };
+                runner.set_mode(RunMode::Paused);
+                for _i in 0..count {
+                    runner.step_instruction(cpu);
+                }
+                show_pc(cpu, runner);
+            }
+            ["regs"] => show_registers(cpu, runner),
+            ["x", addr] | ["x", addr, _] => {
+                let count = match args.get(2).map(|x| x.parse::<usize>()) {
+                    None => 1,
+                    Some(Ok(x)) => x,
+                    Some(Err(_)) => return invalid(line),
+                };
+                let start = match parse_address(cpu, addr) {
+                    Some(x) => x,
+                    None => return invalid(line),
+                };
+                for addr in start..start + count {
+                    match cpu.read_pseudo(addr) {
+                        Some(x) => println!("{}: {:05o}", location(addr), x),
+                        None => break,
+                    }
+                }
+            }
+            ["set", target, value] => {
+                let value = match octal(value) {
+                    Some(x) if x <= 0o177777 => x as u16,
+                    _ => return invalid(line),
+                };
+                if let Some(idx) = register(target) {
+                    cpu.write(idx, value);
+                } else {
+                    match parse_address(cpu, target) {
+                        Some(addr) if cpu.write_pseudo(addr, value) => {}
+                        Some(addr) => println!(
+                            "{} is read-only, or all {} ROM patches are used, see --rom-write",
+                            location(addr),
+                            ROM_PATCH_NUM
+                        ),
+                        None => return invalid(line),
+                    }
+                }
+            }
+            ["io", channel] | ["io", channel, _] => {
+                let channel = match octal(channel) {
+                    Some(x) if x <= 0o777 => x,
+                    _ => return invalid(line),
+                };
+                match args.get(2).map(|x| octal(x)) {
+                    None => println!("{:03o}: {:05o}", channel, cpu.read_io(channel)),
+                    Some(Some(x)) if x <= 0o77777 => cpu.write_io(channel, x as u16),
+                    Some(_) => return invalid(line),
+                }
+            }
+            ["break"] => {
+                for addr in self.breakpoints.iter() {
+                    println!("{}", location(*addr));
+                }
+            }
+            ["break", addr] => match parse_address(cpu, addr) {
+                Some(x) if !self.breakpoints.contains(&x) => self.breakpoints.push(x),
+                Some(_) => {}
+                None => return invalid(line),
+            },
+            ["delete"] => self.breakpoints.clear(),
+            ["delete", addr] => match parse_address(cpu, addr) {
+                Some(x) => self.breakpoints.retain(|b| *b != x),
+                None => return invalid(line),
+            },
+            _ => return invalid(line),
+        }
+        true
+    }
+}
+
+fn invalid(line: &str) -> bool {
+    println!("Invalid command: {}, `help` for the commands", line);
+    true
+}
+
+fn prompt() {
+    print!("ragc> ");
+    let _res = io::stdout().flush();
+}
+
+fn show_pc(cpu: &mut AgcCpu, runner: &AgcRunner) {
+    let pc = pc_address(cpu);
+    println!(
+        "PC {} after {} MCTs at {}",
+        location(pc),
+        cpu.total_cycles,
+        runner.mission_time(cpu)
+    );
+}
+
+fn show_registers(cpu: &mut AgcCpu, runner: &AgcRunner) {
+    for row in REGISTERS.chunks(4) {
+        let line: Vec<String> = row
+            .iter()
+            .map(|(name, idx)| format!("{:>10} {:06o}", name.to_uppercase(), cpu.read(*idx)))
+            .collect();
+        println!("{}", line.join(""));
+    }
+    println!("{:>10} {:06o}", "IR", cpu.ir);
+    show_pc(cpu, runner);
+}
+
+#[cfg(test)]
+mod monitor_tests {
+    use super::*;
+    use crate::script::NoDownrupt;
+    use ragc_core::consts::cpu::REG_EB;
+    use ragc_core::mem::AgcMemoryMap;
+
+    ///
+    /// # Description
+    ///
+    /// Tests each form of monitor address maps to its pseudo-address, and
+    /// unmapped or malformed addresses are rejected.
+    ///
+    #[test]
+    fn test_parse_address() {
+        let mut q = heapless::spsc::Queue::new();
+        let (rupt_tx, _rupt_rx) = q.split();
+        let mut downrupt = NoDownrupt;
+        let mut dsky = NoDownrupt;
+        let mm = AgcMemoryMap::new(ragc_ropes::BLANK_ROPE, &mut downrupt, &mut dsky, rupt_tx);
+        let mut cpu = AgcCpu::new(mm);
+        cpu.write(REG_EB, 0o2400);
+
+        let valid = [
+            ("@2400", 0o2400),
+            ("E5,1400", 0o2400),
+            ("e3,1777", 0o1777),
+            ("E0,1401", 0o0001),
+            ("23,2000", AGC_PSEUDO_FIXED_START + 0o23 * 0o2000),
+            ("43,3777", AGC_PSEUDO_FIXED_END),
+            ("1402", 0o2402),
+            ("0061", 0o61),
+            ("4001", 0o14001),
+        ];
+        for (text, addr) in valid.iter() {
+            assert_eq!(parse_address(&cpu, text), Some(*addr), "{:?}", text);
+        }
+
+        let invalid = [
+            "@4000", "E8,1400", "E5,2000", "E5", "44,2000", "23,4000", "10000", "x", "",
+        ];
+        for text in invalid.iter() {
+            assert_eq!(parse_address(&cpu, text), None, "{:?}", text);
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests pseudo-addresses are written back as monitor addresses.
+    ///
+    #[test]
+    fn test_location() {
+        assert_eq!(location(0o61), "0061 (@000061)");
+        assert_eq!(location(0o1377), "1377 (@001377)");
+        assert_eq!(location(0o2400), "E5,1400 (@002400)");
+        assert_eq!(location(AGC_PSEUDO_FIXED_START + 0o23 * 0o2000 + 5), "23,2005 (@056005)");
+        assert_eq!(location(0o5000), "@005000");
+    }
+}
@@ -0,0 +1,314 @@
+// Headless `ragc run` mode, running the AGC as fast as possible until one of
+// the conditions given on the command line is met.
+
+use log::error;
+
+use ragc_core::fault::FaultInjector;
+use ragc_core::runner::{AgcRunner, FixedAddress, RunMode, RunOutcome, StopCondition};
+use ragc_core::{cpu, mem};
+use ragc_periph::dsky_script::{DskyScript, ScriptStatus, ScriptedDsky, MCTS_PER_SECOND};
+
+use std::cell::Cell;
+
+use crate::script::{dsky_summary, NoDownrupt, EXIT_FAILED, EXIT_INVALID, EXIT_PASSED};
+
+/// Default timeout of a run, in seconds of AGC time.
+pub const DEFAULT_TIMEOUT: &str = "60";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name("run")
+        .about("Run a rope headless until a condition is met, exiting with 0, or 1 on timeout")
+        .arg(clap::Arg::with_name("rope")
+            .index(1)
+            .required(true)
+            .value_name("ROPE")
+            .help("retread50, luminary131, validation or the path of an agcbin file")
+        )
+        .arg(clap::Arg::with_name("mcts")
+            .long("mcts")
+            .takes_value(true)
+            .value_name("N")
+            .help("Stop once N MCTs have gone by")
+        )
+        .arg(clap::Arg::with_name("until_pc")
+            .long("until-pc")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("BB,AAAA")
+            .help("Stop when the next instruction is at the octal fixed address")
+        )
+        .arg(clap::Arg::with_name("until_restart")
+            .long("until-restart")
+            .help("Stop when the AGC goes through a GOJAM restart")
+        )
+        .arg(clap::Arg::with_name("until_dsky")
+            .long("until-dsky")
+            .takes_value(true)
+            .value_name("FIELDS")
+            .help("Stop when the DSKY shows FIELDS, such as \"VERB=06, NOUN=63\"")
+        )
+        .arg(clap::Arg::with_name("until_channel")
+            .long("until-channel")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("CH=VALUE[/MASK]")
+            .help("Stop when the octal IO channel, masked with MASK, reads VALUE")
+        )
+        .arg(clap::Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .value_name("SECS")
+            .default_value(DEFAULT_TIMEOUT)
+            .help("Give up after SECS seconds of AGC time")
+        )
+        .arg(clap::Arg::with_name("faults")
+            .long("faults")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Inject the faults of the scenario FILE, see ragc_core::fault::FaultInjector")
+        )
+}
+
+fn parse_channel(text: &str) -> Option<StopCondition> {
+    let octal = |x: &str| u16::from_str_radix(x.trim(), 8).ok();
+    let idx = text.find('=')?;
+    let channel = octal(&text[..idx])? as usize;
+    let (value, mask) = match text[idx + 1..].find('/') {
+        Some(x) => (octal(&text[idx + 1..idx + 1 + x])?, octal(&text[idx + 2 + x..])?),
+        None => (octal(&text[idx + 1..])?, 0o77777),
+    };
+    match channel {
+        0..=0o777 => Some(StopCondition::Channel {
+            channel,
+            mask,
+            value: value & mask,
+        }),
+        _ => None,
+    }
+}
+
+fn parse_conditions(matches: &clap::ArgMatches) -> Result<Vec<StopCondition>, String> {
+    let mut conditions = vec![];
+    if let Some(x) = matches.value_of("mcts") {
+        let mcts = x.parse().map_err(|_| format!("Invalid MCT count: {}", x))?;
+        conditions.push(StopCondition::Mcts(mcts));
+    }
+    for x in matches.values_of("until_pc").into_iter().flatten() {
+        let addr = FixedAddress::parse(x).ok_or(format!("Invalid address: {}", x))?;
+        conditions.push(StopCondition::Address(addr));
+    }
+    if matches.is_present("until_restart") {
+        conditions.push(StopCondition::Restart);
+    }
+    for x in matches.values_of("until_channel").into_iter().flatten() {
+        conditions.push(parse_channel(x).ok_or(format!("Invalid channel condition: {}", x))?);
+    }
+    Ok(conditions)
+}
+
+///
+/// ## `run` Function
+///
+/// Runs the `run` subcommand and returns the exit code of `ragc`.
+///
+pub fn run(matches: &clap::ArgMatches) -> i32 {
+    let name = matches.value_of("rope").unwrap();
+    let rope = match crate::load_rope(name) {
+        Some(x) => x,
+        None => return EXIT_INVALID,
+    };
+    let conditions = match parse_conditions(matches) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("{}", x);
+            return EXIT_INVALID;
+        }
+    };
+    let timeout = match matches.value_of("timeout").unwrap().parse::<f64>() {
+        Ok(x) if x > 0.0 => x,
+        _ => {
+            error!("Invalid timeout: {}", matches.value_of("timeout").unwrap());
+            return EXIT_INVALID;
+        }
+    };
+
+    // DSKY conditions are waited for by a DSKY script, with the syntax of
+    // `wait_display`
+    let text = match matches.value_of("until_dsky") {
+        Some(x) => format!("wait_display({}, timeout={})", x, timeout),
+        None => String::new(),
+    };
+    let script = match DskyScript::parse(&text) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("Invalid DSKY condition: {:?}", x.kind);
+            return EXIT_INVALID;
+        }
+    };
+    let dsky_condition = matches.is_present("until_dsky");
+    let mut faults = match matches.value_of("faults") {
+        Some(filename) => match crate::load_faults(filename) {
+            Some(x) => x,
+            None => return EXIT_INVALID,
+        },
+        None => FaultInjector::new(),
+    };
+
+    let mut q = heapless::spsc::Queue::new();
+    let (rupt_tx, _rupt_rx) = q.split();
+
+    let status = Cell::new(ScriptStatus::Running);
+    let mut downrupt = NoDownrupt;
+    let mut dsky = ScriptedDsky::new(&script, &status);
+    let (outcome, time, pc, cause) = {
+        let mm = mem::AgcMemoryMap::new(&rope, &mut downrupt, &mut dsky, rupt_tx);
+        let mut cpu = cpu::AgcCpu::new(mm);
+        cpu.reset();
+
+        let mut runner = AgcRunner::new(RunMode::MaxSpeed);
+        let timeout_mcts = (timeout * MCTS_PER_SECOND) as u64;
+        let outcome = runner.run_until_with(&mut cpu, &conditions, timeout_mcts, |cpu| {
+            faults.pump(cpu);
+            dsky_condition && status.get() == ScriptStatus::Passed
+        });
+        let pc = FixedAddress::of_pc(&mut cpu).map_or(String::from("erasable"), |x| x.to_string());
+        (outcome, runner.mission_time(&cpu), pc, cpu.restart_cause)
+    };
+
+    let summary = dsky_summary(dsky.state());
+    match outcome {
+        RunOutcome::Stopped { condition, mcts } => {
+            let reason = match conditions.get(condition) {
+                Some(StopCondition::Restart) if cause.is_some() => {
+                    format!("restart ({:?})", cause.unwrap())
+                }
+                Some(x) => x.to_string(),
+                None => format!("DSKY {}", matches.value_of("until_dsky").unwrap()),
+            };
+            println!("{}: stopped on {} after {} MCTs at {}, PC {}, {}", name, reason, mcts, time, pc, summary);
+            EXIT_PASSED
+        }
+        RunOutcome::Timeout => {
+            println!("{}: timed out at {}, PC {}, {}", name, time, pc, summary);
+            EXIT_FAILED
+        }
+    }
+}
+
+#[cfg(test)]
+mod run_tests {
+    use super::*;
+
+    fn run_matches(args: &[&str]) -> clap::ArgMatches<'static> {
+        let matches = clap::App::new("ragc")
+            .subcommand(subcommand())
+            .get_matches_from_safe(["ragc", "run"].iter().chain(args.iter()))
+            .unwrap();
+        matches.subcommand_matches("run").unwrap().clone()
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the parsing of `CH=VALUE[/MASK]` channel conditions, with the
+    /// value masked and the malformed conditions rejected.
+    ///
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(
+            parse_channel("30=40000/40000"),
+            Some(StopCondition::Channel {
+                channel: 0o30,
+                mask: 0o40000,
+                value: 0o40000,
+            })
+        );
+        assert_eq!(
+            parse_channel(" 163 = 77 "),
+            Some(StopCondition::Channel {
+                channel: 0o163,
+                mask: 0o77777,
+                value: 0o77,
+            })
+        );
+        assert_eq!(
+            parse_channel("11=177/7"),
+            Some(StopCondition::Channel {
+                channel: 0o11,
+                mask: 0o7,
+                value: 0o7,
+            })
+        );
+
+        for text in ["30", "30=", "30=8", "30=1/", "30=1/2/3", "=1", "1000=1", "x=1"].iter() {
+            assert_eq!(parse_channel(text), None, "{:?}", text);
+        }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the stop conditions built from the command line, in order, and
+    /// the errors of invalid ones.
+    ///
+    #[test]
+    fn test_parse_conditions() {
+        let matches = run_matches(&[
+            "validation",
+            "--until-channel",
+            "30=1/1",
+            "--until-restart",
+            "--until-pc",
+            "02,2000",
+            "--mcts",
+            "100",
+        ]);
+        assert_eq!(
+            parse_conditions(&matches),
+            Ok(vec![
+                StopCondition::Mcts(100),
+                StopCondition::Address(FixedAddress::parse("02,2000").unwrap()),
+                StopCondition::Restart,
+                StopCondition::Channel {
+                    channel: 0o30,
+                    mask: 1,
+                    value: 1,
+                },
+            ])
+        );
+        assert_eq!(parse_conditions(&run_matches(&["validation"])), Ok(vec![]));
+
+        let err = |args: &[&str]| parse_conditions(&run_matches(args)).unwrap_err();
+        assert_eq!(err(&["validation", "--mcts", "1e3"]), "Invalid MCT count: 1e3");
+        assert_eq!(err(&["validation", "--until-pc", "2,9999"]), "Invalid address: 2,9999");
+        assert_eq!(
+            err(&["validation", "--until-channel", "30"]),
+            "Invalid channel condition: 30"
+        );
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the exit codes of a run: 0 once a condition is met, 1 on timeout
+    /// and 2 on invalid arguments.
+    ///
+    #[test]
+    fn test_run_exit_codes() {
+        assert_eq!(run(&run_matches(&["validation", "--mcts", "100"])), EXIT_PASSED);
+        assert_eq!(run(&run_matches(&["validation", "--timeout", "0.001"])), EXIT_FAILED);
+        assert_eq!(
+            run(&run_matches(&["validation", "--until-channel", "30=1/x"])),
+            EXIT_INVALID
+        );
+        assert_eq!(run(&run_matches(&["validation", "--timeout", "0"])), EXIT_INVALID);
+        assert_eq!(run(&run_matches(&["validation", "--until-dsky", "BEEP=1"])), EXIT_INVALID);
+        assert_eq!(run(&run_matches(&["no/such/rope.agcbin", "--mcts", "1"])), EXIT_INVALID);
+        assert_eq!(
+            run(&run_matches(&["validation", "--faults", "no/such/faults.txt"])),
+            EXIT_INVALID
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// Headless DSKY script runner, stepping the AGC as fast as possible until the
+// script passes or fails.
+
+use log::{error, info};
+
+use ragc_core::consts::io;
+use ragc_core::mem::periph::AgcIoPeriph;
+use ragc_core::{cpu, mem};
+use ragc_periph::dsky_script::{DskyScript, ScriptStatus, ScriptedDsky, MCTS_PER_SECOND};
+use ragc_periph::dsky_state::DskyState;
+
+use std::cell::Cell;
+
+use crate::{ROM_BANKS_NUM, ROM_BANK_NUM_WORDS};
+
+// Exit codes of a script run
+pub const EXIT_PASSED: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_INVALID: i32 = 2;
+
+// Stand-in for the DOWNRUPT peripheral, as telemetry is not served
+pub struct NoDownrupt;
+
+impl AgcIoPeriph for NoDownrupt {
+    fn read(&self, channel_idx: usize) -> u16 {
+        match channel_idx {
+            io::CHANNEL_CHAN32 => 0o77777,
+            _ => 0o00000,
+        }
+    }
+
+    fn write(&mut self, _channel_idx: usize, _value: u16) {}
+
+    fn is_interrupt(&mut self) -> u16 {
+        0
+    }
+}
+
+///
+/// ## `dsky_summary` Function
+///
+/// Returns the DSKY display on a single line.
+///
+pub fn dsky_summary(state: &DskyState) -> String {
+    let display = |x: Option<u8>| x.map_or(String::from("--"), |x| format!("{:02}", x));
+    format!(
+        "PROG {} VERB {} NOUN {} R1 {} R2 {} R3 {}",
+        display(state.prog_value()),
+        display(state.verb_value()),
+        display(state.noun_value()),
+        state.registers[0],
+        state.registers[1],
+        state.registers[2]
+    )
+}
+
+///
+/// ## `run` Function
+///
+/// Runs the DSKY script of `filename` against the rope and returns the exit
+/// code of `ragc`.
+///
+pub fn run(rope: &[[u16; ROM_BANK_NUM_WORDS]; ROM_BANKS_NUM], filename: &str) -> i32 {
+    let text = match std::fs::read_to_string(filename) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("Unable to read script {:?}: {}", filename, x);
+            return EXIT_INVALID;
+        }
+    };
+    let script = match DskyScript::parse(&text) {
+        Ok(x) => x,
+        Err(x) => {
+            error!("{}:{}: {:?}", filename, x.line, x.kind);
+            return EXIT_INVALID;
+        }
+    };
+
+    let mut q = heapless::spsc::Queue::new();
+    let (rupt_tx, _rupt_rx) = q.split();
+
+    let status = Cell::new(ScriptStatus::Running);
+    let mut downrupt = NoDownrupt;
+    let mut dsky = ScriptedDsky::new(&script, &status);
+    {
+        let mm = mem::AgcMemoryMap::new(rope, &mut downrupt, &mut dsky, rupt_tx);
+        let mut cpu = cpu::AgcCpu::new(mm);
+        cpu.reset();
+        while status.get() == ScriptStatus::Running {
+            cpu.step();
+        }
+    }
+
+    let secs = dsky.mcts() as f64 / MCTS_PER_SECOND;
+    let summary = dsky_summary(dsky.state());
+    match status.get() {
+        ScriptStatus::Failed { line, kind } => {
+            error!("{}:{}: {:?} at {:.2}s, {}", filename, line, kind, secs, summary);
+            EXIT_FAILED
+        }
+        _ => {
+            info!("{}: passed in {:.2}s, {}", filename, secs, summary);
+            EXIT_PASSED
+        }
+    }
+}
+
+#[cfg(test)]
+mod script_tests {
+    use super::*;
+
+    fn run_script(name: &str, text: &str) -> i32 {
+        let path = std::env::temp_dir().join(format!("ragc-{}-{}.dsky", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let res = run(ragc_ropes::BLANK_ROPE, path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        res
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the exit codes of a script run: 0 when the script passes, 1 when
+    /// an assertion fails and 2 for a missing or malformed script.
+    ///
+    #[test]
+    fn test_script_exit_codes() {
+        assert_eq!(run_script("passed", "wait(0.01)\nexpect_lamp(PROG, off)\n"), EXIT_PASSED);
+        assert_eq!(
+            run_script("failed", "wait_display(VERB=37, timeout=0.01)\n"),
+            EXIT_FAILED
+        );
+        assert_eq!(run_script("invalid", "V37E\nbeep()\n"), EXIT_INVALID);
+        assert_eq!(run(ragc_ropes::BLANK_ROPE, "no/such/script.dsky"), EXIT_INVALID);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the single line summary of the DSKY display.
+    ///
+    #[test]
+    fn test_dsky_summary() {
+        let mut state = DskyState::new();
+        assert_eq!(
+            dsky_summary(&state),
+            "PROG -- VERB -- NOUN -- R1        R2        R3       "
+        );
+
+        // PROG 63, VERB 06
+        state.update(io::CHANNEL_DSKY, (11 << 11) | (28 << 5) | 27);
+        state.update(io::CHANNEL_DSKY, (10 << 11) | (21 << 5) | 28);
+        assert!(dsky_summary(&state).starts_with("PROG 63 VERB 06 NOUN --"));
+    }
+}
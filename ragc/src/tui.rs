@@ -0,0 +1,182 @@
+// Terminal DSKY, rendering the DSKY outputs broadcast by the yaAGC server and
+// sending host keys back as DSKY keypresses.
+
+use crossbeam_channel::Sender;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use ragc_periph::dsky_state::DskyState;
+use ragc_periph::keycodes;
+use ragc_periph::server::LocalClient;
+use yaagc_protocol::agc::AGCPacket;
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// Time the PRO key is held down, as the terminal does not report key releases
+const PRO_HOLD: Duration = Duration::from_millis(500);
+
+// Time waited for a key before checking for display updates
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Lamps of the left panel, in pairs as laid out on the DSKY, with whether
+// they are amber lamps
+const LAMP_PANEL: [[(&str, bool); 2]; 7] = [
+    [("UPLINK ACTY", false), ("TEMP", true)],
+    [("NO ATT", false), ("GIMBAL LOCK", true)],
+    [("STBY", false), ("PROG", true)],
+    [("KEY REL", false), ("RESTART", true)],
+    [("OPR ERR", false), ("TRACKER", true)],
+    [("PRIO DISP", true), ("ALT", true)],
+    [("NO DAP", true), ("VEL", true)],
+];
+
+fn digits(pair: &[Option<u8>; 2]) -> String {
+    pair.iter()
+        .map(|x| x.map_or(' ', |d| (b'0' + d) as char))
+        .collect()
+}
+
+fn render<W: Write>(state: &DskyState, out: &mut W) -> io::Result<()> {
+    let (verb, noun) = if state.flash_off {
+        (String::from("  "), String::from("  "))
+    } else {
+        (digits(&state.verb), digits(&state.noun))
+    };
+
+    let lit_lamps = state.lit_lamps();
+    queue!(out, Clear(ClearType::All))?;
+    for (idx, lamps) in LAMP_PANEL.iter().enumerate() {
+        queue!(out, MoveTo(1, 1 + idx as u16))?;
+        for (name, amber) in lamps.iter() {
+            let lit = lit_lamps.get(name).unwrap_or(false);
+            render_lamp(out, name, lit, *amber)?;
+        }
+        queue!(out, Print("   "))?;
+        match idx {
+            0 => {
+                render_lamp(out, "COMP ACTY", lit_lamps.comp_acty, false)?;
+                queue!(out, Print("  PROG"))?;
+            }
+            1 => render_digits(out, &format!("{:>18}", digits(&state.prog)))?,
+            2 => queue!(out, Print("  VERB       NOUN"))?,
+            3 => render_digits(out, &format!("   {}         {}", verb, noun))?,
+            4..=6 => render_digits(out, &format!("{:>17}", state.registers[idx - 4]))?,
+            _ => {}
+        }
+    }
+    queue!(
+        out,
+        MoveTo(1, 9),
+        Print("V VERB  N NOUN  + -  0-9  C CLR  P PRO  K KEY REL"),
+        MoveTo(1, 10),
+        Print("E/Enter ENTR  R RSET  Esc quit")
+    )?;
+    out.flush()
+}
+
+fn render_lamp<W: Write>(out: &mut W, name: &str, lit: bool, amber: bool) -> io::Result<()> {
+    let text = format!(" {:^11} ", name);
+    if lit {
+        let color = if amber { Color::Yellow } else { Color::White };
+        queue!(
+            out,
+            SetBackgroundColor(color),
+            SetForegroundColor(Color::Black),
+            Print(text),
+            ResetColor
+        )
+    } else {
+        queue!(out, SetForegroundColor(Color::DarkGrey), Print(text), ResetColor)
+    }
+}
+
+fn render_digits<W: Write>(out: &mut W, text: &str) -> io::Result<()> {
+    queue!(out, SetForegroundColor(Color::Green), Print(text), ResetColor)
+}
+
+fn key_packet(keycode: u16) -> AGCPacket {
+    AGCPacket::ChannelWrite {
+        channel: 0o15,
+        value: keycode,
+    }
+}
+
+// PRO is reported on channel 32 bit 14, cleared while the key is pressed
+fn pro_packet(pressed: bool) -> AGCPacket {
+    AGCPacket::ChannelWrite {
+        channel: 0o32,
+        value: if pressed { 0o00000 } else { 0o20000 },
+    }
+}
+
+fn event_loop<W: Write>(client: &LocalClient, out: &mut W) -> io::Result<()> {
+    let mut state = DskyState::new();
+    let mut pro_release: Option<Instant> = None;
+    let mut dirty = true;
+
+    loop {
+        for msg in client.output().try_iter() {
+            if let Ok(AGCPacket::ChannelWrite { channel, value }) = AGCPacket::deserialize(&msg) {
+                dirty |= state.update(channel as usize, value);
+            }
+        }
+        if dirty {
+            render(&state, out)?;
+            dirty = false;
+        }
+
+        if let Some(release) = pro_release {
+            if Instant::now() >= release {
+                client.send(pro_packet(false));
+                pro_release = None;
+            }
+        }
+
+        if !event::poll(POLL_INTERVAL)? {
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
+                KeyCode::Char('p') | KeyCode::Char('P') => {
+                    client.send(pro_packet(true));
+                    pro_release = Some(Instant::now() + PRO_HOLD);
+                }
+                KeyCode::Enter => client.send(key_packet(keycodes::KEY_ENTR)),
+                KeyCode::Char(c) => {
+                    if let Some(code) = keycodes::from_char(c.to_ascii_uppercase()) {
+                        client.send(key_packet(code));
+                    }
+                }
+                _ => {}
+            },
+            Event::Resize(..) => dirty = true,
+            _ => {}
+        }
+    }
+}
+
+///
+/// ## `run` Function
+///
+/// Runs the terminal DSKY until Esc or Ctrl-C is pressed, then restores the
+/// terminal and notifies `quit` so the emulator stops.
+///
+pub fn run(client: LocalClient, quit: Sender<()>) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    terminal::enable_raw_mode()?;
+    let res = execute!(stdout, EnterAlternateScreen, Hide)
+        .and_then(|_| event_loop(&client, &mut stdout));
+
+    let _res = execute!(stdout, Show, LeaveAlternateScreen);
+    let _res = terminal::disable_raw_mode();
+    let _res = quit.try_send(());
+    res
+}
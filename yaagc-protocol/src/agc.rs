@@ -1,69 +1,307 @@
-pub struct AGCPacket {
-    _hw_packet: bool,
-    io_addr: usize,
-    io_value: u16,
+use core::fmt;
+
+/// Highest channel (or counter) address which fits in a packet.
+pub const MAX_PACKET_CHANNEL: u8 = 0o177;
+
+/// Highest 15-bit value which fits in a packet.
+pub const MAX_PACKET_VALUE: u16 = 0o77777;
+
+// Flags of the first byte of a packet
+const FLAG_U: u8 = 0x20;
+const FLAG_T: u8 = 0x10;
+
+///
+/// ## `CounterIncrement` Enum
+///
+/// Kind of counter cell update requested by a t-bit packet. The value
+/// matches the data field of the packet.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterIncrement {
+    Pinc = 0,
+    Pcdu = 1,
+    Minc = 2,
+    Mcdu = 3,
+    Dinc = 4,
+    Shinc = 5,
+    Shanc = 6,
+}
+
+impl CounterIncrement {
+    pub fn from_value(value: u16) -> Option<Self> {
+        match value {
+            0 => Some(CounterIncrement::Pinc),
+            1 => Some(CounterIncrement::Pcdu),
+            2 => Some(CounterIncrement::Minc),
+            3 => Some(CounterIncrement::Mcdu),
+            4 => Some(CounterIncrement::Dinc),
+            5 => Some(CounterIncrement::Shinc),
+            6 => Some(CounterIncrement::Shanc),
+            _ => None,
+        }
+    }
+}
+
+///
+/// ## `PacketError` Enum
+///
+/// Errors found while serializing or deserializing a packet.
+///
+///  - `InvalidTag` - The byte at the given index does not carry the `00`,
+///    `01`, `10`, `11` tag expected at its position
+///  - `InvalidFlags` - Both the u and t flags are set
+///  - `InvalidCounterIncrement` - The data of a t-bit packet is not a known
+///    counter increment
+///  - `ChannelOutOfRange` - The channel does not fit in 7 bits
+///  - `ValueOutOfRange` - The value does not fit in 15 bits
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketError {
+    InvalidTag(usize),
+    InvalidFlags,
+    InvalidCounterIncrement(u16),
+    ChannelOutOfRange(u8),
+    ValueOutOfRange(u16),
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::InvalidTag(idx) => write!(f, "invalid tag on byte {}", idx),
+            PacketError::InvalidFlags => write!(f, "both u and t flags are set"),
+            PacketError::InvalidCounterIncrement(x) => {
+                write!(f, "invalid counter increment {}", x)
+            }
+            PacketError::ChannelOutOfRange(x) => write!(f, "channel {:o} out of range", x),
+            PacketError::ValueOutOfRange(x) => write!(f, "value {:o} out of range", x),
+        }
+    }
+}
+
+///
+/// ## `AGCPacket` Enum
+///
+/// Packet of the yaAGC socket protocol. Each packet is 4 bytes long:
+///
+/// ```text
+/// 00utpppp 01pppddd 10dddddd 11dddddd
+/// ```
+///
+/// where `p` is the 7-bit channel and `d` the 15-bit value.
+///
+///  - `ChannelWrite` - Value written to (or read from) an IO channel
+///  - `ChannelMask` - u-bit packet. Sets the mask of the bits of the channel
+///    which are affected by the next `ChannelWrite` to the same channel,
+///    allowing peripherals to drive only the bits they own
+///  - `CounterIncrement` - t-bit packet. Requests an update of the counter
+///    cell at address `counter`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AGCPacket {
+    ChannelWrite { channel: u8, value: u16 },
+    ChannelMask { channel: u8, mask: u16 },
+    CounterIncrement { counter: u8, increment: CounterIncrement },
 }
 
 impl AGCPacket {
-    pub fn new(data: &[u8; 4]) -> Self {
-        let res = parse_yaagc_packet(*data);
-        match res {
-            Some((channel, value)) => AGCPacket {
-                io_addr: channel as usize,
-                io_value: value,
-                _hw_packet: false,
-            },
-            _ => AGCPacket {
-                io_addr: 0x0,
-                io_value: 0x0,
-                _hw_packet: false,
-            },
+    ///
+    /// ## `channel` Function
+    ///
+    /// Returns the channel of the packet, or the counter address for counter
+    /// increment packets.
+    ///
+    pub fn channel(&self) -> u8 {
+        match *self {
+            AGCPacket::ChannelWrite { channel, .. } => channel,
+            AGCPacket::ChannelMask { channel, .. } => channel,
+            AGCPacket::CounterIncrement { counter, .. } => counter,
         }
     }
 
-    pub fn serialize(&self) -> [u8; 4] {
-        generate_yaagc_packet(self.io_addr, self.io_value)
+    ///
+    /// ## `serialize` Function
+    ///
+    /// Converts the packet into its 4 byte wire format.
+    ///
+    pub fn serialize(&self) -> Result<[u8; 4], PacketError> {
+        let (flags, channel, value) = match *self {
+            AGCPacket::ChannelWrite { channel, value } => (0, channel, value),
+            AGCPacket::ChannelMask { channel, mask } => (FLAG_U, channel, mask),
+            AGCPacket::CounterIncrement { counter, increment } => {
+                (FLAG_T, counter, increment as u16)
+            }
+        };
+
+        if channel > MAX_PACKET_CHANNEL {
+            return Err(PacketError::ChannelOutOfRange(channel));
+        }
+        if value > MAX_PACKET_VALUE {
+            return Err(PacketError::ValueOutOfRange(value));
+        }
+
+        Ok([
+            flags | (channel >> 3),
+            0x40 | ((channel & 0x7) << 3) | ((value >> 12) & 0x7) as u8,
+            0x80 | ((value >> 6) & 0x3F) as u8,
+            0xC0 | (value & 0x3F) as u8,
+        ])
+    }
+
+    ///
+    /// ## `deserialize` Function
+    ///
+    /// Parses a packet from its 4 byte wire format.
+    ///
+    pub fn deserialize(data: &[u8; 4]) -> Result<Self, PacketError> {
+        for (idx, byte) in data.iter().enumerate() {
+            if byte >> 6 != idx as u8 {
+                return Err(PacketError::InvalidTag(idx));
+            }
+        }
+
+        let flags = data[0] & (FLAG_U | FLAG_T);
+        let channel = ((data[0] & 0x0F) << 3) | ((data[1] >> 3) & 0x7);
+        let value = ((data[1] as u16) & 0x7) << 12
+            | ((data[2] as u16) & 0x3F) << 6
+            | ((data[3] as u16) & 0x3F);
+
+        match flags {
+            0 => Ok(AGCPacket::ChannelWrite { channel, value }),
+            FLAG_U => Ok(AGCPacket::ChannelMask {
+                channel,
+                mask: value,
+            }),
+            FLAG_T => match CounterIncrement::from_value(value) {
+                Some(increment) => Ok(AGCPacket::CounterIncrement {
+                    counter: channel,
+                    increment,
+                }),
+                None => Err(PacketError::InvalidCounterIncrement(value)),
+            },
+            _ => Err(PacketError::InvalidFlags),
+        }
     }
 }
 
 pub fn generate_yaagc_packet(channel: usize, value: u16) -> [u8; 4] {
     [
-        0x0 | ((channel >> 3) & 0x1F) as u8,
+        ((channel >> 3) & 0x0F) as u8,
         0x40 | ((channel & 0x7) << 3) as u8 | ((value >> 12) & 0x7) as u8,
         0x80 | ((value >> 6) & 0x3F) as u8,
         0xC0 | (value & 0x3F) as u8,
     ]
 }
 
+///
+/// ## `parse_yaagc_packet` Function
+///
+/// Parses a channel write packet into its channel and value. Mask and counter
+/// packets, as well as invalid packets, return `None`.
+///
 pub fn parse_yaagc_packet(msg: [u8; 4]) -> Option<(u16, u16)> {
-    let a;
-    let b;
-    let c;
-    let d;
-
-    match msg.len() {
-        4 => {
-            a = *msg.get(0).unwrap();
-            b = *msg.get(1).unwrap();
-            c = *msg.get(2).unwrap();
-            d = *msg.get(3).unwrap();
-        }
-        5 => {
-            a = *msg.get(0).unwrap();
-            b = *msg.get(1).unwrap();
-            c = *msg.get(2).unwrap();
-            d = *msg.get(3).unwrap();
-        }
-        _ => {
-            return None;
+    match AGCPacket::deserialize(&msg) {
+        Ok(AGCPacket::ChannelWrite { channel, value }) => Some((channel as u16, value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod agc_tests {
+    use super::*;
+
+    ///
+    /// # Description
+    ///
+    /// Tests every kind of packet survives a serialize/deserialize round trip.
+    ///
+    #[test]
+    fn test_packet_round_trip() {
+        let packets = [
+            AGCPacket::ChannelWrite {
+                channel: 0o10,
+                value: 0o77777,
+            },
+            AGCPacket::ChannelWrite {
+                channel: 0o177,
+                value: 0,
+            },
+            AGCPacket::ChannelMask {
+                channel: 0o32,
+                mask: 0o20000,
+            },
+            AGCPacket::CounterIncrement {
+                counter: 0o37,
+                increment: CounterIncrement::Minc,
+            },
+            AGCPacket::CounterIncrement {
+                counter: 0o32,
+                increment: CounterIncrement::Shanc,
+            },
+        ];
+
+        for packet in packets.iter() {
+            let data = packet.serialize().unwrap();
+            assert_eq!(AGCPacket::deserialize(&data), Ok(*packet));
         }
     }
 
-    if a & 0xC0 != 0x00 || b & 0xC0 != 0x40 || c & 0xC0 != 0x80 || d & 0xC0 != 0xC0 {
-        return None;
+    ///
+    /// # Description
+    ///
+    /// Tests the wire format of channel writes matches the original packet
+    /// functions, and of u-bit and t-bit packets.
+    ///
+    #[test]
+    fn test_packet_format() {
+        let write = AGCPacket::ChannelWrite {
+            channel: 0o163,
+            value: 0o12345,
+        };
+        assert_eq!(write.serialize(), Ok(generate_yaagc_packet(0o163, 0o12345)));
+        assert_eq!(parse_yaagc_packet(write.serialize().unwrap()), Some((0o163, 0o12345)));
+
+        let mask = AGCPacket::ChannelMask {
+            channel: 0o15,
+            mask: 0o37,
+        };
+        assert_eq!(mask.serialize(), Ok([0x21, 0x68, 0x80, 0xDF]));
+
+        let counter = AGCPacket::CounterIncrement {
+            counter: 0o42,
+            increment: CounterIncrement::Pcdu,
+        };
+        assert_eq!(counter.serialize(), Ok([0x14, 0x50, 0x80, 0xC1]));
     }
 
-    let value: u16 = ((b as u16) & 0x7) << 12 | ((c as u16) & 0x3F) << 6 | ((d & 0x3F) as u16);
-    let channel: u16 = ((a as u16) & 0x3F) << 3 | ((b as u16) >> 3 & 0x7);
-    Some((channel, value))
+    ///
+    /// # Description
+    ///
+    /// Tests the errors reported for invalid packets.
+    ///
+    #[test]
+    fn test_packet_errors() {
+        assert_eq!(
+            AGCPacket::deserialize(&[0x00, 0x40, 0xC0, 0xC0]),
+            Err(PacketError::InvalidTag(2))
+        );
+        assert_eq!(
+            AGCPacket::deserialize(&[0x30, 0x40, 0x80, 0xC0]),
+            Err(PacketError::InvalidFlags)
+        );
+        assert_eq!(
+            AGCPacket::deserialize(&[0x10, 0x40, 0x80, 0xC7]),
+            Err(PacketError::InvalidCounterIncrement(7))
+        );
+
+        let bad_channel = AGCPacket::ChannelWrite {
+            channel: 0o200,
+            value: 0,
+        };
+        assert_eq!(bad_channel.serialize(), Err(PacketError::ChannelOutOfRange(0o200)));
+        let bad_value = AGCPacket::ChannelMask {
+            channel: 0o30,
+            mask: 0o100000,
+        };
+        assert_eq!(bad_value.serialize(), Err(PacketError::ValueOutOfRange(0o100000)));
+    }
 }
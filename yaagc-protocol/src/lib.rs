@@ -1,6 +1,7 @@
 #![no_std]
 
 pub mod agc;
+pub mod stream;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,217 @@
+use core::fmt;
+
+use crate::agc::{AGCPacket, PacketError};
+
+///
+/// ## `StreamError` Enum
+///
+/// Errors found while decoding a stream of packets.
+///
+///  - `Resync` - The given number of bytes were dropped before the stream was
+///    aligned on the start of a packet again
+///  - `Packet` - The bytes of a packet were aligned but its content is not
+///    valid
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamError {
+    Resync(usize),
+    Packet(PacketError),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Resync(x) => write!(f, "dropped {} bytes to resynchronise", x),
+            StreamError::Packet(x) => write!(f, "invalid packet: {}", x),
+        }
+    }
+}
+
+///
+/// ## `PacketDecoder` Structure
+///
+/// Incremental decoder of a yaAGC packet stream. Bytes can be pushed as they
+/// arrive, one at a time or in chunks of any size. Each byte of a packet is
+/// tagged with its position (`00`, `01`, `10`, `11`) in its top two bits, so
+/// when a byte shows up out of place the partial packet is dropped and the
+/// decoder waits for the next `00` tagged byte. The number of dropped bytes is
+/// reported once the decoder is aligned again.
+///
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    buf: [u8; 4],
+    len: usize,
+    dropped: usize,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        PacketDecoder {
+            buf: [0; 4],
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    ///
+    /// ## `push` Function
+    ///
+    /// Pushes a single byte of the stream.
+    ///
+    /// ### Result
+    ///
+    ///  - `Some(Ok(packet))` once the last byte of a valid packet is pushed
+    ///  - `Some(Err(StreamError::Resync(n)))` when the byte starts a packet
+    ///    after `n` bytes had to be dropped
+    ///  - `Some(Err(StreamError::Packet(e)))` when an aligned packet is not
+    ///    valid
+    ///  - `None` if more bytes are needed
+    ///
+    pub fn push(&mut self, byte: u8) -> Option<Result<AGCPacket, StreamError>> {
+        let tag = (byte >> 6) as usize;
+        if tag != self.len {
+            // Out of place byte, drop the partial packet
+            self.dropped += self.len;
+            self.len = 0;
+            if tag != 0 {
+                self.dropped += 1;
+                return None;
+            }
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+
+        if self.len == 1 && self.dropped != 0 {
+            let dropped = self.dropped;
+            self.dropped = 0;
+            return Some(Err(StreamError::Resync(dropped)));
+        }
+
+        if self.len < 4 {
+            return None;
+        }
+
+        self.len = 0;
+        Some(AGCPacket::deserialize(&self.buf).map_err(StreamError::Packet))
+    }
+
+    ///
+    /// ## `decode` Function
+    ///
+    /// Pushes a chunk of the stream, returning an iterator over the packets
+    /// and errors found in it. Bytes of an incomplete packet at the end of
+    /// the chunk are kept for the next chunk.
+    ///
+    pub fn decode<'a>(&'a mut self, data: &'a [u8]) -> Decode<'a> {
+        Decode {
+            decoder: self,
+            data: data.iter(),
+        }
+    }
+
+    ///
+    /// ## `reset` Function
+    ///
+    /// Drops any partial packet, i.e. when the connection is reopened.
+    ///
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.dropped = 0;
+    }
+}
+
+///
+/// ## `Decode` Structure
+///
+/// Iterator returned by `PacketDecoder::decode`.
+///
+pub struct Decode<'a> {
+    decoder: &'a mut PacketDecoder,
+    data: core::slice::Iter<'a, u8>,
+}
+
+impl Iterator for Decode<'_> {
+    type Item = Result<AGCPacket, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for byte in self.data.by_ref() {
+            if let Some(res) = self.decoder.push(*byte) {
+                return Some(res);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::agc::generate_yaagc_packet;
+
+    fn write(channel: u8, value: u16) -> AGCPacket {
+        AGCPacket::ChannelWrite { channel, value }
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests packets split over several chunks are decoded once complete.
+    ///
+    #[test]
+    fn test_stream_chunks() {
+        let a = generate_yaagc_packet(0o15, 0o21);
+        let b = generate_yaagc_packet(0o32, 0o20000);
+        let mut decoder = PacketDecoder::new();
+
+        assert_eq!(decoder.decode(&a[..3]).next(), None);
+        let mut chunk = [0; 5];
+        chunk[0] = a[3];
+        chunk[1..].copy_from_slice(&b);
+
+        let mut it = decoder.decode(&chunk);
+        assert_eq!(it.next(), Some(Ok(write(0o15, 0o21))));
+        assert_eq!(it.next(), Some(Ok(write(0o32, 0o20000))));
+        assert_eq!(it.next(), None);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests the decoder recovers after a lost byte, reporting the dropped
+    /// bytes, instead of staying misaligned.
+    ///
+    #[test]
+    fn test_stream_resync() {
+        let a = generate_yaagc_packet(0o15, 0o21);
+        let b = generate_yaagc_packet(0o15, 0o34);
+        let c = generate_yaagc_packet(0o15, 0o37);
+
+        // Second byte of the first packet lost, plus a stray byte
+        let data = [a[0], a[2], a[3], 0xFF, b[0], b[1], b[2], b[3], c[0], c[1], c[2], c[3]];
+        let mut decoder = PacketDecoder::new();
+        let mut it = decoder.decode(&data);
+        assert_eq!(it.next(), Some(Err(StreamError::Resync(4))));
+        assert_eq!(it.next(), Some(Ok(write(0o15, 0o34))));
+        assert_eq!(it.next(), Some(Ok(write(0o15, 0o37))));
+        assert_eq!(it.next(), None);
+    }
+
+    ///
+    /// # Description
+    ///
+    /// Tests aligned packets with invalid content are reported without losing
+    /// the alignment.
+    ///
+    #[test]
+    fn test_stream_invalid_packet() {
+        let mut decoder = PacketDecoder::new();
+        let data = [0x30, 0x40, 0x80, 0xC0, 0x01, 0x68, 0x80, 0xC1];
+        let mut it = decoder.decode(&data);
+        assert_eq!(
+            it.next(),
+            Some(Err(StreamError::Packet(PacketError::InvalidFlags)))
+        );
+        assert_eq!(it.next(), Some(Ok(write(0o15, 1))));
+    }
+}